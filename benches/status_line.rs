@@ -0,0 +1,29 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Every controller's status, as in `testdata/vhci/`.
+const STATUS: [&str; 2] = [
+    include_str!("../testdata/vhci/status"),
+    include_str!("../testdata/vhci/status.1"),
+];
+
+fn status_lines(c: &mut Criterion) {
+    let lines = STATUS
+        .iter()
+        .map(|status| status.lines().count() - 1)
+        .sum::<usize>();
+
+    let mut group = c.benchmark_group("status");
+    group.throughput(Throughput::Elements(lines as u64));
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            STATUS
+                .iter()
+                .map(|status| usbip_core::benching::status(criterion::black_box(status)))
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, status_lines);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usbip_core::net::{bincode_config, OpCommon, Protocol};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((op, _)) = bincode::decode_from_slice::<OpCommon, _>(data, bincode_config()) {
+        let _ = op.validate(Protocol::OP_UNSPEC);
+        let _ = op.validate(Protocol::OP_REP_IMPORT);
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usbip_core::net::{bincode_config, OpImportReply};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((rep, _)) = bincode::decode_from_slice::<OpImportReply, _>(data, bincode_config()) {
+        let device = rep.into_inner();
+        let _ = device.validate();
+        let _ = device.dev_id();
+        let _ = format!("{device:#}");
+        let _ = bincode::encode_to_vec(&device, bincode_config());
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usbip_core::vhci::PortRecord;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = core::str::from_utf8(data) {
+        let _ = s.parse::<PortRecord>();
+    }
+});
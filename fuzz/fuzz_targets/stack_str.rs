@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use usbip_core::{
+    containers::stacktools::{StackStr, Str},
+    net::bincode_config,
+    BusId, SysPath,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((s, _)) = bincode::decode_from_slice::<StackStr<32>, _>(data, bincode_config()) {
+        let _ = s.len();
+        let _ = bincode::encode_to_vec(&s, bincode_config());
+    }
+    if let Ok((s, _)) = bincode::borrow_decode_from_slice::<&Str<32>, _>(data, bincode_config()) {
+        let _ = s.trim_padding().to_owned();
+    }
+    let _ = bincode::decode_from_slice::<BusId<'static>, _>(data, bincode_config());
+    let _ = bincode::borrow_decode_from_slice::<BusId<'_>, _>(data, bincode_config());
+    let _ = bincode::decode_from_slice::<SysPath<'static>, _>(data, bincode_config());
+    let _ = bincode::borrow_decode_from_slice::<SysPath<'_>, _>(data, bincode_config());
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = core::str::from_utf8(data) {
+        usbip_core::fuzzing::status_line(line);
+    }
+});
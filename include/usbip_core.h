@@ -0,0 +1,69 @@
+/* C declarations for usbip-core's `ffi` feature.
+ * Keep in sync with src/ffi.rs, which asserts the struct layouts.
+ *
+ * A usbip_vhci handle isn't synchronized: it may be moved between
+ * threads, but must not be used from two threads at once. */
+
+#ifndef USBIP_CORE_H
+#define USBIP_CORE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define USBIP_BUS_ID_SIZE 32
+#define USBIP_HOST_SIZE 64
+
+typedef int32_t usbip_status;
+
+#define USBIP_OK 0
+#define USBIP_INVALID_INPUT -1
+#define USBIP_DRIVER_MISSING -2
+#define USBIP_PERMISSION_DENIED -3
+#define USBIP_REMOTE_REJECTED -4
+#define USBIP_BUSY -5
+#define USBIP_NOT_CONNECTED -6
+#define USBIP_PROTOCOL -7
+#define USBIP_CANCELLED -8
+#define USBIP_TIMED_OUT -9
+#define USBIP_IO -10
+#define USBIP_BUFFER_TOO_SMALL -11
+#define USBIP_PANIC -12
+
+typedef struct usbip_vhci usbip_vhci;
+
+typedef struct usbip_port_counts {
+    size_t num_controllers;
+    size_t num_ports;
+    size_t free_ports;
+} usbip_port_counts;
+
+typedef struct usbip_imported_device {
+    uint16_t port;
+    uint16_t vendor;
+    uint16_t product;
+    uint32_t devid;
+    char bus_id[USBIP_BUS_ID_SIZE];
+    char host[USBIP_HOST_SIZE];
+} usbip_imported_device;
+
+usbip_status usbip_vhci_open(usbip_vhci **out);
+void usbip_vhci_close(usbip_vhci *vhci);
+usbip_status usbip_vhci_attach(usbip_vhci *vhci, const char *host,
+                               const char *bus_id, uint16_t *port);
+usbip_status usbip_vhci_detach(usbip_vhci *vhci, uint16_t port);
+usbip_status usbip_vhci_port_counts(const usbip_vhci *vhci,
+                                    usbip_port_counts *out);
+usbip_status usbip_vhci_imported_devices(const usbip_vhci *vhci,
+                                         usbip_imported_device *buf,
+                                         size_t cap, size_t *len);
+size_t usbip_last_error(char *buf, size_t cap);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* USBIP_CORE_H */
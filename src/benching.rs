@@ -0,0 +1,13 @@
+//! Entry points into parsers that aren't public, for the
+//! benches in `benches/`. Only built with the `bench`
+//! feature. Run them with `cargo bench --features bench`.
+
+/// Parses a controller's `status` attribute the way
+/// [`VhciDriver::imported_devices`] reads it, returning
+/// how many ports are in use.
+///
+/// [`VhciDriver::imported_devices`]: crate::vhci::VhciDriver::imported_devices
+#[cfg(unix)]
+pub fn status(status: &str) -> usize {
+    crate::unix::vhci2::parse_status(status).expect("the status should parse")
+}
@@ -0,0 +1,362 @@
+//! A small `usbip` work-alike built on usbip-core.
+//!
+//! It covers the everyday subcommands of the usbip
+//! utility, and doubles as an example of the public API.
+//! Build it with `cargo build --features cli`.
+
+use std::{
+    env,
+    error::Error,
+    net::{SocketAddr, ToSocketAddrs},
+    process::ExitCode,
+};
+
+use usbip_core::{
+    names::{Names, NamesBuilder},
+    remote,
+    vhci::{AttachArgs, VhciDriver},
+    BusId, UsbDevice,
+};
+
+const USAGE: &str = "\
+usage: usbip [-t PORT] <command> [options]
+
+commands:
+    list -r HOST            list the devices HOST exports
+    list -l                 list the devices this machine can export
+    attach -r HOST -b BUSID import a device from HOST
+    detach -p PORT          detach the device on a vhci port
+    port                    list the imported devices
+    bind -b BUSID           export a device of this machine
+    unbind -b BUSID         stop exporting a device
+
+options:
+    -t, --tcp-port PORT     connect to hosts on PORT (default 3240)
+    -h, --help              print this message";
+
+/// The port USB/IP hosts listen on.
+const USBIP_PORT: u16 = 3240;
+
+type Result<T> = core::result::Result<T, Box<dyn Error>>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Help,
+    ListRemote { host: String },
+    ListLocal,
+    Attach { host: String, bus_id: String },
+    Detach { port: u16 },
+    Port,
+    Bind { bus_id: String },
+    Unbind { bus_id: String },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    tcp_port: u16,
+    command: Command,
+}
+
+/// Parses the command line, without the program name.
+fn parse(args: impl IntoIterator<Item = String>) -> core::result::Result<Args, String> {
+    let mut args = args.into_iter();
+    let mut tcp_port = USBIP_PORT;
+    let command = loop {
+        match args.next().as_deref() {
+            Some("-t" | "--tcp-port") => {
+                let port = value(&mut args, "--tcp-port")?;
+                tcp_port = port.parse().map_err(|_| format!("bad port `{port}`"))?;
+            }
+            Some("-h" | "--help") | None => {
+                return Ok(Args {
+                    tcp_port,
+                    command: Command::Help,
+                })
+            }
+            Some(command) => break command.to_owned(),
+        }
+    };
+
+    let mut remote = None;
+    let mut local = false;
+    let mut bus_id = None;
+    let mut port = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-r" | "--remote" => remote = Some(value(&mut args, "--remote")?),
+            "-l" | "--local" => local = true,
+            "-b" | "--busid" => bus_id = Some(value(&mut args, "--busid")?),
+            "-p" | "--port" => {
+                let value = value(&mut args, "--port")?;
+                port = Some(value.parse().map_err(|_| format!("bad port `{value}`"))?);
+            }
+            "-h" | "--help" => {
+                return Ok(Args {
+                    tcp_port,
+                    command: Command::Help,
+                })
+            }
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+
+    let command = match (command.as_str(), remote, local) {
+        ("list", Some(host), false) => Command::ListRemote { host },
+        ("list", None, true) => Command::ListLocal,
+        ("list", _, _) => return Err("list needs one of --remote or --local".to_owned()),
+        ("attach", Some(host), _) => Command::Attach {
+            host,
+            bus_id: bus_id.ok_or("attach needs --busid")?,
+        },
+        ("attach", None, _) => return Err("attach needs --remote".to_owned()),
+        ("detach", ..) => Command::Detach {
+            port: port.ok_or("detach needs --port")?,
+        },
+        ("port", ..) => Command::Port,
+        ("bind", ..) => Command::Bind {
+            bus_id: bus_id.ok_or("bind needs --busid")?,
+        },
+        ("unbind", ..) => Command::Unbind {
+            bus_id: bus_id.ok_or("unbind needs --busid")?,
+        },
+        (command, ..) => return Err(format!("unknown command `{command}`")),
+    };
+    Ok(Args { tcp_port, command })
+}
+
+fn value(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> core::result::Result<String, String> {
+    args.next().ok_or_else(|| format!("{flag} needs a value"))
+}
+
+fn main() -> ExitCode {
+    let args = match parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("usbip: {msg}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprint!("usbip: {err}");
+            let mut source = err.source();
+            while let Some(err) = source {
+                eprint!(": {err}");
+                source = err.source();
+            }
+            eprintln!();
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    match args.command {
+        Command::Help => println!("{USAGE}"),
+        Command::ListRemote { host } => list_remote(&host, resolve(&host, args.tcp_port)?)?,
+        Command::ListLocal => list_local()?,
+        Command::Attach { host, bus_id } => {
+            let addr = resolve(&host, args.tcp_port)?;
+            let args = AttachArgs {
+                host: addr,
+                bus_id: BusId::parse(&bus_id)?,
+            };
+            let port = VhciDriver::open()?.attach(args)?;
+            println!("Attached {host}/{bus_id} on port {port}");
+        }
+        Command::Detach { port } => {
+            VhciDriver::open()?.detach(port)?;
+            println!("Detached port {port}");
+        }
+        Command::Port => port()?,
+        Command::Bind { bus_id } => bind(&bus_id)?,
+        Command::Unbind { bus_id } => unbind(&bus_id)?,
+    }
+    Ok(())
+}
+
+fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("`{host}` has no addresses").into())
+}
+
+/// The usb.ids database, or an empty one
+/// that names everything "unknown".
+fn names() -> &'static Names {
+    static EMPTY: std::sync::OnceLock<Names> = std::sync::OnceLock::new();
+    Names::global().unwrap_or_else(|| EMPTY.get_or_init(|| NamesBuilder::new().build()))
+}
+
+fn list_remote(host: &str, addr: SocketAddr) -> Result<()> {
+    let exported = remote::list_devices(addr)?;
+
+    println!("Exportable USB devices");
+    println!("======================");
+    println!(" - {host}");
+    let names = names();
+    for exported in &exported {
+        let dev = exported.device();
+        let description = dev.describe(names);
+        println!("{:>11}: {}", dev.bus_id(), description.product());
+        println!("{:>11}: {}", "", dev.path().display());
+        println!("{:>11}: {}", "", description.class());
+        for (i, interface) in exported.interfaces().iter().enumerate() {
+            println!("{:>11}: {i:2} - {}", "", interface.class_display(names));
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// The USB devices of this machine, leaving out those that
+/// can't be read, like those that are going away, rather
+/// than failing the list.
+#[cfg(all(unix, feature = "udev"))]
+fn local_devices() -> Result<Vec<UsbDevice>> {
+    let mut enumerator = udev::Enumerator::new()?;
+    enumerator.match_subsystem("usb")?;
+    enumerator.match_property("DEVTYPE", "usb_device")?;
+    Ok(enumerator
+        .scan_devices()?
+        .filter_map(|udev| UsbDevice::try_from(udev).ok())
+        .collect())
+}
+
+#[cfg(all(unix, not(feature = "udev")))]
+fn local_devices() -> Result<Vec<UsbDevice>> {
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir("/sys/bus/usb/devices")? {
+        let entry = entry?;
+        // Interfaces are named after their device, like `1-1:1.0`.
+        if entry.file_name().to_string_lossy().contains(':') {
+            continue;
+        }
+        if let Ok(dev) = UsbDevice::from_syspath(entry.path()) {
+            devices.push(dev);
+        }
+    }
+    devices.sort_unstable_by(|a, b| a.path().cmp(b.path()));
+    Ok(devices)
+}
+
+#[cfg(unix)]
+fn list_local() -> Result<()> {
+    let names = names();
+    for dev in local_devices()? {
+        // Hubs can't be exported.
+        if dev.device_class() == 0x09 {
+            continue;
+        }
+        println!(
+            " - busid {} ({:04x}:{:04x})",
+            dev.bus_id(),
+            dev.id_vendor(),
+            dev.id_product()
+        );
+        println!("   {}", dev.describe(names).product());
+        println!();
+    }
+    Ok(())
+}
+
+fn port() -> Result<()> {
+    let driver = VhciDriver::open()?;
+    let idevs = driver.imported_devices()?;
+    let names = names();
+
+    println!("Imported USB devices");
+    println!("====================");
+    for idev in idevs.get() {
+        println!("Port {:02}: at {}", idev.port(), idev.speed());
+        println!(
+            "       {}",
+            names.product_display(idev.vendor(), idev.product())
+        );
+        match idev.record() {
+            Some(record) => println!("       -> usbip://{}/{}", record.host(), record.bus_id()),
+            None => println!("       -> unknown host and remote busid"),
+        }
+        println!(
+            "       -> remote bus/dev {:03}/{:03}",
+            idev.bus_num(),
+            idev.dev_num()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn bind(bus_id: &str) -> Result<()> {
+    usbip_core::unix::host::Driver::new()?.bind(bus_id)?;
+    println!("Bound {bus_id} to usbip-host");
+    Ok(())
+}
+
+#[cfg(unix)]
+fn unbind(bus_id: &str) -> Result<()> {
+    usbip_core::unix::host::Driver::new()?.unbind(bus_id)?;
+    println!("Unbound {bus_id} from usbip-host");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn list_local() -> Result<()> {
+    Err("exporting devices is only supported on Linux".into())
+}
+
+#[cfg(not(unix))]
+fn bind(_: &str) -> Result<()> {
+    list_local()
+}
+
+#[cfg(not(unix))]
+fn unbind(_: &str) -> Result<()> {
+    list_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(line: &str) -> core::result::Result<Args, String> {
+        parse(line.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(
+            parse_str("-t 3241 attach -r example.com -b 1-1.2").unwrap(),
+            Args {
+                tcp_port: 3241,
+                command: Command::Attach {
+                    host: "example.com".to_owned(),
+                    bus_id: "1-1.2".to_owned(),
+                },
+            }
+        );
+        assert_eq!(
+            parse_str("detach --port 3").unwrap().command,
+            Command::Detach { port: 3 }
+        );
+        assert_eq!(parse_str("list -l").unwrap().command, Command::ListLocal);
+        assert_eq!(parse_str("").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn rejects_incomplete_commands() {
+        assert_eq!(
+            parse_str("list").unwrap_err(),
+            "list needs one of --remote or --local"
+        );
+        assert_eq!(parse_str("bind").unwrap_err(), "bind needs --busid");
+        assert_eq!(parse_str("detach -p").unwrap_err(), "--port needs a value");
+        assert_eq!(parse_str("frob").unwrap_err(), "unknown command `frob`");
+    }
+}
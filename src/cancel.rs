@@ -0,0 +1,173 @@
+//! Cooperative cancellation for long-running driver operations.
+
+#[cfg(feature = "client")]
+use std::sync::atomic::AtomicUsize;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    #[cfg(feature = "client")]
+    next_id: AtomicUsize,
+    callbacks: Mutex<Vec<(usize, Callback)>>,
+}
+
+/// A token that can be shared across threads to
+/// abort an in-flight operation, such as an attach
+/// to a host that never responds.
+///
+/// Cloning a [`CancellationToken`] creates another
+/// handle to the same token.
+///
+/// # Examples
+///
+/// ```
+/// use usbip_core::cancel::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let other = token.clone();
+///
+/// other.cancel();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    /// Creates a new token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels every operation using this token.
+    ///
+    /// Cancelling an already cancelled
+    /// token does nothing.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // Callbacks run while holding the lock so that a
+        // `CancelGuard` can't be dropped (and whatever the
+        // callback touches freed) halfway through a call.
+        let mut callbacks = self.inner.callbacks.lock().unwrap();
+        for (_, callback) in callbacks.drain(..) {
+            callback();
+        }
+    }
+
+    /// Returns `true` if [`CancellationToken::cancel`]
+    /// was called on this token or any of its clones.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Registers `callback` to be run when the token is cancelled,
+    /// or runs it right away if the token already was.
+    ///
+    /// The callback is unregistered once the
+    /// returned [`CancelGuard`] is dropped.
+    #[cfg(feature = "client")]
+    pub(crate) fn on_cancel<F>(&self, callback: F) -> CancelGuard
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut callbacks = self.inner.callbacks.lock().unwrap();
+        if self.is_cancelled() {
+            drop(callbacks);
+            callback();
+        } else {
+            callbacks.push((id, Box::new(callback)));
+        }
+
+        CancelGuard {
+            inner: Arc::clone(&self.inner),
+            id,
+        }
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Unregisters a cancellation callback when dropped.
+#[cfg(feature = "client")]
+pub(crate) struct CancelGuard {
+    inner: Arc<Inner>,
+    id: usize,
+}
+
+#[cfg(feature = "client")]
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        self.inner
+            .callbacks
+            .lock()
+            .unwrap()
+            .retain(|(id, _)| *id != self.id);
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    #[test]
+    fn callback_runs_on_cancel() {
+        let token = CancellationToken::new();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits2 = Arc::clone(&hits);
+
+        let _guard = token.on_cancel(move || {
+            hits2.fetch_add(1, Ordering::Relaxed);
+        });
+        token.cancel();
+        token.cancel();
+
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn dropped_guard_unregisters() {
+        let token = CancellationToken::new();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits2 = Arc::clone(&hits);
+
+        drop(token.on_cancel(move || {
+            hits2.fetch_add(1, Ordering::Relaxed);
+        }));
+        token.cancel();
+
+        assert_eq!(hits.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn already_cancelled_runs_immediately() {
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits2 = Arc::clone(&hits);
+        let _guard = token.on_cancel(move || {
+            hits2.fetch_add(1, Ordering::Relaxed);
+        });
+
+        assert_eq!(hits.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,379 @@
+//! Recording the PDUs exchanged with a host or client into
+//! a pcapng file, to inspect with Wireshark when debugging
+//! interop bugs.
+//!
+//! pcap has no link type for USB/IP, which Wireshark
+//! dissects on top of TCP instead. So each read and write of
+//! a [`Capture`]d stream is written as a TCP segment inside a
+//! raw IP packet, between the stream's addresses. Wireshark
+//! decodes port 3240 as USB/IP, and other ports with
+//! *Decode As…*.
+//!
+//! # Examples
+//! ```no_run
+//! use std::{
+//!     fs::File,
+//!     net::TcpStream,
+//!     sync::{Arc, Mutex},
+//! };
+//! use usbip_core::capture::{Capture, PcapngWriter};
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let writer = PcapngWriter::new(File::create("usbip.pcapng")?)?;
+//! let writer = Arc::new(Mutex::new(writer));
+//! let socket = Capture::tcp(TcpStream::connect("10.0.0.2:3240")?, writer)?;
+//! // Send and receive PDUs through `socket` as usual.
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// `LINKTYPE_RAW`: packets start with an IPv4 or IPv6 header.
+const LINKTYPE_RAW: u16 = 101;
+/// The most payload put in one segment, so that
+/// IPv4's 16-bit total length can't overflow.
+const MAX_SEGMENT: usize = 65_000;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH_ACK: u8 = 0x18;
+const TCP_SYN_ACK: u8 = 0x12;
+
+/// Writes a pcapng file with one interface,
+/// whose packets are raw IP.
+#[derive(Debug)]
+pub struct PcapngWriter<W> {
+    out: W,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Writes the file's section and interface headers to `out`.
+    ///
+    /// # Errors
+    /// This function will return an error if
+    /// writing the headers failed.
+    pub fn new(mut out: W) -> io::Result<Self> {
+        // Section header block: no options, unknown length.
+        let mut shb = Vec::with_capacity(28);
+        shb.extend(0x0A0D_0D0Au32.to_le_bytes());
+        shb.extend(28u32.to_le_bytes());
+        shb.extend(0x1A2B_3C4Du32.to_le_bytes());
+        shb.extend(1u16.to_le_bytes());
+        shb.extend(0u16.to_le_bytes());
+        shb.extend((-1i64).to_le_bytes());
+        shb.extend(28u32.to_le_bytes());
+        out.write_all(&shb)?;
+
+        // Interface description block: no snap length,
+        // microsecond timestamps.
+        let mut idb = Vec::with_capacity(20);
+        idb.extend(1u32.to_le_bytes());
+        idb.extend(20u32.to_le_bytes());
+        idb.extend(LINKTYPE_RAW.to_le_bytes());
+        idb.extend(0u16.to_le_bytes());
+        idb.extend(0u32.to_le_bytes());
+        idb.extend(20u32.to_le_bytes());
+        out.write_all(&idb)?;
+
+        Ok(Self { out })
+    }
+
+    /// Writes an enhanced packet block for `packet`.
+    fn write_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let padded = packet.len().next_multiple_of(4);
+        let total = u32::try_from(32 + padded).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let len = packet.len() as u32;
+
+        let mut epb = Vec::with_capacity(total as usize);
+        epb.extend(6u32.to_le_bytes());
+        epb.extend(total.to_le_bytes());
+        epb.extend(0u32.to_le_bytes());
+        epb.extend(((micros >> 32) as u32).to_le_bytes());
+        epb.extend((micros as u32).to_le_bytes());
+        epb.extend(len.to_le_bytes());
+        epb.extend(len.to_le_bytes());
+        epb.extend(packet);
+        epb.resize(epb.len() + padded - packet.len(), 0);
+        epb.extend(total.to_le_bytes());
+        self.out.write_all(&epb)
+    }
+
+    /// Writes a TCP segment from `src` to `dst`.
+    fn write_segment(
+        &mut self,
+        src: SocketAddr,
+        dst: SocketAddr,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut packet = ip_header(src.ip(), dst.ip(), 20 + payload.len());
+        packet.extend(src.port().to_be_bytes());
+        packet.extend(dst.port().to_be_bytes());
+        packet.extend(seq.to_be_bytes());
+        packet.extend(ack.to_be_bytes());
+        // A 20 byte header, and a checksum of 0 that
+        // Wireshark doesn't check unless told to.
+        packet.extend([5 << 4, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+        packet.extend(payload);
+        self.write_packet(&packet)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// An IPv4 or IPv6 header for a TCP segment
+/// of `len` bytes from `src` to `dst`.
+fn ip_header(src: IpAddr, dst: IpAddr, len: usize) -> Vec<u8> {
+    let v4 = |ip: IpAddr| match ip {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(ip) => ip.to_ipv4_mapped(),
+    };
+    if let (Some(src), Some(dst)) = (v4(src), v4(dst)) {
+        let total = (20 + len) as u16;
+        let mut header = vec![0x45, 0];
+        header.extend(total.to_be_bytes());
+        // No id, don't fragment, a TTL of 64, TCP.
+        header.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+        header.extend(src.octets());
+        header.extend(dst.octets());
+        let sum = header
+            .chunks(2)
+            .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+            .sum::<u32>();
+        let sum = (sum & 0xFFFF) + (sum >> 16);
+        let checksum = !((sum & 0xFFFF) + (sum >> 16)) as u16;
+        header[10..12].copy_from_slice(&checksum.to_be_bytes());
+        return header;
+    }
+
+    let v6 = |ip: IpAddr| match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    let mut header = vec![0x60, 0, 0, 0];
+    header.extend((len as u16).to_be_bytes());
+    // TCP, with a hop limit of 64.
+    header.extend([6, 64]);
+    header.extend(v6(src).octets());
+    header.extend(v6(dst).octets());
+    header
+}
+
+/// A stream whose traffic is recorded
+/// into a shared [`PcapngWriter`].
+///
+/// Failing to write the capture doesn't fail the stream's
+/// reads and writes, so that debugging doesn't get in the
+/// way of the connection.
+pub struct Capture<T, W: Write> {
+    inner: T,
+    writer: Arc<Mutex<PcapngWriter<W>>>,
+    local: SocketAddr,
+    peer: SocketAddr,
+    /// The next sequence number of each direction.
+    sent: u32,
+    received: u32,
+}
+
+impl<W: Write> Capture<TcpStream, W> {
+    /// Records `socket`'s traffic, between its own addresses.
+    ///
+    /// # Errors
+    /// This function will return an error if the
+    /// socket's addresses couldn't be read.
+    pub fn tcp(socket: TcpStream, writer: Arc<Mutex<PcapngWriter<W>>>) -> io::Result<Self> {
+        let (local, peer) = (socket.local_addr()?, socket.peer_addr()?);
+        Ok(Self::new(socket, local, peer, writer))
+    }
+}
+
+impl<T, W: Write> Capture<T, W> {
+    /// Records `inner`'s traffic as a TCP connection from
+    /// `local` to `peer`, starting with its handshake.
+    pub fn new(
+        inner: T,
+        local: SocketAddr,
+        peer: SocketAddr,
+        writer: Arc<Mutex<PcapngWriter<W>>>,
+    ) -> Self {
+        let capture = Self {
+            inner,
+            writer,
+            local,
+            peer,
+            sent: 1,
+            received: 1,
+        };
+        capture.record(|writer| {
+            writer.write_segment(local, peer, 0, 0, TCP_SYN, &[])?;
+            writer.write_segment(peer, local, 0, 1, TCP_SYN_ACK, &[])
+        });
+        capture
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&self, f: impl FnOnce(&mut PcapngWriter<W>) -> io::Result<()>) {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let _ = f(&mut writer);
+    }
+
+    /// Records `bytes` as sent by `local` if `outgoing`,
+    /// or by `peer` otherwise.
+    fn record_data(&mut self, outgoing: bool, bytes: &[u8]) {
+        let (src, dst, mut seq, ack) = if outgoing {
+            (self.local, self.peer, self.sent, self.received)
+        } else {
+            (self.peer, self.local, self.received, self.sent)
+        };
+        self.record(|writer| {
+            for segment in bytes.chunks(MAX_SEGMENT) {
+                writer.write_segment(src, dst, seq, ack, TCP_PSH_ACK, segment)?;
+                seq = seq.wrapping_add(segment.len() as u32);
+            }
+            Ok(())
+        });
+        let next = if outgoing {
+            &mut self.sent
+        } else {
+            &mut self.received
+        };
+        *next = next.wrapping_add(bytes.len() as u32);
+    }
+}
+
+impl<T: Read, W: Write> Read for Capture<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.record_data(false, &buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write, W: Write> Write for Capture<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.record_data(true, &buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream that replies with `incoming`.
+    struct Loopback {
+        incoming: io::Cursor<Vec<u8>>,
+        outgoing: Vec<u8>,
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.incoming.read(buf)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.outgoing.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Splits a pcapng file into its blocks' types and bodies.
+    fn blocks(file: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        let mut rest = file;
+        while !rest.is_empty() {
+            let word = |at: usize| u32::from_le_bytes(rest[at..at + 4].try_into().unwrap());
+            let (kind, len) = (word(0), word(4) as usize);
+            assert_eq!(word(len - 4) as usize, len);
+            blocks.push((kind, &rest[8..len - 4]));
+            rest = &rest[len..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn traffic_is_written_as_tcp_segments() {
+        let writer = Arc::new(Mutex::new(PcapngWriter::new(Vec::new()).unwrap()));
+        let local: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        let peer: SocketAddr = "10.0.0.2:3240".parse().unwrap();
+        let reply = [0x01, 0x11, 0x00, 0x05];
+        let stream = Loopback {
+            incoming: io::Cursor::new(reply.to_vec()),
+            outgoing: Vec::new(),
+        };
+        let mut capture = Capture::new(stream, local, peer, writer);
+
+        capture.write_all(&[0x01, 0x11, 0x80, 0x05]).unwrap();
+        let mut buf = [0; 4];
+        capture.read_exact(&mut buf).unwrap();
+
+        assert_eq!(capture.get_ref().outgoing, [0x01, 0x11, 0x80, 0x05]);
+        let writer = Arc::clone(&capture.writer);
+        drop(capture);
+        let file = Arc::into_inner(writer)
+            .unwrap()
+            .into_inner()
+            .unwrap()
+            .into_inner();
+        let blocks = blocks(&file);
+        let kinds: Vec<_> = blocks.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [0x0A0D_0D0A, 1, 6, 6, 6, 6]);
+
+        // The packet follows the block's 20 bytes of fields,
+        // and the payload follows the IP and TCP headers.
+        let packet = |index: usize| {
+            let body = blocks[index].1;
+            let len = u32::from_le_bytes(body[12..16].try_into().unwrap()) as usize;
+            &body[20..20 + len]
+        };
+        let (request, response) = (packet(4), packet(5));
+        assert_eq!(request[0], 0x45);
+        assert_eq!(&request[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&request[40..], &[0x01, 0x11, 0x80, 0x05]);
+        assert_eq!(&response[20..22], &3240u16.to_be_bytes());
+        assert_eq!(&response[40..], &reply);
+        // The response acknowledges the request.
+        assert_eq!(&response[28..32], &5u32.to_be_bytes());
+    }
+}
@@ -0,0 +1,302 @@
+//! One configuration format for daemons built on this crate,
+//! read from TOML or JSON.
+//!
+//! Every setting is optional and falls back to the library's
+//! default. Timeouts are given in seconds, and the policy is
+//! a list of [`Rule`](crate::policy::Rule)s. A [`Watcher`] reloads
+//! the file when it changes, and tells what changed.
+//!
+//! # Examples
+//! ```
+//! use usbip_core::config::Config;
+//!
+//! let config = Config::from_toml(
+//!     r#"
+//!     usb_ids = "/usr/share/hwdata/usb.ids"
+//!
+//!     [timeouts]
+//!     connect = 2.5
+//!
+//!     [server]
+//!     listen = "0.0.0.0:3240"
+//!
+//!     [[policy]]
+//!     match = { vendor = 0x076b }
+//!     action = "attach"
+//!     "#,
+//! )
+//! .unwrap();
+//! assert_eq!(config.policy.rules().len(), 1);
+//! ```
+
+use core::fmt;
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{names::Names, policy::Policy};
+
+mod watch;
+pub use watch::{diff, Change, Watcher};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(_) => write!(f, "Failed to read the config file"),
+            Error::Toml(_) => write!(f, "Invalid TOML config"),
+            Error::Json(_) => write!(f, "Invalid JSON config"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Toml(err) => Some(err),
+            Error::Json(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Toml(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+/// The library's settings, as a daemon would load them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Where to keep port records, instead of
+    /// [`STATE_PATH`](crate::vhci::STATE_PATH).
+    pub state_path: Option<PathBuf>,
+    /// The usb.ids database to look up names in, instead
+    /// of the first of [`names::default_paths`] that exists.
+    ///
+    /// [`names::default_paths`]: crate::names::default_paths
+    pub usb_ids: Option<PathBuf>,
+    pub timeouts: Timeouts,
+    pub policy: Policy,
+    pub server: ServerConfig,
+}
+
+/// How long to wait on hosts and devices.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeouts {
+    /// How long to wait for a host to accept a connection.
+    /// Unset, the system's TCP timeout applies.
+    #[serde(with = "secs::option")]
+    pub connect: Option<Duration>,
+    /// How long [`VhciDriver::attach_verified_with_config`]
+    /// waits for an attached device to be enumerated.
+    ///
+    /// [`VhciDriver::attach_verified_with_config`]: crate::vhci::VhciDriver
+    #[serde(with = "secs")]
+    pub verify: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: None,
+            verify: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Settings for a [`Server`](crate::server::Server).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// The address to listen on.
+    pub listen: SocketAddr,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 3240)),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_toml(s: &str) -> Result<Self, Error> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Reads the config file at `path`, as JSON if its
+    /// extension is `json`, or as TOML otherwise.
+    ///
+    /// # Errors
+    /// This function will return an error if the file
+    /// couldn't be read or isn't a valid config.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)?;
+        if path.extension().is_some_and(|ext| ext == "json") {
+            Self::from_json(&s)
+        } else {
+            Self::from_toml(&s)
+        }
+    }
+
+    /// Parses the configured usb.ids, or the default one.
+    ///
+    /// # Errors
+    /// This function will return an error if the
+    /// database couldn't be found or read.
+    pub fn names(&self) -> io::Result<Names> {
+        match &self.usb_ids {
+            Some(path) => crate::names::parse(path),
+            None => Names::open_default(),
+        }
+    }
+
+    /// The socket options for connecting to hosts.
+    #[cfg(unix)]
+    pub fn socket_options(&self) -> crate::unix::SocketOptions {
+        let mut options = crate::unix::SocketOptions::new();
+        self.update_socket_options(&mut options);
+        options
+    }
+
+    /// Sets the socket options this config has settings for,
+    /// leaving the others, like a proxy, as they are.
+    #[cfg(unix)]
+    pub fn update_socket_options(&self, options: &mut crate::unix::SocketOptions) {
+        options.set_connect_timeout(self.timeouts.connect);
+    }
+}
+
+/// Durations as a number of seconds.
+mod secs {
+    use std::time::Duration;
+
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use std::time::Duration;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            duration: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => super::serialize(duration, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            #[derive(Deserialize)]
+            struct Secs(#[serde(with = "super")] Duration);
+
+            Ok(Option::<Secs>::deserialize(deserializer)?.map(|Secs(duration)| duration))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Action;
+
+    #[test]
+    fn toml_and_json_agree() {
+        let toml = Config::from_toml(
+            r#"
+            state_path = "/run/usbipd"
+
+            [timeouts]
+            connect = 2.5
+
+            [[policy]]
+            match = { host = "10.0.0.2", bus_id = "1-1" }
+            action = "attach"
+
+            [[policy]]
+            action = "ignore"
+            "#,
+        )
+        .unwrap();
+        let json = Config::from_json(
+            r#"{
+                "state_path": "/run/usbipd",
+                "timeouts": { "connect": 2.5 },
+                "policy": [
+                    { "match": { "host": "10.0.0.2", "bus_id": "1-1" }, "action": "attach" },
+                    { "action": "ignore" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(toml, json);
+
+        assert_eq!(toml.timeouts.connect, Some(Duration::from_millis(2500)));
+        assert_eq!(toml.timeouts.verify, Timeouts::default().verify);
+        assert_eq!(toml.server, ServerConfig::default());
+        let actions: Vec<_> = toml.policy.rules().iter().map(|rule| rule.action).collect();
+        assert_eq!(actions, [Action::Attach, Action::Ignore]);
+
+        assert!(matches!(
+            Config::from_toml("[timeouts]\nconnect = -1"),
+            Err(Error::Toml(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_options_keep_what_the_config_has_no_setting_for() {
+        use crate::unix::SocketOptions;
+
+        let mut options = SocketOptions::new()
+            .recv_buffer(4096)
+            .connect_timeout(Duration::from_secs(1));
+        Config::default().update_socket_options(&mut options);
+        assert_eq!(options, SocketOptions::new().recv_buffer(4096));
+    }
+}
@@ -0,0 +1,365 @@
+//! Reloading the config file when it changes.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use super::{Config, Error};
+use crate::policy::Rule;
+
+/// Something that differs between two configs.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Change {
+    StatePath,
+    UsbIds,
+    Timeouts,
+    Server,
+    RuleAdded(Rule),
+    RuleRemoved(Rule),
+    /// The same rules, in a different order.
+    RulesReordered,
+}
+
+/// Lists what changed from `old` to `new`.
+pub fn diff(old: &Config, new: &Config) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if old.state_path != new.state_path {
+        changes.push(Change::StatePath);
+    }
+    if old.usb_ids != new.usb_ids {
+        changes.push(Change::UsbIds);
+    }
+    if old.timeouts != new.timeouts {
+        changes.push(Change::Timeouts);
+    }
+    if old.server != new.server {
+        changes.push(Change::Server);
+    }
+
+    // Pair up equal rules, so that a rule listed twice
+    // and then once counts as one removal.
+    let mut added: Vec<_> = new.policy.rules().iter().map(Some).collect();
+    let mut removed = Vec::new();
+    for rule in old.policy.rules() {
+        match added.iter_mut().find(|new| *new == &Some(rule)) {
+            Some(new) => *new = None,
+            None => removed.push(rule),
+        }
+    }
+    let rule_changes = changes.len();
+    changes.extend(removed.into_iter().cloned().map(Change::RuleRemoved));
+    changes.extend(added.into_iter().flatten().cloned().map(Change::RuleAdded));
+    if changes.len() == rule_changes && old.policy != new.policy {
+        changes.push(Change::RulesReordered);
+    }
+    changes
+}
+
+/// A config file, reloaded when it's written or replaced.
+///
+/// Nothing happens in the background: [`Watcher::poll`]
+/// checks for changes, so it's meant to be called from
+/// a daemon's loop, like before each [`Policy::apply`].
+///
+/// # Examples
+/// ```no_run
+/// use std::{net::SocketAddr, thread, time::Duration};
+/// use usbip_core::{config::Watcher, vhci::VhciDriver};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let host = SocketAddr::from(([192, 168, 1, 2], 3240));
+/// let mut watcher = Watcher::new("/etc/usbip/usbip.toml")?;
+/// let mut driver = VhciDriver::open_with_config(watcher.config())?;
+/// loop {
+///     match watcher.poll() {
+///         Ok(changes) if !changes.is_empty() => {
+///             eprintln!("config changed: {changes:?}");
+///             driver.apply_config(watcher.config());
+///         }
+///         Ok(_) => {}
+///         Err(err) => eprintln!("kept the old config: {err}"),
+///     }
+///     watcher.config().policy.apply(&mut driver, host)?;
+///     thread::sleep(Duration::from_secs(10));
+/// }
+/// # }
+/// ```
+///
+/// [`Policy::apply`]: crate::policy::Policy::apply
+pub struct Watcher {
+    path: PathBuf,
+    config: Config,
+    watch: Option<platform::Watch>,
+}
+
+impl Watcher {
+    /// Loads the config file at `path` and starts watching it.
+    ///
+    /// # Errors
+    /// This function will return an error if the file
+    /// couldn't be read or isn't a valid config, or
+    /// its directory couldn't be watched.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        // Watch first, so that changes made
+        // while loading aren't missed.
+        let watch = platform::Watch::new(&path)?;
+        let config = Config::load(&path)?;
+        Ok(Self {
+            path,
+            config,
+            watch: Some(watch),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The config as it was last loaded.
+    pub const fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Reloads the config if the file changed since the last
+    /// call, and returns what changed. Returns right away if
+    /// it hasn't.
+    ///
+    /// # Errors
+    /// This function will return an error if the file changed
+    /// but couldn't be read or isn't a valid config. The old
+    /// config is kept then, until the file changes again.
+    pub fn poll(&mut self) -> Result<Vec<Change>, Error> {
+        let changed = match &mut self.watch {
+            Some(watch) => watch.changed(),
+            // The directory went away, so there's no telling what
+            // happened to the file while it wasn't watched.
+            None => {
+                self.watch = platform::Watch::new(&self.path).ok();
+                self.watch.is_some()
+            }
+        };
+        if self.watch.as_ref().is_some_and(platform::Watch::is_gone) {
+            self.watch = None;
+        }
+        if !changed {
+            return Ok(Vec::new());
+        }
+
+        let config = Config::load(&self.path)?;
+        let changes = diff(&self.config, &config);
+        for _change in &changes {
+            crate::util::trace_event!(
+                path = %self.path.display(),
+                change = ?_change,
+                "config changed"
+            );
+            #[cfg(feature = "log")]
+            log::info!("{}: {_change:?}", self.path.display());
+        }
+        self.config = config;
+        Ok(changes)
+    }
+}
+
+/// The directory holding `path`, and the
+/// file's name in it.
+fn split(path: &Path) -> (&Path, OsString) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    (dir, path.file_name().unwrap_or_default().to_owned())
+}
+
+// Editors tend to save by writing a new file and renaming it over
+// the old one, so it's the directory that's watched, not the file.
+
+#[cfg(unix)]
+mod platform {
+    use std::{ffi::OsString, fs::File, io, ops::ControlFlow, path::Path};
+
+    use crate::unix::inotify;
+
+    pub struct Watch {
+        inotify: File,
+        name: OsString,
+        gone: bool,
+    }
+
+    impl Watch {
+        pub fn new(path: &Path) -> io::Result<Self> {
+            let (dir, name) = super::split(path);
+            // Not IN_CREATE: the new file is still empty then.
+            let mask = libc::IN_CLOSE_WRITE
+                | libc::IN_MOVED_TO
+                | libc::IN_DELETE_SELF
+                | libc::IN_MOVE_SELF;
+            Ok(Self {
+                inotify: inotify::watch(dir, mask)?,
+                name,
+                gone: false,
+            })
+        }
+
+        pub fn changed(&mut self) -> bool {
+            use std::os::unix::ffi::OsStrExt;
+
+            let (name, gone) = (self.name.as_bytes(), &mut self.gone);
+            let mut changed = false;
+            let _ = inotify::read_events(&mut self.inotify, |mask, event| {
+                // A moved directory is still watched, but
+                // not where the file is looked for anymore.
+                if mask & (libc::IN_IGNORED | libc::IN_MOVE_SELF) != 0 {
+                    *gone = true;
+                    return ControlFlow::Break(());
+                }
+                // On overflow, events were lost, so the file may have changed.
+                changed |= event == name || mask & libc::IN_Q_OVERFLOW != 0;
+                ControlFlow::Continue(())
+            });
+            changed
+        }
+
+        pub const fn is_gone(&self) -> bool {
+            self.gone
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::{io, os::windows::ffi::OsStrExt, path::Path};
+
+    use windows::{
+        core::PCWSTR,
+        Win32::{
+            Foundation::{HANDLE, WAIT_OBJECT_0},
+            Storage::FileSystem::{
+                FindCloseChangeNotification, FindFirstChangeNotificationW,
+                FindNextChangeNotification, FILE_NOTIFY_CHANGE_FILE_NAME,
+                FILE_NOTIFY_CHANGE_LAST_WRITE,
+            },
+            System::Threading::WaitForSingleObject,
+        },
+    };
+
+    /// A change notification handle. These don't say which file
+    /// changed, so any change in the directory reloads the config.
+    pub struct Watch {
+        handle: HANDLE,
+        gone: bool,
+    }
+
+    impl Watch {
+        pub fn new(path: &Path) -> io::Result<Self> {
+            let (dir, _) = super::split(path);
+            let dir: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+            let handle = unsafe {
+                FindFirstChangeNotificationW(
+                    PCWSTR(dir.as_ptr()),
+                    false,
+                    FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                )
+            }?;
+            Ok(Self {
+                handle,
+                gone: false,
+            })
+        }
+
+        pub fn changed(&mut self) -> bool {
+            if unsafe { WaitForSingleObject(self.handle, 0) } != WAIT_OBJECT_0 {
+                return false;
+            }
+            // This fails once the directory is gone.
+            self.gone = unsafe { FindNextChangeNotification(self.handle) }.is_err();
+            true
+        }
+
+        pub const fn is_gone(&self) -> bool {
+            self.gone
+        }
+    }
+
+    impl Drop for Watch {
+        fn drop(&mut self) {
+            let _ = unsafe { FindCloseChangeNotification(self.handle) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Action, DeviceMatch, Policy};
+
+    fn rule(vendor: u16, action: Action) -> Rule {
+        Rule {
+            matches: DeviceMatch {
+                vendor: Some(vendor),
+                ..DeviceMatch::default()
+            },
+            action,
+        }
+    }
+
+    #[test]
+    fn diff_lists_rules_added_and_removed() {
+        let old = Config {
+            policy: Policy::new(vec![
+                rule(0x076b, Action::Attach),
+                rule(0x046d, Action::Ignore),
+            ]),
+            ..Config::default()
+        };
+        let new = Config {
+            usb_ids: Some("/usr/share/usb.ids".into()),
+            policy: Policy::new(vec![
+                rule(0x076b, Action::Attach),
+                rule(0x046d, Action::Attach),
+            ]),
+            ..Config::default()
+        };
+        assert_eq!(
+            diff(&old, &new),
+            [
+                Change::UsbIds,
+                Change::RuleRemoved(rule(0x046d, Action::Ignore)),
+                Change::RuleAdded(rule(0x046d, Action::Attach)),
+            ]
+        );
+
+        let mut rules = old.policy.rules().to_vec();
+        rules.reverse();
+        let reordered = Config {
+            policy: Policy::new(rules),
+            ..old.clone()
+        };
+        assert_eq!(diff(&old, &reordered), [Change::RulesReordered]);
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn poll_reloads_a_replaced_file() {
+        let dir = std::env::temp_dir().join(format!("usbip-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("usbip.toml");
+        std::fs::write(&path, "[[policy]]\naction = \"ignore\"\n").unwrap();
+
+        let mut watcher = Watcher::new(&path).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+
+        let staged = dir.join("usbip.toml.new");
+        std::fs::write(&staged, "").unwrap();
+        std::fs::rename(&staged, &path).unwrap();
+        let changes = watcher.poll().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(matches!(changes[..], [Change::RuleRemoved(_)]));
+        assert!(watcher.config().policy.rules().is_empty());
+    }
+}
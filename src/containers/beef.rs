@@ -1,14 +1,28 @@
-use std::{
-    borrow::{Borrow, Cow},
+use alloc::{
+    borrow::{Cow, ToOwned},
+    string::String,
+};
+use core::{
+    borrow::Borrow,
     fmt::Display,
+    hash::{Hash, Hasher},
     ops::Deref,
 };
 
 /// Like [`Cow`], but differentiates between borrowed items
 /// and `'static` items.
 ///
-/// Currently doesn't support mutation due to the project
-/// not requiring it at the moment.
+/// # Examples
+///
+/// ```
+/// use usbip_core::containers::beef::Beef;
+///
+/// let mut beef: Beef<str> = Beef::Static("1-1");
+/// beef.to_mut().push_str(".2");
+///
+/// assert!(matches!(beef, Beef::Owned(_)));
+/// assert_eq!(beef, "1-1.2");
+/// ```
 pub enum Beef<'a, B>
 where
     B: 'static + ?Sized + ToOwned,
@@ -18,6 +32,36 @@ where
     Static(&'static B),
 }
 
+impl<'a, B> Beef<'a, B>
+where
+    B: 'static + ?Sized + ToOwned,
+{
+    /// Acquires a mutable reference to the owned
+    /// data, cloning borrowed and `'static` data
+    /// into [`Beef::Owned`] first.
+    pub fn to_mut(&mut self) -> &mut <B as ToOwned>::Owned {
+        match *self {
+            Beef::Borrowed(borrowed) | Beef::Static(borrowed) => {
+                *self = Beef::Owned(borrowed.to_owned());
+                match *self {
+                    Beef::Owned(ref mut owned) => owned,
+                    _ => unreachable!(),
+                }
+            }
+            Beef::Owned(ref mut owned) => owned,
+        }
+    }
+
+    /// Extracts the owned data, cloning
+    /// it if it isn't already owned.
+    pub fn into_owned(self) -> <B as ToOwned>::Owned {
+        match self {
+            Beef::Borrowed(borrowed) | Beef::Static(borrowed) => borrowed.to_owned(),
+            Beef::Owned(owned) => owned,
+        }
+    }
+}
+
 impl<'a, B> Clone for Beef<'a, B>
 where
     B: ?Sized + ToOwned,
@@ -32,12 +76,12 @@ where
     }
 }
 
-impl<'a, B> std::fmt::Debug for Beef<'a, B>
+impl<'a, B> core::fmt::Debug for Beef<'a, B>
 where
-    B: 'static + ?Sized + ToOwned + std::fmt::Debug,
+    B: 'static + ?Sized + ToOwned + core::fmt::Debug,
     <B as ToOwned>::Owned: core::fmt::Debug,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.deref().fmt(f)
     }
 }
@@ -74,9 +118,73 @@ where
     B: ?Sized + ToOwned + Display + 'static,
     <B as ToOwned>::Owned: Display,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.deref().fmt(f)
     }
 }
 
+impl<'a, 'b, B, C> PartialEq<Beef<'b, C>> for Beef<'a, B>
+where
+    B: ?Sized + ToOwned + PartialEq<C> + 'static,
+    C: ?Sized + ToOwned + 'static,
+{
+    fn eq(&self, other: &Beef<'b, C>) -> bool {
+        PartialEq::eq(&**self, &**other)
+    }
+}
+
+impl<'a, B> Eq for Beef<'a, B> where B: ?Sized + ToOwned + Eq + 'static {}
 
+impl PartialEq<str> for Beef<'_, str> {
+    fn eq(&self, other: &str) -> bool {
+        &**self == other
+    }
+}
+
+impl<'b> PartialEq<&'b str> for Beef<'_, str> {
+    fn eq(&self, other: &&'b str) -> bool {
+        &**self == *other
+    }
+}
+
+impl PartialEq<String> for Beef<'_, str> {
+    fn eq(&self, other: &String) -> bool {
+        &**self == other.as_str()
+    }
+}
+
+impl<'a, B> Hash for Beef<'a, B>
+where
+    B: ?Sized + ToOwned + Hash + 'static,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Hash::hash(&**self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_mut_only_clones_once() {
+        let s = String::from("1-1");
+        let mut beef: Beef<str> = Beef::Borrowed(&s);
+        beef.to_mut().push('.');
+        beef.to_mut().push('2');
+        assert!(matches!(beef, Beef::Owned(_)));
+        assert_eq!(beef.into_owned(), "1-1.2");
+    }
+
+    #[test]
+    fn eq_and_hash_match_borrowed() {
+        use std::collections::HashSet;
+
+        let owned: Beef<str> = Beef::Owned("1-1".to_owned());
+        assert_eq!(owned, Beef::<str>::Static("1-1"));
+        assert_eq!(owned, "1-1");
+
+        let set = HashSet::from([owned]);
+        assert!(set.contains(&Beef::Static("1-1")));
+    }
+}
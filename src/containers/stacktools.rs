@@ -1,12 +1,15 @@
-use core::fmt::{self, Write};
-use std::{
+use alloc::borrow::ToOwned;
+use core::{
     borrow::Borrow,
-    ffi::{c_char, OsStr},
-    fmt::Arguments,
+    cmp::Ordering,
+    ffi::c_char,
+    fmt::{self, Arguments, Write},
+    hash::{Hash, Hasher},
     ops::Deref,
-    path::Path,
     str::Utf8Error,
 };
+#[cfg(feature = "std")]
+use std::{ffi::OsStr, path::Path};
 
 /// A UTF-8 encoded string, but stored entirely on the stack.
 ///
@@ -52,7 +55,17 @@ use std::{
 /// takes_str(&s);
 ///
 /// ```
-#[derive(Debug, PartialEq, Eq)]
+///
+/// # Comparisons
+///
+/// `StackStr` compares, orders, and hashes exactly
+/// like the [`str`] it derefs to, so it can stand in
+/// for a `String` key in a [`HashMap`] or [`BTreeMap`]
+/// and be looked up by `&str`.
+///
+/// [`HashMap`]: std::collections::HashMap
+/// [`BTreeMap`]: std::collections::BTreeMap
+#[derive(Debug, Clone, Copy)]
 pub struct StackStr<const N: usize> {
     len: usize,
     buf: [c_char; N],
@@ -70,11 +83,13 @@ impl<const N: usize> StackStr<N> {
     }
 
     /// Converts a [`StackStr`] to a [`Path`].
+    #[cfg(feature = "std")]
     pub fn as_path(&self) -> &Path {
         Path::new(self.deref())
     }
 
     /// Converts a [`StackStr`] into an [`OsStr`].
+    #[cfg(feature = "std")]
     #[inline]
     pub fn as_os_str(&self) -> &OsStr {
         OsStr::new(self.deref())
@@ -94,6 +109,66 @@ impl<const N: usize> StackStr<N> {
         self.buf[0..len].fill(value);
     }
 
+    /// Returns the number of bytes `self` can hold.
+    #[inline(always)]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of bytes that can still
+    /// be appended to `self`.
+    #[inline(always)]
+    pub const fn remaining_capacity(&self) -> usize {
+        N - self.len
+    }
+
+    /// Appends `s` onto the end of `self`.
+    ///
+    /// # Errors
+    /// This function will return an error, leaving `self`
+    /// untouched, if `s` doesn't fit in the remaining capacity.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use usbip_core::containers::stacktools::StackStr;
+    ///
+    /// let mut s = StackStr::<8>::new();
+    /// s.try_push_str("usb").unwrap();
+    /// s.try_push_str("ip").unwrap();
+    /// assert_eq!(&*s, "usbip");
+    /// assert!(s.try_push_str("-core").is_err());
+    /// ```
+    pub fn try_push_str(&mut self, s: &str) -> Result<(), TryFromStrErr> {
+        self.write_str(s).map_err(|_| TryFromStrErr::Length {
+            max: N,
+            actual: self.len + s.len(),
+        })
+    }
+
+    /// Shortens `self` to `new_len` bytes, zeroing the
+    /// removed bytes. Does nothing if `new_len` is greater
+    /// than the current length.
+    ///
+    /// # Panics
+    /// Panics if `new_len` does not lie on a [`char`] boundary.
+    pub fn truncate(&mut self, new_len: usize) {
+        if new_len < self.len {
+            assert!(self.is_char_boundary(new_len));
+            let len = self.len;
+            self.buf[new_len..len].fill(0);
+            self.len = new_len;
+        }
+    }
+
+    /// Removes the last character from `self` and
+    /// returns it, or `None` if `self` is empty.
+    pub fn pop(&mut self) -> Option<char> {
+        let ch = self.chars().next_back()?;
+        self.truncate(self.len - ch.len_utf8());
+        Some(ch)
+    }
+
     /// Form a [`StackStr`] from an array and a length.
     ///
     /// The `len` argument is the number of bytes.
@@ -107,6 +182,62 @@ impl<const N: usize> StackStr<N> {
     }
 }
 
+impl<const N: usize> Default for StackStr<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> PartialEq for StackStr<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.deref() == other.deref()
+    }
+}
+
+impl<const N: usize> Eq for StackStr<N> {}
+
+impl<const N: usize> PartialEq<str> for StackStr<N> {
+    fn eq(&self, other: &str) -> bool {
+        self.deref() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&str> for StackStr<N> {
+    fn eq(&self, other: &&str) -> bool {
+        self.deref() == *other
+    }
+}
+
+impl<const N: usize> PartialOrd for StackStr<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for StackStr<N> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.deref().cmp(other.deref())
+    }
+}
+
+impl<const N: usize> Hash for StackStr<N> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.deref().hash(state)
+    }
+}
+
+impl<const N: usize> AsRef<str> for StackStr<N> {
+    fn as_ref(&self) -> &str {
+        self
+    }
+}
+
+impl<const N: usize> Borrow<str> for StackStr<N> {
+    fn borrow(&self) -> &str {
+        self
+    }
+}
+
 impl<const N: usize> Borrow<Str<N>> for StackStr<N> {
     #[inline]
     fn borrow(&self) -> &Str<N> {
@@ -124,7 +255,7 @@ impl<const N: usize> Deref for StackStr<N> {
         // SAFETY: `StackStr` is always instantiated
         //         with valid UTF-8, and cannot be constructed
         //         any other way.
-        unsafe { std::str::from_utf8_unchecked(slice) }
+        unsafe { core::str::from_utf8_unchecked(slice) }
     }
 }
 
@@ -204,7 +335,7 @@ fn decode_and_validate<D: bincode::de::Decoder, const N: usize>(
     let buf: [c_char; N] = bincode::Decode::decode(decoder)?;
 
     let u8_buf = crate::util::cast_cchar_to_u8(&buf[0..N]);
-    let len = std::str::from_utf8(u8_buf)
+    let len = core::str::from_utf8(u8_buf)
         .map_err(|err| bincode::error::DecodeError::Utf8 { inner: err })?
         // What happens if the start of the string has a buncha null bytes?
         //.trim_start_matches(char::from(0u8))
@@ -223,6 +354,23 @@ impl<const N: usize> bincode::Encode for StackStr<N> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for StackStr<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self)
+    }
+}
+
+/// Fails if the string doesn't fit in `N` bytes,
+/// rather than truncating it.
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> serde::Deserialize<'de> for StackStr<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <alloc::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        StackStr::try_from(s.as_ref()).map_err(serde::de::Error::custom)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum TryFromStrErr {
     Length { max: usize, actual: usize },
@@ -237,24 +385,46 @@ impl fmt::Display for TryFromStrErr {
                 "invalid length of str (max: {}, actual: {})",
                 max, actual
             ),
-            TryFromStrErr::NotUtf8(err) => write!(f, "{err}"),
+            TryFromStrErr::NotUtf8(_) => write!(f, "str is not valid UTF-8"),
         }
     }
 }
 
-impl std::error::Error for TryFromStrErr {}
+impl core::error::Error for TryFromStrErr {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            TryFromStrErr::Length { .. } => None,
+            TryFromStrErr::NotUtf8(err) => Some(err),
+        }
+    }
+}
 
 unsafe impl<const N: usize> crate::util::EncodedSize for StackStr<N> {
     const ENCODED_SIZE_OF: usize = N;
 }
 
-#[derive(Debug)]
+/// A borrowed string slice of at most `N` bytes,
+/// the zero-copy counterpart of [`StackStr`].
+///
+/// # Invariants
+///
+/// `Str<N>` is a `#[repr(transparent)]` wrapper around [`str`],
+/// so it is always valid UTF-8, and every constructor checks that
+/// it is no longer than `N` bytes. This means it always fits in
+/// a `StackStr<N>`, which is its [`ToOwned::Owned`] type.
+///
+/// A `Str` borrowed out of a fixed-size wire field spans the
+/// whole field, including any trailing NUL padding. Use
+/// [`Str::trim_padding`] to get at the string itself.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct Str<const N: usize> {
     inner: str,
 }
 
 impl<const N: usize> Str<N> {
+    /// Borrows `s` as a [`Str`], returning `None`
+    /// if `s` is longer than `N` bytes.
     pub const fn new(s: &str) -> Option<&Self> {
         if s.as_bytes().len() > N {
             None
@@ -268,6 +438,32 @@ impl<const N: usize> Str<N> {
     pub const fn as_str(&self) -> &str {
         &self.inner
     }
+
+    /// Returns `self` without its trailing NUL bytes.
+    pub fn trim_padding(&self) -> &Self {
+        Str::new(self.inner.trim_end_matches('\0')).unwrap()
+    }
+}
+
+impl<const N: usize> Deref for Str<N> {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<const N: usize> AsRef<str> for Str<N> {
+    fn as_ref(&self) -> &str {
+        &self.inner
+    }
+}
+
+impl<const N: usize> fmt::Display for Str<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
 }
 
 impl<const N: usize> Borrow<str> for Str<N> {
@@ -303,6 +499,13 @@ impl<const N: usize> bincode::Encode for Str<N> {
     }
 }
 
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for Str<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 impl<'de, const N: usize> bincode::BorrowDecode<'de> for &'de Str<N> {
     fn borrow_decode<D: bincode::de::BorrowDecoder<'de>>(
         decoder: &mut D,
@@ -310,7 +513,7 @@ impl<'de, const N: usize> bincode::BorrowDecode<'de> for &'de Str<N> {
         use bincode::de::read::BorrowReader;
 
         let s = decoder.borrow_reader().take_bytes(N)?;
-        let s = std::str::from_utf8(s)
+        let s = core::str::from_utf8(s)
             .map_err(|err| bincode::error::DecodeError::Utf8 { inner: err })?;
         Ok(Str::new(s).unwrap())
     }
@@ -327,6 +530,14 @@ mod tests {
         assert_eq!(strstr.as_str().len(), str.len());
     }
     
+    #[test]
+    fn str_trims_padding() {
+        let padded = Str::<8>::new("1-1\0\0\0\0\0").unwrap();
+        assert_eq!(padded.trim_padding().as_str(), "1-1");
+        assert_eq!(&*padded.trim_padding().to_owned(), "1-1");
+        assert!(Str::<2>::new("1-1").is_none());
+    }
+
     #[test]
     fn valid_len_try_from_str_works() {
         let str = "Hello!";
@@ -355,6 +566,49 @@ mod tests {
         assert_eq!(stack_s.len(), s.len());
     }
 
+    #[test]
+    fn truncate_and_pop_zero_removed_bytes() {
+        let mut s = StackStr::<8>::try_from("1-1.é").unwrap();
+        assert_eq!(s.remaining_capacity(), 2);
+        assert_eq!(s.pop(), Some('é'));
+        assert_eq!(&*s, "1-1.");
+        s.truncate(3);
+        assert_eq!(&*s, "1-1");
+        assert_eq!(s, StackStr::<8>::try_from("1-1").unwrap());
+        assert_eq!(
+            s.try_push_str(".10.20"),
+            Err(TryFromStrErr::Length { max: 8, actual: 9 })
+        );
+        s.try_push_str(".2").unwrap();
+        assert_eq!(&*s, "1-1.2");
+    }
+
+    #[test]
+    fn stack_str_works_as_map_key() {
+        use std::collections::{BTreeSet, HashMap};
+
+        let mut map = HashMap::new();
+        map.insert(StackStr::<32>::try_from("1-1").unwrap(), 1);
+        assert_eq!(map.get("1-1"), Some(&1));
+
+        let set: BTreeSet<_> = ["2-1", "1-10", "1-2"]
+            .into_iter()
+            .map(|s| StackStr::<32>::try_from(s).unwrap())
+            .collect();
+        let sorted: Vec<&str> = set.iter().map(AsRef::as_ref).collect();
+        assert_eq!(sorted, ["1-10", "1-2", "2-1"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn stack_str_serde_checks_capacity() {
+        let s = StackStr::<8>::try_from("1-1").unwrap();
+        let json = serde_json::to_string(&s).unwrap();
+        assert_eq!(json, "\"1-1\"");
+        assert_eq!(serde_json::from_str::<StackStr<8>>(&json).unwrap(), s);
+        assert!(serde_json::from_str::<StackStr<2>>(&json).is_err());
+    }
+
     #[test]
     fn convert_from_format_args() {
         let mexico = "Mexico";
@@ -364,4 +618,22 @@ mod tests {
             Ok(StackStr::<256>::try_from("Hello from Mexico!").unwrap())
         )
     }
+
+    proptest::proptest! {
+        #[test]
+        fn bincode_round_trips(s in "[^\\x00]{0,8}") {
+            use crate::util::EncodedSize;
+
+            let config = crate::net::bincode_config();
+            let s = StackStr::<32>::try_from(s.as_str()).unwrap();
+            let bytes = bincode::encode_to_vec(s, config).unwrap();
+            assert_eq!(bytes.len(), StackStr::<32>::ENCODED_SIZE_OF);
+
+            let (decoded, _): (StackStr<32>, _) = bincode::decode_from_slice(&bytes, config).unwrap();
+            assert_eq!(decoded, s);
+            let (borrowed, _): (&Str<32>, _) =
+                bincode::borrow_decode_from_slice(&bytes, config).unwrap();
+            assert_eq!(borrowed.trim_padding().as_str(), &*s);
+        }
+    }
 }
@@ -0,0 +1,204 @@
+//! The vhci driver as a D-Bus service, so that desktop
+//! frontends can attach and detach devices through a
+//! privileged service instead of running as root.
+//!
+//! [`serve`] claims [`BUS_NAME`] on the system bus and exports
+//! a [`VhciService`] at [`OBJECT_PATH`]. The bus policy in
+//! `dbus/org.usbip.Vhci1.conf` lets anyone list the imported
+//! devices, and members of the `usbip` group attach and detach.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use usbip_core::{dbus::{self, VhciService}, vhci::AsyncVhciDriver};
+//!
+//! let driver = AsyncVhciDriver::open().await?;
+//! let _conn = dbus::serve(VhciService::new(driver)).await?;
+//! std::future::pending::<()>().await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+use zbus::{interface, object_server::SignalEmitter, zvariant::Type, DBusError};
+
+use crate::{
+    util::ErrorChain,
+    vhci::{AsyncVhciDriver, AttachArgs, ErrorKind},
+    BusId,
+};
+
+/// The well-known name the service claims on the system bus.
+pub const BUS_NAME: &str = "org.usbip.Vhci1";
+/// Where the service's object lives.
+pub const OBJECT_PATH: &str = "/org/usbip/Vhci1";
+
+/// A device attached to the vhci driver,
+/// sent over D-Bus as `(qqquss)`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub struct ImportedDevice {
+    pub port: u16,
+    pub vendor: u16,
+    pub product: u16,
+    /// The remote bus and device numbers, as `bus << 16 | dev`.
+    pub devid: u32,
+    /// The remote bus id, or empty if it wasn't recorded.
+    pub bus_id: String,
+    /// The host as `address:port`, or empty if it wasn't recorded.
+    pub host: String,
+}
+
+impl From<&crate::vhci::ImportedDevice> for ImportedDevice {
+    fn from(idev: &crate::vhci::ImportedDevice) -> Self {
+        let record = idev.record();
+        Self {
+            port: idev.port(),
+            vendor: idev.vendor(),
+            product: idev.product(),
+            devid: idev.dev_id().as_u32(),
+            bus_id: record.map_or_else(String::new, |record| record.bus_id().to_owned()),
+            host: record.map_or_else(String::new, |record| record.host().to_string()),
+        }
+    }
+}
+
+/// The errors a [`VhciService`] replies with. Each is named
+/// after the [`ErrorKind`] of the driver's error, like
+/// `org.usbip.Vhci1.Error.Busy`, and carries its message.
+#[derive(Debug, DBusError)]
+#[zbus(prefix = "org.usbip.Vhci1.Error")]
+pub enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    InvalidInput(String),
+    DriverMissing(String),
+    PermissionDenied(String),
+    RemoteRejected(String),
+    Busy(String),
+    NotConnected(String),
+    Protocol(String),
+    Cancelled(String),
+    TimedOut(String),
+    Io(String),
+}
+
+impl From<crate::vhci::Error> for Error {
+    fn from(value: crate::vhci::Error) -> Self {
+        let message = ErrorChain(&value).to_string();
+        match value.kind() {
+            ErrorKind::InvalidInput => Error::InvalidInput(message),
+            ErrorKind::DriverMissing => Error::DriverMissing(message),
+            ErrorKind::PermissionDenied => Error::PermissionDenied(message),
+            ErrorKind::RemoteRejected => Error::RemoteRejected(message),
+            ErrorKind::Busy => Error::Busy(message),
+            ErrorKind::NotConnected => Error::NotConnected(message),
+            ErrorKind::Protocol => Error::Protocol(message),
+            ErrorKind::Cancelled => Error::Cancelled(message),
+            ErrorKind::TimedOut => Error::TimedOut(message),
+            _ => Error::Io(message),
+        }
+    }
+}
+
+/// The `org.usbip.Vhci1` interface, backed by an [`AsyncVhciDriver`].
+///
+/// The `Attached` and `Detached` signals are only sent for
+/// changes made through the service, not by other tools.
+pub struct VhciService {
+    driver: AsyncVhciDriver,
+}
+
+impl VhciService {
+    pub const fn new(driver: AsyncVhciDriver) -> Self {
+        Self { driver }
+    }
+}
+
+#[interface(name = "org.usbip.Vhci1")]
+impl VhciService {
+    /// Lists the devices attached to the vhci driver.
+    async fn list_imported(&self) -> Result<Vec<ImportedDevice>, Error> {
+        let idevs = self.driver.imported_devices().await?;
+        Ok(idevs.get().iter().map(ImportedDevice::from).collect())
+    }
+
+    /// Imports the device at `bus_id` from `host`, given
+    /// as `address:port`, and returns its port.
+    async fn attach(
+        &self,
+        host: &str,
+        bus_id: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> Result<u16, Error> {
+        let host: SocketAddr = host
+            .parse()
+            .map_err(|_| Error::InvalidInput(format!("`{host}` is not an address and port")))?;
+        let bus_id = BusId::parse(bus_id).map_err(|err| Error::InvalidInput(err.to_string()))?;
+
+        let port = self
+            .driver
+            .attach(AttachArgs {
+                host,
+                bus_id: bus_id.clone(),
+            })
+            .await?;
+        // The device is attached either way, and
+        // a frontend can always list the devices.
+        let _ = Self::attached(&emitter, port, &host.to_string(), bus_id.as_str()).await;
+        Ok(port)
+    }
+
+    /// Detaches the device on `port`.
+    async fn detach(
+        &self,
+        port: u16,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> Result<(), Error> {
+        self.driver.detach(port).await?;
+        let _ = Self::detached(&emitter, port).await;
+        Ok(())
+    }
+
+    /// Sent once a device is attached on `port`.
+    #[zbus(signal)]
+    async fn attached(
+        emitter: &SignalEmitter<'_>,
+        port: u16,
+        host: &str,
+        bus_id: &str,
+    ) -> zbus::Result<()>;
+
+    /// Sent once the device on `port` is detached.
+    #[zbus(signal)]
+    async fn detached(emitter: &SignalEmitter<'_>, port: u16) -> zbus::Result<()>;
+}
+
+/// Serves `service` on the system bus under [`BUS_NAME`].
+///
+/// The service runs until the returned connection is dropped.
+///
+/// # Errors
+/// This function will return an error if the system bus
+/// couldn't be reached or the name couldn't be claimed.
+pub async fn serve(service: VhciService) -> zbus::Result<zbus::Connection> {
+    zbus::connection::Builder::system()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_format_is_stable() {
+        assert_eq!(ImportedDevice::SIGNATURE.to_string(), "(qqquss)");
+
+        let err = Error::from(crate::vhci::Error::DriverNotFound);
+        assert_eq!(err.name(), "org.usbip.Vhci1.Error.DriverMissing");
+    }
+}
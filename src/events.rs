@@ -0,0 +1,88 @@
+//! Structured events for the lifecycle of devices, for
+//! applications to forward to webhooks, syslog or a
+//! message bus.
+//!
+//! A [`VhciDriver`] reports attaches and detaches, and a
+//! [`Server`] reports the imports it grants and denies, to
+//! the [`EventSink`] they're given. With the `serde` feature,
+//! events serialize with their name in an `event` field.
+//!
+//! # Examples
+//! ```no_run
+//! # #[cfg(feature = "client")]
+//! # fn main() -> usbip_core::vhci::Result<()> {
+//! use std::sync::Arc;
+//! use usbip_core::{events::Event, vhci::VhciDriver};
+//!
+//! let mut driver = VhciDriver::open()?;
+//! driver.set_event_sink(Arc::new(|event: &Event| eprintln!("{event:?}")));
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "client"))]
+//! # fn main() {}
+//! ```
+//!
+//! [`VhciDriver`]: crate::vhci::VhciDriver
+//! [`Server`]: crate::server::Server
+
+use std::net::SocketAddr;
+
+#[cfg(feature = "client")]
+use crate::vhci::ErrorKind;
+use crate::{net::Status, BusId};
+
+/// Something that happened to a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "event", rename_all = "snake_case")
+)]
+#[non_exhaustive]
+pub enum Event {
+    /// `host`'s device at `bus_id` was attached to `port`.
+    Attached {
+        host: SocketAddr,
+        bus_id: BusId<'static>,
+        port: u16,
+    },
+    #[cfg(feature = "client")]
+    AttachFailed {
+        host: SocketAddr,
+        bus_id: BusId<'static>,
+        kind: ErrorKind,
+    },
+    /// The device on `port` was detached.
+    Detached { port: u16 },
+    /// A client at `peer` imported the device at `bus_id`.
+    ImportGranted {
+        peer: SocketAddr,
+        bus_id: BusId<'static>,
+    },
+    /// A client at `peer` was refused the device
+    /// at `bus_id`, and told `status`.
+    ImportDenied {
+        peer: SocketAddr,
+        bus_id: BusId<'static>,
+        status: Status,
+    },
+}
+
+/// Receives [`Event`]s.
+///
+/// Events are delivered on the thread or task they happened
+/// on, in the middle of the operation, so sinks shouldn't
+/// block for long. Sinks that talk to the network should
+/// queue events and send them from a thread of their own.
+pub trait EventSink: Send + Sync + 'static {
+    fn event(&self, event: &Event);
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&Event) + Send + Sync + 'static,
+{
+    fn event(&self, event: &Event) {
+        self(event)
+    }
+}
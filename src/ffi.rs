@@ -0,0 +1,381 @@
+//! A C ABI over [`VhciDriver`], for tools that
+//! aren't written in Rust.
+//!
+//! Every function returns a [`UsbipStatus`]. After a failure,
+//! [`usbip_last_error`] describes the error on the same thread.
+//! The C declarations are in `include/usbip_core.h`.
+//!
+//! The structs' layouts and the status codes are stable:
+//! new fields and codes are only ever added at the end.
+//!
+//! A [`UsbipVhci`] handle isn't synchronized. It may be moved
+//! between threads, but must not be used from two threads at
+//! once; callers that share one must guard it with a lock.
+
+use core::ffi::{c_char, CStr};
+use std::{
+    cell::RefCell,
+    ffi::CString,
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    util::ErrorChain,
+    vhci::{AttachArgs, ErrorKind, VhciDriver},
+    BusId, BUS_ID_SIZE,
+};
+
+/// The size of [`UsbipImportedDevice::host`], enough
+/// for any IPv6 address with a scope and a port.
+pub const USBIP_HOST_SIZE: usize = 64;
+
+/// The result of a call through the C ABI.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbipStatus {
+    Ok = 0,
+    InvalidInput = -1,
+    DriverMissing = -2,
+    PermissionDenied = -3,
+    RemoteRejected = -4,
+    Busy = -5,
+    NotConnected = -6,
+    Protocol = -7,
+    Cancelled = -8,
+    TimedOut = -9,
+    Io = -10,
+    /// The caller's buffer is too small. The
+    /// length it needs has been written.
+    BufferTooSmall = -11,
+    /// The library panicked, which is a bug.
+    Panic = -12,
+}
+
+impl From<ErrorKind> for UsbipStatus {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::InvalidInput => UsbipStatus::InvalidInput,
+            ErrorKind::DriverMissing => UsbipStatus::DriverMissing,
+            ErrorKind::PermissionDenied => UsbipStatus::PermissionDenied,
+            ErrorKind::RemoteRejected => UsbipStatus::RemoteRejected,
+            ErrorKind::Busy => UsbipStatus::Busy,
+            ErrorKind::NotConnected => UsbipStatus::NotConnected,
+            ErrorKind::Protocol => UsbipStatus::Protocol,
+            ErrorKind::Cancelled => UsbipStatus::Cancelled,
+            ErrorKind::TimedOut => UsbipStatus::TimedOut,
+            _ => UsbipStatus::Io,
+        }
+    }
+}
+
+/// An opened vhci driver, freed with [`usbip_vhci_close`].
+///
+/// It must not be used from two threads at once.
+pub struct UsbipVhci(VhciDriver);
+
+/// See [`PortCounts`](crate::vhci::PortCounts).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsbipPortCounts {
+    pub num_controllers: usize,
+    pub num_ports: usize,
+    pub free_ports: usize,
+}
+
+/// A device attached to the vhci driver.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UsbipImportedDevice {
+    pub port: u16,
+    pub vendor: u16,
+    pub product: u16,
+    /// The remote bus and device numbers, as `bus << 16 | dev`.
+    pub devid: u32,
+    /// The remote bus id, NUL-terminated.
+    /// Empty if it wasn't recorded.
+    pub bus_id: [c_char; BUS_ID_SIZE],
+    /// The host as `address:port`, NUL-terminated.
+    /// Empty if it wasn't recorded.
+    pub host: [c_char; USBIP_HOST_SIZE],
+}
+
+// Matches the layouts declared in include/usbip_core.h.
+const _: () = {
+    use core::mem::{offset_of, size_of};
+
+    assert!(size_of::<UsbipStatus>() == 4);
+    assert!(size_of::<UsbipPortCounts>() == 3 * size_of::<usize>());
+    assert!(offset_of!(UsbipPortCounts, num_controllers) == 0);
+    assert!(offset_of!(UsbipPortCounts, num_ports) == size_of::<usize>());
+    assert!(offset_of!(UsbipPortCounts, free_ports) == 2 * size_of::<usize>());
+    assert!(size_of::<UsbipImportedDevice>() == 108);
+    assert!(offset_of!(UsbipImportedDevice, port) == 0);
+    assert!(offset_of!(UsbipImportedDevice, vendor) == 2);
+    assert!(offset_of!(UsbipImportedDevice, product) == 4);
+    assert!(offset_of!(UsbipImportedDevice, devid) == 8);
+    assert!(offset_of!(UsbipImportedDevice, bus_id) == 12);
+    assert!(offset_of!(UsbipImportedDevice, host) == 12 + BUS_ID_SIZE);
+};
+
+type Failure = (UsbipStatus, String);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, turning its error or panic into a
+/// status and keeping its message for later.
+fn call(f: impl FnOnce() -> Result<(), Failure>) -> UsbipStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return UsbipStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(_) => (UsbipStatus::Panic, "usbip-core panicked".to_owned()),
+    };
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+    status
+}
+
+fn invalid(message: &str) -> Failure {
+    (UsbipStatus::InvalidInput, message.to_owned())
+}
+
+fn failed(err: crate::vhci::Error) -> Failure {
+    (err.kind().into(), ErrorChain(&err).to_string())
+}
+
+/// Reads a UTF-8 C string.
+///
+/// # Safety
+/// `ptr` must be null or point to a NUL-terminated string.
+unsafe fn read_str<'a>(ptr: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(invalid(&format!("{what} is null")));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| invalid(&format!("{what} is not UTF-8")))
+}
+
+/// Copies as much of `src` as fits into `dst`, NUL-terminated.
+fn write_str(dst: &mut [c_char], src: &str) {
+    let len = src.len().min(dst.len().saturating_sub(1));
+    for (dst, src) in dst.iter_mut().zip(&src.as_bytes()[..len]) {
+        *dst = *src as c_char;
+    }
+    if let Some(nul) = dst.get_mut(len) {
+        *nul = 0;
+    }
+}
+
+/// Opens the vhci driver, writing its handle to `out`.
+///
+/// # Safety
+/// `out` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_vhci_open(out: *mut *mut UsbipVhci) -> UsbipStatus {
+    call(|| {
+        let out = out.as_mut().ok_or_else(|| invalid("out is null"))?;
+        let driver = VhciDriver::open().map_err(failed)?;
+        *out = Box::into_raw(Box::new(UsbipVhci(driver)));
+        Ok(())
+    })
+}
+
+/// Closes a driver opened with [`usbip_vhci_open`].
+/// Does nothing if `vhci` is null.
+///
+/// # Safety
+/// `vhci` must be null or a handle from [`usbip_vhci_open`]
+/// that hasn't been closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_vhci_close(vhci: *mut UsbipVhci) {
+    if !vhci.is_null() {
+        drop(Box::from_raw(vhci));
+    }
+}
+
+/// Imports the device at `bus_id` from `host`, given as
+/// `address:port`, writing the port it's attached to to `port`.
+///
+/// # Safety
+/// `vhci` must be null or an open handle, `host` and `bus_id`
+/// null or NUL-terminated, and `port` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_vhci_attach(
+    vhci: *mut UsbipVhci,
+    host: *const c_char,
+    bus_id: *const c_char,
+    port: *mut u16,
+) -> UsbipStatus {
+    call(|| {
+        let vhci = vhci.as_mut().ok_or_else(|| invalid("vhci is null"))?;
+        let host: SocketAddr = read_str(host, "host")?
+            .parse()
+            .map_err(|_| invalid("host is not an address and port"))?;
+        let bus_id: BusId = read_str(bus_id, "bus_id")?
+            .try_into()
+            .map_err(|_| invalid("bus_id is not a bus id"))?;
+        let port = port.as_mut().ok_or_else(|| invalid("port is null"))?;
+
+        *port = vhci.0.attach(AttachArgs { host, bus_id }).map_err(failed)?;
+        Ok(())
+    })
+}
+
+/// Detaches the device on `port`.
+///
+/// # Safety
+/// `vhci` must be null or an open handle.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_vhci_detach(vhci: *mut UsbipVhci, port: u16) -> UsbipStatus {
+    call(|| {
+        let vhci = vhci.as_mut().ok_or_else(|| invalid("vhci is null"))?;
+        vhci.0.detach(port).map_err(failed)
+    })
+}
+
+/// Writes the driver's port counts to `out`.
+///
+/// # Safety
+/// `vhci` must be null or an open handle,
+/// and `out` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_vhci_port_counts(
+    vhci: *const UsbipVhci,
+    out: *mut UsbipPortCounts,
+) -> UsbipStatus {
+    call(|| {
+        let vhci = vhci.as_ref().ok_or_else(|| invalid("vhci is null"))?;
+        let out = out.as_mut().ok_or_else(|| invalid("out is null"))?;
+        let counts = vhci.0.port_counts().map_err(failed)?;
+        *out = UsbipPortCounts {
+            num_controllers: counts.num_controllers(),
+            num_ports: counts.num_ports(),
+            free_ports: counts.free_ports(),
+        };
+        Ok(())
+    })
+}
+
+/// Writes the attached devices to `buf`, which has room for
+/// `cap` of them, and their number to `len`.
+///
+/// If there are more than `cap`, nothing is written to `buf`,
+/// and [`UsbipStatus::BufferTooSmall`] is returned.
+///
+/// # Safety
+/// `vhci` must be null or an open handle, `buf` valid for
+/// writes of `cap` devices, and `len` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_vhci_imported_devices(
+    vhci: *const UsbipVhci,
+    buf: *mut UsbipImportedDevice,
+    cap: usize,
+    len: *mut usize,
+) -> UsbipStatus {
+    call(|| {
+        let vhci = vhci.as_ref().ok_or_else(|| invalid("vhci is null"))?;
+        let len = len.as_mut().ok_or_else(|| invalid("len is null"))?;
+        let idevs = vhci.0.imported_devices().map_err(failed)?;
+        let idevs = idevs.get();
+
+        *len = idevs.len();
+        if idevs.len() > cap {
+            return Err((
+                UsbipStatus::BufferTooSmall,
+                format!("{} devices don't fit in {cap}", idevs.len()),
+            ));
+        }
+        if idevs.is_empty() {
+            return Ok(());
+        }
+        if buf.is_null() {
+            return Err(invalid("buf is null"));
+        }
+
+        for (i, idev) in idevs.iter().enumerate() {
+            let mut out = UsbipImportedDevice {
+                port: idev.port(),
+                vendor: idev.vendor(),
+                product: idev.product(),
+                devid: idev.dev_id().as_u32(),
+                bus_id: [0; BUS_ID_SIZE],
+                host: [0; USBIP_HOST_SIZE],
+            };
+            if let Some(record) = idev.record() {
+                write_str(&mut out.bus_id, record.bus_id());
+                write_str(&mut out.host, &record.host().to_string());
+            }
+            buf.add(i).write(out);
+        }
+        Ok(())
+    })
+}
+
+/// Copies the message of the last error on this thread
+/// to `buf`, truncated to `cap - 1` bytes and NUL-terminated.
+///
+/// Returns the message's full length, without the NUL,
+/// or 0 if nothing has failed on this thread yet.
+///
+/// # Safety
+/// `buf` must be valid for writes of `cap` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn usbip_last_error(buf: *mut c_char, cap: usize) -> usize {
+    LAST_ERROR.with(|last| {
+        let last = last.borrow();
+        let Some(message) = last.as_deref() else {
+            return 0;
+        };
+        let message = message.to_str().unwrap_or_default();
+        if !buf.is_null() && cap > 0 {
+            write_str(core::slice::from_raw_parts_mut(buf, cap), message);
+        }
+        message.len()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let mut buf = [0; 64];
+        let len = unsafe { usbip_last_error(buf.as_mut_ptr(), buf.len()) };
+        let message = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        assert_eq!(message.to_bytes().len(), len.min(buf.len() - 1));
+        message.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn null_handles_are_invalid_input() {
+        let mut port = 0;
+        let status = unsafe {
+            usbip_vhci_attach(
+                core::ptr::null_mut(),
+                c"127.0.0.1:3240".as_ptr(),
+                c"1-1".as_ptr(),
+                &mut port,
+            )
+        };
+        assert_eq!(status, UsbipStatus::InvalidInput);
+        assert_eq!(last_error(), "vhci is null");
+
+        let status = unsafe { usbip_vhci_detach(core::ptr::null_mut(), 1) };
+        assert_eq!(status, UsbipStatus::InvalidInput);
+        unsafe { usbip_vhci_close(core::ptr::null_mut()) };
+    }
+
+    #[test]
+    fn last_error_is_truncated_to_the_buffer() {
+        LAST_ERROR.with(|last| *last.borrow_mut() = CString::new("a".repeat(100)).ok());
+        let mut buf = [1; 8];
+        let len = unsafe { usbip_last_error(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(len, 100);
+        assert_eq!(
+            unsafe { CStr::from_ptr(buf.as_ptr()) }.to_bytes(),
+            b"aaaaaaa"
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Entry points into parsers that aren't public, for the
+//! targets in `fuzz/`. Only built under `cargo fuzz`, which
+//! passes `--cfg fuzzing`. Run one with, e.g.,
+//! `cargo +nightly fuzz run devlist`.
+
+/// Decodes a devlist reply the way [`list_devices`] reads
+/// it off a host's socket.
+///
+/// [`list_devices`]: crate::remote::list_devices
+pub fn devlist(mut data: &[u8]) {
+    let _ = crate::remote::read_devlist(&mut data);
+}
+
+/// Parses a port's line of the vhci driver's `status` attribute.
+#[cfg(unix)]
+pub fn status_line(line: &str) {
+    crate::unix::vhci2::parse_status_line(line);
+}
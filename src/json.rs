@@ -0,0 +1,248 @@
+//! Serde models with stable field names, for printing device
+//! lists as JSON, such as for a CLI's `--json` flag.
+//!
+//! The device types in the crate root serialize the way they're
+//! laid out, which may change between versions. These models
+//! don't: fields are only ever added, and any other change bumps
+//! [`VERSION`], which each top-level model carries in `version`.
+//! Unknown fields are ignored when deserializing, so older
+//! readers can take output from newer versions.
+//!
+//! # Examples
+//! ```
+//! use usbip_core::{json::DeviceList, UsbDeviceBuilder};
+//!
+//! let dev = UsbDeviceBuilder::new()
+//!     .path("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1")
+//!     .bus_id("1-1")
+//!     .bus_num(1)
+//!     .dev_num(2)
+//!     .build()
+//!     .unwrap();
+//! let list = DeviceList::new(Some("10.0.0.2:3240".into()), [(&dev, &[][..])]);
+//! assert_eq!(list.devices[0].bus_id, "1-1");
+//! ```
+
+use alloc::{string::String, vec::Vec};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{UsbDevice, UsbInterface};
+
+/// The version of the models' format.
+pub const VERSION: u32 = 1;
+
+/// A host's exportable devices, as in a device list reply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceList {
+    pub version: u32,
+    /// The host that sent the list, if it's remote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    pub devices: Vec<Device>,
+}
+
+impl DeviceList {
+    /// Collects the `devices` and their interfaces.
+    pub fn new<'a>(
+        host: Option<String>,
+        devices: impl IntoIterator<Item = (&'a UsbDevice, &'a [UsbInterface])>,
+    ) -> Self {
+        Self {
+            version: VERSION,
+            host,
+            devices: devices
+                .into_iter()
+                .map(|(device, interfaces)| Device::new(device, interfaces))
+                .collect(),
+        }
+    }
+}
+
+/// A USB device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub bus_id: String,
+    /// The device's sysfs path on its host.
+    pub path: String,
+    pub bus_num: u32,
+    pub dev_num: u32,
+    /// In Mbit/s, the way sysfs spells it, like `"480"`.
+    pub speed: String,
+    pub vendor: u16,
+    pub product: u16,
+    /// The release number, in binary-coded decimal.
+    pub bcd_device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub configuration_value: u8,
+    pub num_configurations: u8,
+    #[serde(default)]
+    pub interfaces: Vec<Interface>,
+}
+
+impl Device {
+    pub fn new(device: &UsbDevice, interfaces: &[UsbInterface]) -> Self {
+        Self {
+            bus_id: device.bus_id().into(),
+            path: device.path.as_str().into(),
+            bus_num: device.bus_num(),
+            dev_num: device.dev_num(),
+            speed: device.speed().as_mbps_str().into(),
+            vendor: device.id_vendor(),
+            product: device.id_product(),
+            bcd_device: device.bcd_device(),
+            class: device.device_class(),
+            subclass: device.device_subclass(),
+            protocol: device.device_protocol(),
+            configuration_value: device.configuration_value(),
+            num_configurations: device.num_configurations(),
+            interfaces: interfaces.iter().map(Interface::from).collect(),
+        }
+    }
+}
+
+/// An interface of a [`Device`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interface {
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+}
+
+impl From<&UsbInterface> for Interface {
+    fn from(interface: &UsbInterface) -> Self {
+        Self {
+            class: interface.interface_class(),
+            subclass: interface.interface_subclass(),
+            protocol: interface.interface_protocol(),
+        }
+    }
+}
+
+/// The devices attached to the vhci driver.
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedDevices {
+    pub version: u32,
+    pub devices: Vec<ImportedDevice>,
+}
+
+#[cfg(feature = "client")]
+impl From<&crate::vhci::ImportedDevices> for ImportedDevices {
+    fn from(idevs: &crate::vhci::ImportedDevices) -> Self {
+        Self {
+            version: VERSION,
+            devices: idevs.get().iter().map(ImportedDevice::from).collect(),
+        }
+    }
+}
+
+/// A device attached to the vhci driver.
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportedDevice {
+    pub port: u16,
+    /// In Mbit/s, the way sysfs spells it, like `"480"`.
+    pub speed: String,
+    pub vendor: u16,
+    pub product: u16,
+    /// The bus number on the remote host.
+    pub bus_num: u32,
+    /// The device number on the remote host.
+    pub dev_num: u32,
+    /// The host as `address:port`, if it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// The bus id on the remote host, if it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bus_id: Option<String>,
+}
+
+#[cfg(feature = "client")]
+impl From<&crate::vhci::ImportedDevice> for ImportedDevice {
+    fn from(idev: &crate::vhci::ImportedDevice) -> Self {
+        let record = idev.record();
+        Self {
+            port: idev.port(),
+            speed: idev.speed().as_mbps_str().into(),
+            vendor: idev.vendor(),
+            product: idev.product(),
+            bus_num: idev.bus_num(),
+            dev_num: idev.dev_num(),
+            host: record.map(|record| record.host().to_string()),
+            bus_id: record.map(|record| record.bus_id().into()),
+        }
+    }
+}
+
+/// How many of the vhci driver's ports are in use.
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ports {
+    pub version: u32,
+    pub num_controllers: usize,
+    pub num_ports: usize,
+    pub free_ports: usize,
+    pub used_ports: usize,
+}
+
+#[cfg(feature = "client")]
+impl From<crate::vhci::PortCounts> for Ports {
+    fn from(counts: crate::vhci::PortCounts) -> Self {
+        Self {
+            version: VERSION,
+            num_controllers: counts.num_controllers(),
+            num_ports: counts.num_ports(),
+            free_ports: counts.free_ports(),
+            used_ports: counts.used_ports(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DeviceSpeed;
+
+    #[test]
+    fn device_list_field_names_are_stable() {
+        let dev = crate::test_device("1-1")
+            .speed(DeviceSpeed::High)
+            .ids(0x1d6b, 0x0002)
+            .build()
+            .unwrap();
+        let interfaces = [UsbInterface::new(3, 1, 2)];
+        let list = DeviceList::new(None, [(&dev, &interfaces[..])]);
+
+        let json = serde_json::to_value(&list).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "devices": [{
+                    "bus_id": "1-1",
+                    "path": "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1",
+                    "bus_num": 1,
+                    "dev_num": 2,
+                    "speed": "480",
+                    "vendor": 0x1d6b,
+                    "product": 0x0002,
+                    "bcd_device": 0,
+                    "class": 0,
+                    "subclass": 0,
+                    "protocol": 0,
+                    "configuration_value": 1,
+                    "num_configurations": 1,
+                    "interfaces": [{ "class": 3, "subclass": 1, "protocol": 2 }],
+                }],
+            })
+        );
+
+        // Fields added by later versions are ignored.
+        let mut json = json;
+        json["devices"][0]["serial"] = "abc".into();
+        assert_eq!(serde_json::from_value::<DeviceList>(json).unwrap(), list);
+    }
+}
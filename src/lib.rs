@@ -8,188 +8,93 @@
 //! The goal of this library is to provide a platform-independent interface for sharing USB devices across
 //! the local internet. Currently only client-mode is supported, but future work will focus on supporting
 //! server-mode for at least Linux.
+//!
+//! # Features
+//!
+//! The `std` feature is on by default. Turning it off leaves the
+//! [`protocol`] PDUs, the device types, and [`containers`], which only
+//! need `alloc`, for use on targets without an operating system.
+//!
+//! The `client` and `server` features, also on by default, build the
+//! importing and exporting sides on top of that. An embedded client
+//! can turn off `server`, and a host `client`; the protocol is
+//! there either way.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
-#[cfg(unix)]
+#[cfg(all(unix, feature = "std"))]
 pub mod unix;
-#[cfg(windows)]
+#[cfg(all(windows, feature = "std"))]
 mod windows;
+#[cfg(feature = "std")]
 mod platform {
     #[cfg(unix)]
-    pub use crate::unix::USB_IDS;
+    pub use crate::unix::{USB_IDS, USB_IDS_CANDIDATES};
     #[cfg(windows)]
-    pub use crate::windows::USB_IDS;
+    pub use crate::windows::{USB_IDS, USB_IDS_CANDIDATES};
 }
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(feature = "std")]
 pub mod names;
+#[cfg(feature = "client")]
 pub mod vhci;
+#[cfg(feature = "client")]
+pub mod remote;
+#[cfg(feature = "client")]
+pub mod policy;
+#[cfg(feature = "std")]
+pub mod events;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(all(feature = "server", feature = "tokio"))]
+pub mod server;
+#[cfg(all(unix, feature = "dbus"))]
+pub mod dbus;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(all(windows, feature = "service"))]
+pub mod service;
+#[cfg(feature = "serde")]
+pub mod json;
+#[cfg(feature = "metrics")]
+pub mod stats;
+#[cfg(all(unix, feature = "verify"))]
+pub mod verify;
 pub mod containers {
     pub mod beef;
     pub mod stacktools;
-    pub mod iterators {
-        use std::num::NonZeroU32;
-
-        pub struct BitShiftLeft {
-            mask: NonZeroU32,
-            num: usize,
-        }
-
-        impl BitShiftLeft {
-            pub const fn new(mask: NonZeroU32, num: usize) -> Self {
-                Self { mask, num }
-            }
-        }
-
-        impl Iterator for BitShiftLeft {
-            type Item = usize;
-
-            fn next(&mut self) -> Option<Self::Item> {
-                let next = self.num;
-                self.num = self.num.checked_shl(self.mask.get())?;
-                Some(next)
-            }
-        }
-    }
 }
 mod util;
+#[cfg(all(fuzzing, feature = "client"))]
+#[doc(hidden)]
+pub mod fuzzing;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod benching;
+pub mod protocol;
 pub mod net {
     //! Contains the implementation of the USB/IP [protocol]
     //! as defined by the linux kernel.
     //!
+    //! The PDUs are defined in [`protocol`](crate::protocol), which
+    //! is re-exported here. This adds sending and receiving them
+    //! over std I/O.
+    //!
     //! [protocol]: https://www.kernel.org/doc/html/latest/usb/usbip_protocol.html
-    use core::fmt;
-    use std::borrow::Cow;
-
-    use bincode::{
-        config::{BigEndian, Configuration, Fixint},
-        error::AllowedEnumVariants,
-        impl_borrow_decode,
-    };
-
-    use crate::{
-        containers::stacktools::{StackStr, Str},
-        util::{__private::Sealed, self},
-        UsbDevice, BUS_ID_SIZE, USBIP_VERSION,
-    };
-
-    use bitflags::bitflags;
-
-    bitflags! {
-        /// The USB/IP protocol.
-        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-        pub struct Protocol: u16 {
-            // Common header for all the kinds of PDUs.
-            const OP_REQUEST = 0x80 << 8;
-            const OP_REPLY = 0x00 << 8;
-
-            // Import a remote USB device.
-            const OP_IMPORT = 0x03;
-            const OP_REQ_IMPORT = Self::OP_REQUEST.bits() | Self::OP_IMPORT.bits();
-            const OP_REP_IMPORT = Self::OP_REPLY.bits() | Self::OP_IMPORT.bits();
-
-            // Dummy code
-            const OP_UNSPEC = 0x00;
-            const _OP_REQ_UNSPEC = Self::OP_UNSPEC.bits();
-            const _OP_REP_UNSPEC = Self::OP_UNSPEC.bits();
-
-            const OP_DEVLIST = 0x05;
-            /// Retrieve the list of exported USB devices.
-            const OP_REQ_DEVLIST = Self::OP_REQUEST.bits() | Self::OP_DEVLIST.bits();
-            /// Reply with the list of exported USB devices.
-            const OP_REP_DEVLIST = Self::OP_REPLY.bits() | Self::OP_DEVLIST.bits();
-
-            // Export a USB device to a remote host
-            const OP_EXPORT = 0x06;
-            const OP_REQ_EXPORT = Self::OP_REQUEST.bits() | Self::OP_EXPORT.bits();
-            const OP_REP_EXPORT = Self::OP_REPLY.bits() | Self::OP_EXPORT.bits();
-        }
-    }
-
-    impl bincode::Encode for Protocol {
-        fn encode<E: bincode::enc::Encoder>(
-            &self,
-            encoder: &mut E,
-        ) -> Result<(), bincode::error::EncodeError> {
-            self.bits().encode(encoder)
-        }
-    }
-
-    impl bincode::Decode for Protocol {
-        fn decode<D: bincode::de::Decoder>(
-            decoder: &mut D,
-        ) -> Result<Self, bincode::error::DecodeError> {
-            static PROTO_SIMPLE_FLAGS: &'static [u32] = &[
-                Protocol::OP_REQUEST.bits() as u32,
-                Protocol::OP_REPLY.bits() as u32,
-                Protocol::OP_IMPORT.bits() as u32,
-                Protocol::OP_REQ_IMPORT.bits() as u32,
-                Protocol::OP_REP_IMPORT.bits() as u32,
-                Protocol::OP_UNSPEC.bits() as u32,
-                Protocol::_OP_REQ_UNSPEC.bits() as u32,
-                Protocol::_OP_REP_UNSPEC.bits() as u32,
-                Protocol::OP_DEVLIST.bits() as u32,
-                Protocol::OP_REQ_DEVLIST.bits() as u32,
-                Protocol::OP_REP_DEVLIST.bits() as u32,
-                Protocol::OP_EXPORT.bits() as u32,
-                Protocol::OP_REQ_EXPORT.bits() as u32,
-                Protocol::OP_REP_EXPORT.bits() as u32,
-            ];
-
-            static BINCODE_PROTO_ALLOWED_FLAGS: AllowedEnumVariants =
-                AllowedEnumVariants::Allowed(PROTO_SIMPLE_FLAGS);
-
-            let code = u16::decode(decoder)?;
-
-            Self::from_bits(code).ok_or(bincode::error::DecodeError::UnexpectedVariant {
-                type_name: "Protocol",
-                allowed: &BINCODE_PROTO_ALLOWED_FLAGS,
-                found: code as u32,
-            })
-        }
-    }
-
-    impl_borrow_decode!(Protocol);
-
-    /// The result of a USB/IP network request.
-    /// Will encode/decode as a 4 byte value.
-    #[derive(Debug, Clone, Copy, bincode::Encode, bincode::Decode, PartialEq, Eq)]
-    pub enum Status {
-        Success = 0x00,
-        Failed = 0x01,
-        DevBusy = 0x02,
-        DevErr = 0x03,
-        NoDev = 0x04,
-        Unexpected = 0x05,
-    }
-
-    impl fmt::Display for Status {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            match self {
-                Status::Success => write!(f, "Request succeeded"),
-                Status::Failed => write!(f, "Request failed"),
-                Status::DevBusy => write!(f, "Device busy (exported)"),
-                Status::DevErr => write!(f, "Device in error state"),
-                Status::NoDev => write!(f, "Device not found"),
-                Status::Unexpected => write!(f, "Unexpected response"),
-            }
-        }
-    }
+    pub use crate::protocol::*;
 
-    /// Returns the [`bincode::Configuration`] used
-    /// for network communication.
-    ///
-    /// The current config is no limit on transfers, big endian, and fixed int encoding.
-    ///
-    /// [`bincode::Configuration`]: bincode::config::Configuration
-    pub const fn bincode_config() -> Configuration<BigEndian, Fixint> {
-        bincode::config::standard()
-            .with_no_limit()
-            .with_big_endian()
-            .with_fixed_int_encoding()
-    }
+    #[cfg(feature = "std")]
+    use crate::util::__private::Sealed;
 
     /// Convenience trait for encoding and
     /// writing the encoded data into a buffer
     /// that implements the [`std::io::Write`]
     /// trait.
+    #[cfg(feature = "std")]
     pub trait Send: std::io::Write + Sealed {
         fn send<T: bincode::Encode>(&mut self, data: &T) -> Result<usize, Error>;
     }
@@ -197,204 +102,54 @@ pub mod net {
     /// Convenience trait for reading data from
     /// a buffer that implements [`std::io::Read`]
     /// and decoding it into the type `T`.
+    #[cfg(feature = "std")]
     pub trait Recv: std::io::Read + Sealed {
         fn recv<T: bincode::Decode>(&mut self) -> Result<T, Error>;
     }
 
-    impl From<bincode::error::DecodeError> for Error {
-        fn from(value: bincode::error::DecodeError) -> Self {
-            Self::De(value)
-        }
-    }
-
-    impl From<bincode::error::EncodeError> for Error {
-        fn from(value: bincode::error::EncodeError) -> Self {
-            Self::Enc(value)
-        }
-    }
-
-    /// Represents all the possible userspace errors
-    /// that could occur with communicating between
-    /// a host and client.
-    #[derive(Debug)]
-    pub enum Error {
-        VersionMismatch(u16),
-        BusIdMismatch(Cow<'static, str>),
-        Enc(bincode::error::EncodeError),
-        De(bincode::error::DecodeError),
-    }
-
-    impl core::fmt::Display for Error {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            match self {
-                Error::VersionMismatch(bad_version) => write!(
-                    f,
-                    "Version mismatch! Them: {}, Us: {}",
-                    bad_version, USBIP_VERSION
-                ),
-                Error::BusIdMismatch(bus_id) => write!(f, "Received different busid \"{bus_id}\""),
-                Error::Enc(enc) => write!(f, "Encode error! {enc}"),
-                Error::De(de) => write!(f, "Decode error! {de}"),
-            }
-        }
-    }
+    #[cfg(feature = "std")]
+    pub mod loopback;
 
-    impl std::error::Error for Error {}
+    #[cfg(all(test, feature = "client"))]
+    mod golden;
 
-    impl From<Error> for crate::vhci::error2::Error {
+    #[cfg(feature = "client")]
+    impl From<Error> for crate::vhci::Error {
         fn from(value: Error) -> Self {
             Self::Net(value)
         }
     }
-
-    #[derive(Debug, Clone, Copy, bincode::Encode, bincode::Decode)]
-    pub struct OpCommon {
-        version: u16,
-        code: Protocol,
-        status: Status,
-    }
-
-    impl OpCommon {
-        /// Creates an [`OpCommon`] with
-        /// `code` as the request.
-        ///
-        /// Depending on the [`Protocol`] used,
-        /// the remote device will expect
-        /// more data to be sent.
-        #[inline]
-        pub const fn request(code: Protocol) -> Self {
-            Self {
-                version: super::USBIP_VERSION as u16,
-                code,
-                status: Status::Success,
-            }
-        }
-
-        /// Consumes an [`OpCommon`] and returns another
-        /// one with the `status` of attempting to fulfill
-        /// the remote device's request.
-        #[inline]
-        pub const fn reply(self, status: Status) -> Self {
-            Self { status, ..self }
-        }
-
-        /// Performs basic validation on the [`OpCommon`] object.
-        ///
-        /// On success, returns the [`Status`] code of the [`OpCommon`].
-        ///
-        /// # Error
-        ///
-        /// This function will return an error if:
-        /// - the version number differs from the version number
-        ///   used in this userspace library
-        /// - the code inside the [`OpCommon`] object does not match
-        ///   `expected`
-        pub fn validate(&self, expected: Protocol) -> Result<Status, Error> {
-            if self.version as usize != USBIP_VERSION {
-                Err(Error::VersionMismatch(self.version))
-            } else if expected != Protocol::OP_UNSPEC && expected != self.code {
-                Ok(Status::Unexpected)
-            } else {
-                Ok(self.status)
-            }
-        }
-    }
-
-    #[derive(Debug)]
-    pub struct OpImportRequest<'a> {
-        bus_id: Cow<'a, Str<{ BUS_ID_SIZE - 1 }>>,
-    }
-
-    impl bincode::Encode for OpImportRequest<'_> {
-        fn encode<E: bincode::enc::Encoder>(
-            &self,
-            encoder: &mut E,
-        ) -> Result<(), bincode::error::EncodeError> {
-            self.bus_id.as_ref().encode(encoder)?;
-            0u8.encode(encoder)
-        }
-    }
-
-    impl bincode::Decode for OpImportRequest<'_> {
-        fn decode<D: bincode::de::Decoder>(
-            decoder: &mut D,
-        ) -> Result<Self, bincode::error::DecodeError> {
-            let s = StackStr::<{ BUS_ID_SIZE - 1 }>::decode(decoder)?;
-
-            util::decode_zero_byte(decoder)?;
-            Ok(OpImportRequest { bus_id: Cow::Owned(s) })
-        }
-    }
-
-    impl<'a> OpImportRequest<'a> {
-        /// Constructs a new [`OpImportRequest`]
-        /// out of a [`str`] slice, returning `None`
-        /// if `bus_id.as_bytes().len() > BUS_ID_SIZE`.
-        #[inline(always)]
-        pub const fn new(bus_id: &'a str) -> Option<Self> {
-            if let Some(s) = Str::<{ BUS_ID_SIZE - 1 }>::new(bus_id) {
-                Some(Self {
-                    bus_id: Cow::Borrowed(s),
-                })
-            } else {
-                None
-            }
-        }
-
-        #[inline(always)]
-        pub const fn into_inner(self) -> Cow<'a, Str<{ BUS_ID_SIZE - 1 }>> {
-            self.bus_id
-        }
-    }
-
-    #[derive(Debug, bincode::Encode, bincode::Decode)]
-    pub struct OpImportReply {
-        usb_dev: UsbDevice,
-    }
-
-    impl OpImportReply {
-        #[inline(always)]
-        pub const fn new(usb_dev: UsbDevice) -> Self {
-            Self { usb_dev }
-        }
-
-        #[inline(always)]
-        pub const fn into_inner(self) -> UsbDevice {
-            self.usb_dev
-        }
-    }
-
-    #[derive(Debug, bincode::Encode, bincode::Decode)]
-    pub struct OpDevlistReply {
-        num_devices: u32,
-    }
-
-    impl OpDevlistReply {
-        #[inline(always)]
-        pub const fn new(num_devices: u32) -> Self {
-            Self { num_devices }
-        }
-
-        #[inline(always)]
-        pub const fn num_devices(&self) -> u32 {
-            self.num_devices
-        }
-    }
 }
 
-use core::fmt;
-use std::{borrow::Cow, num::ParseIntError, path::Path, str::FromStr};
+use alloc::{
+    borrow::{Cow, ToOwned},
+    string::{String, ToString},
+};
+use core::{fmt, num::ParseIntError, str::FromStr};
+#[cfg(feature = "std")]
+use std::path::Path;
 
-use bincode::{de::read::Reader, impl_borrow_decode};
+use bincode::impl_borrow_decode;
 use containers::stacktools::{StackStr, Str};
+use util::EncodedSize;
 
-pub use platform::USB_IDS;
+#[cfg(feature = "std")]
+pub use platform::{USB_IDS, USB_IDS_CANDIDATES};
+pub use protocol::{OpCommon, OpDevlistReply, OpImportReply, OpImportRequest, Protocol, Status};
 
 pub const USBIP_VERSION: usize = 0x111;
 pub const DEV_PATH_MAX: usize = 256;
 pub const BUS_ID_SIZE: usize = 32;
 
-#[derive(Debug)]
+/// The sysfs path of a USB device, like
+/// `/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1`.
+///
+/// # Invariants
+///
+/// A `SysPath` is at most `DEV_PATH_MAX - 1` bytes, so that it fits,
+/// with its null terminator, in the `path` field of the wire format.
+/// When decoded off the wire, the trailing NUL padding is removed.
+#[derive(Debug, Clone)]
 pub struct SysPath<'a>(Cow<'a, Str<{ DEV_PATH_MAX - 1 }>>);
 
 impl<'a> SysPath<'a> {
@@ -403,6 +158,8 @@ impl<'a> SysPath<'a> {
         Self(s)
     }
 
+    /// Borrows `s` as a [`SysPath`], returning `None` if
+    /// it's longer than `DEV_PATH_MAX - 1` bytes.
     pub const fn new_from_str(s: &'a str) -> Option<SysPath<'a>> {
         if let Some(s) = Str::new(s) {
             Some(SysPath(Cow::Borrowed(s)))
@@ -411,6 +168,7 @@ impl<'a> SysPath<'a> {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn as_path(&self) -> &Path {
         Path::new(self.as_str())
     }
@@ -418,6 +176,11 @@ impl<'a> SysPath<'a> {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+
+    /// Copies the path if it's borrowed.
+    pub fn into_owned(self) -> SysPath<'static> {
+        SysPath(Cow::Owned(self.0.into_owned()))
+    }
 }
 
 impl SysPath<'static> {
@@ -427,6 +190,20 @@ impl SysPath<'static> {
     }
 }
 
+impl PartialEq for SysPath<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for SysPath<'_> {}
+
+impl fmt::Display for SysPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl bincode::Encode for SysPath<'_> {
     fn encode<E: bincode::enc::Encoder>(
         &self,
@@ -451,6 +228,10 @@ impl bincode::Decode for SysPath<'static> {
     }
 }
 
+unsafe impl EncodedSize for SysPath<'_> {
+    const ENCODED_SIZE_OF: usize = DEV_PATH_MAX;
+}
+
 impl<'de> bincode::BorrowDecode<'de> for SysPath<'de> {
     fn borrow_decode<D: bincode::de::BorrowDecoder<'de>>(
         decoder: &mut D,
@@ -458,11 +239,48 @@ impl<'de> bincode::BorrowDecode<'de> for SysPath<'de> {
         let s: &Str<{ DEV_PATH_MAX - 1 }> = bincode::BorrowDecode::borrow_decode(decoder)?;
 
         util::decode_zero_byte(decoder)?;
-        Ok(SysPath::new(Cow::Borrowed(s)))
+        Ok(SysPath::new(Cow::Borrowed(s.trim_padding())))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SysPath<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
     }
 }
 
-#[derive(Debug)]
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SysPath<'static> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        StackStr::deserialize(deserializer).map(SysPath::new_from_stack)
+    }
+}
+
+/// A USB bus id, like `1-1` or `2-1.4`.
+///
+/// A bus id is at most `BUS_ID_SIZE - 1` bytes of ASCII
+/// without any NUL bytes, so that it fits, with its null
+/// terminator, in the `busid` fields of the wire format
+/// and the kernel's `usbip_usb_device`.
+///
+/// # Invariants
+///
+/// [`BusId::parse`] checks all of the above. [`BusId::new`]
+/// only checks the length, through [`Str`], and is meant for
+/// bus ids that come from a trusted source like sysfs.
+///
+/// # Examples
+///
+/// ```
+/// use usbip_core::BusId;
+///
+/// let bus_id = BusId::parse("1-1").unwrap();
+/// assert_eq!(bus_id.as_str(), "1-1");
+///
+/// assert!(BusId::parse("1-1\0").is_err());
+/// ```
+#[derive(Debug, Clone)]
 pub struct BusId<'a>(Cow<'a, Str<{ BUS_ID_SIZE - 1 }>>);
 
 impl<'a> BusId<'a> {
@@ -470,9 +288,95 @@ impl<'a> BusId<'a> {
         Self(bus_id)
     }
 
+    /// Validates `bus_id` and borrows it as a [`BusId`].
+    ///
+    /// # Errors
+    /// This function will return an error if `bus_id`
+    /// is empty, is longer than `BUS_ID_SIZE - 1` bytes,
+    /// contains a NUL byte, or isn't ASCII.
+    pub const fn parse(bus_id: &'a str) -> Result<Self, ParseBusIdError> {
+        match validate_bus_id(bus_id) {
+            Ok(()) => match Str::new(bus_id) {
+                Some(s) => Ok(BusId(Cow::Borrowed(s))),
+                None => unreachable!(),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+
+    /// Copies the bus id if it's borrowed.
+    pub fn into_owned(self) -> BusId<'static> {
+        BusId(Cow::Owned(self.0.into_owned()))
+    }
+}
+
+const fn validate_bus_id(bus_id: &str) -> Result<(), ParseBusIdError> {
+    let bytes = bus_id.as_bytes();
+    if bytes.is_empty() {
+        return Err(ParseBusIdError::Empty);
+    }
+    if bytes.len() > BUS_ID_SIZE - 1 {
+        return Err(ParseBusIdError::Length {
+            max: BUS_ID_SIZE - 1,
+            actual: bytes.len(),
+        });
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            0 => return Err(ParseBusIdError::Nul(i)),
+            b if !b.is_ascii() => return Err(ParseBusIdError::NotAscii(i)),
+            _ => i += 1,
+        }
+    }
+    Ok(())
+}
+
+impl FromStr for BusId<'static> {
+    type Err = ParseBusIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BusId::parse(s).map(BusId::into_owned)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for BusId<'a> {
+    type Error = ParseBusIdError;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        BusId::parse(value)
+    }
+}
+
+impl PartialEq for BusId<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for BusId<'_> {}
+
+impl PartialEq<str> for BusId<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for BusId<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Display for BusId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl bincode::Encode for BusId<'_> {
@@ -486,19 +390,157 @@ impl bincode::Decode for BusId<'static> {
     fn decode<D: bincode::de::Decoder>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
         let s = StackStr::<{ BUS_ID_SIZE - 1 }>::decode(decoder)?;
         util::decode_zero_byte(decoder)?;
+        validate_bus_id(&s).map_err(|err| bincode::error::DecodeError::OtherString(err.to_string()))?;
         Ok(BusId::new(Cow::Owned(s)))
     }
 }
 
+unsafe impl EncodedSize for BusId<'_> {
+    const ENCODED_SIZE_OF: usize = BUS_ID_SIZE;
+}
+
 impl<'de> bincode::BorrowDecode<'de> for BusId<'de> {
     fn borrow_decode<D: bincode::de::BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, bincode::error::DecodeError> {
         let s: &Str<{ BUS_ID_SIZE - 1 }> = bincode::BorrowDecode::borrow_decode(decoder)?;
         util::decode_zero_byte(decoder)?;
-        Ok(BusId(Cow::Borrowed(s)))
+        BusId::parse(s.trim_padding().as_str())
+            .map_err(|err| bincode::error::DecodeError::OtherString(err.to_string()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for BusId<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for BusId<'static> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The error type for parsing a [`BusId`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseBusIdError {
+    Empty,
+    Length { max: usize, actual: usize },
+    /// A NUL byte at the given index.
+    Nul(usize),
+    /// A non-ASCII byte at the given index.
+    NotAscii(usize),
+}
+
+impl fmt::Display for ParseBusIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseBusIdError::Empty => write!(f, "Empty bus id"),
+            ParseBusIdError::Length { max, actual } => {
+                write!(f, "Bus id is too long (max: {max}, actual: {actual})")
+            }
+            ParseBusIdError::Nul(i) => write!(f, "Bus id has a NUL byte at index {i}"),
+            ParseBusIdError::NotAscii(i) => write!(f, "Bus id has a non-ASCII byte at index {i}"),
+        }
+    }
+}
+
+impl core::error::Error for ParseBusIdError {}
+
+/// Formats a list of devices, like the one in a
+/// devlist reply, one [`UsbDevice`] per line.
+///
+/// Writes straight into the formatter, so it can render
+/// into any [`fmt::Write`] without building a `String`.
+/// The alternate flag (`{:#}`) is passed on to each device.
+///
+/// # Examples
+///
+/// ```
+/// use core::fmt::Write;
+/// use usbip_core::{containers::stacktools::StackStr, DevlistDisplay, UsbDevice};
+///
+/// let devices: [UsbDevice; 0] = [];
+/// let mut out = StackStr::<64>::new();
+/// write!(out, "{}", DevlistDisplay::new(&devices)).unwrap();
+/// assert!(out.is_empty());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct DevlistDisplay<'a> {
+    devices: &'a [UsbDevice],
+}
+
+impl<'a> DevlistDisplay<'a> {
+    pub const fn new(devices: &'a [UsbDevice]) -> Self {
+        Self { devices }
+    }
+}
+
+impl fmt::Display for DevlistDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, device) in self.devices.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            if f.alternate() {
+                write!(f, "{device:#}")?;
+            } else {
+                write!(f, "{device}")?;
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, bincode::Encode)]
+/// The highest device address on a USB bus.
+const MAX_DEV_NUM: u32 = 127;
+/// Mirrors the kernel's `USB_MAXCONFIG`.
+const MAX_CONFIGURATIONS: u8 = 8;
+/// Mirrors the kernel's `USB_MAXINTERFACES`.
+const MAX_INTERFACES: u8 = 32;
+/// The class codes USB-IF allows in a device descriptor,
+/// as opposed to ones only valid for an interface.
+const DEVICE_CLASSES: [u8; 8] = [0x00, 0x02, 0x09, 0x11, 0xdc, 0xe0, 0xef, 0xff];
+
+/// The reason a [`UsbDevice`] failed [`UsbDevice::validate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The bus number was zero or didn't fit in a [`DevId`].
+    BusNum(u32),
+    /// The device number was zero or past the last address on a bus.
+    DevNum(u32),
+    /// The vhci driver can't attach a device at this speed.
+    Speed(DeviceSpeed),
+    NumConfigurations(u8),
+    NumInterfaces(u8),
+    /// The active configuration is past the last one.
+    ConfigurationValue(u8),
+    /// The class code isn't allowed in a device descriptor.
+    Class(u8),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::BusNum(b) => write!(f, "Invalid bus number {b}"),
+            ValidationError::DevNum(d) => write!(f, "Invalid device number {d}"),
+            ValidationError::Speed(s) => write!(f, "Unsupported speed: {s}"),
+            ValidationError::NumConfigurations(n) => {
+                write!(f, "Invalid number of configurations {n}")
+            }
+            ValidationError::NumInterfaces(n) => write!(f, "Invalid number of interfaces {n}"),
+            ValidationError::ConfigurationValue(v) => write!(f, "Configuration {v} doesn't exist"),
+            ValidationError::Class(c) => write!(f, "Invalid device class {c:#04x}"),
+        }
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+#[derive(Debug, Clone, bincode::Encode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UsbDevice {
     path: SysPath<'static>,
     busid: BusId<'static>,
@@ -541,7 +583,56 @@ impl bincode::Decode for UsbDevice {
 
 impl_borrow_decode!(UsbDevice);
 
+unsafe impl EncodedSize for UsbDevice {
+    const ENCODED_SIZE_OF: usize = SysPath::ENCODED_SIZE_OF
+        + BusId::ENCODED_SIZE_OF
+        + 2 * core::mem::size_of::<u32>()
+        + DeviceSpeed::ENCODED_SIZE_OF
+        + 3 * core::mem::size_of::<u16>()
+        + 6 * core::mem::size_of::<u8>();
+}
+
+/// Formats the device without a [`Names`](names::Names) database,
+/// as `1-1: 1d6b:0002, High Speed (480 Mbit/s), class 09/00/01`.
+///
+/// The alternate flag (`{:#}`) also prints the sysfs path, bus
+/// and device numbers, release number, and configuration, one
+/// field per line.
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:04x}:{:04x}, {}, class {:02x}/{:02x}/{:02x}",
+            self.bus_id(),
+            self.id_vendor,
+            self.id_product,
+            self.speed,
+            self.b_device_class,
+            self.b_device_subclass,
+            self.b_device_protocol
+        )?;
+
+        if f.alternate() {
+            write!(f, "\n    path: {}", self.path.as_str())?;
+            write!(f, "\n    bus {:03} device {:03}", self.busnum, self.devnum)?;
+            write!(
+                f,
+                "\n    bcdDevice: {:x}.{:02x}",
+                self.bcd_device >> 8,
+                self.bcd_device & 0xff
+            )?;
+            write!(
+                f,
+                "\n    configuration {} of {}, {} interface(s)",
+                self.b_configuration_value, self.b_num_configurations, self.b_num_interfaces
+            )?;
+        }
+        Ok(())
+    }
+}
+
 impl UsbDevice {
+    #[cfg(feature = "std")]
     pub fn path(&self) -> &Path {
         self.path.as_path()
     }
@@ -550,8 +641,8 @@ impl UsbDevice {
         self.busid.as_str()
     }
 
-    pub const fn dev_id(&self) -> u32 {
-        (self.bus_num() << 16) | self.dev_num()
+    pub const fn dev_id(&self) -> DevId {
+        DevId::from_u32((self.bus_num() << 16) | self.dev_num())
     }
 
     pub const fn speed(&self) -> DeviceSpeed {
@@ -565,98 +656,623 @@ impl UsbDevice {
     pub const fn dev_num(&self) -> u32 {
         self.devnum
     }
-}
-
-/// The state of a [`vhci`] device port.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DeviceStatus {
-    DevAvailable = 0x01,
-    DevInUse,
-    DevError,
-    PortAvailable,
-    PortInitializing,
-    PortInUse,
-    PortError,
-}
 
-impl fmt::Display for DeviceStatus {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DeviceStatus::DevAvailable => write!(f, "Device Available"),
-            DeviceStatus::DevInUse => write!(f, "Device in Use"),
-            DeviceStatus::DevError => write!(f, "Device Unusable Due To Fatal Error"),
-            DeviceStatus::PortAvailable => write!(f, "Port Available"),
-            DeviceStatus::PortInitializing => write!(f, "Port Initializing"),
-            DeviceStatus::PortInUse => write!(f, "Port in Use"),
-            DeviceStatus::PortError => write!(f, "Port Error"),
+    /// Checks that the device's fields make sense
+    /// for a real USB device.
+    ///
+    /// Devices decoded off the wire are taken as-is, so
+    /// this catches a misbehaving server before its reply
+    /// reaches the vhci driver.
+    ///
+    /// # Errors
+    /// This function will return the first problem found.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if self.busnum == 0 || self.busnum > u16::MAX as u32 {
+            Err(ValidationError::BusNum(self.busnum))
+        } else if self.devnum == 0 || self.devnum > MAX_DEV_NUM {
+            Err(ValidationError::DevNum(self.devnum))
+        } else if self.speed == DeviceSpeed::Unknown {
+            Err(ValidationError::Speed(self.speed))
+        } else if self.b_num_configurations == 0 || self.b_num_configurations > MAX_CONFIGURATIONS {
+            Err(ValidationError::NumConfigurations(self.b_num_configurations))
+        } else if self.b_num_interfaces > MAX_INTERFACES {
+            Err(ValidationError::NumInterfaces(self.b_num_interfaces))
+        } else if self.b_configuration_value > self.b_num_configurations {
+            Err(ValidationError::ConfigurationValue(
+                self.b_configuration_value,
+            ))
+        } else if !DEVICE_CLASSES.contains(&self.b_device_class) {
+            Err(ValidationError::Class(self.b_device_class))
+        } else {
+            Ok(())
         }
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ParseDeviceStatusError {
-    Invalid,
-    Parse(ParseIntError),
-}
+    pub const fn id_vendor(&self) -> u16 {
+        self.id_vendor
+    }
 
-impl fmt::Display for ParseDeviceStatusError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ParseDeviceStatusError::Invalid => write!(f, "Invalid device status"),
-            ParseDeviceStatusError::Parse(p) => write!(f, "{p}"),
-        }
+    pub const fn id_product(&self) -> u16 {
+        self.id_product
     }
-}
 
-impl std::error::Error for ParseDeviceStatusError {}
+    /// The device release number, in binary-coded decimal.
+    pub const fn bcd_device(&self) -> u16 {
+        self.bcd_device
+    }
 
-impl FromStr for DeviceStatus {
-    type Err = ParseDeviceStatusError;
+    pub const fn device_class(&self) -> u8 {
+        self.b_device_class
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let status = match s.parse::<u8>().map_err(Self::Err::Parse)? {
-            1 => Self::DevAvailable,
-            2 => Self::DevInUse,
-            3 => Self::DevError,
-            4 => Self::PortAvailable,
-            5 => Self::PortInitializing,
-            6 => Self::PortInUse,
-            7 => Self::PortError,
-            _ => return Err(ParseDeviceStatusError::Invalid),
-        };
-        Ok(status)
+    pub const fn device_subclass(&self) -> u8 {
+        self.b_device_subclass
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub struct UsbInterface {
-    b_interface_class: u8,
-    b_interface_subclass: u8,
-    b_interface_protocol: u8,
-}
+    pub const fn device_protocol(&self) -> u8 {
+        self.b_device_protocol
+    }
 
-impl bincode::Encode for UsbInterface {
-    fn encode<E: bincode::enc::Encoder>(
-        &self,
-        encoder: &mut E,
-    ) -> Result<(), bincode::error::EncodeError> {
-        bincode::Encode::encode(&self.b_interface_class, encoder)?;
-        bincode::Encode::encode(&self.b_interface_subclass, encoder)?;
-        bincode::Encode::encode(&self.b_interface_protocol, encoder)?;
-        bincode::Encode::encode(&0u8, encoder)?;
-        Ok(())
+    /// The value of the active configuration,
+    /// or `0` if the device is unconfigured.
+    pub const fn configuration_value(&self) -> u8 {
+        self.b_configuration_value
     }
-}
 
-impl bincode::Decode for UsbInterface {
-    fn decode<D: bincode::de::Decoder>(
-        decoder: &mut D,
-    ) -> Result<Self, bincode::error::DecodeError> {
+    pub const fn num_configurations(&self) -> u8 {
+        self.b_num_configurations
+    }
+
+    /// The number of interfaces in the active configuration.
+    pub const fn num_interfaces(&self) -> u8 {
+        self.b_num_interfaces
+    }
+
+    /// Hashes the device's ids and where it's plugged in, for
+    /// recognizing it again, such as in attach policies.
+    ///
+    /// Bus numbers are left out, so the fingerprint survives buses
+    /// being renumbered, like across reboots, but not the device
+    /// moving to another port. Two identical devices on the same
+    /// port, one after the other, get the same fingerprint.
+    ///
+    /// # Examples
+    /// ```
+    /// use usbip_core::UsbDeviceBuilder;
+    ///
+    /// let dev = |path, bus_id, bus_num| {
+    ///     UsbDeviceBuilder::new()
+    ///         .path(path)
+    ///         .bus_id(bus_id)
+    ///         .bus_num(bus_num)
+    ///         .dev_num(2)
+    ///         .ids(0x0781, 0x5583)
+    ///         .build()
+    ///         .unwrap()
+    /// };
+    /// let before = dev("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2", "1-2", 1);
+    /// let after = dev("/sys/devices/pci0000:00/0000:00:14.0/usb3/3-2", "3-2", 3);
+    /// assert_eq!(before.fingerprint(), after.fingerprint());
+    /// ```
+    pub fn fingerprint(&self) -> Fingerprint {
+        let path = self.path.as_str();
+        // The controller is everything up to its root hub, `usbN`.
+        let mut controller = path;
+        let mut offset = 0;
+        for part in path.split('/') {
+            let is_root_hub = part
+                .strip_prefix("usb")
+                .is_some_and(|num| !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()));
+            if is_root_hub {
+                controller = &path[..offset];
+                break;
+            }
+            offset += part.len() + 1;
+        }
+        // And the ports are the bus id without the bus.
+        let ports = self.bus_id().split_once('-').map_or("", |(_, ports)| ports);
+
+        Fingerprint::hash(self, &[&[0], controller.as_bytes(), &[0], ports.as_bytes()])
+    }
+
+    /// Like [`UsbDevice::fingerprint`], but identifies the device
+    /// by its serial number instead of where it's plugged in.
+    ///
+    /// Serial numbers aren't sent over USB/IP, so `serial` has to
+    /// come from elsewhere, like the device's `serial` attribute
+    /// in sysfs. Not every device has a unique one.
+    pub fn fingerprint_with_serial(&self, serial: &str) -> Fingerprint {
+        Fingerprint::hash(self, &[&[1], serial.as_bytes()])
+    }
+
+    /// Returns an adapter that formats `self` like a
+    /// line of `lsusb`, with names from `names`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # fn print(dev: &usbip_core::UsbDevice) -> std::io::Result<()> {
+    /// use usbip_core::names::Names;
+    ///
+    /// let names = Names::open_default()?;
+    /// // Bus 001 Device 002: ID 1d6b:0002 Linux Foundation 2.0 root hub
+    /// println!("{}", dev.describe(&names));
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "std")]
+    pub const fn describe<'a>(&'a self, names: &'a names::Names) -> names::Description<'a> {
+        names::Description::new(self, names)
+    }
+}
+
+/// Builds a [`UsbDevice`] by hand, such as for
+/// a server implementation or for tests.
+///
+/// # Examples
+///
+/// ```
+/// use usbip_core::{DevId, DeviceSpeed, UsbDeviceBuilder};
+///
+/// let dev = UsbDeviceBuilder::new()
+///     .bus_id("1-1")
+///     .bus_num(1)
+///     .dev_num(2)
+///     .speed(DeviceSpeed::High)
+///     .ids(0x1d6b, 0x0002)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(dev.bus_id(), "1-1");
+/// assert_eq!(dev.dev_id(), DevId::new(1, 2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct UsbDeviceBuilder {
+    path: String,
+    bus_id: String,
+    bus_num: u32,
+    dev_num: u32,
+    speed: Option<DeviceSpeed>,
+    id_vendor: u16,
+    id_product: u16,
+    bcd_device: u16,
+    class: (u8, u8, u8),
+    configuration_value: u8,
+    num_configurations: u8,
+    num_interfaces: u8,
+}
+
+/// The defaults describe a valid device at address 1 of bus 1,
+/// so only the bus id has to be set.
+impl Default for UsbDeviceBuilder {
+    fn default() -> Self {
+        Self {
+            path: String::new(),
+            bus_id: String::new(),
+            bus_num: 1,
+            dev_num: 1,
+            speed: None,
+            id_vendor: 0,
+            id_product: 0,
+            bcd_device: 0,
+            class: (0, 0, 0),
+            configuration_value: 1,
+            num_configurations: 1,
+            num_interfaces: 1,
+        }
+    }
+}
+
+impl UsbDeviceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the sysfs path of the device.
+    pub fn path(&mut self, path: &str) -> &mut Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    /// Sets the bus id of the device. This is required.
+    pub fn bus_id(&mut self, bus_id: &str) -> &mut Self {
+        self.bus_id = bus_id.to_owned();
+        self
+    }
+
+    pub fn bus_num(&mut self, bus_num: u32) -> &mut Self {
+        self.bus_num = bus_num;
+        self
+    }
+
+    pub fn dev_num(&mut self, dev_num: u32) -> &mut Self {
+        self.dev_num = dev_num;
+        self
+    }
+
+    /// Sets the speed of the device, which
+    /// is [`DeviceSpeed::High`] if unset.
+    pub fn speed(&mut self, speed: DeviceSpeed) -> &mut Self {
+        self.speed = Some(speed);
+        self
+    }
+
+    /// Sets the vendor and product ids.
+    pub fn ids(&mut self, vendor: u16, product: u16) -> &mut Self {
+        self.id_vendor = vendor;
+        self.id_product = product;
+        self
+    }
+
+    pub fn bcd_device(&mut self, bcd_device: u16) -> &mut Self {
+        self.bcd_device = bcd_device;
+        self
+    }
+
+    /// Sets the device class, subclass, and protocol.
+    pub fn class(&mut self, class: u8, subclass: u8, protocol: u8) -> &mut Self {
+        self.class = (class, subclass, protocol);
+        self
+    }
+
+    /// Sets the active configuration, and the
+    /// number of configurations and interfaces.
+    pub fn configuration(
+        &mut self,
+        value: u8,
+        num_configurations: u8,
+        num_interfaces: u8,
+    ) -> &mut Self {
+        self.configuration_value = value;
+        self.num_configurations = num_configurations;
+        self.num_interfaces = num_interfaces;
+        self
+    }
+
+    /// Validates the fields and creates the [`UsbDevice`].
+    ///
+    /// # Errors
+    /// This function will return an error if the bus id
+    /// is missing or [invalid](BusId::parse), the path
+    /// doesn't fit in its wire format, or the device
+    /// fails [`UsbDevice::validate`].
+    pub fn build(&self) -> Result<UsbDevice, BuildUsbDeviceError> {
+        if self.bus_id.is_empty() {
+            return Err(BuildUsbDeviceError::MissingBusId);
+        }
+        let busid: BusId<'static> = self.bus_id.parse().map_err(BuildUsbDeviceError::BusId)?;
+        let path = StackStr::try_from(self.path.as_str()).map_err(BuildUsbDeviceError::Path)?;
+
+        let (class, subclass, protocol) = self.class;
+        let device = UsbDevice {
+            path: SysPath::new_from_stack(path),
+            busid,
+            busnum: self.bus_num,
+            devnum: self.dev_num,
+            speed: self.speed.unwrap_or(DeviceSpeed::High),
+            id_vendor: self.id_vendor,
+            id_product: self.id_product,
+            bcd_device: self.bcd_device,
+            b_device_class: class,
+            b_device_subclass: subclass,
+            b_device_protocol: protocol,
+            b_configuration_value: self.configuration_value,
+            b_num_configurations: self.num_configurations,
+            b_num_interfaces: self.num_interfaces,
+        };
+        device.validate().map_err(BuildUsbDeviceError::Invalid)?;
+        Ok(device)
+    }
+}
+
+/// A builder for the device on the first port of an
+/// xHCI root hub, which tests can adjust further.
+#[cfg(all(
+    test,
+    any(
+        feature = "client",
+        feature = "serde",
+        all(feature = "server", feature = "tokio")
+    )
+))]
+pub(crate) fn test_device(bus_id: &str) -> UsbDeviceBuilder {
+    let mut builder = UsbDeviceBuilder::new();
+    builder
+        .path("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1")
+        .bus_id(bus_id)
+        .bus_num(1)
+        .dev_num(2);
+    builder
+}
+
+/// The error type for [`UsbDeviceBuilder::build`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum BuildUsbDeviceError {
+    MissingBusId,
+    BusId(ParseBusIdError),
+    Path(containers::stacktools::TryFromStrErr),
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for BuildUsbDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildUsbDeviceError::MissingBusId => write!(f, "Missing bus id"),
+            BuildUsbDeviceError::BusId(_) => write!(f, "Invalid bus id"),
+            BuildUsbDeviceError::Path(_) => write!(f, "Invalid path"),
+            BuildUsbDeviceError::Invalid(_) => write!(f, "Invalid device"),
+        }
+    }
+}
+
+impl core::error::Error for BuildUsbDeviceError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            BuildUsbDeviceError::BusId(b) => Some(b),
+            BuildUsbDeviceError::Path(p) => Some(p),
+            BuildUsbDeviceError::Invalid(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// A device's bus and device numbers, packed
+/// like the kernel's `devid` as `busnum << 16 | devnum`.
+///
+/// # Examples
+///
+/// ```
+/// use usbip_core::DevId;
+///
+/// let dev_id = DevId::new(1, 2);
+/// assert_eq!(dev_id.as_u32(), 0x0001_0002);
+/// assert_eq!((dev_id.bus(), dev_id.dev()), (1, 2));
+/// ```
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, bincode::Encode, bincode::Decode,
+)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DevId(u32);
+
+impl DevId {
+    pub const fn new(bus: u16, dev: u16) -> Self {
+        Self((bus as u32) << 16 | dev as u32)
+    }
+
+    pub const fn from_u32(dev_id: u32) -> Self {
+        Self(dev_id)
+    }
+
+    pub const fn as_u32(self) -> u32 {
+        self.0
+    }
+
+    pub const fn bus(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    pub const fn dev(self) -> u16 {
+        self.0 as u16
+    }
+}
+
+impl From<u32> for DevId {
+    fn from(value: u32) -> Self {
+        Self::from_u32(value)
+    }
+}
+
+impl From<DevId> for u32 {
+    fn from(value: DevId) -> Self {
+        value.as_u32()
+    }
+}
+
+/// Parses the hex `devid` found in the vhci `status`
+/// attribute, which the kernel prints as `%08x`.
+impl FromStr for DevId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s, 16).map(Self::from_u32)
+    }
+}
+
+/// A stable hash identifying a USB device, see
+/// [`UsbDevice::fingerprint`].
+///
+/// Fingerprints are the same across platforms and versions of
+/// this crate, so they can be stored. They're shown and parsed
+/// as 16 hex digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub const fn from_u64(fingerprint: u64) -> Self {
+        Self(fingerprint)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// FNV-1a over the device's ids and then `parts`.
+    fn hash(dev: &UsbDevice, parts: &[&[u8]]) -> Self {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0100_0000_01b3;
+
+        let ids = [
+            dev.id_vendor().to_be_bytes(),
+            dev.id_product().to_be_bytes(),
+        ];
+        let hash = ids
+            .iter()
+            .map(|id| &id[..])
+            .chain(parts.iter().copied())
+            .flatten()
+            .fold(OFFSET_BASIS, |hash, &byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+            });
+        Self(hash)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+
+/// The state of a [`vhci`] device port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeviceStatus {
+    DevAvailable = 0x01,
+    DevInUse,
+    DevError,
+    PortAvailable,
+    PortInitializing,
+    PortInUse,
+    PortError,
+}
+
+impl fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceStatus::DevAvailable => write!(f, "Device Available"),
+            DeviceStatus::DevInUse => write!(f, "Device in Use"),
+            DeviceStatus::DevError => write!(f, "Device Unusable Due To Fatal Error"),
+            DeviceStatus::PortAvailable => write!(f, "Port Available"),
+            DeviceStatus::PortInitializing => write!(f, "Port Initializing"),
+            DeviceStatus::PortInUse => write!(f, "Port in Use"),
+            DeviceStatus::PortError => write!(f, "Port Error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseDeviceStatusError {
+    Invalid,
+    Parse(ParseIntError),
+}
+
+impl fmt::Display for ParseDeviceStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseDeviceStatusError::Invalid => write!(f, "Invalid device status"),
+            ParseDeviceStatusError::Parse(_) => write!(f, "Device status is not a number"),
+        }
+    }
+}
+
+impl core::error::Error for ParseDeviceStatusError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            ParseDeviceStatusError::Invalid => None,
+            ParseDeviceStatusError::Parse(p) => Some(p),
+        }
+    }
+}
+
+impl FromStr for DeviceStatus {
+    type Err = ParseDeviceStatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let status = match s.parse::<u8>().map_err(Self::Err::Parse)? {
+            1 => Self::DevAvailable,
+            2 => Self::DevInUse,
+            3 => Self::DevError,
+            4 => Self::PortAvailable,
+            5 => Self::PortInitializing,
+            6 => Self::PortInUse,
+            7 => Self::PortError,
+            _ => return Err(ParseDeviceStatusError::Invalid),
+        };
+        Ok(status)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsbInterface {
+    b_interface_class: u8,
+    b_interface_subclass: u8,
+    b_interface_protocol: u8,
+}
+
+impl UsbInterface {
+    pub const fn new(class: u8, subclass: u8, protocol: u8) -> Self {
+        Self {
+            b_interface_class: class,
+            b_interface_subclass: subclass,
+            b_interface_protocol: protocol,
+        }
+    }
+
+    pub const fn interface_class(&self) -> u8 {
+        self.b_interface_class
+    }
+
+    pub const fn interface_subclass(&self) -> u8 {
+        self.b_interface_subclass
+    }
+
+    pub const fn interface_protocol(&self) -> u8 {
+        self.b_interface_protocol
+    }
+
+    /// Names the interface's class triple with `names`,
+    /// formatted like [`Names::class_display`].
+    ///
+    /// [`Names::class_display`]: names::Names::class_display
+    #[cfg(feature = "std")]
+    pub fn class_display<'a>(&self, names: &'a names::Names) -> names::Class<'a> {
+        names.class_display(
+            self.b_interface_class,
+            self.b_interface_subclass,
+            self.b_interface_protocol,
+        )
+    }
+}
+
+impl bincode::Encode for UsbInterface {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        bincode::Encode::encode(&self.b_interface_class, encoder)?;
+        bincode::Encode::encode(&self.b_interface_subclass, encoder)?;
+        bincode::Encode::encode(&self.b_interface_protocol, encoder)?;
+        util::Padding::<u8>::new().encode(encoder)
+    }
+}
+
+unsafe impl EncodedSize for UsbInterface {
+    // The class triple, then a byte of padding.
+    const ENCODED_SIZE_OF: usize = 4;
+}
+
+impl bincode::Decode for UsbInterface {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
         let b_interface_class = u8::decode(decoder)?;
         let b_interface_subclass = u8::decode(decoder)?;
         let b_interface_protocol = u8::decode(decoder)?;
-        decoder.claim_bytes_read(core::mem::size_of::<u8>())?;
-        decoder.reader().consume(core::mem::size_of::<u8>());
+        util::Padding::<u8>::decode(decoder)?;
 
         Ok(UsbInterface {
             b_interface_class,
@@ -666,7 +1282,139 @@ impl bincode::Decode for UsbInterface {
     }
 }
 
+/// An endpoint descriptor belonging to a [`UsbInterface`].
+///
+/// The USB/IP devlist only carries each interface's class
+/// triple, so endpoints are read from the exporting host's
+/// sysfs (`ep_XX` directories) instead of off the wire.
+///
+/// # Examples
+///
+/// ```
+/// use usbip_core::{EndpointDirection, TransferType, UsbEndpoint};
+///
+/// // A high-speed interrupt IN endpoint doing
+/// // 3 transactions of 1024 bytes per microframe.
+/// let ep = UsbEndpoint::new(0x81, 0x03, 0x1400, 1);
+/// assert_eq!(ep.number(), 1);
+/// assert_eq!(ep.direction(), EndpointDirection::In);
+/// assert_eq!(ep.transfer_type(), TransferType::Interrupt);
+/// assert_eq!(ep.max_packet_size(), 1024);
+/// assert_eq!(ep.transactions_per_microframe(), 3);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsbEndpoint {
+    b_endpoint_address: u8,
+    bm_attributes: u8,
+    w_max_packet_size: u16,
+    b_interval: u8,
+}
+
+impl UsbEndpoint {
+    pub const fn new(
+        b_endpoint_address: u8,
+        bm_attributes: u8,
+        w_max_packet_size: u16,
+        b_interval: u8,
+    ) -> Self {
+        Self {
+            b_endpoint_address,
+            bm_attributes,
+            w_max_packet_size,
+            b_interval,
+        }
+    }
+
+    /// The raw `bEndpointAddress`, direction bit included.
+    pub const fn address(&self) -> u8 {
+        self.b_endpoint_address
+    }
+
+    /// The endpoint number, without the direction bit.
+    pub const fn number(&self) -> u8 {
+        self.b_endpoint_address & 0x0f
+    }
+
+    pub const fn direction(&self) -> EndpointDirection {
+        if self.b_endpoint_address & 0x80 != 0 {
+            EndpointDirection::In
+        } else {
+            EndpointDirection::Out
+        }
+    }
+
+    /// The raw `bmAttributes`.
+    pub const fn attributes(&self) -> u8 {
+        self.bm_attributes
+    }
+
+    pub const fn transfer_type(&self) -> TransferType {
+        match self.bm_attributes & 0x03 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// The raw `wMaxPacketSize`, including the
+    /// high-bandwidth bits for high speed endpoints.
+    pub const fn raw_max_packet_size(&self) -> u16 {
+        self.w_max_packet_size
+    }
+
+    /// The largest packet the endpoint can send
+    /// or receive in a single transaction.
+    pub const fn max_packet_size(&self) -> u16 {
+        self.w_max_packet_size & 0x07ff
+    }
+
+    /// The number of transactions a high speed periodic
+    /// endpoint can do per microframe. Always 1 for
+    /// other endpoints.
+    pub const fn transactions_per_microframe(&self) -> u8 {
+        ((self.w_max_packet_size >> 11) & 0x03) as u8 + 1
+    }
+
+    /// The raw `bInterval`, whose unit depends
+    /// on the transfer type and device speed.
+    pub const fn interval(&self) -> u8 {
+        self.b_interval
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EndpointDirection {
+    /// Device to host.
+    In,
+    /// Host to device.
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+impl fmt::Display for TransferType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferType::Control => write!(f, "Control"),
+            TransferType::Isochronous => write!(f, "Isochronous"),
+            TransferType::Bulk => write!(f, "Bulk"),
+            TransferType::Interrupt => write!(f, "Interrupt"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, bincode::Decode, bincode::Encode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum DeviceSpeed {
     Unknown = 0,
@@ -678,6 +1426,10 @@ pub enum DeviceSpeed {
     SuperPlus,
 }
 
+unsafe impl EncodedSize for DeviceSpeed {
+    const ENCODED_SIZE_OF: usize = core::mem::size_of::<u32>();
+}
+
 impl fmt::Display for DeviceSpeed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -705,7 +1457,7 @@ impl fmt::Display for TryFromDeviceSpeedError {
     }
 }
 
-impl std::error::Error for TryFromDeviceSpeedError {}
+impl core::error::Error for TryFromDeviceSpeedError {}
 
 impl FromStr for DeviceSpeed {
     type Err = ParseIntError;
@@ -720,15 +1472,75 @@ impl FromStr for DeviceSpeed {
     }
 }
 
+impl DeviceSpeed {
+    /// Converts the speed to the kernel's `enum usb_device_speed`,
+    /// as written to the vhci `attach` attribute.
+    pub const fn to_kernel_value(self) -> u32 {
+        match self {
+            DeviceSpeed::Unknown => 0,
+            DeviceSpeed::Low => 1,
+            DeviceSpeed::Full => 2,
+            DeviceSpeed::High => 3,
+            DeviceSpeed::Wireless => 4,
+            DeviceSpeed::Super => 5,
+            DeviceSpeed::SuperPlus => 6,
+        }
+    }
+
+    /// Converts a kernel `enum usb_device_speed` value to a speed.
+    ///
+    /// # Errors
+    /// This function will return an error if `value`
+    /// isn't a known `usb_device_speed`.
+    pub const fn from_kernel_value(value: u32) -> Result<Self, TryFromDeviceSpeedError> {
+        let speed = match value {
+            0 => DeviceSpeed::Unknown,
+            1 => DeviceSpeed::Low,
+            2 => DeviceSpeed::Full,
+            3 => DeviceSpeed::High,
+            4 => DeviceSpeed::Wireless,
+            5 => DeviceSpeed::Super,
+            6 => DeviceSpeed::SuperPlus,
+            _ => return Err(TryFromDeviceSpeedError::Invalid),
+        };
+        Ok(speed)
+    }
+
+    /// Converts a whole number of Mbit/s, as found in
+    /// the sysfs `speed` attribute, to a speed.
+    ///
+    /// Low and wireless speeds aren't whole numbers,
+    /// so they (and any other value) map to
+    /// [`DeviceSpeed::Unknown`]. Use [`str::parse`]
+    /// to read the attribute verbatim.
+    pub const fn from_mbps(mbps: u32) -> Self {
+        match mbps {
+            12 => DeviceSpeed::Full,
+            480 => DeviceSpeed::High,
+            5000 => DeviceSpeed::Super,
+            10000 => DeviceSpeed::SuperPlus,
+            _ => DeviceSpeed::Unknown,
+        }
+    }
+
+    /// Returns the speed in Mbit/s the way the
+    /// sysfs `speed` attribute spells it.
+    pub const fn as_mbps_str(self) -> &'static str {
+        match self {
+            DeviceSpeed::Unknown => "unknown",
+            DeviceSpeed::Low => "1.5",
+            DeviceSpeed::Full => "12",
+            DeviceSpeed::High => "480",
+            DeviceSpeed::Wireless => "53.3-480",
+            DeviceSpeed::Super => "5000",
+            DeviceSpeed::SuperPlus => "10000",
+        }
+    }
+}
+
 impl From<u32> for DeviceSpeed {
     fn from(value: u32) -> Self {
-        match value {
-            12 => Self::Full,
-            480 => Self::High,
-            5000 => Self::Super,
-            10000 => Self::SuperPlus,
-            _ => Self::Unknown,
-        }
+        Self::from_mbps(value)
     }
 }
 
@@ -736,6 +1548,368 @@ impl From<u32> for DeviceSpeed {
 mod tests {
     use super::*;
 
+    #[test]
+    fn bus_id_validates() {
+        assert_eq!(BusId::parse("2-1.4").unwrap(), "2-1.4");
+        assert_eq!(BusId::parse("").unwrap_err(), ParseBusIdError::Empty);
+        assert_eq!(
+            BusId::parse(&"1".repeat(BUS_ID_SIZE)).unwrap_err(),
+            ParseBusIdError::Length {
+                max: BUS_ID_SIZE - 1,
+                actual: BUS_ID_SIZE
+            }
+        );
+        assert_eq!(BusId::parse("1-\x001").unwrap_err(), ParseBusIdError::Nul(2));
+        assert_eq!(BusId::parse("1-é").unwrap_err(), ParseBusIdError::NotAscii(2));
+        assert!(matches!("1-1".parse::<BusId>(), Ok(BusId(Cow::Owned(_)))));
+    }
+
+    #[test]
+    fn sys_path_borrow_decode_trims_padding() {
+        let config = net::bincode_config();
+        let path = SysPath::new_from_str("/sys/devices/usb1/1-1").unwrap();
+        let bytes = bincode::encode_to_vec(&path, config).unwrap();
+        assert_eq!(bytes.len(), DEV_PATH_MAX);
+
+        let (decoded, _): (SysPath, _) = bincode::borrow_decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded, path);
+    }
+
+    #[test]
+    fn usb_interface_encodes_trailing_padding() {
+        let config = net::bincode_config();
+        let interface = UsbInterface {
+            b_interface_class: 0x09,
+            b_interface_subclass: 0x00,
+            b_interface_protocol: 0x01,
+        };
+        let bytes = bincode::encode_to_vec(interface, config).unwrap();
+        assert_eq!(bytes, [0x09, 0x00, 0x01, 0x00]);
+
+        let (decoded, read): (UsbInterface, _) =
+            bincode::decode_from_slice(&[0x09, 0x00, 0x01, 0xff], config).unwrap();
+        assert_eq!(read, 4);
+        assert_eq!(decoded.b_interface_protocol, 0x01);
+    }
+
+    #[test]
+    fn bus_id_decode_rejects_interior_nul() {
+        let config = net::bincode_config();
+        let mut bytes = [0u8; BUS_ID_SIZE];
+        bytes[..3].copy_from_slice(b"1-1");
+        let (bus_id, _): (BusId, _) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(bus_id, "1-1");
+        let (bus_id, _): (BusId, _) = bincode::borrow_decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(bus_id, "1-1");
+
+        bytes[4] = b'2';
+        assert!(bincode::decode_from_slice::<BusId, _>(&bytes, config).is_err());
+    }
+
+    #[test]
+    fn usb_device_builder_validates() {
+        assert_eq!(
+            UsbDeviceBuilder::new().build().unwrap_err(),
+            BuildUsbDeviceError::MissingBusId
+        );
+        assert!(matches!(
+            UsbDeviceBuilder::new()
+                .bus_id(&"1".repeat(BUS_ID_SIZE))
+                .build(),
+            Err(BuildUsbDeviceError::BusId(_))
+        ));
+        assert_eq!(
+            UsbDeviceBuilder::new()
+                .bus_id("1-1")
+                .dev_num(0x10000)
+                .build()
+                .unwrap_err(),
+            BuildUsbDeviceError::Invalid(ValidationError::DevNum(0x10000))
+        );
+        assert_eq!(
+            UsbDeviceBuilder::new()
+                .bus_id("1-1")
+                .configuration(2, 1, 1)
+                .build()
+                .unwrap_err(),
+            BuildUsbDeviceError::Invalid(ValidationError::ConfigurationValue(2))
+        );
+        assert_eq!(
+            UsbDeviceBuilder::new()
+                .bus_id("1-1")
+                .class(0x03, 0x01, 0x02)
+                .build()
+                .unwrap_err(),
+            BuildUsbDeviceError::Invalid(ValidationError::Class(0x03))
+        );
+    }
+
+    #[test]
+    fn default_built_device_is_valid() {
+        let dev = UsbDeviceBuilder::new().bus_id("1-1").build().unwrap();
+        assert_eq!(dev.validate(), Ok(()));
+        assert_eq!(dev.dev_id(), DevId::new(1, 1));
+        assert_eq!(dev.speed(), DeviceSpeed::High);
+
+        #[cfg(any(
+            feature = "client",
+            feature = "serde",
+            all(feature = "server", feature = "tokio")
+        ))]
+        assert_eq!(test_device("1-1").build().unwrap().validate(), Ok(()));
+    }
+
+    #[test]
+    fn usb_device_builder_round_trips_wire_format() {
+        let dev = UsbDeviceBuilder::new()
+            .path("/sys/devices/pci0000:00/usb1/1-1")
+            .bus_id("1-1")
+            .bus_num(1)
+            .dev_num(2)
+            .speed(DeviceSpeed::Super)
+            .ids(0x1d6b, 0x0003)
+            .class(0x09, 0x00, 0x03)
+            .configuration(1, 1, 1)
+            .build()
+            .unwrap();
+
+        let config = net::bincode_config();
+        let bytes = bincode::encode_to_vec(&dev, config).unwrap();
+        let (decoded, _): (UsbDevice, _) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded.path(), dev.path());
+        assert_eq!(decoded.bus_id(), "1-1");
+        assert_eq!(decoded.dev_id(), dev.dev_id());
+        assert_eq!(decoded.speed(), DeviceSpeed::Super);
+        assert_eq!((decoded.id_vendor(), decoded.id_product()), (0x1d6b, 0x0003));
+        assert_eq!(
+            (
+                decoded.device_class(),
+                decoded.device_subclass(),
+                decoded.device_protocol()
+            ),
+            (0x09, 0x00, 0x03)
+        );
+        assert_eq!(decoded.configuration_value(), 1);
+        assert_eq!(decoded.num_configurations(), 1);
+        assert_eq!(decoded.num_interfaces(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn usb_device_round_trips_json() {
+        let dev = UsbDeviceBuilder::new()
+            .path("/sys/devices/pci0000:00/usb1/1-1")
+            .bus_id("1-1")
+            .bus_num(1)
+            .dev_num(2)
+            .speed(DeviceSpeed::High)
+            .ids(0x1d6b, 0x0002)
+            .build()
+            .unwrap();
+
+        let json = serde_json::to_string(&dev).unwrap();
+        assert!(json.contains(r#""busid":"1-1""#));
+        assert!(json.contains(r#""speed":"High""#));
+        let decoded: UsbDevice = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.path(), dev.path());
+        assert_eq!(decoded.bus_id(), "1-1");
+        assert_eq!(decoded.dev_id(), dev.dev_id());
+
+        let too_long = json.replace(r#""1-1""#, &format!("\"{}\"", "1".repeat(BUS_ID_SIZE)));
+        assert!(serde_json::from_str::<UsbDevice>(&too_long).is_err());
+    }
+
+    #[test]
+    fn usb_device_validate_rejects_bogus_fields() {
+        // Decoded devices skip the builder, so start
+        // from a valid one and corrupt its fields.
+        let build = |speed, dev_num, configs| {
+            let mut dev = UsbDeviceBuilder::new().bus_id("1-1").build().unwrap();
+            dev.speed = speed;
+            dev.devnum = dev_num;
+            dev.b_num_configurations = configs;
+            dev
+        };
+
+        assert_eq!(build(DeviceSpeed::High, 2, 1).validate(), Ok(()));
+        assert_eq!(build(DeviceSpeed::Wireless, 2, 1).validate(), Ok(()));
+        assert_eq!(
+            build(DeviceSpeed::Unknown, 2, 1).validate(),
+            Err(ValidationError::Speed(DeviceSpeed::Unknown))
+        );
+        assert_eq!(
+            build(DeviceSpeed::High, 0, 1).validate(),
+            Err(ValidationError::DevNum(0))
+        );
+        assert_eq!(
+            build(DeviceSpeed::High, 2, 200).validate(),
+            Err(ValidationError::NumConfigurations(200))
+        );
+    }
+
+    #[test]
+    fn devlist_display_writes_one_device_per_line() {
+        use core::fmt::Write;
+
+        let build = |bus_id| {
+            UsbDeviceBuilder::new()
+                .path("/sys/devices/pci0000:00/usb1/1-1")
+                .bus_id(bus_id)
+                .speed(DeviceSpeed::High)
+                .ids(0x1d6b, 0x0002)
+                .class(0x09, 0x00, 0x01)
+                .build()
+                .unwrap()
+        };
+        let devices = [build("1-1"), build("1-2")];
+
+        let mut out = StackStr::<128>::new();
+        write!(out, "{}", DevlistDisplay::new(&devices)).unwrap();
+        assert_eq!(
+            &*out,
+            "1-1: 1d6b:0002, High Speed (480 Mbit/s), class 09/00/01\n\
+             1-2: 1d6b:0002, High Speed (480 Mbit/s), class 09/00/01"
+        );
+    }
+
+    #[test]
+    fn build_error_chains_its_source() {
+        use core::error::Error as _;
+
+        let err = UsbDeviceBuilder::new()
+            .path("/sys/devices/usb1/1-1")
+            .bus_id("1-\u{e9}")
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid bus id");
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            "Bus id has a non-ASCII byte at index 2"
+        );
+    }
+
+    #[test]
+    fn usb_device_display() {
+        let dev = UsbDeviceBuilder::new()
+            .path("/sys/devices/pci0000:00/usb1/1-1")
+            .bus_id("1-1")
+            .bus_num(1)
+            .dev_num(2)
+            .speed(DeviceSpeed::High)
+            .ids(0x1d6b, 0x0002)
+            .bcd_device(0x0610)
+            .class(0x09, 0x00, 0x01)
+            .configuration(1, 1, 1)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            dev.to_string(),
+            "1-1: 1d6b:0002, High Speed (480 Mbit/s), class 09/00/01"
+        );
+        assert_eq!(
+            format!("{dev:#}"),
+            "1-1: 1d6b:0002, High Speed (480 Mbit/s), class 09/00/01\n    \
+             path: /sys/devices/pci0000:00/usb1/1-1\n    \
+             bus 001 device 002\n    \
+             bcdDevice: 6.10\n    \
+             configuration 1 of 1, 1 interface(s)"
+        );
+    }
+
+    #[test]
+    fn device_speed_kernel_values() {
+        let speeds = [
+            (DeviceSpeed::Unknown, 0),
+            (DeviceSpeed::Low, 1),
+            (DeviceSpeed::Full, 2),
+            (DeviceSpeed::High, 3),
+            (DeviceSpeed::Wireless, 4),
+            (DeviceSpeed::Super, 5),
+            (DeviceSpeed::SuperPlus, 6),
+        ];
+        for (speed, value) in speeds {
+            assert_eq!(speed.to_kernel_value(), value);
+            assert_eq!(DeviceSpeed::from_kernel_value(value).unwrap(), speed);
+            assert_eq!(speed.as_mbps_str().parse::<DeviceSpeed>().unwrap(), speed);
+        }
+        assert!(DeviceSpeed::from_kernel_value(7).is_err());
+        assert_eq!(DeviceSpeed::from_mbps(480), DeviceSpeed::High);
+        assert_eq!(DeviceSpeed::from_mbps(20000), DeviceSpeed::Unknown);
+    }
+
+    #[test]
+    fn dev_id_splits_bus_and_dev() {
+        let dev_id = DevId::new(0x0102, 0xfffe);
+        assert_eq!(dev_id.as_u32(), 0x0102_fffe);
+        assert_eq!(dev_id.bus(), 0x0102);
+        assert_eq!(dev_id.dev(), 0xfffe);
+        assert_eq!("00010002".parse::<DevId>().unwrap(), DevId::new(1, 2));
+        assert_eq!("0102fffe".parse::<DevId>().unwrap(), dev_id);
+        assert!("66302z".parse::<DevId>().is_err());
+
+        let config = net::bincode_config();
+        let bytes = bincode::encode_to_vec(dev_id, config).unwrap();
+        assert_eq!(bytes, [0x01, 0x02, 0xff, 0xfe]);
+    }
+
+    #[test]
+    fn fingerprint_ignores_the_bus_number() {
+        let dev = |bus_num, port, vendor| {
+            let bus_id = format!("{bus_num}-2.{port}");
+            let path = format!("/sys/devices/pci0000:00/0000:00:14.0/usb{bus_num}/{bus_id}");
+            UsbDeviceBuilder::new()
+                .path(&path)
+                .bus_id(&bus_id)
+                .bus_num(bus_num)
+                .dev_num(2)
+                .ids(vendor, 0x5583)
+                .build()
+                .unwrap()
+        };
+        let usb1 = dev(1, 4, 0x0781);
+        let usb3 = dev(3, 4, 0x0781);
+        let other_port = dev(1, 3, 0x0781);
+        let other_vendor = dev(1, 4, 0x0782);
+
+        assert_eq!(usb1.fingerprint(), usb3.fingerprint());
+        assert_ne!(usb1.fingerprint(), other_port.fingerprint());
+        assert_ne!(usb1.fingerprint(), other_vendor.fingerprint());
+        assert_eq!(
+            other_port.fingerprint_with_serial("4C53"),
+            usb1.fingerprint_with_serial("4C53")
+        );
+
+        // Fingerprints get stored, so they mustn't change.
+        assert_eq!(usb1.fingerprint().to_string(), "97d21a403f57fe23");
+        let parsed: Fingerprint = usb1.fingerprint().to_string().parse().unwrap();
+        assert_eq!(parsed, usb1.fingerprint());
+    }
+
+    #[test]
+    fn incremental_decoder_keeps_the_next_pdu() {
+        use net::{IncrementalDecoder, OpCommon, OpImportRequest, Protocol};
+
+        let config = net::bincode_config();
+        let req = (
+            OpCommon::request(Protocol::OP_REQ_IMPORT),
+            OpImportRequest::new(BusId::parse("1-1").unwrap()),
+        );
+        let bytes = bincode::encode_to_vec(req, config).unwrap();
+
+        let mut decoder = IncrementalDecoder::new();
+        for byte in &bytes[..7] {
+            assert!(decoder.feed::<OpCommon>(&[*byte]).unwrap().is_none());
+        }
+        let common: OpCommon = decoder.feed(&bytes[7..10]).unwrap().unwrap();
+        assert_eq!(common.code(), Protocol::OP_REQ_IMPORT);
+        assert!(decoder.feed::<OpImportRequest>(&[]).unwrap().is_none());
+
+        let req: OpImportRequest = decoder.feed(&bytes[10..]).unwrap().unwrap();
+        assert_eq!(req.into_inner(), "1-1");
+        assert!(decoder.is_empty());
+    }
+
     #[test]
     fn device_speed_sizeof_i32() {
         assert_eq!(
@@ -743,4 +1917,157 @@ mod tests {
             std::mem::size_of::<i32>()
         );
     }
+
+    /// Checks that `value` encodes to exactly `ENCODED_SIZE_OF` bytes,
+    /// and that decoding them and encoding again gives the same bytes.
+    fn assert_round_trips<T>(value: &T) -> T
+    where
+        T: bincode::Encode + bincode::Decode + EncodedSize,
+    {
+        let config = net::bincode_config();
+        let bytes = bincode::encode_to_vec(value, config).unwrap();
+        assert_eq!(bytes.len(), T::ENCODED_SIZE_OF);
+        let (decoded, read): (T, _) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(read, bytes.len());
+        assert_eq!(bincode::encode_to_vec(&decoded, config).unwrap(), bytes);
+        decoded
+    }
+
+    mod strategies {
+        use proptest::{prelude::*, sample::select};
+
+        use super::*;
+        use crate::net::{Protocol, Status};
+
+        pub fn bus_id() -> impl Strategy<Value = BusId<'static>> {
+            "[\x01-\x7f]{1,31}".prop_map(|s| BusId::parse(&s).unwrap().into_owned())
+        }
+
+        pub fn sys_path() -> impl Strategy<Value = SysPath<'static>> {
+            "[^\x00]{0,63}".prop_map(|s| SysPath::new_from_str(&s).unwrap().into_owned())
+        }
+
+        pub fn speed() -> impl Strategy<Value = DeviceSpeed> {
+            select(vec![
+                DeviceSpeed::Unknown,
+                DeviceSpeed::Low,
+                DeviceSpeed::Full,
+                DeviceSpeed::High,
+                DeviceSpeed::Wireless,
+                DeviceSpeed::Super,
+                DeviceSpeed::SuperPlus,
+            ])
+        }
+
+        pub fn protocol() -> impl Strategy<Value = Protocol> {
+            select(vec![
+                Protocol::OP_UNSPEC,
+                Protocol::OP_REQ_IMPORT,
+                Protocol::OP_REP_IMPORT,
+                Protocol::OP_REQ_DEVLIST,
+                Protocol::OP_REP_DEVLIST,
+                Protocol::OP_REQ_EXPORT,
+                Protocol::OP_REP_EXPORT,
+            ])
+        }
+
+        pub fn status() -> impl Strategy<Value = Status> {
+            select(vec![
+                Status::Success,
+                Status::Failed,
+                Status::DevBusy,
+                Status::DevErr,
+                Status::NoDev,
+                Status::Unexpected,
+            ])
+        }
+
+        pub fn usb_device() -> impl Strategy<Value = UsbDevice> {
+            (
+                (sys_path(), bus_id(), any::<u32>(), any::<u32>(), speed()),
+                any::<[u16; 3]>(),
+                any::<[u8; 6]>(),
+            )
+                .prop_map(|((path, busid, busnum, devnum, speed), ids, bytes)| {
+                    UsbDevice {
+                        path,
+                        busid,
+                        busnum,
+                        devnum,
+                        speed,
+                        id_vendor: ids[0],
+                        id_product: ids[1],
+                        bcd_device: ids[2],
+                        b_device_class: bytes[0],
+                        b_device_subclass: bytes[1],
+                        b_device_protocol: bytes[2],
+                        b_configuration_value: bytes[3],
+                        b_num_configurations: bytes[4],
+                        b_num_interfaces: bytes[5],
+                    }
+                })
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn bus_id_round_trips(bus_id in strategies::bus_id()) {
+            assert_eq!(assert_round_trips(&bus_id), bus_id);
+
+            let bytes = bincode::encode_to_vec(&bus_id, net::bincode_config()).unwrap();
+            let (borrowed, _): (BusId, _) =
+                bincode::borrow_decode_from_slice(&bytes, net::bincode_config()).unwrap();
+            assert_eq!(borrowed, bus_id);
+        }
+
+        #[test]
+        fn sys_path_round_trips(path in strategies::sys_path()) {
+            assert_eq!(assert_round_trips(&path), path);
+        }
+
+        #[test]
+        fn usb_device_round_trips(device in strategies::usb_device()) {
+            let decoded = assert_round_trips(&device);
+            assert_eq!(decoded.path(), device.path());
+            assert_eq!(decoded.bus_id(), device.bus_id());
+
+            let reply = assert_round_trips(&net::OpImportReply::new(device));
+            assert_eq!(reply.into_inner().bus_id(), decoded.bus_id());
+        }
+
+        #[test]
+        fn usb_interface_round_trips(triple in proptest::prelude::any::<[u8; 3]>()) {
+            let decoded = assert_round_trips(&UsbInterface::new(triple[0], triple[1], triple[2]));
+            assert_eq!(
+                [
+                    decoded.interface_class(),
+                    decoded.interface_subclass(),
+                    decoded.interface_protocol()
+                ],
+                triple
+            );
+        }
+
+        #[test]
+        fn op_common_round_trips(
+            code in strategies::protocol(),
+            status in strategies::status(),
+        ) {
+            let common = net::OpCommon::request(code).reply(status);
+            let decoded = assert_round_trips(&common);
+            assert_eq!(decoded.code(), code);
+            assert_eq!(decoded.validate(code).unwrap(), status);
+        }
+
+        #[test]
+        fn op_requests_and_replies_round_trip(
+            bus_id in strategies::bus_id(),
+            num_devices in proptest::prelude::any::<u32>(),
+        ) {
+            let req = assert_round_trips(&net::OpImportRequest::new(bus_id.clone()));
+            assert_eq!(req.into_inner(), bus_id);
+            let rep = assert_round_trips(&net::OpDevlistReply::new(num_devices));
+            assert_eq!(rep.num_devices(), num_devices);
+        }
+    }
 }
@@ -1,112 +1,607 @@
 use core::fmt;
-use std::{collections::HashMap, fs, io, num::ParseIntError, path::Path, str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufRead},
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
 
-#[derive(Debug)]
+pub mod cache;
+#[cfg(all(unix, feature = "hwdb"))]
+mod hwdb;
+mod mapped;
+#[cfg(feature = "update")]
+pub mod update;
+
+#[cfg(all(unix, feature = "hwdb"))]
+pub use hwdb::HwdbNames;
+pub use mapped::MappedNames;
+
+/// The file name of the cached usb.ids
+/// inside of a cache directory.
+pub const CACHE_FILE_NAME: &str = "usb.ids";
+
+/// The file name of the [parsed snapshot](cache)
+/// inside of a cache directory.
+pub const SNAPSHOT_FILE_NAME: &str = "usb.ids.bin";
+
+/// Every table in usb.ids, with names stored as `V`.
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+struct Tables<V> {
+    vendor: HashMap<VendorKey, V>,
+    product: HashMap<ProductKey, V>,
+    class: HashMap<ClassKey, V>,
+    subclass: HashMap<SubclassKey, V>,
+    protocol: HashMap<ProtocolKey, V>,
+    audio_terminal: HashMap<AudioTerminalKey, V>,
+    hid_descriptor: HashMap<HidDescriptorKey, V>,
+    report_tag: HashMap<ReportTagKey, V>,
+    usage_page: HashMap<UsagePageKey, V>,
+    usage: HashMap<UsageKey, V>,
+    lang: HashMap<LangKey, V>,
+    dialect: HashMap<DialectKey, V>,
+}
+
+/// Where a name lives inside of an
+/// [`Arena`], or of a mapped file.
+#[derive(Debug, Clone, Copy, bincode::Encode, bincode::Decode)]
+struct Span {
+    start: u32,
+    len: u32,
+}
+
+/// Every name of a database, stored back to back
+/// in one allocation instead of one per entry.
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
+struct Arena(String);
+
+impl Arena {
+    fn push(&mut self, name: &str) -> Span {
+        let start = self.0.len();
+        self.0.push_str(name);
+        Span {
+            start: start as u32,
+            len: name.len() as u32,
+        }
+    }
+
+    fn get(&self, span: Span) -> &str {
+        let start = span.start as usize;
+        // Spans are only ever created by `push`, so
+        // they always lie on character boundaries.
+        &self.0[start..start + span.len as usize]
+    }
+}
+
+#[derive(Debug, Default, bincode::Encode, bincode::Decode)]
 struct NamesInner {
-    vendor: HashMap<VendorKey, Box<str>>,
-    product: HashMap<ProductKey, Box<str>>,
-    class: HashMap<ClassKey, Box<str>>,
-    subclass: HashMap<SubclassKey, Box<str>>,
-    protocol: HashMap<ProtocolKey, Box<str>>,
+    arena: Arena,
+    tables: Tables<Span>,
+}
+
+impl NamesInner {
+    fn get(&self, span: Option<&Span>) -> Option<&str> {
+        span.map(|span| self.arena.get(*span))
+    }
+
+    /// Checks that every span lies inside of the arena,
+    /// which is only in doubt when it was deserialized.
+    fn is_valid(&self) -> bool {
+        self.tables.values().all(|span| {
+            let start = span.start as usize;
+            self.arena.0.get(start..start + span.len as usize).is_some()
+        })
+    }
 }
 
+/// A parsed usb.ids database.
+///
+/// Cloning is cheap, since clones
+/// share the same parsed tables.
+#[derive(Clone)]
 pub struct Names {
     inner: Arc<NamesInner>,
+    source_hash: u64,
+    path: Option<PathBuf>,
 }
 
-impl Names {
-    fn parse(buf: &str) -> Names {
-        let mut names = NamesInner::new();
-        let mut last_state = LastState::Start;
+/// Incrementally builds the [`Tables`] of a usb.ids
+/// database, one line at a time.
+///
+/// `to_value` decides how each name is stored.
+struct Parser<V, F> {
+    names: Tables<V>,
+    last_state: LastState,
+    hasher: cache::SourceHasher,
+    to_value: F,
+    line_number: usize,
+    report: ParseReport,
+}
 
-        for (line, _num) in buf.lines().zip(1usize..) {
-            if can_skip(line) {
-                continue;
-            }
+impl<V, F> Parser<V, F>
+where
+    F: FnMut(&str) -> V,
+{
+    fn new(to_value: F) -> Self {
+        Self {
+            names: Tables::new(),
+            last_state: LastState::Start,
+            hasher: cache::SourceHasher::new(),
+            to_value,
+            line_number: 0,
+            report: ParseReport::default(),
+        }
+    }
 
-            if line.contains("L ") {
-                last_state = LastState::Lang;
-                continue;
-            }
+    /// Parses a single line, including its line ending.
+    fn raw_line(&mut self, raw: &str) {
+        self.hasher.write(raw.as_bytes());
+        self.line_number += 1;
+        if let Err(kind) = self.line(raw.trim_end_matches(['\n', '\r'])) {
+            self.report.warnings.push(ParseWarning {
+                line: self.line_number,
+                kind,
+            });
+        }
+    }
 
-            if let Some((key, text)) = parse_class(line) {
-                if names.class.insert(key, text).is_some() {
-                    // Print message about duplicate vendor spec?
-                }
-                last_state = LastState::Class(key);
-                continue;
-            }
+    fn line(&mut self, line: &str) -> Result<(), WarningKind> {
+        let names = &mut self.names;
+        let last_state = &mut self.last_state;
+        let to_value = &mut self.to_value;
+
+        if can_skip(line) {
+            return Ok(());
+        }
+
+        if is_unsupported_section(line) {
+            *last_state = LastState::Unsupported;
+            return Ok(());
+        }
+
+        if let Some((key, text)) = parse_lang(line) {
+            *last_state = LastState::Lang(key);
+            return insert(&mut names.lang, key, to_value(text), Section::Lang);
+        }
+
+        if let Some((key, text)) = parse_class(line) {
+            *last_state = LastState::Class(key);
+            return insert(&mut names.class, key, to_value(text), Section::Class);
+        }
+
+        if let Some((key, text)) = parse_vendor(line) {
+            *last_state = LastState::Vendor(key);
+            return insert(&mut names.vendor, key, to_value(text), Section::Vendor);
+        }
+
+        if let Some((key, text)) = parse_usage_page(line) {
+            *last_state = LastState::Hut(key);
+            return insert(
+                &mut names.usage_page,
+                key,
+                to_value(text),
+                Section::UsagePage,
+            );
+        }
 
-            if let Some((key, text)) = parse_vendor(line) {
-                if names.vendor.insert(key, text).is_some() {
-                    // Etc...
+        if let Some((key, text)) = parse_audio_terminal(line) {
+            *last_state = LastState::Flat;
+            return insert(
+                &mut names.audio_terminal,
+                key,
+                to_value(text),
+                Section::AudioTerminal,
+            );
+        }
+
+        if let Some((key, text)) = parse_hid_descriptor(line) {
+            *last_state = LastState::Flat;
+            return insert(
+                &mut names.hid_descriptor,
+                key,
+                to_value(text),
+                Section::HidDescriptor,
+            );
+        }
+
+        if let Some((key, text)) = parse_report_tag(line) {
+            *last_state = LastState::Flat;
+            return insert(
+                &mut names.report_tag,
+                key,
+                to_value(text),
+                Section::ReportTag,
+            );
+        }
+
+        match *last_state {
+            LastState::Start | LastState::Flat => Err(WarningKind::Unrecognized),
+            LastState::Unsupported if line.starts_with('\t') => Ok(()),
+            LastState::Unsupported => Err(WarningKind::Unrecognized),
+            LastState::Lang(LangKey(lang)) => {
+                let (key, text) = parse_dialect(line, lang).ok_or(WarningKind::Unrecognized)?;
+                insert(&mut names.dialect, key, to_value(text), Section::Dialect)
+            }
+            LastState::Hut(UsagePageKey(page)) => {
+                let (key, text) = parse_usage(line, page).ok_or(WarningKind::Unrecognized)?;
+                insert(&mut names.usage, key, to_value(text), Section::Usage)
+            }
+            LastState::Class(ClassKey(class)) => {
+                let (key, text) = parse_subclass(line, class).ok_or(WarningKind::Unrecognized)?;
+                *last_state = LastState::Subclass(key);
+                insert(&mut names.subclass, key, to_value(text), Section::Subclass)
+            }
+            LastState::Subclass(SubclassKey { class, subclass }) => {
+                if let Some((key, text)) = parse_subclass(line, class) {
+                    *last_state = LastState::Subclass(key);
+                    insert(&mut names.subclass, key, to_value(text), Section::Subclass)
+                } else {
+                    let (key, text) =
+                        parse_protocol(line, class, subclass).ok_or(WarningKind::Unrecognized)?;
+                    insert(&mut names.protocol, key, to_value(text), Section::Protocol)
                 }
-                last_state = LastState::Vendor(key);
-                continue;
             }
-
-            if line.contains("HUT ") {
-                last_state = LastState::Hut;
-                continue;
+            // Interfaces of a product aren't kept.
+            LastState::Product(_) if line.starts_with("\t\t") => Ok(()),
+            LastState::Vendor(VendorKey(vendor))
+            | LastState::Product(ProductKey { vendor, product: _ }) => {
+                let (key, text) = parse_product(line, vendor).ok_or(WarningKind::Unrecognized)?;
+                *last_state = LastState::Product(key);
+                insert(&mut names.product, key, to_value(text), Section::Product)
             }
+        }
+    }
 
-            match last_state {
-                LastState::Start | LastState::Lang | LastState::Hut => {}
-                LastState::Class(ClassKey(class)) => {
-                    if let Some((key, text)) = parse_subclass(line, class) {
-                        if names.subclass.insert(key, text).is_some() {
-                            // Err...
-                        }
-                        last_state = LastState::Subclass(key);
-                    }
-                }
-                LastState::Subclass(SubclassKey { class, subclass }) => {
-                    if let Some((key, text)) = parse_subclass(line, class) {
-                        if names.subclass.insert(key, text).is_some() {
-                            // Err...
-                        }
-                        last_state = LastState::Subclass(key);
-                    } else if let Some((key, text)) = parse_protocol(line, class, subclass) {
-                        if names.protocol.insert(key, text).is_some() {
-                            // Err...
-                        }
-                    }
-                }
-                LastState::Vendor(VendorKey(vendor))
-                | LastState::Product(ProductKey { vendor, product: _ }) => {
-                    if let Some((key, text)) = parse_product(line, vendor) {
-                        if names.product.insert(key, text).is_some() {
-                            // Print message about duplicate vendor spec?
-                        }
-                        last_state = LastState::Product(key);
-                    }
+    /// Returns the finished tables, along with the hash
+    /// of every line seen and everything that was ignored.
+    fn finish(self) -> (Tables<V>, u64, ParseReport) {
+        (self.names, self.hasher.finish(), self.report)
+    }
+}
+
+/// Inserts a parsed entry, keeping the newer
+/// name if `key` was already in `table`.
+fn insert<K, V>(
+    table: &mut HashMap<K, V>,
+    key: K,
+    value: V,
+    section: Section,
+) -> Result<(), WarningKind>
+where
+    K: std::hash::Hash + Eq,
+{
+    match table.insert(key, value) {
+        Some(_) => Err(WarningKind::Duplicate(section)),
+        None => Ok(()),
+    }
+}
+
+/// A table of usb.ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Vendor,
+    Product,
+    Class,
+    Subclass,
+    Protocol,
+    AudioTerminal,
+    HidDescriptor,
+    ReportTag,
+    UsagePage,
+    Usage,
+    Lang,
+    Dialect,
+}
+
+/// Why a line of usb.ids was ignored, or
+/// replaced an earlier entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    /// The entry's ID was already defined in the
+    /// same section. The later entry wins.
+    Duplicate(Section),
+    /// The line isn't a comment, a known section
+    /// entry, or an entry nested under one.
+    Unrecognized,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::Duplicate(section) => write!(f, "duplicate {section:?} entry"),
+            WarningKind::Unrecognized => write!(f, "unrecognized line"),
+        }
+    }
+}
+
+/// A problem found on a single line of usb.ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseWarning {
+    line: usize,
+    kind: WarningKind,
+}
+
+impl ParseWarning {
+    /// The line number, starting at 1.
+    pub const fn line(&self) -> usize {
+        self.line
+    }
+
+    pub const fn kind(&self) -> WarningKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// Everything the parser ignored or overwrote,
+/// for maintainers of custom usb.ids files.
+#[derive(Debug, Clone, Default)]
+pub struct ParseReport {
+    warnings: Vec<ParseWarning>,
+}
+
+impl ParseReport {
+    pub fn warnings(&self) -> &[ParseWarning] {
+        &self.warnings
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+impl Names {
+    fn from_parts(arena: Arena, tables: Tables<Span>, source_hash: u64) -> Names {
+        let mut arena = arena;
+        arena.0.shrink_to_fit();
+        Names {
+            inner: Arc::new(NamesInner { arena, tables }),
+            source_hash,
+            path: None,
+        }
+    }
+}
+
+impl Names {
+    fn parse(buf: &str) -> (Names, ParseReport) {
+        let mut arena = Arena::default();
+        let mut parser = Parser::new(|name: &str| arena.push(name));
+        for line in buf.split_inclusive('\n') {
+            parser.raw_line(line);
+        }
+        let (tables, source_hash, report) = parser.finish();
+        (Names::from_parts(arena, tables, source_hash), report)
+    }
+
+    fn parse_reader<R: BufRead>(mut reader: R) -> io::Result<(Names, ParseReport)> {
+        let mut arena = Arena::default();
+        let mut parser = Parser::new(|name: &str| arena.push(name));
+        let mut line = String::new();
+        while reader.read_line(&mut line)? != 0 {
+            parser.raw_line(&line);
+            line.clear();
+        }
+        let (tables, source_hash, report) = parser.finish();
+        Ok((Names::from_parts(arena, tables, source_hash), report))
+    }
+
+    /// Opens the preferred usb.ids database, trying each
+    /// of [`default_paths`] in order.
+    ///
+    /// A copy cached in [`default_cache_dir`] (such as one
+    /// downloaded by `update::fetch`) is preferred, falling
+    /// back to the copy shipped with the operating system.
+    /// The file that was used is reported by [`Names::path`].
+    ///
+    /// The parsed database is [snapshotted](cache) as
+    /// [`SNAPSHOT_FILE_NAME`] in [`default_cache_dir`], and
+    /// later calls load the snapshot instead of parsing
+    /// again as long as the usb.ids it came from is unchanged.
+    ///
+    /// # Errors
+    /// This function will return an error of kind
+    /// [`NotFound`] if none of the candidates could
+    /// be read.
+    ///
+    /// [`NotFound`]: io::ErrorKind::NotFound
+    pub fn open_default() -> io::Result<Names> {
+        let candidates = default_paths();
+        let cache_dir = default_cache_dir();
+        let snapshot = cache_dir.as_ref().map(|dir| dir.join(SNAPSHOT_FILE_NAME));
+        for path in &candidates {
+            if let Some(names) = snapshot
+                .as_ref()
+                .and_then(|snapshot| Names::load_cache_if_fresh(snapshot, path).ok())
+            {
+                return Ok(names);
+            }
+            if let Ok(names) = parse(path) {
+                if let (Some(dir), Some(snapshot)) = (&cache_dir, &snapshot) {
+                    // The snapshot only saves time, so failing
+                    // to write it just means parsing next time.
+                    let _ = fs::create_dir_all(dir)
+                        .map_err(cache::Error::from)
+                        .and_then(|()| names.save_cache(snapshot));
                 }
+                return Ok(names);
             }
         }
 
-        Names {
-            inner: Arc::from(names),
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("usb.ids not found in any of {candidates:?}"),
+        ))
+    }
+
+    /// Combines several databases into one, where entries
+    /// of later sources override those of earlier ones.
+    ///
+    /// This allows layering, for example, site-local
+    /// overrides on top of the system usb.ids.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use usbip_core::names::{self, Names};
+    ///
+    /// let system = names::parse_from_str("1234  Old Name\n");
+    /// let local = names::parse_from_str("1234  New Name\n");
+    ///
+    /// let names = Names::merge([&system, &local]);
+    /// assert_eq!(names.vendor(0x1234), Some("New Name"));
+    /// ```
+    pub fn merge<'a, I>(sources: I) -> Names
+    where
+        I: IntoIterator<Item = &'a Names>,
+    {
+        let mut builder = NamesBuilder::new();
+        for source in sources {
+            builder.extend(source);
+        }
+        builder.build()
+    }
+
+    /// Returns a process-wide database, opened with
+    /// [`Names::open_default`] the first time this
+    /// succeeds on any thread.
+    ///
+    /// Returns `None` if no usb.ids could be found,
+    /// see [`Names::try_global`].
+    pub fn global() -> Option<&'static Names> {
+        Self::try_global().ok()
+    }
+
+    /// Like [`Names::global`], but returns why
+    /// the database couldn't be opened.
+    ///
+    /// A failed open isn't remembered, so a later call
+    /// tries again, e.g. once usb.ids has been installed.
+    /// Threads that race to open it never wait on each
+    /// other, and all of them get the first one's database.
+    ///
+    /// # Errors
+    /// This function will return the error from
+    /// [`Names::open_default`].
+    pub fn try_global() -> io::Result<&'static Names> {
+        static GLOBAL: OnceLock<Names> = OnceLock::new();
+        Self::get_or_open(&GLOBAL, Names::open_default)
+    }
+
+    /// Returns the database in `cell`, or fills it
+    /// with `open` if it's empty and `open` succeeds.
+    fn get_or_open<F>(cell: &'static OnceLock<Names>, open: F) -> io::Result<&'static Names>
+    where
+        F: FnOnce() -> io::Result<Names>,
+    {
+        if let Some(names) = cell.get() {
+            return Ok(names);
         }
+        let names = open()?;
+        Ok(cell.get_or_init(|| names))
+    }
+
+    /// The file `self` was parsed from, if it
+    /// wasn't parsed from a reader or a string.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// A hash of the usb.ids data `self` was parsed
+    /// from, for telling whether a cache is stale.
+    ///
+    /// For a [merged](Names::merge) or [built](NamesBuilder)
+    /// database, this is a hash of its sources.
+    ///
+    /// See [`cache::hash_source`].
+    pub const fn source_hash(&self) -> u64 {
+        self.source_hash
     }
 
     pub fn vendor(&self, vendor: u16) -> Option<&str> {
-        self.inner.vendor(vendor)
+        self.inner.get(self.inner.tables.vendor(vendor))
     }
 
     pub fn product(&self, vendor: u16, product: u16) -> Option<&str> {
-        self.inner.product(vendor, product)
+        self.inner.get(self.inner.tables.product(vendor, product))
     }
 
     pub fn class(&self, class: u8) -> Option<&str> {
-        self.inner.class(class)
+        self.inner.get(self.inner.tables.class(class))
     }
 
     pub fn subclass(&self, class: u8, subclass: u8) -> Option<&str> {
-        self.inner.subclass(class, subclass)
+        self.inner.get(self.inner.tables.subclass(class, subclass))
     }
 
     pub fn protocol(&self, class: u8, subclass: u8, protocol: u8) -> Option<&str> {
-        self.inner.protocol(class, subclass, protocol)
+        self.inner
+            .get(self.inner.tables.protocol(class, subclass, protocol))
+    }
+
+    /// Iterates over every vendor as `(id, name)`,
+    /// in no particular order.
+    pub fn vendors(&self) -> impl Iterator<Item = (u16, &str)> + '_ {
+        self.inner
+            .tables
+            .vendor
+            .iter()
+            .map(|(key, span)| (key.0, self.inner.arena.get(*span)))
+    }
+
+    /// Iterates over every product of `vendor` as
+    /// `(id, name)`, in no particular order.
+    pub fn products_of(&self, vendor: u16) -> impl Iterator<Item = (u16, &str)> + '_ {
+        self.inner
+            .tables
+            .product
+            .iter()
+            .filter(move |(key, _)| key.vendor == vendor)
+            .map(|(key, span)| (key.product, self.inner.arena.get(*span)))
+    }
+
+    /// Looks up the name of an audio class terminal type.
+    pub fn audio_terminal(&self, terminal_type: u16) -> Option<&str> {
+        self.inner
+            .get(self.inner.tables.audio_terminal(terminal_type))
+    }
+
+    /// Looks up the name of a HID class descriptor type.
+    pub fn hid_descriptor(&self, descriptor_type: u8) -> Option<&str> {
+        self.inner
+            .get(self.inner.tables.hid_descriptor(descriptor_type))
+    }
+
+    /// Looks up the name of a HID report item tag.
+    pub fn report_tag(&self, tag: u8) -> Option<&str> {
+        self.inner.get(self.inner.tables.report_tag(tag))
+    }
+
+    /// Looks up the name of a HID usage page.
+    pub fn usage_page(&self, page: u16) -> Option<&str> {
+        self.inner.get(self.inner.tables.usage_page(page))
+    }
+
+    /// Looks up the name of a HID usage within a usage page.
+    pub fn usage(&self, page: u16, usage: u16) -> Option<&str> {
+        self.inner.get(self.inner.tables.usage(page, usage))
+    }
+
+    /// Looks up the name of a language ID.
+    pub fn lang(&self, lang: u16) -> Option<&str> {
+        self.inner.get(self.inner.tables.lang(lang))
+    }
+
+    /// Looks up the name of a language's dialect.
+    pub fn dialect(&self, lang: u16, dialect: u8) -> Option<&str> {
+        self.inner.get(self.inner.tables.dialect(lang, dialect))
     }
 
     pub fn product_display<'a: 'b, 'b>(&'a self, vendor: u16, product: u16) -> Product<'b> {
@@ -128,6 +623,180 @@ impl Names {
             protocol,
         }
     }
+
+    /// Writes the product name, as formatted by
+    /// [`Names::product_display`], into `buf`.
+    ///
+    /// This mirrors `usbip_names_get_product` from
+    /// libusbip, so `buf` can be a fixed-size buffer
+    /// such as a [`StackStr`].
+    ///
+    /// # Errors
+    /// This function will return an error if `buf`
+    /// couldn't hold the whole name.
+    ///
+    /// [`StackStr`]: crate::containers::stacktools::StackStr
+    pub fn read_product<W: fmt::Write>(
+        &self,
+        buf: &mut W,
+        vendor: u16,
+        product: u16,
+    ) -> fmt::Result {
+        write!(buf, "{}", self.product_display(vendor, product))
+    }
+
+    /// Writes the class triple, as formatted by
+    /// [`Names::class_display`], into `buf`.
+    ///
+    /// This mirrors `usbip_names_get_class` from libusbip.
+    ///
+    /// # Errors
+    /// This function will return an error if `buf`
+    /// couldn't hold the whole name.
+    pub fn read_class<W: fmt::Write>(
+        &self,
+        buf: &mut W,
+        class: u8,
+        subclass: u8,
+        protocol: u8,
+    ) -> fmt::Result {
+        write!(buf, "{}", self.class_display(class, subclass, protocol))
+    }
+}
+
+/// Assembles a [`Names`] database by hand, such as
+/// for proprietary devices that aren't in usb.ids.
+///
+/// # Examples
+///
+/// ```
+/// use usbip_core::names::{self, NamesBuilder};
+///
+/// let system = names::parse_from_str("1d6b  Linux Foundation\n");
+///
+/// let mut builder = NamesBuilder::new();
+/// builder
+///     .extend(&system)
+///     .insert_vendor(0xfff0, "Acme Corp.")
+///     .insert_product(0xfff0, 0x0001, "Widget");
+/// let names = builder.build();
+///
+/// assert_eq!(names.vendor(0x1d6b), Some("Linux Foundation"));
+/// assert_eq!(names.product(0xfff0, 0x0001), Some("Widget"));
+/// ```
+pub struct NamesBuilder {
+    inner: NamesInner,
+    hasher: cache::SourceHasher,
+}
+
+impl NamesBuilder {
+    pub fn new() -> Self {
+        Self {
+            inner: NamesInner::default(),
+            hasher: cache::SourceHasher::new(),
+        }
+    }
+
+    /// Copies every entry of `names` into the
+    /// builder, replacing existing entries.
+    pub fn extend(&mut self, names: &Names) -> &mut Self {
+        let inner = &mut self.inner;
+        let other = &names.inner;
+        inner.tables.extend_from(&other.tables, |span| {
+            inner.arena.push(other.arena.get(*span))
+        });
+        self.hasher.write(&names.source_hash.to_le_bytes());
+        self
+    }
+
+    /// Adds or replaces the name of `vendor`.
+    pub fn insert_vendor(&mut self, vendor: u16, name: &str) -> &mut Self {
+        self.hasher.write(&vendor.to_le_bytes());
+        self.hasher.write(name.as_bytes());
+        let span = self.inner.arena.push(name);
+        self.inner.tables.vendor.insert(VendorKey(vendor), span);
+        self
+    }
+
+    /// Adds or replaces the name of a product of `vendor`.
+    pub fn insert_product(&mut self, vendor: u16, product: u16, name: &str) -> &mut Self {
+        self.hasher.write(&vendor.to_le_bytes());
+        self.hasher.write(&product.to_le_bytes());
+        self.hasher.write(name.as_bytes());
+        let span = self.inner.arena.push(name);
+        self.inner
+            .tables
+            .product
+            .insert(ProductKey { vendor, product }, span);
+        self
+    }
+
+    /// Freezes the builder into a [`Names`]
+    /// that can be shared across threads.
+    pub fn build(self) -> Names {
+        let NamesInner { arena, tables } = self.inner;
+        Names::from_parts(arena, tables, self.hasher.finish())
+    }
+}
+
+impl Default for NamesBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Names a [`UsbDevice`], as returned
+/// by [`UsbDevice::describe`].
+///
+/// [`UsbDevice`]: crate::UsbDevice
+/// [`UsbDevice::describe`]: crate::UsbDevice::describe
+#[derive(Clone, Copy)]
+pub struct Description<'a> {
+    device: &'a crate::UsbDevice,
+    names: &'a Names,
+}
+
+impl<'a> Description<'a> {
+    pub(crate) const fn new(device: &'a crate::UsbDevice, names: &'a Names) -> Self {
+        Self { device, names }
+    }
+
+    /// The vendor and product of the device,
+    /// formatted like `usbip list`.
+    pub fn product(&self) -> Product<'a> {
+        self.names
+            .product_display(self.device.id_vendor(), self.device.id_product())
+    }
+
+    /// The device class triple, formatted like `usbip list`.
+    pub fn class(&self) -> Class<'a> {
+        self.names.class_display(
+            self.device.device_class(),
+            self.device.device_subclass(),
+            self.device.device_protocol(),
+        )
+    }
+}
+
+impl fmt::Display for Description<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dev = self.device;
+        write!(
+            f,
+            "Bus {:03} Device {:03}: ID {:04x}:{:04x}",
+            dev.bus_num(),
+            dev.dev_num(),
+            dev.id_vendor(),
+            dev.id_product()
+        )?;
+        if let Some(vendor) = self.names.vendor(dev.id_vendor()) {
+            write!(f, " {vendor}")?;
+        }
+        if let Some(product) = self.names.product(dev.id_vendor(), dev.id_product()) {
+            write!(f, " {product}")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -181,59 +850,143 @@ impl fmt::Display for Product<'_> {
     }
 }
 
-impl NamesInner {
-    pub fn new() -> Self {
+impl<V> Default for Tables<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Tables<V> {
+    fn new() -> Self {
         Self {
             vendor: HashMap::new(),
             product: HashMap::new(),
             class: HashMap::new(),
             subclass: HashMap::new(),
             protocol: HashMap::new(),
+            audio_terminal: HashMap::new(),
+            hid_descriptor: HashMap::new(),
+            report_tag: HashMap::new(),
+            usage_page: HashMap::new(),
+            usage: HashMap::new(),
+            lang: HashMap::new(),
+            dialect: HashMap::new(),
         }
     }
 
-    pub fn vendor(&self, vendor: u16) -> Option<&str> {
-        self.vendor.get(&VendorKey(vendor)).map(Box::as_ref)
+    fn values(&self) -> impl Iterator<Item = &V> {
+        self.vendor
+            .values()
+            .chain(self.product.values())
+            .chain(self.class.values())
+            .chain(self.subclass.values())
+            .chain(self.protocol.values())
+            .chain(self.audio_terminal.values())
+            .chain(self.hid_descriptor.values())
+            .chain(self.report_tag.values())
+            .chain(self.usage_page.values())
+            .chain(self.usage.values())
+            .chain(self.lang.values())
+            .chain(self.dialect.values())
     }
 
-    pub fn product(&self, vendor: u16, product: u16) -> Option<&str> {
-        self.product
-            .get(&ProductKey { vendor, product })
-            .map(Box::as_ref)
+    /// Copies every entry of `other` into `self`, converted
+    /// with `f`, replacing entries that are already present.
+    fn extend_from<W, F>(&mut self, other: &Tables<W>, mut f: F)
+    where
+        F: FnMut(&W) -> V,
+    {
+        fn extend<K: Copy + Eq + std::hash::Hash, V, W>(
+            table: &mut HashMap<K, V>,
+            other: &HashMap<K, W>,
+            f: &mut impl FnMut(&W) -> V,
+        ) {
+            table.extend(other.iter().map(|(key, value)| (*key, f(value))));
+        }
+
+        extend(&mut self.vendor, &other.vendor, &mut f);
+        extend(&mut self.product, &other.product, &mut f);
+        extend(&mut self.class, &other.class, &mut f);
+        extend(&mut self.subclass, &other.subclass, &mut f);
+        extend(&mut self.protocol, &other.protocol, &mut f);
+        extend(&mut self.audio_terminal, &other.audio_terminal, &mut f);
+        extend(&mut self.hid_descriptor, &other.hid_descriptor, &mut f);
+        extend(&mut self.report_tag, &other.report_tag, &mut f);
+        extend(&mut self.usage_page, &other.usage_page, &mut f);
+        extend(&mut self.usage, &other.usage, &mut f);
+        extend(&mut self.lang, &other.lang, &mut f);
+        extend(&mut self.dialect, &other.dialect, &mut f);
     }
 
-    pub fn class(&self, class: u8) -> Option<&str> {
-        self.class.get(&ClassKey(class)).map(Box::as_ref)
+    fn vendor(&self, vendor: u16) -> Option<&V> {
+        self.vendor.get(&VendorKey(vendor))
     }
 
-    pub fn subclass(&self, class: u8, subclass: u8) -> Option<&str> {
-        self.subclass
-            .get(&SubclassKey { class, subclass })
-            .map(Box::as_ref)
+    fn product(&self, vendor: u16, product: u16) -> Option<&V> {
+        self.product.get(&ProductKey { vendor, product })
     }
 
-    pub fn protocol(&self, class: u8, subclass: u8, protocol: u8) -> Option<&str> {
-        self.protocol
-            .get(&ProtocolKey {
-                class,
-                subclass,
-                protocol,
-            })
-            .map(Box::as_ref)
+    fn class(&self, class: u8) -> Option<&V> {
+        self.class.get(&ClassKey(class))
+    }
+
+    fn subclass(&self, class: u8, subclass: u8) -> Option<&V> {
+        self.subclass.get(&SubclassKey { class, subclass })
+    }
+
+    fn protocol(&self, class: u8, subclass: u8, protocol: u8) -> Option<&V> {
+        self.protocol.get(&ProtocolKey {
+            class,
+            subclass,
+            protocol,
+        })
+    }
+
+    fn audio_terminal(&self, terminal_type: u16) -> Option<&V> {
+        self.audio_terminal.get(&AudioTerminalKey(terminal_type))
+    }
+
+    fn hid_descriptor(&self, descriptor_type: u8) -> Option<&V> {
+        self.hid_descriptor.get(&HidDescriptorKey(descriptor_type))
+    }
+
+    fn report_tag(&self, tag: u8) -> Option<&V> {
+        self.report_tag.get(&ReportTagKey(tag))
+    }
+
+    fn usage_page(&self, page: u16) -> Option<&V> {
+        self.usage_page.get(&UsagePageKey(page))
+    }
+
+    fn usage(&self, page: u16, usage: u16) -> Option<&V> {
+        self.usage.get(&UsageKey { page, usage })
+    }
+
+    fn lang(&self, lang: u16) -> Option<&V> {
+        self.lang.get(&LangKey(lang))
+    }
+
+    fn dialect(&self, lang: u16, dialect: u8) -> Option<&V> {
+        self.dialect.get(&DialectKey { lang, dialect })
     }
 }
 
 enum LastState {
     Start,
-    Lang,
+    /// A section without nested entries.
+    Flat,
+    /// A section that isn't parsed, whose
+    /// nested entries are ignored.
+    Unsupported,
+    Lang(LangKey),
     Class(ClassKey),
     Subclass(SubclassKey),
     Vendor(VendorKey),
     Product(ProductKey),
-    Hut,
+    Hut(UsagePageKey),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
 struct VendorKey(u16);
 
 impl FromStr for VendorKey {
@@ -244,7 +997,7 @@ impl FromStr for VendorKey {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
 struct ProductKey {
     vendor: u16,
     product: u16,
@@ -259,16 +1012,7 @@ impl ProductKey {
     }
 }
 
-impl std::hash::Hash for ProductKey {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        let vendor = self.vendor as u32;
-        let product = self.product as u32;
-        let key: u32 = (vendor << 16) | product;
-        key.hash(state)
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
 struct ClassKey(u8);
 
 impl FromStr for ClassKey {
@@ -279,7 +1023,7 @@ impl FromStr for ClassKey {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
 struct SubclassKey {
     class: u8,
     subclass: u8,
@@ -293,16 +1037,7 @@ impl SubclassKey {
     }
 }
 
-impl std::hash::Hash for SubclassKey {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        let class = self.class as u16;
-        let subclass = self.class as u16;
-        let key: u16 = (class << 8) | subclass;
-        key.hash(state)
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
 struct ProtocolKey {
     class: u8,
     subclass: u8,
@@ -323,70 +1058,215 @@ impl ProtocolKey {
     }
 }
 
-impl std::hash::Hash for ProtocolKey {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        let class = self.class as u32;
-        let subclass = self.class as u32;
-        let protocol = self.class as u32;
-        let key: u32 = (class << 16) | (subclass << 8) | protocol;
-        key.hash(state)
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct AudioTerminalKey(u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct HidDescriptorKey(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct ReportTagKey(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct UsagePageKey(u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct UsageKey {
+    page: u16,
+    usage: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct LangKey(u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, bincode::Encode, bincode::Decode)]
+struct DialectKey {
+    lang: u16,
+    dialect: u8,
 }
 
 fn can_skip(line: &str) -> bool {
-    line.is_empty()
-        || line.starts_with('#')
-        || line.starts_with("PHYSDES ")
+    line.is_empty() || line.starts_with('#')
+}
+
+fn is_unsupported_section(line: &str) -> bool {
+    line.starts_with("PHYSDES ")
         || line.starts_with("PHY ")
         || line.starts_with("BIAS ")
-        || line.starts_with("AT ")
         || line.starts_with("HCC ")
-        || line.starts_with("HID ")
-        || line.starts_with("R ")
         || line.starts_with("VT")
 }
 
-fn parse_value<F, T>(possible: &str, f: F) -> Option<(T, Box<str>)>
+fn parse_value<F, T>(possible: &str, f: F) -> Option<(T, &str)>
 where
     F: Fn(&str) -> Result<T, ParseIntError>,
 {
     possible
         .split_once(' ')
-        .and_then(|(key_token, rest)| f(key_token).ok().map(|key| (key, Box::from(rest.trim()))))
+        .and_then(|(key_token, rest)| f(key_token).ok().map(|key| (key, rest.trim())))
 }
 
-fn parse_class(line: &str) -> Option<(ClassKey, Box<str>)> {
+fn parse_class(line: &str) -> Option<(ClassKey, &str)> {
     parse_value(line.strip_prefix("C ")?, str::parse::<ClassKey>)
 }
 
-fn parse_product(line: &str, vendor: u16) -> Option<(ProductKey, Box<str>)> {
+fn parse_product(line: &str, vendor: u16) -> Option<(ProductKey, &str)> {
     parse_value(line.strip_prefix('\t')?, |token| {
         ProductKey::from_str_and_vendor(token, vendor)
     })
 }
 
-fn parse_subclass(line: &str, class: u8) -> Option<(SubclassKey, Box<str>)> {
+fn parse_subclass(line: &str, class: u8) -> Option<(SubclassKey, &str)> {
     parse_value(line.strip_prefix('\t')?, |token| {
         SubclassKey::from_str_and_class(token, class)
     })
 }
 
-fn parse_protocol(line: &str, class: u8, subclass: u8) -> Option<(ProtocolKey, Box<str>)> {
+fn parse_protocol(line: &str, class: u8, subclass: u8) -> Option<(ProtocolKey, &str)> {
     parse_value(line.strip_prefix("\t\t")?, |token| {
         ProtocolKey::from_str_class_and_subclass(token, class, subclass)
     })
 }
 
-fn parse_vendor(line: &str) -> Option<(VendorKey, Box<str>)> {
+fn parse_vendor(line: &str) -> Option<(VendorKey, &str)> {
     parse_value(line, str::parse::<VendorKey>)
 }
 
+fn parse_audio_terminal(line: &str) -> Option<(AudioTerminalKey, &str)> {
+    parse_value(line.strip_prefix("AT ")?, |token| {
+        u16::from_str_radix(token, 16).map(AudioTerminalKey)
+    })
+}
+
+fn parse_hid_descriptor(line: &str) -> Option<(HidDescriptorKey, &str)> {
+    parse_value(line.strip_prefix("HID ")?, |token| {
+        u8::from_str_radix(token, 16).map(HidDescriptorKey)
+    })
+}
+
+fn parse_report_tag(line: &str) -> Option<(ReportTagKey, &str)> {
+    parse_value(line.strip_prefix("R ")?, |token| {
+        u8::from_str_radix(token, 16).map(ReportTagKey)
+    })
+}
+
+fn parse_usage_page(line: &str) -> Option<(UsagePageKey, &str)> {
+    parse_value(line.strip_prefix("HUT ")?, |token| {
+        u16::from_str_radix(token, 16).map(UsagePageKey)
+    })
+}
+
+fn parse_usage(line: &str, page: u16) -> Option<(UsageKey, &str)> {
+    parse_value(line.strip_prefix('\t')?, |token| {
+        u16::from_str_radix(token, 16).map(|usage| UsageKey { page, usage })
+    })
+}
+
+fn parse_lang(line: &str) -> Option<(LangKey, &str)> {
+    parse_value(line.strip_prefix("L ")?, |token| {
+        u16::from_str_radix(token, 16).map(LangKey)
+    })
+}
+
+fn parse_dialect(line: &str, lang: u16) -> Option<(DialectKey, &str)> {
+    parse_value(line.strip_prefix('\t')?, |token| {
+        u8::from_str_radix(token, 16).map(|dialect| DialectKey { lang, dialect })
+    })
+}
+
+/// Returns the per-user directory that caches
+/// usb.ids for [`Names::open_default`].
+///
+/// # Platform-specific behavior
+/// On unix, this is `$XDG_CACHE_HOME/usbip-core`, or
+/// `$HOME/.cache/usbip-core` if that isn't set.
+///
+/// On windows, this is `%LOCALAPPDATA%\usbip-core`.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    #[cfg(unix)]
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")));
+    #[cfg(windows)]
+    let base = std::env::var_os("LOCALAPPDATA").map(PathBuf::from);
+
+    base.map(|dir| dir.join("usbip-core"))
+}
+
+/// Returns every path [`Names::open_default`] tries,
+/// from most to least preferred.
+///
+/// # Platform-specific behavior
+/// On unix, these are the per-user cache followed
+/// by [`USB_IDS_CANDIDATES`].
+///
+/// On windows, these are the per-user cache, the path
+/// stored in the `UsbIdsPath` value of the registry key
+/// `SOFTWARE\usbip-core` (under `HKEY_CURRENT_USER`, then
+/// `HKEY_LOCAL_MACHINE`), `usb.ids` next to the running
+/// executable, and the copies installed by usbip-win2
+/// and Wireshark under `%ProgramFiles%`.
+///
+/// [`USB_IDS_CANDIDATES`]: crate::USB_IDS_CANDIDATES
+pub fn default_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    paths.extend(default_cache_dir().map(|dir| dir.join(CACHE_FILE_NAME)));
+    paths.extend(crate::USB_IDS_CANDIDATES.iter().map(PathBuf::from));
+
+    #[cfg(windows)]
+    paths.extend(crate::windows::usb_ids_paths());
+
+    paths
+}
+
+/// Parses the usb.ids file at `path`.
 pub fn parse<P>(path: P) -> io::Result<Names>
 where
     P: AsRef<Path>,
 {
-    let reader = fs::read_to_string(path)?;
-    Ok(Names::parse(&reader))
+    parse_with_report(path).map(|(names, _)| names)
+}
+
+/// Like [`parse`], but also reports every line
+/// that was ignored or replaced an earlier entry.
+pub fn parse_with_report<P>(path: P) -> io::Result<(Names, ParseReport)>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let (mut names, report) =
+        parse_from_reader_with_report(io::BufReader::new(fs::File::open(path)?))?;
+    names.path = Some(path.to_path_buf());
+    Ok((names, report))
+}
+
+/// Parses usb.ids data line by line from `reader`,
+/// without reading all of it into memory first.
+///
+/// # Errors
+/// This function will return an error if reading
+/// from `reader` fails, or if the data is not UTF-8.
+pub fn parse_from_reader<R: BufRead>(reader: R) -> io::Result<Names> {
+    parse_from_reader_with_report(reader).map(|(names, _)| names)
+}
+
+/// Like [`parse_from_reader`], but also reports every
+/// line that was ignored or replaced an earlier entry.
+pub fn parse_from_reader_with_report<R: BufRead>(reader: R) -> io::Result<(Names, ParseReport)> {
+    Names::parse_reader(reader)
+}
+
+/// Parses usb.ids data that is already in memory,
+/// such as a copy embedded with `include_str!`.
+pub fn parse_from_str(buf: &str) -> Names {
+    Names::parse(buf).0
+}
+
+/// Like [`parse_from_str`], but also reports every
+/// line that was ignored or replaced an earlier entry.
+pub fn parse_from_str_with_report(buf: &str) -> (Names, ParseReport) {
+    Names::parse(buf)
 }
 
 #[cfg(test)]
@@ -409,4 +1289,314 @@ mod tests {
     fn parse_product_works() {
         parse_product("\t7778  Counterfeit flash drive [Kingston]", 1).unwrap();
     }
+
+    const SAMPLE: &str = "\
+# Sample usb.ids
+0001  Fry's Electronics
+\t7778  Counterfeit flash drive [Kingston]
+1d6b  Linux Foundation
+\t0002  2.0 root hub
+C 09  Hub
+\t00  Unused
+\t\t01  Single TT
+";
+
+    #[test]
+    fn parse_from_str_finds_entries() {
+        let names = parse_from_str(SAMPLE);
+        assert_eq!(names.vendor(0x1d6b), Some("Linux Foundation"));
+        assert_eq!(names.product(0x1d6b, 0x0002), Some("2.0 root hub"));
+        assert_eq!(names.class(0x09), Some("Hub"));
+    }
+
+    #[test]
+    fn lookups_distinguish_nested_keys() {
+        let names = parse_from_str(
+            "\
+C 03  Human Interface Device
+\t00  No Subclass
+\t\t00  None
+\t\t01  Keyboard
+\t01  Boot Interface Subclass
+\t\t01  Keyboard
+\t\t02  Mouse
+C 09  Hub
+\t00  Unused
+\t\t00  Full speed (or root) hub
+\t\t01  Single TT
+",
+        );
+        assert_eq!(names.subclass(0x03, 0x00), Some("No Subclass"));
+        assert_eq!(names.subclass(0x03, 0x01), Some("Boot Interface Subclass"));
+        assert_eq!(names.subclass(0x09, 0x00), Some("Unused"));
+        assert_eq!(names.subclass(0x09, 0x01), None);
+        assert_eq!(names.protocol(0x03, 0x00, 0x01), Some("Keyboard"));
+        assert_eq!(names.protocol(0x03, 0x01, 0x02), Some("Mouse"));
+        assert_eq!(names.protocol(0x03, 0x00, 0x02), None);
+        assert_eq!(
+            names.protocol(0x09, 0x00, 0x00),
+            Some("Full speed (or root) hub")
+        );
+
+        // Every field of a nested key must feed its hash,
+        // or siblings all land in the same bucket.
+        fn distinct_hashes<K: std::hash::Hash, V>(table: &HashMap<K, V>) -> usize {
+            use std::hash::BuildHasher;
+
+            let hashes: std::collections::HashSet<u64> = table
+                .keys()
+                .map(|key| table.hasher().hash_one(key))
+                .collect();
+            hashes.len()
+        }
+        let tables = &names.inner.tables;
+        assert_eq!(distinct_hashes(&tables.subclass), tables.subclass.len());
+        assert_eq!(distinct_hashes(&tables.protocol), tables.protocol.len());
+    }
+
+    #[test]
+    fn parse_descriptor_sections() {
+        let names = parse_from_str(
+            "\
+AT 0101  USB Streaming
+HID 22  Report
+R 08  Usage
+HUT 01  Generic Desktop Controls
+\t002  Mouse
+L 0009  English
+\t01  US
+0a5c  Broadcom Corp.
+",
+        );
+        assert_eq!(names.audio_terminal(0x0101), Some("USB Streaming"));
+        assert_eq!(names.hid_descriptor(0x22), Some("Report"));
+        assert_eq!(names.report_tag(0x08), Some("Usage"));
+        assert_eq!(names.usage_page(0x01), Some("Generic Desktop Controls"));
+        assert_eq!(names.usage(0x01, 0x002), Some("Mouse"));
+        assert_eq!(names.lang(0x0009), Some("English"));
+        assert_eq!(names.dialect(0x0009, 0x01), Some("US"));
+        assert_eq!(names.vendor(0x0a5c), Some("Broadcom Corp."));
+    }
+
+    #[test]
+    fn usage_pages_above_0xff_keep_all_16_bits() {
+        let names = parse_from_str(
+            "\
+HUT 01  Generic Desktop Controls
+\t002  Mouse
+HUT f1d0  FIDO Alliance
+\t001  U2F Authenticator Device
+HUT ff00  Vendor-defined
+",
+        );
+        assert_eq!(names.usage_page(0xf1d0), Some("FIDO Alliance"));
+        assert_eq!(names.usage(0xf1d0, 0x001), Some("U2F Authenticator Device"));
+        assert_eq!(names.usage_page(0xff00), Some("Vendor-defined"));
+        assert_eq!(names.usage_page(0xd0), None);
+        assert_eq!(names.usage(0x01, 0x002), Some("Mouse"));
+    }
+
+    #[test]
+    fn vendor_containing_lang_prefix_is_not_a_lang() {
+        let names = parse_from_str("0bda  REALTEK L SERIES\n\t8153  Ethernet\n");
+        assert_eq!(names.product(0x0bda, 0x8153), Some("Ethernet"));
+    }
+
+    #[test]
+    fn default_paths_include_os_copies() {
+        let paths = default_paths();
+        for candidate in crate::USB_IDS_CANDIDATES {
+            assert!(paths.contains(&PathBuf::from(candidate)));
+        }
+    }
+
+    #[test]
+    fn interface_class_display() {
+        let names = parse_from_str(SAMPLE);
+        let interface = crate::UsbInterface {
+            b_interface_class: 0x09,
+            b_interface_subclass: 0x00,
+            b_interface_protocol: 0x01,
+        };
+        assert_eq!(
+            interface.class_display(&names).to_string(),
+            "Hub / Unused / Single TT (09/00/01)"
+        );
+    }
+
+    #[test]
+    fn read_product_fills_stack_buffers() {
+        use crate::containers::stacktools::StackStr;
+
+        let names = parse_from_str(SAMPLE);
+        let mut buf = StackStr::<128>::new();
+        names.read_product(&mut buf, 0x1d6b, 0x0002).unwrap();
+        assert_eq!(&*buf, "Linux Foundation : 2.0 root hub (1d6b:0002)");
+
+        let mut small = StackStr::<8>::new();
+        assert!(names.read_class(&mut small, 0x09, 0x00, 0x01).is_err());
+    }
+
+    #[test]
+    fn iterates_vendors_and_products() {
+        let names = parse_from_str(SAMPLE);
+        let mut vendors = names.vendors().collect::<Vec<_>>();
+        vendors.sort();
+        assert_eq!(
+            vendors,
+            [(0x0001, "Fry's Electronics"), (0x1d6b, "Linux Foundation")]
+        );
+        assert_eq!(
+            names.products_of(0x1d6b).collect::<Vec<_>>(),
+            [(0x0002, "2.0 root hub")]
+        );
+        assert_eq!(names.products_of(0xffff).count(), 0);
+    }
+
+    #[test]
+    fn merge_prefers_later_sources() {
+        let system = parse_from_str(SAMPLE);
+        let local = parse_from_str("1d6b  Local Foundation\n\t0003  3.0 root hub\n");
+        let names = Names::merge([&system, &local]);
+
+        assert_eq!(names.vendor(0x1d6b), Some("Local Foundation"));
+        assert_eq!(names.product(0x1d6b, 0x0002), Some("2.0 root hub"));
+        assert_eq!(names.product(0x1d6b, 0x0003), Some("3.0 root hub"));
+        assert_eq!(names.class(0x09), Some("Hub"));
+    }
+
+    #[test]
+    fn clones_share_tables() {
+        let names = parse_from_str(SAMPLE);
+        let clone = names.clone();
+        assert!(Arc::ptr_eq(&names.inner, &clone.inner));
+    }
+
+    #[test]
+    fn global_is_initialized_once() {
+        static CELL: OnceLock<Names> = OnceLock::new();
+        let opens = std::sync::atomic::AtomicUsize::new(0);
+        let open = |result: io::Result<Names>| {
+            opens.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            result
+        };
+
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert!(Names::get_or_open(&CELL, || open(Err(missing))).is_err());
+        let first = Names::get_or_open(&CELL, || open(Ok(parse_from_str(SAMPLE)))).unwrap();
+        let second = Names::get_or_open(&CELL, || open(Ok(parse_from_str("")))).unwrap();
+
+        // The failed open wasn't remembered, and the
+        // successful one was never repeated.
+        assert!(core::ptr::eq(first, second));
+        assert_eq!(second.vendor(0x1d6b), Some("Linux Foundation"));
+        assert_eq!(opens.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn report_lists_duplicates_and_unrecognized_lines() {
+        let (names, report) = parse_from_str_with_report(
+            "\
+# Comment
+1d6b  Linux Foundation
+\t0002  2.0 root hub
+\tzzzz  Not hex
+1d6b  Linux Foundation Again
+",
+        );
+        assert_eq!(names.vendor(0x1d6b), Some("Linux Foundation Again"));
+        assert_eq!(
+            report.warnings(),
+            &[
+                ParseWarning {
+                    line: 4,
+                    kind: WarningKind::Unrecognized
+                },
+                ParseWarning {
+                    line: 5,
+                    kind: WarningKind::Duplicate(Section::Vendor)
+                },
+            ]
+        );
+        assert!(parse_from_str_with_report(SAMPLE).1.is_empty());
+    }
+
+    #[test]
+    fn interface_lines_are_not_reported() {
+        let (names, report) = parse_from_str_with_report(
+            "\
+1d6b  Linux Foundation
+\t0002  2.0 root hub
+\t\t00  Hub interface
+\t0003  3.0 root hub
+",
+        );
+        assert_eq!(names.product(0x1d6b, 0x0003), Some("3.0 root hub"));
+        assert!(report.is_empty(), "{:?}", report.warnings());
+    }
+
+    #[test]
+    fn unsupported_sections_hide_their_entries() {
+        let (names, report) = parse_from_str_with_report(
+            "\
+HUT 01  Generic Desktop Controls
+\t001  Pointer
+PHYSDES 00  None
+\t002  Hand
+",
+        );
+        assert_eq!(names.usage(0x01, 0x001), Some("Pointer"));
+        assert_eq!(names.usage(0x01, 0x002), None);
+        assert!(report.is_empty(), "{:?}", report.warnings());
+    }
+
+    #[test]
+    fn describe_formats_like_lsusb() {
+        use std::borrow::Cow;
+
+        let dev = crate::UsbDevice {
+            path: crate::SysPath::new_from_str("/sys/devices/usb1/1-1").unwrap(),
+            busid: crate::BusId::new(Cow::Borrowed(
+                crate::containers::stacktools::Str::new("1-1").unwrap(),
+            )),
+            busnum: 1,
+            devnum: 2,
+            speed: crate::DeviceSpeed::High,
+            id_vendor: 0x1d6b,
+            id_product: 0x0002,
+            bcd_device: 0,
+            b_device_class: 0x09,
+            b_device_subclass: 0x00,
+            b_device_protocol: 0x01,
+            b_configuration_value: 1,
+            b_num_configurations: 1,
+            b_num_interfaces: 1,
+        };
+        let names = parse_from_str(SAMPLE);
+        let description = dev.describe(&names);
+
+        assert_eq!(
+            description.to_string(),
+            "Bus 001 Device 002: ID 1d6b:0002 Linux Foundation 2.0 root hub"
+        );
+        assert_eq!(
+            description.product().to_string(),
+            "Linux Foundation : 2.0 root hub (1d6b:0002)"
+        );
+        assert_eq!(
+            description.class().to_string(),
+            "Hub / Unused / Single TT (09/00/01)"
+        );
+    }
+
+    #[test]
+    fn parse_from_reader_matches_str() {
+        let names = parse_from_reader(io::Cursor::new(SAMPLE.replace('\n', "\r\n"))).unwrap();
+        assert_eq!(names.vendor(0x0001), Some("Fry's Electronics"));
+        assert_eq!(
+            names.product(0x0001, 0x7778),
+            Some("Counterfeit flash drive [Kingston]")
+        );
+        assert_eq!(names.subclass(0x09, 0x00), Some("Unused"));
+    }
 }
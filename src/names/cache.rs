@@ -0,0 +1,338 @@
+//! A compact binary snapshot of a parsed [`Names`]
+//! database, so usb.ids doesn't have to be parsed
+//! on every startup.
+//!
+//! Each cache file records the format version it was
+//! written with and a hash of the usb.ids it came from.
+//! A cache written by a different version of this crate
+//! is rejected by [`Names::load_cache`], and one built
+//! from an outdated usb.ids is rejected by
+//! [`Names::load_cache_if_fresh`].
+
+use core::fmt;
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read},
+    path::Path,
+    sync::Arc,
+};
+
+use super::{Names, NamesInner};
+
+/// Identifies a file as a [`Names`] cache.
+const MAGIC: [u8; 4] = *b"UIDC";
+
+/// Bumped whenever the layout of [`NamesInner`]
+/// or of the cache header changes.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The most a cache may allocate while being decoded,
+/// well above what the full usb.ids needs. This keeps
+/// a corrupt length prefix from exhausting memory.
+const MAX_DECODED_SIZE: usize = 64 << 20;
+
+type Config = bincode::config::Configuration<
+    bincode::config::LittleEndian,
+    bincode::config::Varint,
+    bincode::config::Limit<MAX_DECODED_SIZE>,
+>;
+
+const fn config() -> Config {
+    bincode::config::standard().with_limit::<MAX_DECODED_SIZE>()
+}
+
+#[derive(bincode::Encode, bincode::Decode)]
+struct Header {
+    magic: [u8; 4],
+    version: u32,
+    source_hash: u64,
+}
+
+/// The error type for [`Names::load_cache`]
+/// and [`Names::save_cache`].
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Encode(bincode::error::EncodeError),
+    Decode(bincode::error::DecodeError),
+    /// The file is not a [`Names`] cache.
+    NotACache,
+    /// The cache was written with a different
+    /// [`FORMAT_VERSION`] and has to be rebuilt.
+    Version(u32),
+    /// The cache was built from a different
+    /// usb.ids than the one it was checked against.
+    Stale,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(_) => write!(f, "Cache I/O error"),
+            Error::Encode(_) => write!(f, "Failed to encode cache"),
+            Error::Decode(_) => write!(f, "Failed to decode cache"),
+            Error::NotACache => write!(f, "File is not a usb.ids cache"),
+            Error::Version(found) => write!(
+                f,
+                "Unsupported cache version (expected: {FORMAT_VERSION}, found: {found})"
+            ),
+            Error::Stale => write!(f, "Cache is out of date with its usb.ids"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(io) => Some(io),
+            Error::Encode(enc) => Some(enc),
+            Error::Decode(dec) => Some(dec),
+            Error::NotACache | Error::Version(_) | Error::Stale => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<bincode::error::EncodeError> for Error {
+    fn from(value: bincode::error::EncodeError) -> Self {
+        Self::Encode(value)
+    }
+}
+
+impl From<bincode::error::DecodeError> for Error {
+    fn from(value: bincode::error::DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+/// 64-bit FNV-1a, which unlike [`std::hash::DefaultHasher`]
+/// is stable across Rust releases.
+pub(super) struct SourceHasher(u64);
+
+impl SourceHasher {
+    pub(super) const fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub(super) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(super) const fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hashes the usb.ids file at `path` the same way
+/// the parser does, for comparing against
+/// [`Names::source_hash`].
+pub fn hash_source<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = SourceHasher::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finish()),
+            n => hasher.write(&buf[..n]),
+        }
+    }
+}
+
+impl Names {
+    /// Writes a binary snapshot of `self` to `path`,
+    /// which can be read back with [`Names::load_cache`].
+    ///
+    /// The file is replaced atomically, so readers never
+    /// see a partially written cache.
+    ///
+    /// # Errors
+    /// This function will return an error if the
+    /// cache file couldn't be written.
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let mut writer = BufWriter::new(fs::File::create(&tmp_path)?);
+        let result = (|| {
+            let header = Header {
+                magic: MAGIC,
+                version: FORMAT_VERSION,
+                source_hash: self.source_hash,
+            };
+            bincode::encode_into_std_write(&header, &mut writer, config())?;
+            bincode::encode_into_std_write(&*self.inner, &mut writer, config())?;
+            writer
+                .into_inner()
+                .map_err(|err| err.into_error())?
+                .sync_all()?;
+            fs::rename(&tmp_path, path)?;
+            Ok(())
+        })();
+
+        result.inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })
+    }
+
+    /// Reads a snapshot written by [`Names::save_cache`].
+    ///
+    /// This doesn't check whether the cache is still up to
+    /// date, see [`Names::load_cache_if_fresh`] for that.
+    ///
+    /// # Errors
+    /// This function will return an error if the file
+    /// couldn't be read, isn't a cache, or was written
+    /// with a different [`FORMAT_VERSION`].
+    pub fn load_cache<P: AsRef<Path>>(path: P) -> Result<Names, Error> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        let header: Header =
+            bincode::decode_from_std_read(&mut reader, config()).map_err(|_| Error::NotACache)?;
+        if header.magic != MAGIC {
+            return Err(Error::NotACache);
+        }
+        if header.version != FORMAT_VERSION {
+            return Err(Error::Version(header.version));
+        }
+
+        let inner: NamesInner = bincode::decode_from_std_read(&mut reader, config())?;
+        if !inner.is_valid() {
+            return Err(Error::Decode(bincode::error::DecodeError::Other(
+                "name outside of the string arena",
+            )));
+        }
+        Ok(Names {
+            inner: Arc::new(inner),
+            source_hash: header.source_hash,
+            path: None,
+        })
+    }
+
+    /// Like [`Names::load_cache`], but only accepts
+    /// a cache built from the usb.ids at `source`.
+    ///
+    /// The returned database reports `source` as its [path](Names::path).
+    ///
+    /// # Errors
+    /// This function will return [`Error::Stale`] if
+    /// `source` changed since the cache was written, and
+    /// the errors of [`Names::load_cache`] otherwise.
+    pub fn load_cache_if_fresh<P, S>(path: P, source: S) -> Result<Names, Error>
+    where
+        P: AsRef<Path>,
+        S: AsRef<Path>,
+    {
+        let source = source.as_ref();
+        let source_hash = hash_source(source)?;
+        let mut names = Names::load_cache(path)?;
+        if names.source_hash != source_hash {
+            return Err(Error::Stale);
+        }
+        names.path = Some(source.to_owned());
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("usbip-core-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn cache_round_trips() {
+        let source = "1d6b  Linux Foundation\n\t0002  2.0 root hub\nC 09  Hub\n\t00  Unused\n";
+        let names = super::super::parse_from_str(source);
+        let path = temp_path("round-trip.cache");
+
+        names.save_cache(&path).unwrap();
+        let loaded = Names::load_cache(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded.product(0x1d6b, 0x0002), Some("2.0 root hub"));
+        assert_eq!(loaded.subclass(0x09, 0x00), Some("Unused"));
+        assert_eq!(loaded.source_hash(), names.source_hash());
+    }
+
+    #[test]
+    fn source_hash_matches_file() {
+        let source = "1d6b  Linux Foundation\r\n\t0002  2.0 root hub\n";
+        let path = temp_path("source.ids");
+        fs::write(&path, source).unwrap();
+        let hash = hash_source(&path).unwrap();
+        let parsed = super::super::parse(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(hash, super::super::parse_from_str(source).source_hash());
+        assert_eq!(hash, parsed.source_hash());
+        assert_eq!(parsed.path(), Some(path.as_path()));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let path = temp_path("version.cache");
+        let header = Header {
+            magic: MAGIC,
+            version: FORMAT_VERSION + 1,
+            source_hash: 0,
+        };
+        fs::write(&path, bincode::encode_to_vec(header, config()).unwrap()).unwrap();
+        let result = Names::load_cache(&path);
+        let _ = fs::remove_file(&path);
+
+        assert!(matches!(result, Err(Error::Version(v)) if v == FORMAT_VERSION + 1));
+    }
+
+    #[test]
+    fn stale_caches_are_rejected() {
+        let source_path = temp_path("fresh.ids");
+        let cache_path = temp_path("fresh.cache");
+        fs::write(&source_path, "1d6b  Linux Foundation\n").unwrap();
+        super::super::parse(&source_path)
+            .unwrap()
+            .save_cache(&cache_path)
+            .unwrap();
+
+        let fresh = Names::load_cache_if_fresh(&cache_path, &source_path);
+        fs::write(&source_path, "1d6b  Linux Foundation, Inc.\n").unwrap();
+        let stale = Names::load_cache_if_fresh(&cache_path, &source_path);
+        let _ = fs::remove_file(&source_path);
+        let _ = fs::remove_file(&cache_path);
+
+        let fresh = fresh.unwrap();
+        assert_eq!(fresh.vendor(0x1d6b), Some("Linux Foundation"));
+        assert_eq!(fresh.path(), Some(source_path.as_path()));
+        assert!(matches!(stale, Err(Error::Stale)));
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        let path = temp_path("oversized.cache");
+        let header = Header {
+            magic: MAGIC,
+            version: FORMAT_VERSION,
+            source_hash: 0,
+        };
+        let mut bytes = bincode::encode_to_vec(header, config()).unwrap();
+        // The arena's length prefix, claiming far more than the limit.
+        bytes.extend(bincode::encode_to_vec(u64::MAX, config()).unwrap());
+        fs::write(&path, bytes).unwrap();
+        let result = Names::load_cache(&path);
+        let _ = fs::remove_file(&path);
+
+        assert!(matches!(
+            result,
+            Err(Error::Decode(bincode::error::DecodeError::LimitExceeded))
+        ));
+    }
+}
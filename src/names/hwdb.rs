@@ -0,0 +1,83 @@
+//! Looks up names through the systemd/udev hardware
+//! database instead of parsing usb.ids.
+//!
+//! The hwdb is compiled from the same usb.ids that ships
+//! with the operating system, so names stay in sync with
+//! what other system tools report, and no file path has
+//! to be configured.
+
+use std::io;
+
+/// A handle to the udev hardware database.
+///
+/// Unlike [`Names`](super::Names), every lookup returns
+/// an owned string, since libudev reuses the memory of
+/// a result on the next query.
+pub struct HwdbNames {
+    hwdb: udev::Hwdb,
+}
+
+impl HwdbNames {
+    /// Opens the hardware database.
+    ///
+    /// # Errors
+    /// This function will return an error if
+    /// the database couldn't be loaded.
+    pub fn open() -> io::Result<HwdbNames> {
+        Ok(HwdbNames {
+            hwdb: udev::Hwdb::new()?,
+        })
+    }
+
+    fn query(&self, modalias: &str, property: &str) -> Option<String> {
+        self.hwdb
+            .query_one(modalias, property)
+            .and_then(|value| value.to_str())
+            .map(str::to_owned)
+    }
+
+    pub fn vendor(&self, vendor: u16) -> Option<String> {
+        self.query(&format!("usb:v{vendor:04X}"), "ID_VENDOR_FROM_DATABASE")
+    }
+
+    pub fn product(&self, vendor: u16, product: u16) -> Option<String> {
+        self.query(
+            &format!("usb:v{vendor:04X}p{product:04X}"),
+            "ID_MODEL_FROM_DATABASE",
+        )
+    }
+
+    pub fn class(&self, class: u8) -> Option<String> {
+        self.query(
+            &format!("usb:v0000p0000d0000dc{class:02X}"),
+            "ID_USB_CLASS_FROM_DATABASE",
+        )
+    }
+
+    pub fn subclass(&self, class: u8, subclass: u8) -> Option<String> {
+        self.query(
+            &format!("usb:v0000p0000d0000dc{class:02X}dsc{subclass:02X}"),
+            "ID_USB_SUBCLASS_FROM_DATABASE",
+        )
+    }
+
+    pub fn protocol(&self, class: u8, subclass: u8, protocol: u8) -> Option<String> {
+        self.query(
+            &format!("usb:v0000p0000d0000dc{class:02X}dsc{subclass:02X}dp{protocol:02X}"),
+            "ID_USB_PROTOCOL_FROM_DATABASE",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a compiled udev hwdb, see systemd-hwdb(8)"]
+    fn hwdb_knows_linux_foundation() {
+        let hwdb = HwdbNames::open().unwrap();
+        assert_eq!(hwdb.vendor(0x1d6b).as_deref(), Some("Linux Foundation"));
+        assert_eq!(hwdb.class(0x09).as_deref(), Some("Hub"));
+    }
+}
@@ -0,0 +1,298 @@
+//! A low-memory alternative to [`Names`] that keeps
+//! usb.ids mapped into memory instead of copying
+//! every name onto the heap.
+//!
+//! [`Names`]: super::Names
+
+use std::{fs, io, path::Path};
+
+use super::{Parser, Span, Tables};
+
+/// A usb.ids database that is looked up
+/// directly from the memory-mapped file.
+///
+/// Only an index of offsets into the file is kept on
+/// the heap, so the names themselves stay in the page
+/// cache and are only paged in when they are queried.
+/// This suits long-running daemons that rarely
+/// query names.
+pub struct MappedNames {
+    index: Tables<Span>,
+    source_hash: u64,
+    map: sys::Mapping,
+}
+
+impl MappedNames {
+    /// Maps the usb.ids file at `path` and
+    /// builds an index of its entries.
+    ///
+    /// # Errors
+    /// This function will return an error if the file
+    /// couldn't be opened or mapped, is larger than 4 GiB,
+    /// or is not UTF-8.
+    ///
+    /// # Safety
+    /// The file must not be modified or truncated for as
+    /// long as the returned value is alive. Reading a page
+    /// past the end of a truncated file raises `SIGBUS`,
+    /// and a modified file breaks the guarantee that the
+    /// mapped bytes behind a `&str` never change.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<MappedNames> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        if len > u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "usb.ids is too large to be mapped",
+            ));
+        }
+
+        let map = sys::Mapping::new(&file, len as usize)?;
+        let text = std::str::from_utf8(map.bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let base = text.as_ptr() as usize;
+        let mut parser = Parser::new(|name: &str| Span {
+            start: (name.as_ptr() as usize - base) as u32,
+            len: name.len() as u32,
+        });
+        for line in text.split_inclusive('\n') {
+            parser.raw_line(line);
+        }
+        let (index, source_hash, _) = parser.finish();
+
+        Ok(MappedNames {
+            index,
+            source_hash,
+            map,
+        })
+    }
+
+    fn get(&self, span: Option<&Span>) -> Option<&str> {
+        let span = span?;
+        let start = span.start as usize;
+        let bytes = self.map.bytes().get(start..start + span.len as usize)?;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    /// See [`Names::source_hash`](super::Names::source_hash).
+    pub const fn source_hash(&self) -> u64 {
+        self.source_hash
+    }
+
+    pub fn vendor(&self, vendor: u16) -> Option<&str> {
+        self.get(self.index.vendor(vendor))
+    }
+
+    pub fn product(&self, vendor: u16, product: u16) -> Option<&str> {
+        self.get(self.index.product(vendor, product))
+    }
+
+    pub fn class(&self, class: u8) -> Option<&str> {
+        self.get(self.index.class(class))
+    }
+
+    pub fn subclass(&self, class: u8, subclass: u8) -> Option<&str> {
+        self.get(self.index.subclass(class, subclass))
+    }
+
+    pub fn protocol(&self, class: u8, subclass: u8, protocol: u8) -> Option<&str> {
+        self.get(self.index.protocol(class, subclass, protocol))
+    }
+
+    pub fn audio_terminal(&self, terminal_type: u16) -> Option<&str> {
+        self.get(self.index.audio_terminal(terminal_type))
+    }
+
+    pub fn hid_descriptor(&self, descriptor_type: u8) -> Option<&str> {
+        self.get(self.index.hid_descriptor(descriptor_type))
+    }
+
+    pub fn report_tag(&self, tag: u8) -> Option<&str> {
+        self.get(self.index.report_tag(tag))
+    }
+
+    pub fn usage_page(&self, page: u16) -> Option<&str> {
+        self.get(self.index.usage_page(page))
+    }
+
+    pub fn usage(&self, page: u16, usage: u16) -> Option<&str> {
+        self.get(self.index.usage(page, usage))
+    }
+
+    pub fn lang(&self, lang: u16) -> Option<&str> {
+        self.get(self.index.lang(lang))
+    }
+
+    pub fn dialect(&self, lang: u16, dialect: u8) -> Option<&str> {
+        self.get(self.index.dialect(lang, dialect))
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{fs, io, os::fd::AsRawFd, ptr::NonNull};
+
+    /// A read-only, private mapping of a whole file.
+    pub struct Mapping {
+        ptr: NonNull<libc::c_void>,
+        len: usize,
+    }
+
+    impl Mapping {
+        pub fn new(file: &fs::File, len: usize) -> io::Result<Mapping> {
+            if len == 0 {
+                // mmap rejects empty mappings.
+                return Ok(Mapping {
+                    ptr: NonNull::dangling(),
+                    len,
+                });
+            }
+
+            // SAFETY: The fd is valid for the duration of the call,
+            //         and the mapping stays valid after it is closed.
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(Mapping {
+                // SAFETY: mmap never returns null on success.
+                ptr: unsafe { NonNull::new_unchecked(ptr) },
+                len,
+            })
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            // SAFETY: `ptr` points to `len` readable bytes
+            //         until the mapping is dropped.
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len) }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            if self.len != 0 {
+                // SAFETY: This is the mapping created in `new`.
+                unsafe { libc::munmap(self.ptr.as_ptr(), self.len) };
+            }
+        }
+    }
+
+    // SAFETY: The mapping is read-only, so
+    //         it can be shared across threads.
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{fs, io, os::windows::io::AsRawHandle};
+
+    use windows::Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        System::Memory::{
+            CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_READ,
+            MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READONLY,
+        },
+    };
+
+    /// A read-only view of a whole file.
+    pub struct Mapping {
+        view: MEMORY_MAPPED_VIEW_ADDRESS,
+        len: usize,
+    }
+
+    impl Mapping {
+        pub fn new(file: &fs::File, len: usize) -> io::Result<Mapping> {
+            if len == 0 {
+                // Empty files can't be mapped.
+                return Ok(Mapping {
+                    view: MEMORY_MAPPED_VIEW_ADDRESS {
+                        Value: std::ptr::NonNull::<u8>::dangling().as_ptr().cast(),
+                    },
+                    len,
+                });
+            }
+
+            // SAFETY: The file handle is valid for the duration of the
+            //         call, and the view keeps the mapping object alive
+            //         after its handle is closed.
+            unsafe {
+                let mapping = CreateFileMappingW(
+                    HANDLE(file.as_raw_handle() as isize),
+                    None,
+                    PAGE_READONLY,
+                    0,
+                    0,
+                    None,
+                )?;
+                let view = MapViewOfFile(mapping, FILE_MAP_READ, 0, 0, 0);
+                let _ = CloseHandle(mapping);
+                if view.Value.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+
+                Ok(Mapping { view, len })
+            }
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            // SAFETY: The view is `len` readable bytes
+            //         until the mapping is dropped.
+            unsafe { std::slice::from_raw_parts(self.view.Value.cast(), self.len) }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            if self.len != 0 {
+                // SAFETY: This is the view created in `new`.
+                let _ = unsafe { UnmapViewOfFile(self.view) };
+            }
+        }
+    }
+
+    // SAFETY: The view is read-only, so
+    //         it can be shared across threads.
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_matches_parsed() {
+        let source = "\
+1d6b  Linux Foundation
+\t0002  2.0 root hub
+C 09  Hub
+\t00  Unused
+\t\t01  Single TT
+";
+        let path =
+            std::env::temp_dir().join(format!("usbip-core-{}-mapped.ids", std::process::id()));
+        fs::write(&path, source).unwrap();
+        // SAFETY: Nothing else touches the file while it is mapped.
+        let mapped = unsafe { MappedNames::open(&path) }.unwrap();
+        let _ = fs::remove_file(&path);
+
+        let names = super::super::parse_from_str(source);
+        assert_eq!(mapped.vendor(0x1d6b), names.vendor(0x1d6b));
+        assert_eq!(mapped.product(0x1d6b, 0x0002), Some("2.0 root hub"));
+        assert_eq!(mapped.class(0x09), Some("Hub"));
+        assert_eq!(mapped.vendor(0xffff), None);
+        assert_eq!(mapped.source_hash(), names.source_hash());
+    }
+}
@@ -0,0 +1,124 @@
+//! Downloads the latest usb.ids and keeps a
+//! validated copy in a cache directory.
+//!
+//! [`Names::open_default`] prefers this cached copy
+//! over the one shipped with the operating system.
+//!
+//! [`Names::open_default`]: super::Names::open_default
+
+use core::fmt;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use super::{Names, CACHE_FILE_NAME};
+
+/// Where the usb.ids maintainers publish the latest database.
+pub const DEFAULT_URL: &str = "https://www.linux-usb.org/usb.ids";
+
+/// The error type for [`fetch`].
+#[derive(Debug)]
+pub enum Error {
+    Http(Box<ureq::Error>),
+    Io(io::Error),
+    /// The download parsed, but contained no vendors,
+    /// so it's most likely not a usb.ids file.
+    Invalid,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(_) => write!(f, "Download failed"),
+            Error::Io(_) => write!(f, "Cache I/O error"),
+            Error::Invalid => write!(f, "Downloaded file is not a valid usb.ids"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(http) => Some(&**http),
+            Error::Io(io) => Some(io),
+            Error::Invalid => None,
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(value: ureq::Error) -> Self {
+        Self::Http(Box::new(value))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Downloads usb.ids from `url` and replaces the
+/// cached copy in `cache_dir` with it, returning
+/// the freshly parsed [`Names`].
+///
+/// The download is validated before it is written,
+/// and the cache file is replaced atomically, so
+/// readers never see a partially written file.
+///
+/// # Errors
+/// This function will return an error if the download
+/// fails, the downloaded file isn't a usb.ids database,
+/// or the cache file couldn't be written. The existing
+/// cache file is left untouched in all of these cases.
+pub fn fetch<P: AsRef<Path>>(url: &str, cache_dir: P) -> Result<Names, Error> {
+    let cache_dir = cache_dir.as_ref();
+    let body = ureq::get(url).call()?.body_mut().read_to_string()?;
+
+    let (names, _) = Names::parse(&body);
+    if names.inner.tables.vendor.is_empty() {
+        return Err(Error::Invalid);
+    }
+
+    replace_cache(cache_dir, body.as_bytes())?;
+    Ok(names)
+}
+
+/// Writes `data` to a temporary file in `cache_dir`, then
+/// renames it over the cache file. The temporary file is
+/// removed if either step fails.
+fn replace_cache(cache_dir: &Path, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    let tmp_path = cache_dir.join(format!("{CACHE_FILE_NAME}.tmp"));
+    let written = fs::File::create(&tmp_path)
+        .and_then(|mut tmp| {
+            tmp.write_all(data)?;
+            tmp.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, cache_dir.join(CACHE_FILE_NAME)));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_replace_leaves_no_temporary_file() {
+        let dir = std::env::temp_dir().join(format!("usbip-core-{}-update", std::process::id()));
+        // A directory in the cache file's place can't be renamed over.
+        fs::create_dir_all(dir.join(CACHE_FILE_NAME).join("taken")).unwrap();
+
+        let result = replace_cache(&dir, b"1d6b  Linux Foundation\n");
+        let tmp_exists = dir.join(format!("{CACHE_FILE_NAME}.tmp")).exists();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(result.is_err());
+        assert!(!tmp_exists);
+    }
+}
@@ -0,0 +1,241 @@
+//! An in-memory connection, for running both ends of
+//! the protocol in one process, like in tests.
+//!
+//! [`pair`] returns two connected halves. Whatever one half
+//! writes, the other reads, and dropping a half hangs up:
+//! the other half reads end of file once it has read
+//! everything that was sent, and its writes fail.
+//!
+//! # Examples
+//! ```
+//! use usbip_core::net::{loopback, OpCommon, Protocol, Recv, Send};
+//!
+//! let (mut client, mut server) = loopback::pair();
+//! client.send(&OpCommon::request(Protocol::OP_REQ_DEVLIST)).unwrap();
+//! let req: OpCommon = server.recv().unwrap();
+//! assert_eq!(req.code(), Protocol::OP_REQ_DEVLIST);
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+use super::{bincode_config, Error, Recv, Send};
+use crate::util::__private::Sealed;
+
+/// The bytes sent one way, and whether
+/// either end has hung up.
+#[derive(Debug, Default)]
+struct Pipe {
+    buf: VecDeque<u8>,
+    closed: bool,
+    #[cfg(feature = "tokio")]
+    reader: Option<core::task::Waker>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    pipe: Mutex<Pipe>,
+    readable: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Pipe> {
+        self.pipe.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wakes whoever is waiting to read, after
+    /// bytes were added or the pipe was closed.
+    fn notify(&self, _pipe: &mut Pipe) {
+        self.readable.notify_all();
+        #[cfg(feature = "tokio")]
+        if let Some(waker) = _pipe.reader.take() {
+            waker.wake();
+        }
+    }
+
+    fn close(&self) {
+        let mut pipe = self.lock();
+        pipe.closed = true;
+        self.notify(&mut pipe);
+    }
+}
+
+/// One half of an in-memory connection, see [`pair`].
+///
+/// Reads block until the other half writes or hangs up.
+/// Writes never block.
+#[derive(Debug)]
+pub struct Loopback {
+    incoming: Arc<Shared>,
+    outgoing: Arc<Shared>,
+}
+
+/// Creates two connected halves of an in-memory connection.
+pub fn pair() -> (Loopback, Loopback) {
+    let (a, b) = (Arc::<Shared>::default(), Arc::<Shared>::default());
+    (
+        Loopback {
+            incoming: Arc::clone(&a),
+            outgoing: Arc::clone(&b),
+        },
+        Loopback {
+            incoming: b,
+            outgoing: a,
+        },
+    )
+}
+
+impl Loopback {
+    /// Moves bytes from `pipe` to `buf`.
+    fn take(pipe: &mut Pipe, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
+
+    fn put(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.outgoing.lock();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.buf.extend(buf);
+        self.outgoing.notify(&mut pipe);
+        Ok(buf.len())
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl Read for Loopback {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut pipe = self.incoming.lock();
+        while pipe.buf.is_empty() && !pipe.closed && !buf.is_empty() {
+            pipe = self
+                .incoming
+                .readable
+                .wait(pipe)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        Ok(Self::take(&mut pipe, buf))
+    }
+}
+
+impl Write for Loopback {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.put(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Sealed for Loopback {}
+
+impl Send for Loopback {
+    fn send<T: bincode::Encode>(&mut self, data: &T) -> Result<usize, Error> {
+        let bytes = bincode::encode_to_vec(data, bincode_config()).map_err(Error::Enc)?;
+        self.write_all(&bytes)
+            .map_err(|inner| Error::Enc(bincode::error::EncodeError::Io { inner, index: 0 }))?;
+        Ok(bytes.len())
+    }
+}
+
+impl Recv for Loopback {
+    fn recv<T: bincode::Decode>(&mut self) -> Result<T, Error> {
+        bincode::decode_from_std_read(self, bincode_config()).map_err(Error::De)
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod asynchronous {
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use std::io;
+
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    use super::Loopback;
+
+    impl AsyncRead for Loopback {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let mut pipe = self.incoming.lock();
+            if pipe.buf.is_empty() && !pipe.closed {
+                pipe.reader = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let len = Self::take(&mut pipe, buf.initialize_unfilled());
+            buf.advance(len);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for Loopback {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(self.put(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.outgoing.close();
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{OpCommon, Protocol, Status};
+
+    #[test]
+    fn halves_talk_across_threads() {
+        let (mut client, mut server) = pair();
+        let host = std::thread::spawn(move || {
+            let req: OpCommon = server.recv().unwrap();
+            req.validate(Protocol::OP_UNSPEC).unwrap();
+            let rep = OpCommon::request(Protocol::OP_REP_DEVLIST).reply(Status::NoDev);
+            server.send(&rep).unwrap();
+        });
+
+        client
+            .send(&OpCommon::request(Protocol::OP_REQ_DEVLIST))
+            .unwrap();
+        let rep: OpCommon = client.recv().unwrap();
+        assert_eq!(
+            rep.validate(Protocol::OP_REP_DEVLIST).unwrap(),
+            Status::NoDev
+        );
+        host.join().unwrap();
+
+        // The server hung up.
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+        assert_eq!(
+            client.write(&[0]).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+}
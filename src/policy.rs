@@ -0,0 +1,259 @@
+//! Rules for which devices to attach from which hosts, so that a
+//! daemon can keep, say, a badge reader attached without any code
+//! of its own for picking devices.
+//!
+//! A [`Policy`] is a list of [`Rule`]s. For each device a host
+//! exports, the first rule that matches it decides whether it's
+//! attached, and devices no rule matches are left alone.
+//!
+//! [`Policy::apply`] attaches what the rules call for and isn't
+//! attached yet. Calling it periodically also re-attaches devices
+//! that were dropped, like when their host restarted.
+//!
+//! Rules only pick devices. Every attach uses the driver's own
+//! settings, since rules can't carry attach options yet, and
+//! nothing calls [`Policy::apply`] on its own, like a monitor
+//! reacting to hosts coming back, so that's up to the caller.
+//!
+//! # Examples
+//! ```no_run
+//! use std::{net::SocketAddr, thread, time::Duration};
+//! use usbip_core::{
+//!     policy::{Action, DeviceMatch, Policy, Rule},
+//!     vhci::VhciDriver,
+//! };
+//!
+//! # fn main() -> usbip_core::vhci::Result<()> {
+//! let host = SocketAddr::from(([192, 168, 1, 2], 3240));
+//! let policy = Policy::new(vec![Rule {
+//!     matches: DeviceMatch {
+//!         host: Some(host.ip()),
+//!         vendor: Some(0x076b),
+//!         ..DeviceMatch::default()
+//!     },
+//!     action: Action::Attach,
+//! }]);
+//!
+//! let mut driver = VhciDriver::open()?;
+//! loop {
+//!     for result in policy.apply(&mut driver, host)? {
+//!         if let Err(err) = result {
+//!             eprintln!("{err}");
+//!         }
+//!     }
+//!     thread::sleep(Duration::from_secs(10));
+//! }
+//! # }
+//! ```
+
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{
+    remote::{self, ExportedDevice},
+    vhci::{self, AttachArgs, ImportedDevices, VhciDriver},
+    BusId, Fingerprint, UsbDevice,
+};
+
+/// What a [`Rule`] does with the devices it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Action {
+    Attach,
+    /// Leaves the device alone, even if a
+    /// later rule would attach it.
+    Ignore,
+}
+
+/// Which devices a [`Rule`] applies to. Unset fields match
+/// anything, so the default matches every device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct DeviceMatch {
+    /// The host exporting the device.
+    pub host: Option<IpAddr>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    /// The device's bus id on its host.
+    pub bus_id: Option<BusId<'static>>,
+    /// See [`UsbDevice::fingerprint`].
+    pub fingerprint: Option<Fingerprint>,
+}
+
+impl DeviceMatch {
+    /// Returns `true` if `device`, exported by `host`, matches.
+    pub fn matches(&self, host: IpAddr, device: &UsbDevice) -> bool {
+        self.host.is_none_or(|want| want == host)
+            && self.vendor.is_none_or(|want| want == device.id_vendor())
+            && self.product.is_none_or(|want| want == device.id_product())
+            && self
+                .bus_id
+                .as_ref()
+                .is_none_or(|want| want == device.bus_id())
+            && self
+                .fingerprint
+                .is_none_or(|want| want == device.fingerprint())
+    }
+}
+
+/// Does `action` with the devices `matches` picks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule {
+    #[cfg_attr(feature = "serde", serde(rename = "match", default))]
+    pub matches: DeviceMatch,
+    pub action: Action,
+}
+
+/// An ordered list of [`Rule`]s.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    pub const fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The action of the first rule that matches `device`,
+    /// exported by `host`, if any does.
+    pub fn decide(&self, host: IpAddr, device: &UsbDevice) -> Option<Action> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches.matches(host, device))
+            .map(|rule| rule.action)
+    }
+
+    /// Picks the devices exported by `host` that the rules
+    /// attach, leaving out those in `imported` already.
+    pub fn plan<'a>(
+        &self,
+        host: SocketAddr,
+        exported: &'a [ExportedDevice],
+        imported: &ImportedDevices,
+    ) -> Vec<AttachArgs<'a>> {
+        let attached: Vec<_> = imported
+            .get()
+            .iter()
+            .filter_map(|idev| idev.record())
+            .filter(|record| *record.host() == host)
+            .map(|record| record.bus_id())
+            .collect();
+        self.plan_with(host, exported.iter().map(ExportedDevice::device), &attached)
+    }
+
+    fn plan_with<'a>(
+        &self,
+        host: SocketAddr,
+        exported: impl IntoIterator<Item = &'a UsbDevice>,
+        attached: &[&str],
+    ) -> Vec<AttachArgs<'a>> {
+        exported
+            .into_iter()
+            .filter(|device| self.decide(host.ip(), device) == Some(Action::Attach))
+            .filter(|device| !attached.contains(&device.bus_id()))
+            .filter_map(|device| {
+                Some(AttachArgs {
+                    host,
+                    bus_id: BusId::parse(device.bus_id()).ok()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Lists the devices `host` exports, and attaches those
+    /// that the rules call for and that aren't attached yet.
+    ///
+    /// Returns a result for each device it tried to attach,
+    /// whose errors carry the device's bus id.
+    ///
+    /// On unix, the host is asked with the driver's
+    /// [`socket_options`], like the attaches are.
+    ///
+    /// # Errors
+    /// This function will return an error if the host's devices
+    /// or the attached devices couldn't be listed.
+    ///
+    /// [`socket_options`]: VhciDriver::socket_options
+    pub fn apply(
+        &self,
+        driver: &mut VhciDriver,
+        host: SocketAddr,
+    ) -> vhci::Result<Vec<vhci::Result<u16>>> {
+        #[cfg(unix)]
+        let exported = remote::list_devices_with(host, driver.socket_options())?;
+        #[cfg(not(unix))]
+        let exported = remote::list_devices(host)?;
+        let imported = driver.imported_devices()?;
+        let results = self
+            .plan(host, &exported, &imported)
+            .into_iter()
+            .map(|args| driver.attach(args))
+            .collect();
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(bus_id: &str, vendor: u16) -> UsbDevice {
+        crate::test_device(bus_id)
+            .ids(vendor, 0x0001)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let host: SocketAddr = "10.0.0.2:3240".parse().unwrap();
+        let policy = Policy::new(vec![
+            Rule {
+                matches: DeviceMatch {
+                    bus_id: Some(BusId::parse("1-3").unwrap().into_owned()),
+                    ..DeviceMatch::default()
+                },
+                action: Action::Ignore,
+            },
+            Rule {
+                matches: DeviceMatch {
+                    host: Some(host.ip()),
+                    vendor: Some(0x076b),
+                    ..DeviceMatch::default()
+                },
+                action: Action::Attach,
+            },
+        ]);
+
+        let exported = [
+            device("1-1", 0x076b),
+            device("1-2", 0x076b),
+            device("1-3", 0x076b),
+            device("1-4", 0x046d),
+        ];
+        let plan = policy.plan_with(host, &exported, &["1-2"]);
+        let bus_ids: Vec<_> = plan.iter().map(|args| args.bus_id.as_str()).collect();
+        assert_eq!(bus_ids, ["1-1"]);
+
+        let elsewhere: SocketAddr = "10.0.0.3:3240".parse().unwrap();
+        assert!(policy.plan_with(elsewhere, &exported, &[]).is_empty());
+    }
+}
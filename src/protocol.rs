@@ -0,0 +1,454 @@
+//! The USB/IP [protocol]'s wire format, as defined by the linux
+//! kernel: the PDUs a client and host exchange, and the
+//! [`Protocol`] and [`Status`] codes in their headers.
+//!
+//! Nothing here needs more than `alloc`, so tools that only look
+//! at USB/IP traffic, like packet analyzers, can decode it without
+//! the drivers, libudev or the Windows APIs. The device types the
+//! PDUs carry are in the crate root, and [`StackStr`], which their
+//! strings are stored in, is re-exported here. [`crate::net`]
+//! re-exports all of this, along with reading and writing PDUs
+//! over std I/O.
+//!
+//! # Examples
+//! ```
+//! use usbip_core::protocol::{bincode_config, OpCommon, Protocol};
+//!
+//! // A client asking for the devices a host exports.
+//! let bytes = [0x01, 0x11, 0x80, 0x05, 0x00, 0x00, 0x00, 0x00];
+//! let (req, _): (OpCommon, _) = bincode::decode_from_slice(&bytes, bincode_config()).unwrap();
+//! assert_eq!(req.code(), Protocol::OP_REQ_DEVLIST);
+//! ```
+//!
+//! [protocol]: https://www.kernel.org/doc/html/latest/usb/usbip_protocol.html
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::fmt;
+
+use bincode::{
+    config::{BigEndian, Configuration, Fixint},
+    error::AllowedEnumVariants,
+    impl_borrow_decode,
+};
+
+use crate::{util::EncodedSize, BusId, UsbDevice, USBIP_VERSION};
+
+use bitflags::bitflags;
+
+pub use crate::containers::stacktools::StackStr;
+
+bitflags! {
+    /// The USB/IP protocol.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Protocol: u16 {
+        // Common header for all the kinds of PDUs.
+        const OP_REQUEST = 0x80 << 8;
+        const OP_REPLY = 0x00 << 8;
+
+        // Import a remote USB device.
+        const OP_IMPORT = 0x03;
+        const OP_REQ_IMPORT = Self::OP_REQUEST.bits() | Self::OP_IMPORT.bits();
+        const OP_REP_IMPORT = Self::OP_REPLY.bits() | Self::OP_IMPORT.bits();
+
+        // Dummy code
+        const OP_UNSPEC = 0x00;
+        const _OP_REQ_UNSPEC = Self::OP_UNSPEC.bits();
+        const _OP_REP_UNSPEC = Self::OP_UNSPEC.bits();
+
+        const OP_DEVLIST = 0x05;
+        /// Retrieve the list of exported USB devices.
+        const OP_REQ_DEVLIST = Self::OP_REQUEST.bits() | Self::OP_DEVLIST.bits();
+        /// Reply with the list of exported USB devices.
+        const OP_REP_DEVLIST = Self::OP_REPLY.bits() | Self::OP_DEVLIST.bits();
+
+        // Export a USB device to a remote host
+        const OP_EXPORT = 0x06;
+        const OP_REQ_EXPORT = Self::OP_REQUEST.bits() | Self::OP_EXPORT.bits();
+        const OP_REP_EXPORT = Self::OP_REPLY.bits() | Self::OP_EXPORT.bits();
+    }
+}
+
+impl bincode::Encode for Protocol {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.bits().encode(encoder)
+    }
+}
+
+impl bincode::Decode for Protocol {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        static PROTO_SIMPLE_FLAGS: &'static [u32] = &[
+            Protocol::OP_REQUEST.bits() as u32,
+            Protocol::OP_REPLY.bits() as u32,
+            Protocol::OP_IMPORT.bits() as u32,
+            Protocol::OP_REQ_IMPORT.bits() as u32,
+            Protocol::OP_REP_IMPORT.bits() as u32,
+            Protocol::OP_UNSPEC.bits() as u32,
+            Protocol::_OP_REQ_UNSPEC.bits() as u32,
+            Protocol::_OP_REP_UNSPEC.bits() as u32,
+            Protocol::OP_DEVLIST.bits() as u32,
+            Protocol::OP_REQ_DEVLIST.bits() as u32,
+            Protocol::OP_REP_DEVLIST.bits() as u32,
+            Protocol::OP_EXPORT.bits() as u32,
+            Protocol::OP_REQ_EXPORT.bits() as u32,
+            Protocol::OP_REP_EXPORT.bits() as u32,
+        ];
+
+        static BINCODE_PROTO_ALLOWED_FLAGS: AllowedEnumVariants =
+            AllowedEnumVariants::Allowed(PROTO_SIMPLE_FLAGS);
+
+        let code = u16::decode(decoder)?;
+
+        Self::from_bits(code).ok_or(bincode::error::DecodeError::UnexpectedVariant {
+            type_name: "Protocol",
+            allowed: &BINCODE_PROTO_ALLOWED_FLAGS,
+            found: code as u32,
+        })
+    }
+}
+
+impl_borrow_decode!(Protocol);
+
+unsafe impl EncodedSize for Protocol {
+    const ENCODED_SIZE_OF: usize = core::mem::size_of::<u16>();
+}
+
+/// The result of a USB/IP network request.
+/// Will encode/decode as a 4 byte value.
+#[derive(Debug, Clone, Copy, bincode::Encode, bincode::Decode, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Status {
+    Success = 0x00,
+    Failed = 0x01,
+    DevBusy = 0x02,
+    DevErr = 0x03,
+    NoDev = 0x04,
+    Unexpected = 0x05,
+}
+
+unsafe impl EncodedSize for Status {
+    const ENCODED_SIZE_OF: usize = core::mem::size_of::<u32>();
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Success => write!(f, "Request succeeded"),
+            Status::Failed => write!(f, "Request failed"),
+            Status::DevBusy => write!(f, "Device busy (exported)"),
+            Status::DevErr => write!(f, "Device in error state"),
+            Status::NoDev => write!(f, "Device not found"),
+            Status::Unexpected => write!(f, "Unexpected response"),
+        }
+    }
+}
+
+/// Returns the [`bincode::Configuration`] used
+/// for network communication.
+///
+/// The current config is no limit on transfers, big endian, and fixed int encoding.
+///
+/// [`bincode::Configuration`]: bincode::config::Configuration
+pub const fn bincode_config() -> Configuration<BigEndian, Fixint> {
+    bincode::config::standard()
+        .with_no_limit()
+        .with_big_endian()
+        .with_fixed_int_encoding()
+}
+
+impl From<bincode::error::DecodeError> for Error {
+    fn from(value: bincode::error::DecodeError) -> Self {
+        Self::De(value)
+    }
+}
+
+impl From<crate::ValidationError> for Error {
+    fn from(value: crate::ValidationError) -> Self {
+        Self::InvalidDevice(value)
+    }
+}
+
+impl From<bincode::error::EncodeError> for Error {
+    fn from(value: bincode::error::EncodeError) -> Self {
+        Self::Enc(value)
+    }
+}
+
+/// Represents all the possible userspace errors
+/// that could occur with communicating between
+/// a host and client.
+#[derive(Debug)]
+pub enum Error {
+    VersionMismatch(u16),
+    BusIdMismatch(Cow<'static, str>),
+    /// The host replied with a status other than [`Status::Success`].
+    Rejected(Status),
+    InvalidDevice(crate::ValidationError),
+    Enc(bincode::error::EncodeError),
+    De(bincode::error::DecodeError),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::VersionMismatch(bad_version) => write!(
+                f,
+                "Version mismatch! Them: {}, Us: {}",
+                bad_version, USBIP_VERSION
+            ),
+            Error::BusIdMismatch(bus_id) => write!(f, "Received different busid \"{bus_id}\""),
+            Error::Rejected(status) => write!(f, "Host rejected the request: {status}"),
+            Error::InvalidDevice(_) => write!(f, "Received invalid device"),
+            Error::Enc(_) => write!(f, "Encode error!"),
+            Error::De(_) => write!(f, "Decode error!"),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Error::InvalidDevice(v) => Some(v),
+            // bincode's errors only implement `Error` with std.
+            #[cfg(feature = "std")]
+            Error::Enc(enc) => Some(enc),
+            #[cfg(feature = "std")]
+            Error::De(de) => Some(de),
+            _ => None,
+        }
+    }
+}
+
+/// Serializes as `{ "kind", "message" }`, where `kind` is the
+/// variant name and `message` includes the error's sources.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use alloc::string::ToString;
+        use serde::ser::SerializeStruct;
+
+        let kind = match self {
+            Error::VersionMismatch(_) => "version_mismatch",
+            Error::BusIdMismatch(_) => "bus_id_mismatch",
+            Error::Rejected(_) => "rejected",
+            Error::InvalidDevice(_) => "invalid_device",
+            Error::Enc(_) => "encode",
+            Error::De(_) => "decode",
+        };
+        let mut state = serializer.serialize_struct("Error", 2)?;
+        state.serialize_field("kind", kind)?;
+        state.serialize_field("message", &crate::util::ErrorChain(self).to_string())?;
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, Copy, bincode::Encode, bincode::Decode)]
+pub struct OpCommon {
+    version: u16,
+    code: Protocol,
+    status: Status,
+}
+
+unsafe impl EncodedSize for OpCommon {
+    const ENCODED_SIZE_OF: usize =
+        core::mem::size_of::<u16>() + Protocol::ENCODED_SIZE_OF + Status::ENCODED_SIZE_OF;
+}
+
+impl OpCommon {
+    /// Creates an [`OpCommon`] with
+    /// `code` as the request.
+    ///
+    /// Depending on the [`Protocol`] used,
+    /// the remote device will expect
+    /// more data to be sent.
+    #[inline]
+    pub const fn request(code: Protocol) -> Self {
+        Self {
+            version: USBIP_VERSION as u16,
+            code,
+            status: Status::Success,
+        }
+    }
+
+    /// Consumes an [`OpCommon`] and returns another
+    /// one with the `status` of attempting to fulfill
+    /// the remote device's request.
+    #[inline]
+    pub const fn reply(self, status: Status) -> Self {
+        Self { status, ..self }
+    }
+
+    /// The request or reply this header belongs to.
+    #[inline]
+    pub const fn code(&self) -> Protocol {
+        self.code
+    }
+
+    /// Performs basic validation on the [`OpCommon`] object.
+    ///
+    /// On success, returns the [`Status`] code of the [`OpCommon`].
+    ///
+    /// # Error
+    ///
+    /// This function will return an error if:
+    /// - the version number differs from the version number
+    ///   used in this userspace library
+    /// - the code inside the [`OpCommon`] object does not match
+    ///   `expected`
+    pub fn validate(&self, expected: Protocol) -> Result<Status, Error> {
+        if self.version as usize != USBIP_VERSION {
+            Err(Error::VersionMismatch(self.version))
+        } else if expected != Protocol::OP_UNSPEC && expected != self.code {
+            Ok(Status::Unexpected)
+        } else {
+            Ok(self.status)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OpImportRequest<'a> {
+    bus_id: BusId<'a>,
+}
+
+impl bincode::Encode for OpImportRequest<'_> {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.bus_id.encode(encoder)
+    }
+}
+
+impl bincode::Decode for OpImportRequest<'_> {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        Ok(OpImportRequest {
+            bus_id: BusId::decode(decoder)?,
+        })
+    }
+}
+
+unsafe impl EncodedSize for OpImportRequest<'_> {
+    const ENCODED_SIZE_OF: usize = BusId::ENCODED_SIZE_OF;
+}
+
+impl<'a> OpImportRequest<'a> {
+    /// Constructs a new [`OpImportRequest`]
+    /// for the device at `bus_id`.
+    #[inline(always)]
+    pub const fn new(bus_id: BusId<'a>) -> Self {
+        Self { bus_id }
+    }
+
+    #[inline(always)]
+    pub const fn into_inner(self) -> BusId<'a> {
+        self.bus_id
+    }
+}
+
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+pub struct OpImportReply {
+    usb_dev: UsbDevice,
+}
+
+unsafe impl EncodedSize for OpImportReply {
+    const ENCODED_SIZE_OF: usize = UsbDevice::ENCODED_SIZE_OF;
+}
+
+impl OpImportReply {
+    #[inline(always)]
+    pub const fn new(usb_dev: UsbDevice) -> Self {
+        Self { usb_dev }
+    }
+
+    #[inline(always)]
+    pub const fn into_inner(self) -> UsbDevice {
+        self.usb_dev
+    }
+}
+
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+pub struct OpDevlistReply {
+    num_devices: u32,
+}
+
+unsafe impl EncodedSize for OpDevlistReply {
+    const ENCODED_SIZE_OF: usize = core::mem::size_of::<u32>();
+}
+
+impl OpDevlistReply {
+    #[inline(always)]
+    pub const fn new(num_devices: u32) -> Self {
+        Self { num_devices }
+    }
+
+    #[inline(always)]
+    pub const fn num_devices(&self) -> u32 {
+        self.num_devices
+    }
+}
+
+/// Collects bytes as they arrive until
+/// there's enough of them to decode a PDU.
+///
+/// For non-blocking and event-driven I/O, where
+/// [`Recv`] can't wait for a whole PDU to arrive.
+///
+/// # Examples
+/// ```
+/// use usbip_core::net::{bincode_config, IncrementalDecoder, OpCommon, Protocol};
+///
+/// let req = OpCommon::request(Protocol::OP_REQ_DEVLIST);
+/// let bytes = bincode::encode_to_vec(req, bincode_config()).unwrap();
+///
+/// let mut decoder = IncrementalDecoder::new();
+/// assert!(decoder.feed::<OpCommon>(&bytes[..3]).unwrap().is_none());
+/// assert!(decoder.feed::<OpCommon>(&bytes[3..]).unwrap().is_some());
+/// ```
+#[derive(Debug, Default)]
+pub struct IncrementalDecoder {
+    buf: Vec<u8>,
+}
+
+impl IncrementalDecoder {
+    pub const fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    /// Appends `bytes` to what's been fed so far, then
+    /// tries to decode a `T` from the front of it.
+    ///
+    /// Returns `Ok(None)` if more bytes are needed. Bytes
+    /// past the end of the `T` are kept for the next PDU,
+    /// which can be decoded by feeding an empty slice.
+    ///
+    /// # Errors
+    /// This function will return an error if the bytes
+    /// can't be the start of a `T`. They're left in place.
+    pub fn feed<T: bincode::Decode>(&mut self, bytes: &[u8]) -> Result<Option<T>, Error> {
+        self.buf.extend_from_slice(bytes);
+        match bincode::decode_from_slice(&self.buf, bincode_config()) {
+            Ok((data, len)) => {
+                self.buf.drain(..len);
+                Ok(Some(data))
+            }
+            Err(bincode::error::DecodeError::UnexpectedEnd { .. }) => Ok(None),
+            Err(err) => Err(Error::De(err)),
+        }
+    }
+
+    /// Whether no bytes are waiting to be decoded.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
@@ -0,0 +1,385 @@
+//! Talking to USB/IP hosts outside of the vhci driver,
+//! such as to see which devices they export.
+
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use crate::unix::{SocketOptions, UsbipStream};
+
+use crate::{
+    containers::beef::Beef,
+    net::{
+        bincode_config, Error, OpCommon, OpDevlistReply, OpImportReply, OpImportRequest, Protocol,
+        Recv, Send, Status,
+    },
+    vhci, BusId, UsbDevice, UsbInterface,
+};
+
+/// A device a host exports, from its device list.
+#[derive(Debug, Clone)]
+pub struct ExportedDevice {
+    device: UsbDevice,
+    interfaces: Vec<UsbInterface>,
+}
+
+impl ExportedDevice {
+    pub const fn device(&self) -> &UsbDevice {
+        &self.device
+    }
+
+    pub fn interfaces(&self) -> &[UsbInterface] {
+        &self.interfaces
+    }
+}
+
+/// Asks `host` for the devices it exports.
+///
+/// # Errors
+/// This function will return an error if the host couldn't be
+/// reached, refused the request, or sent an invalid device.
+pub fn list_devices(host: SocketAddr) -> vhci::Result<Vec<ExportedDevice>> {
+    #[cfg(unix)]
+    let devices = list_devices_with(host, &SocketOptions::new());
+    #[cfg(not(unix))]
+    let devices = request_devlist(std::net::TcpStream::connect(host)?);
+    devices
+}
+
+/// Like [`list_devices`], but connects to `host`
+/// with `options`, such as through a proxy.
+///
+/// # Errors
+/// See [`list_devices`].
+#[cfg(unix)]
+pub fn list_devices_with(
+    host: SocketAddr,
+    options: &SocketOptions,
+) -> vhci::Result<Vec<ExportedDevice>> {
+    let socket = UsbipStream::connect_with(&host, options)?;
+    request_devlist(socket.as_tcp())
+}
+
+fn request_devlist(mut socket: impl Read + Write) -> vhci::Result<Vec<ExportedDevice>> {
+    bincode::encode_into_std_write(
+        OpCommon::request(Protocol::OP_REQ_DEVLIST),
+        &mut socket,
+        bincode_config(),
+    )
+    .map_err(Error::Enc)?;
+
+    read_devlist(&mut socket)
+}
+
+/// Asks `host` for the device at `bus_id`, without importing it,
+/// such as to check it before giving it a vhci port.
+///
+/// The device is looked up in the host's device list. An import
+/// would have the host hand the device over until the connection
+/// closed, taking it away from anyone else importing it meanwhile.
+///
+/// # Errors
+/// This function will return an error if the device list couldn't
+/// be had, like [`list_devices`], or [`Error::Rejected`] with
+/// [`Status::NoDev`] if the host doesn't export the device.
+pub fn query_device(host: SocketAddr, bus_id: &BusId) -> vhci::Result<UsbDevice> {
+    find_device(list_devices(host)?, bus_id)
+}
+
+/// Like [`query_device`], but connects to `host` with `options`.
+///
+/// # Errors
+/// See [`query_device`].
+#[cfg(unix)]
+pub fn query_device_with(
+    host: SocketAddr,
+    bus_id: &BusId,
+    options: &SocketOptions,
+) -> vhci::Result<UsbDevice> {
+    find_device(list_devices_with(host, options)?, bus_id)
+}
+
+fn find_device(exported: Vec<ExportedDevice>, bus_id: &BusId) -> vhci::Result<UsbDevice> {
+    exported
+        .into_iter()
+        .map(|exported| exported.device)
+        .find(|device| device.bus_id() == bus_id.as_str())
+        .ok_or_else(|| Error::Rejected(Status::NoDev).into())
+}
+
+/// A host whose device list is kept for a while, so that
+/// polling it, like a UI refreshing every second, doesn't
+/// ask the host each time.
+///
+/// # Examples
+/// ```no_run
+/// use std::time::Duration;
+/// use usbip_core::remote::RemoteHost;
+///
+/// # fn main() -> usbip_core::vhci::Result<()> {
+/// let mut host = RemoteHost::new("10.0.0.2:3240".parse().unwrap())
+///     .with_ttl(Duration::from_secs(10));
+/// for exported in host.devices()? {
+///     println!("{}", exported.device().bus_id());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RemoteHost {
+    addr: SocketAddr,
+    ttl: Duration,
+    #[cfg(unix)]
+    options: SocketOptions,
+    cached: Option<(Instant, Vec<ExportedDevice>)>,
+}
+
+impl RemoteHost {
+    /// How long a device list is kept, unless
+    /// another TTL is set with [`with_ttl`].
+    ///
+    /// [`with_ttl`]: RemoteHost::with_ttl
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(5);
+
+    /// Creates a handle to the host at `addr`,
+    /// without asking it for anything yet.
+    pub const fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            ttl: Self::DEFAULT_TTL,
+            #[cfg(unix)]
+            options: SocketOptions::new(),
+            cached: None,
+        }
+    }
+
+    /// Keeps device lists for `ttl` instead of [`DEFAULT_TTL`].
+    ///
+    /// [`DEFAULT_TTL`]: RemoteHost::DEFAULT_TTL
+    pub const fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Connects to the host with `options`.
+    #[cfg(unix)]
+    pub fn with_socket_options(mut self, options: SocketOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub const fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub const fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// The devices the host exports, from the last list
+    /// if it's younger than the TTL, or asked for again
+    /// otherwise.
+    ///
+    /// # Errors
+    /// This function will return an error if the list had to
+    /// be asked for, and [`list_devices`] failed. The last
+    /// list is kept, and may be used by [`cached`].
+    ///
+    /// [`cached`]: RemoteHost::cached
+    pub fn devices(&mut self) -> vhci::Result<&[ExportedDevice]> {
+        let fresh = self
+            .cached
+            .as_ref()
+            .is_some_and(|(fetched, _)| fetched.elapsed() < self.ttl);
+        if !fresh {
+            self.refresh()?;
+        }
+        Ok(self.cached().unwrap_or_default())
+    }
+
+    /// Asks the host for its devices, however
+    /// young the last list is.
+    ///
+    /// # Errors
+    /// This function will return an error if [`list_devices`]
+    /// failed. The last list is kept.
+    pub fn refresh(&mut self) -> vhci::Result<&[ExportedDevice]> {
+        #[cfg(unix)]
+        let devices = list_devices_with(self.addr, &self.options)?;
+        #[cfg(not(unix))]
+        let devices = list_devices(self.addr)?;
+        let (_, devices) = self.cached.insert((Instant::now(), devices));
+        Ok(devices)
+    }
+
+    /// The last list the host sent, however old it is,
+    /// or `None` if it hasn't been asked yet.
+    pub fn cached(&self) -> Option<&[ExportedDevice]> {
+        self.cached.as_ref().map(|(_, devices)| devices.as_slice())
+    }
+
+    /// Forgets the last list, so the next call
+    /// to [`devices`] asks the host again.
+    ///
+    /// [`devices`]: RemoteHost::devices
+    pub fn invalidate(&mut self) {
+        self.cached = None;
+    }
+}
+
+/// Reads a devlist reply, header and all, from `reader`.
+pub(crate) fn read_devlist<R: Read>(reader: &mut R) -> vhci::Result<Vec<ExportedDevice>> {
+    let config = bincode_config();
+    let rep: OpCommon = bincode::decode_from_std_read(reader, config).map_err(Error::De)?;
+    let status = rep.validate(Protocol::OP_REP_DEVLIST)?;
+    if status != Status::Success {
+        return Err(Error::Rejected(status).into());
+    }
+
+    let rep: OpDevlistReply = bincode::decode_from_std_read(reader, config).map_err(Error::De)?;
+    (0..rep.num_devices())
+        .map(|_| {
+            let device: UsbDevice =
+                bincode::decode_from_std_read(reader, config).map_err(Error::De)?;
+            device.validate().map_err(Error::from)?;
+            let interfaces = (0..device.num_interfaces())
+                .map(|_| bincode::decode_from_std_read(reader, config).map_err(Error::De))
+                .collect::<Result<_, _>>()?;
+            Ok(ExportedDevice { device, interfaces })
+        })
+        .collect()
+}
+
+/// Asks the host on the other end of `socket` for the device
+/// at `bus_id`, and checks the device in its reply.
+#[cfg_attr(windows, allow(dead_code))]
+pub(crate) fn import<S: Send + Recv>(socket: &mut S, bus_id: &BusId) -> vhci::Result<UsbDevice> {
+    // One write for the header and the request, so the
+    // host doesn't get a segment that's only the header.
+    let req = (
+        OpCommon::request(Protocol::OP_REQ_IMPORT),
+        OpImportRequest::new(bus_id.clone()),
+    );
+    socket.send(&req)?;
+
+    let rep: OpCommon = socket.recv()?;
+    let status = rep.validate(Protocol::OP_REP_IMPORT)?;
+    if status != Status::Success {
+        return Err(Error::Rejected(status).into());
+    }
+
+    let rep: OpImportReply = socket.recv()?;
+    let usb_dev = rep.into_inner();
+    if usb_dev.bus_id() != bus_id.as_str() {
+        return Err(Error::BusIdMismatch(Beef::Borrowed(usb_dev.bus_id()).into()).into());
+    }
+    usb_dev.validate().map_err(Error::from)?;
+
+    Ok(usb_dev)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{Ipv4Addr, TcpListener},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::DeviceSpeed;
+
+    /// Listens on a local port, answering each connection with
+    /// a device list of `devices`, and counts the connections.
+    fn host(devices: Vec<UsbDevice>) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let asked = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&asked);
+        std::thread::spawn(move || {
+            for mut socket in listener.incoming().map_while(Result::ok) {
+                let _: OpCommon =
+                    bincode::decode_from_std_read(&mut socket, bincode_config()).unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let rep = (
+                    OpCommon::request(Protocol::OP_REP_DEVLIST).reply(Status::Success),
+                    OpDevlistReply::new(devices.len() as u32),
+                );
+                let mut bytes = bincode::encode_to_vec(rep, bincode_config()).unwrap();
+                for device in &devices {
+                    bytes.extend(bincode::encode_to_vec(device, bincode_config()).unwrap());
+                }
+                socket.write_all(&bytes).unwrap();
+            }
+        });
+        (addr, asked)
+    }
+
+    #[test]
+    fn devices_are_kept_for_the_ttl() {
+        let (addr, asked) = host(Vec::new());
+        let mut host = RemoteHost::new(addr).with_ttl(Duration::from_secs(60));
+        assert!(host.cached().is_none());
+
+        assert!(host.devices().unwrap().is_empty());
+        assert!(host.devices().unwrap().is_empty());
+        assert_eq!(asked.load(Ordering::SeqCst), 1);
+
+        host.refresh().unwrap();
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+        host.invalidate();
+        host.devices().unwrap();
+        assert_eq!(asked.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn stale_devices_are_asked_for_again() {
+        let (addr, asked) = host(Vec::new());
+        let mut host = RemoteHost::new(addr).with_ttl(Duration::ZERO);
+        host.devices().unwrap();
+        host.devices().unwrap();
+        assert_eq!(asked.load(Ordering::SeqCst), 2);
+        assert!(host.cached().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn device_lists_go_through_the_socket_options() {
+        let (addr, asked) = host(Vec::new());
+        let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let proxy = crate::unix::Proxy::socks5(closed.local_addr().unwrap().to_string());
+        drop(closed);
+
+        let options = SocketOptions::new().proxy(proxy);
+        let mut host = RemoteHost::new(addr).with_socket_options(options);
+        let err = host.devices().unwrap_err();
+        assert_eq!(err.kind(), vhci::ErrorKind::NotConnected);
+        assert_eq!(asked.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn query_device_finds_the_bus_id() {
+        let device = |bus_id| {
+            crate::test_device(bus_id)
+                .speed(DeviceSpeed::High)
+                .configuration(1, 1, 0)
+                .build()
+                .unwrap()
+        };
+        let (addr, _) = host(vec![device("1-1"), device("1-2")]);
+
+        let found = query_device(addr, &BusId::parse("1-2").unwrap()).unwrap();
+        assert_eq!(found.bus_id(), "1-2");
+        let err = query_device(addr, &BusId::parse("2-1").unwrap()).unwrap_err();
+        assert!(matches!(
+            err,
+            vhci::Error::Net(Error::Rejected(Status::NoDev))
+        ));
+    }
+}
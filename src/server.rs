@@ -0,0 +1,511 @@
+//! The host side of USB/IP: a listener that answers device
+//! list and import requests from clients, on tokio.
+//!
+//! Which devices are exported, and what happens to a
+//! connection once its device is imported, is up to the
+//! [`Exporter`] the server is given.
+
+use core::{fmt, future::Future};
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::watch,
+    task::JoinSet,
+};
+
+use crate::{
+    events::{Event, EventSink},
+    net::{
+        IncrementalDecoder, OpCommon, OpDevlistReply, OpImportReply, OpImportRequest, Protocol,
+        Status,
+    },
+    util::{recv_pdu, send_pdu},
+    BusId, UsbDevice, UsbInterface,
+};
+
+/// A device as it appears in a device list.
+#[derive(Debug)]
+pub struct ExportedDevice {
+    device: UsbDevice,
+    interfaces: Vec<UsbInterface>,
+}
+
+impl ExportedDevice {
+    pub const fn new(device: UsbDevice, interfaces: Vec<UsbInterface>) -> Self {
+        Self { device, interfaces }
+    }
+
+    pub const fn device(&self) -> &UsbDevice {
+        &self.device
+    }
+
+    pub fn interfaces(&self) -> &[UsbInterface] {
+        &self.interfaces
+    }
+}
+
+/// The devices a [`Server`] exports.
+///
+/// These methods may block, so the server
+/// calls them on tokio's blocking pool.
+pub trait Exporter: Send + Sync + 'static {
+    /// Lists the devices that are available for import.
+    fn devices(&self) -> Vec<ExportedDevice>;
+
+    /// Looks up the device at `bus_id` for a client
+    /// that wants to import it.
+    ///
+    /// # Errors
+    /// Returns the [`Status`] to reply to the client
+    /// with if the device can't be imported.
+    fn find(&self, bus_id: &BusId) -> core::result::Result<UsbDevice, Status>;
+
+    /// Takes over the connection of a client that imported
+    /// `device`, after the client has been told it succeeded.
+    ///
+    /// The socket is in blocking mode.
+    ///
+    /// # Errors
+    /// Returned errors are reported by the connection's task.
+    fn export(&self, device: &UsbDevice, socket: std::net::TcpStream) -> std::io::Result<()>;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Net(crate::net::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(_) => write!(f, "I/O error"),
+            Error::Net(_) => write!(f, "Network error"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Net(err) => Some(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<crate::net::Error> for Error {
+    fn from(value: crate::net::Error) -> Self {
+        Self::Net(value)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A USB/IP server, which serves each client on a task
+/// of its own.
+///
+/// # Examples
+/// ```no_run
+/// # async fn serve(exporter: impl usbip_core::server::Exporter) -> std::io::Result<()> {
+/// use usbip_core::server::Server;
+///
+/// let server = Server::bind("0.0.0.0:3240", exporter).await?;
+/// server.run(std::future::pending()).await
+/// # }
+/// ```
+pub struct Server<E> {
+    listener: TcpListener,
+    exporter: Arc<E>,
+    sink: Option<Arc<dyn EventSink>>,
+}
+
+impl<E: Exporter> Server<E> {
+    /// Binds a server for `exporter`'s devices to `addr`.
+    ///
+    /// # Errors
+    /// This function will return an error if the
+    /// listener couldn't be bound.
+    pub async fn bind(addr: impl ToSocketAddrs, exporter: E) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            exporter: Arc::new(exporter),
+            sink: None,
+        })
+    }
+
+    /// Sends an [`Event`] to `sink` for each
+    /// import the server grants or denies.
+    pub fn event_sink(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts and serves clients until `shutdown` completes.
+    ///
+    /// On shutdown, the server stops accepting, clients that
+    /// are waiting to send a request are disconnected, and
+    /// requests in progress are seen through before this
+    /// function returns.
+    ///
+    /// # Errors
+    /// This function will return an error if accepting
+    /// a connection failed. Errors on a connection only
+    /// end that connection.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> std::io::Result<()> {
+        let (stop, stopping) = watch::channel(false);
+        let mut conns = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        let result = loop {
+            tokio::select! {
+                () = &mut shutdown => break Ok(()),
+                accepted = self.listener.accept() => {
+                    let (socket, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => break Err(err),
+                    };
+                    let exporter = Arc::clone(&self.exporter);
+                    let sink = self.sink.clone();
+                    let stopping = stopping.clone();
+                    conns.spawn(async move {
+                        let result = serve(socket, peer, exporter, sink, stopping).await;
+                        (peer, result)
+                    });
+                }
+                Some(joined) = conns.join_next(), if !conns.is_empty() => report(joined),
+            }
+        };
+
+        drop(self.listener);
+        let _ = stop.send(true);
+        while let Some(joined) = conns.join_next().await {
+            report(joined);
+        }
+        result
+    }
+}
+
+fn report(joined: core::result::Result<(SocketAddr, Result<()>), tokio::task::JoinError>) {
+    match joined {
+        Ok((_peer, Ok(()))) => {}
+        Ok((_peer, Err(_err))) => {
+            #[cfg(feature = "metrics")]
+            if matches!(_err, Error::Net(_)) {
+                crate::stats::protocol_error("server");
+            }
+            crate::util::trace_event!(peer = %_peer, error = %_err, "connection failed");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "Connection from {_peer} failed: {}",
+                crate::util::ErrorChain(&_err)
+            );
+        }
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(_) => {}
+    }
+}
+
+/// Serves requests from one client until it hangs up,
+/// imports a device, or the server shuts down.
+async fn serve<E: Exporter>(
+    mut socket: TcpStream,
+    peer: SocketAddr,
+    exporter: Arc<E>,
+    sink: Option<Arc<dyn EventSink>>,
+    stopping: watch::Receiver<bool>,
+) -> Result<()> {
+    let Some(device) = exchange(&mut socket, peer, &exporter, sink, stopping).await? else {
+        return Ok(());
+    };
+
+    // The exporter may hand the socket to the kernel,
+    // which expects it to be in blocking mode.
+    let socket = socket.into_std()?;
+    socket.set_nonblocking(false)?;
+    blocking(move || exporter.export(&device, socket)).await??;
+    Ok(())
+}
+
+/// Answers requests on `socket` like [`serve`], returning
+/// the device the client imported, if it imported one.
+async fn exchange<S, E>(
+    socket: &mut S,
+    peer: SocketAddr,
+    exporter: &Arc<E>,
+    sink: Option<Arc<dyn EventSink>>,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<Option<UsbDevice>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    E: Exporter,
+{
+    let emit = |event| {
+        if let Some(sink) = &sink {
+            sink.event(&event);
+        }
+    };
+    let mut decoder = IncrementalDecoder::new();
+    loop {
+        let req: OpCommon = tokio::select! {
+            req = recv_pdu::<_, _, Error>(socket, &mut decoder) => match req {
+                Ok(req) => req,
+                // A client hanging up between requests is fine.
+                Err(Error::Io(err))
+                    if decoder.is_empty() && err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            },
+            _ = stopping.wait_for(|&stop| stop) => return Ok(None),
+        };
+
+        req.validate(Protocol::OP_UNSPEC)?;
+        if req.code() == Protocol::OP_REQ_DEVLIST {
+            let exporter = Arc::clone(exporter);
+            let devices = blocking(move || exporter.devices()).await?;
+            send_pdu::<_, _, Error>(socket, &Devlist(&devices)).await?;
+        } else if req.code() == Protocol::OP_REQ_IMPORT {
+            let req: OpImportRequest = recv_pdu::<_, _, Error>(socket, &mut decoder).await?;
+            let bus_id = req.into_inner().into_owned();
+            let (finder, wanted) = (Arc::clone(exporter), bus_id.clone());
+            let device = match blocking(move || finder.find(&wanted)).await? {
+                Ok(device) => device,
+                Err(status) => {
+                    emit(Event::ImportDenied {
+                        peer,
+                        bus_id,
+                        status,
+                    });
+                    let rep = OpCommon::request(Protocol::OP_REP_IMPORT).reply(status);
+                    send_pdu::<_, _, Error>(socket, &rep).await?;
+                    continue;
+                }
+            };
+
+            let rep = (
+                OpCommon::request(Protocol::OP_REP_IMPORT).reply(Status::Success),
+                OpImportReply::new(device),
+            );
+            send_pdu::<_, _, Error>(socket, &rep).await?;
+            emit(Event::ImportGranted { peer, bus_id });
+            return Ok(Some(rep.1.into_inner()));
+        } else {
+            let rep = OpCommon::request(Protocol::OP_UNSPEC).reply(Status::Unexpected);
+            send_pdu::<_, _, Error>(socket, &rep).await?;
+            return Ok(None);
+        }
+    }
+}
+
+/// A device list reply, encoded in one go
+/// so that it's sent in as few segments as possible.
+struct Devlist<'a>(&'a [ExportedDevice]);
+
+impl bincode::Encode for Devlist<'_> {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> core::result::Result<(), bincode::error::EncodeError> {
+        let num_devices = u32::try_from(self.0.len()).unwrap_or(u32::MAX);
+        OpCommon::request(Protocol::OP_REP_DEVLIST)
+            .reply(Status::Success)
+            .encode(encoder)?;
+        OpDevlistReply::new(num_devices).encode(encoder)?;
+        for exported in self.0.iter().take(num_devices as usize) {
+            exported.device().encode(encoder)?;
+            for interface in exported.interfaces() {
+                interface.encode(encoder)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` on tokio's blocking pool,
+/// resuming its panic if it panicked.
+async fn blocking<T, F>(f: F) -> std::io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => Ok(value),
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(std::io::Error::other(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::net::bincode_config;
+
+    struct OneDevice;
+
+    impl Exporter for OneDevice {
+        fn devices(&self) -> Vec<ExportedDevice> {
+            let device = crate::test_device("1-1").build().unwrap();
+            vec![ExportedDevice::new(device, Vec::new())]
+        }
+
+        fn find(&self, _bus_id: &BusId) -> core::result::Result<UsbDevice, Status> {
+            Err(Status::DevBusy)
+        }
+
+        fn export(&self, _: &UsbDevice, _: std::net::TcpStream) -> std::io::Result<()> {
+            unreachable!()
+        }
+    }
+
+    /// Lends its device to whoever asks for it.
+    #[cfg(feature = "client")]
+    struct Lending;
+
+    #[cfg(feature = "client")]
+    impl Exporter for Lending {
+        fn devices(&self) -> Vec<ExportedDevice> {
+            let device = crate::test_device("1-1")
+                .speed(crate::DeviceSpeed::High)
+                .configuration(1, 1, 0)
+                .build()
+                .unwrap();
+            vec![ExportedDevice::new(device, Vec::new())]
+        }
+
+        fn find(&self, bus_id: &BusId) -> core::result::Result<UsbDevice, Status> {
+            self.devices()
+                .into_iter()
+                .map(|exported| exported.device)
+                .find(|device| device.bus_id() == bus_id.as_str())
+                .ok_or(Status::NoDev)
+        }
+
+        fn export(&self, _: &UsbDevice, _: std::net::TcpStream) -> std::io::Result<()> {
+            unreachable!()
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "client")]
+    async fn client_imports_over_loopback() {
+        use crate::net::{loopback, Send as _};
+
+        let (mut client, mut host) = loopback::pair();
+        let client = tokio::task::spawn_blocking(move || {
+            client
+                .send(&OpCommon::request(Protocol::OP_REQ_DEVLIST))
+                .unwrap();
+            let devices = crate::remote::read_devlist(&mut client).unwrap();
+            assert_eq!(devices[0].device().bus_id(), "1-1");
+
+            let missing = BusId::parse("2-1").unwrap();
+            let err = crate::remote::import(&mut client, &missing).unwrap_err();
+            assert_eq!(err.kind(), crate::vhci::ErrorKind::RemoteRejected);
+            crate::remote::import(&mut client, &BusId::parse("1-1").unwrap()).unwrap()
+        });
+
+        let peer = SocketAddr::from(([10, 0, 0, 3], 51234));
+        let (_stop, stopping) = watch::channel(false);
+        let imported = exchange(&mut host, peer, &Arc::new(Lending), None, stopping)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(client.await.unwrap().bus_id(), imported.bus_id());
+    }
+
+    #[tokio::test]
+    async fn serves_a_device_list_until_shut_down() {
+        let server = Server::bind("127.0.0.1:0", OneDevice).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(server.run(async {
+            let _ = stopped.await;
+        }));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let req = bincode::encode_to_vec(
+            OpCommon::request(Protocol::OP_REQ_DEVLIST),
+            bincode_config(),
+        )
+        .unwrap();
+        client.write_all(&req).await.unwrap();
+
+        let mut decoder = IncrementalDecoder::new();
+        let rep: OpCommon = recv_pdu::<_, _, Error>(&mut client, &mut decoder)
+            .await
+            .unwrap();
+        assert_eq!(
+            rep.validate(Protocol::OP_REP_DEVLIST).unwrap(),
+            Status::Success
+        );
+        let rep: OpDevlistReply = recv_pdu::<_, _, Error>(&mut client, &mut decoder)
+            .await
+            .unwrap();
+        assert_eq!(rep.num_devices(), 1);
+        let device: UsbDevice = recv_pdu::<_, _, Error>(&mut client, &mut decoder)
+            .await
+            .unwrap();
+        assert_eq!(device.bus_id(), "1-1");
+
+        // The idle connection is closed on shutdown.
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert_eq!(client.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn denied_imports_are_emitted() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        let server = Server::bind("127.0.0.1:0", OneDevice)
+            .await
+            .unwrap()
+            .event_sink(Arc::new(move |event: &Event| {
+                sink.lock().unwrap().push(event.clone());
+            }));
+        let addr = server.local_addr().unwrap();
+        let server = tokio::spawn(server.run(std::future::pending()));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let bus_id = BusId::parse("1-1").unwrap();
+        let req = (
+            OpCommon::request(Protocol::OP_REQ_IMPORT),
+            OpImportRequest::new(bus_id.clone()),
+        );
+        send_pdu::<_, _, Error>(&mut client, &req).await.unwrap();
+        let rep: OpCommon = recv_pdu::<_, _, Error>(&mut client, &mut IncrementalDecoder::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            rep.validate(Protocol::OP_REP_IMPORT).unwrap(),
+            Status::DevBusy
+        );
+        server.abort();
+
+        let peer = client.local_addr().unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [Event::ImportDenied {
+                peer,
+                bus_id: bus_id.into_owned(),
+                status: Status::DevBusy,
+            }]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+//! Scaffolding to run on the vhci driver as a Windows
+//! service, such as to attach devices at boot.
+//!
+//! [`run`] hands the process to the service control manager.
+//! Once the service is started, the driver is opened and given
+//! to the service's body along with a [`StopSignal`]. When the
+//! service is asked to stop, or the machine shuts down, the
+//! signal fires, and the driver is closed once the body returns.
+//!
+//! # Examples
+//! ```no_run
+//! use std::time::Duration;
+//! use usbip_core::{service, vhci::AttachArgs};
+//!
+//! fn main() -> windows::core::Result<()> {
+//!     service::run("usbip-attach", |driver, stop| {
+//!         let host = "192.168.1.2:3240".parse()?;
+//!         // Retry until the host is up, then wait to be stopped.
+//!         loop {
+//!             let args = AttachArgs { host, bus_id: "1-1".try_into()? };
+//!             match driver.attach_with_cancel(args, stop.token()) {
+//!                 Ok(_) => break,
+//!                 Err(_) if stop.is_stopped() => return Ok(()),
+//!                 Err(err) if !err.is_transient() => return Err(err.into()),
+//!                 Err(_) if stop.wait(Duration::from_secs(5)) => return Ok(()),
+//!                 Err(_) => {}
+//!             }
+//!         }
+//!         stop.wait_forever();
+//!         Ok(())
+//!     })
+//! }
+//! ```
+
+use core::ffi::c_void;
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use windows::{
+    core::{PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{
+            ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_ALREADY_RUNNING,
+            ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR,
+        },
+        System::Services::{
+            RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW,
+            SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP, SERVICE_CONTROL_INTERROGATE,
+            SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_START_PENDING,
+            SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE, SERVICE_STATUS_HANDLE, SERVICE_STOPPED,
+            SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+        },
+    },
+};
+
+use crate::{cancel::CancellationToken, vhci::VhciDriver};
+
+/// The error a service's body can fail with. It's reported
+/// to the service control manager as a service-specific
+/// error, and logged if logging is on.
+pub type BodyError = Box<dyn std::error::Error + Send + Sync>;
+
+/// What a service does with the driver until it's stopped.
+pub type Body = fn(&mut VhciDriver, &StopSignal) -> Result<(), BodyError>;
+
+/// How long the service control manager is told to wait
+/// for the service to start or stop before giving up on it.
+const WAIT_HINT: Duration = Duration::from_secs(30);
+
+/// Fires when the service is asked to stop.
+///
+/// Cloning a [`StopSignal`] creates another
+/// handle to the same signal.
+#[derive(Clone, Default)]
+pub struct StopSignal {
+    token: CancellationToken,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl StopSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fires the signal, cancelling the
+    /// operations that use its token.
+    pub fn stop(&self) {
+        let (stopped, cvar) = &*self.stopped;
+        *stopped.lock().unwrap() = true;
+        cvar.notify_all();
+        self.token.cancel();
+    }
+
+    /// Returns `true` once the signal has fired.
+    pub fn is_stopped(&self) -> bool {
+        *self.stopped.0.lock().unwrap()
+    }
+
+    /// A token that's cancelled when the signal fires, for
+    /// calls like [`VhciDriver::attach_with_cancel`].
+    pub const fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Waits up to `timeout` for the signal,
+    /// returning `true` if it fired.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (stopped, cvar) = &*self.stopped;
+        let guard = stopped.lock().unwrap();
+        let (guard, _) = cvar
+            .wait_timeout_while(guard, timeout, |stopped| !*stopped)
+            .unwrap();
+        *guard
+    }
+
+    /// Waits for the signal.
+    pub fn wait_forever(&self) {
+        let (stopped, cvar) = &*self.stopped;
+        let guard = stopped.lock().unwrap();
+        drop(cvar.wait_while(guard, |stopped| !*stopped).unwrap());
+    }
+}
+
+struct Registered {
+    /// The service's name, NUL-terminated.
+    name: Vec<u16>,
+    body: Body,
+}
+
+static SERVICE: OnceLock<Registered> = OnceLock::new();
+
+/// Runs the service `name`, which must be how it was
+/// installed, blocking until the service is stopped.
+///
+/// This has to be called from the process the service control
+/// manager started, soon after it starts. A process can only
+/// run one service this way.
+///
+/// # Errors
+/// This function will return an error if the process wasn't
+/// started as a service, or if it's already running one.
+pub fn run(name: &str, body: Body) -> windows::core::Result<()> {
+    let registered = Registered {
+        name: name.encode_utf16().chain([0]).collect(),
+        body,
+    };
+    if SERVICE.set(registered).is_err() {
+        return Err(windows::core::Error::new(
+            ERROR_SERVICE_ALREADY_RUNNING.to_hresult(),
+            "a service is already running in this process",
+        ));
+    }
+
+    let name = SERVICE.get().unwrap().name.as_ptr();
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR(name.cast_mut()),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR::null(),
+            lpServiceProc: None,
+        },
+    ];
+    // SAFETY: The table ends with a null entry,
+    // and the name lives in a static.
+    unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }
+}
+
+/// The state shared with the control handler,
+/// which lives for as long as the process.
+struct Control {
+    stop: StopSignal,
+    handle: OnceLock<SERVICE_STATUS_HANDLE>,
+    /// Counts the pending reports since the last
+    /// settled state, so the service control manager
+    /// sees the service making progress.
+    checkpoint: AtomicU32,
+}
+
+impl Control {
+    fn report(&self, state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+        let Some(&handle) = self.handle.get() else {
+            return;
+        };
+        let pending = state == SERVICE_START_PENDING || state == SERVICE_STOP_PENDING;
+        let checkpoint = if pending {
+            self.checkpoint.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.checkpoint.store(0, Ordering::Relaxed);
+            0
+        };
+        let status = SERVICE_STATUS {
+            dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+            dwCurrentState: state,
+            dwControlsAccepted: if state == SERVICE_RUNNING {
+                SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+            } else {
+                0
+            },
+            dwWin32ExitCode: if exit_code == 0 {
+                NO_ERROR.0
+            } else {
+                ERROR_SERVICE_SPECIFIC_ERROR.0
+            },
+            dwServiceSpecificExitCode: exit_code,
+            dwCheckPoint: checkpoint,
+            dwWaitHint: if pending {
+                WAIT_HINT.as_millis() as u32
+            } else {
+                0
+            },
+        };
+        // SAFETY: The handle came from RegisterServiceCtrlHandlerExW.
+        let _ = unsafe { SetServiceStatus(handle, &status) };
+    }
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    let Some(service) = SERVICE.get() else {
+        return;
+    };
+    let control: &'static Control = Box::leak(Box::new(Control {
+        stop: StopSignal::new(),
+        handle: OnceLock::new(),
+        checkpoint: AtomicU32::new(0),
+    }));
+
+    let context: *const Control = control;
+    // SAFETY: The name is NUL-terminated, and the context
+    // is leaked, so it outlives every call to the handler.
+    let handle = match unsafe {
+        RegisterServiceCtrlHandlerExW(
+            PCWSTR(service.name.as_ptr()),
+            Some(control_handler),
+            Some(context.cast::<c_void>()),
+        )
+    } {
+        Ok(handle) => handle,
+        Err(_) => return,
+    };
+    let _ = control.handle.set(handle);
+
+    control.report(SERVICE_START_PENDING, 0);
+    let result = VhciDriver::open()
+        .map_err(BodyError::from)
+        .and_then(|mut driver| {
+            control.report(SERVICE_RUNNING, 0);
+            (service.body)(&mut driver, &control.stop)
+        });
+
+    match result {
+        Ok(()) => control.report(SERVICE_STOPPED, 0),
+        Err(_err) => {
+            crate::util::trace_event!(error = %_err, "service failed");
+            #[cfg(feature = "log")]
+            log::error!("Service failed: {}", crate::util::ErrorChain(&*_err));
+            control.report(SERVICE_STOPPED, 1);
+        }
+    }
+}
+
+unsafe extern "system" fn control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    context: *mut c_void,
+) -> u32 {
+    // SAFETY: The context is the leaked `Control`.
+    let control_state = unsafe { &*context.cast::<Control>() };
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            control_state.report(SERVICE_STOP_PENDING, 0);
+            control_state.stop.stop();
+            NO_ERROR.0
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR.0,
+        _ => ERROR_CALL_NOT_IMPLEMENTED.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_signal_wakes_waiters() {
+        let stop = StopSignal::new();
+        assert!(!stop.wait(Duration::from_millis(10)));
+
+        let waiter = {
+            let stop = stop.clone();
+            std::thread::spawn(move || stop.wait_forever())
+        };
+        stop.stop();
+        waiter.join().unwrap();
+        assert!(stop.is_stopped());
+        assert!(stop.token().is_cancelled());
+    }
+}
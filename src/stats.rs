@@ -0,0 +1,109 @@
+//! The metrics recorded through the [`metrics`] facade.
+//!
+//! Nothing is collected until a recorder is installed,
+//! such as `metrics-exporter-prometheus`.
+//!
+//! | Name | Kind | Labels |
+//! |------|------|--------|
+//! | [`ATTACHES`] | counter | `result`: `ok` or the [`ErrorKind`] |
+//! | [`DETACHES`] | counter | `result`: `ok` or the [`ErrorKind`] |
+//! | [`PROTOCOL_ERRORS`] | counter | `side`: `client` or `server` |
+//! | [`DRIVER_CALL_SECONDS`] | histogram | `op`: the driver call |
+//!
+//! Error kinds are spelled in snake case, like `not_connected`.
+//! On unix, the driver calls are the `attach` and `detach` sysfs
+//! writes. On windows, they're the driver's ioctls, like
+//! `attach` or `get_imported_devices`.
+
+#[cfg(feature = "client")]
+use std::time::Instant;
+
+#[cfg(feature = "client")]
+use crate::vhci::{Error, ErrorKind};
+
+/// Attaches through the vhci driver.
+pub const ATTACHES: &str = "usbip_attaches_total";
+/// Detaches through the vhci driver.
+pub const DETACHES: &str = "usbip_detaches_total";
+/// Hosts or clients that broke the USB/IP protocol.
+pub const PROTOCOL_ERRORS: &str = "usbip_protocol_errors_total";
+/// How long calls into the vhci driver took.
+pub const DRIVER_CALL_SECONDS: &str = "usbip_driver_call_duration_seconds";
+
+#[cfg(feature = "client")]
+fn result_label(err: Option<&Error>) -> &'static str {
+    let Some(err) = err else {
+        return "ok";
+    };
+    match err.kind() {
+        ErrorKind::InvalidInput => "invalid_input",
+        ErrorKind::DriverMissing => "driver_missing",
+        ErrorKind::PermissionDenied => "permission_denied",
+        ErrorKind::RemoteRejected => "remote_rejected",
+        ErrorKind::Busy => "busy",
+        ErrorKind::NotConnected => "not_connected",
+        ErrorKind::Protocol => "protocol",
+        ErrorKind::Cancelled => "cancelled",
+        ErrorKind::TimedOut => "timed_out",
+        ErrorKind::Io => "io",
+    }
+}
+
+#[cfg(feature = "client")]
+pub(crate) fn attach<T>(result: &Result<T, Error>) {
+    let err = result.as_ref().err();
+    metrics::counter!(ATTACHES, "result" => result_label(err)).increment(1);
+    if err.is_some_and(|err| err.kind() == ErrorKind::Protocol) {
+        protocol_error("client");
+    }
+}
+
+#[cfg(feature = "client")]
+pub(crate) fn detach<T>(result: &Result<T, Error>) {
+    let label = result_label(result.as_ref().err());
+    metrics::counter!(DETACHES, "result" => label).increment(1);
+}
+
+#[cfg_attr(not(feature = "client"), allow(dead_code))]
+pub(crate) fn protocol_error(side: &'static str) {
+    metrics::counter!(PROTOCOL_ERRORS, "side" => side).increment(1);
+}
+
+#[cfg(feature = "client")]
+pub(crate) fn driver_call(op: &'static str, started: Instant) {
+    metrics::histogram!(DRIVER_CALL_SECONDS, "op" => op).record(started.elapsed());
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    use super::*;
+
+    #[test]
+    fn attach_results_are_labeled_by_kind() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            attach(&Ok(1));
+            attach::<u16>(&Err(Error::Net(crate::net::Error::VersionMismatch(0))));
+        });
+
+        let counters: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| match value {
+                DebugValue::Counter(count) => {
+                    let key = key.key();
+                    let label = key.labels().next()?.value().to_owned();
+                    Some((key.name().to_owned(), label, count))
+                }
+                _ => None,
+            })
+            .collect();
+        assert!(counters.contains(&(ATTACHES.into(), "ok".into(), 1)));
+        assert!(counters.contains(&(ATTACHES.into(), "protocol".into(), 1)));
+        assert!(counters.contains(&(PROTOCOL_ERRORS.into(), "client".into(), 1)));
+    }
+}
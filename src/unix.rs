@@ -1,5 +1,6 @@
+#[cfg(feature = "udev")]
 mod udev_utils {
-    use std::str::FromStr;
+    use std::{convert::Infallible, fmt, num::ParseIntError, str::FromStr};
 
     use crate::util::__private::Sealed;
 
@@ -7,7 +8,7 @@ mod udev_utils {
         fn sysattr<T>(&self, attr: &str) -> Result<T, Error<T::Err>>
         where
             T: FromStr;
-        fn sysattr_str(&self, attr: &str) -> Result<&str, Error<()>>;
+        fn sysattr_hex(&self, attr: &str) -> Result<u16, Error<ParseIntError>>;
     }
 
     impl Sealed for udev::Device {}
@@ -24,11 +25,13 @@ mod udev_utils {
                 .map_err(Error::CustomErr)
         }
 
-        fn sysattr_str(&self, attr: &str) -> Result<&str, Error<()>> {
-            self.attribute_value(attr)
+        fn sysattr_hex(&self, attr: &str) -> Result<u16, Error<ParseIntError>> {
+            let value = self
+                .attribute_value(attr)
                 .ok_or(Error::AttributeNotFound)?
                 .to_str()
-                .ok_or(Error::NotUtf8)
+                .ok_or(Error::NotUtf8)?;
+            u16::from_str_radix(value.trim(), 16).map_err(Error::CustomErr)
         }
     }
 
@@ -55,8 +58,8 @@ mod udev_utils {
         }
     }
 
-    impl<T: std::error::Error + 'static> Error<T> {
-        pub fn into_dyn(self) -> Error<Box<dyn std::error::Error>> {
+    impl<T: std::error::Error + Send + Sync + 'static> Error<T> {
+        pub fn into_dyn(self) -> Error<Box<dyn std::error::Error + Send + Sync>> {
             match self {
                 Error::AttributeNotFound => Error::AttributeNotFound,
                 Error::NotUtf8 => Error::NotUtf8,
@@ -64,7 +67,29 @@ mod udev_utils {
             }
         }
     }
+
+    impl<T> fmt::Display for Error<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Error::AttributeNotFound => write!(f, "udev attribute not found"),
+                Error::NotUtf8 => write!(f, "udev attribute value not in utf8"),
+                Error::CustomErr(_) => write!(f, "Invalid udev attribute value"),
+            }
+        }
+    }
+
+    impl std::error::Error for Error<Box<dyn std::error::Error + Send + Sync>> {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Error::CustomErr(err) => Some(&**err),
+                _ => None,
+            }
+        }
+    }
+
+    impl std::error::Error for Error<Infallible> {}
 }
+#[cfg(any(feature = "client", feature = "server"))]
 mod sysfs {
     use std::path::Path;
 
@@ -85,7 +110,8 @@ mod sysfs {
 
     impl SysAttr {
         pub fn open(path: &str, attr: &str) -> std::io::Result<Self> {
-            let syspath = StackStr::<PATH_MAX>::try_from(format_args!("{path}/{attr}")).unwrap();
+            let syspath = StackStr::<PATH_MAX>::try_from(format_args!("{path}/{attr}"))
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
             let file = open(&*syspath)?;
             Ok(Self { attr: file })
         }
@@ -101,10 +127,170 @@ mod sysfs {
         }
     }
 }
+/// Non-blocking inotify watches, read whenever it's
+/// convenient instead of on a thread of their own.
+#[cfg(feature = "client")]
+pub(crate) mod inotify {
+    use std::{
+        fs::File,
+        io::{self, Read},
+        ops::ControlFlow,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+    };
+
+    /// Watches `path` for the events in `mask`.
+    pub fn watch(path: &Path, mask: u32) -> io::Result<File> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from(fd))
+    }
+
+    /// Calls `f` with the mask and file name of each event
+    /// queued on `watch`, until there are none left or `f`
+    /// breaks. The name is empty for events on `path` itself.
+    pub fn read_events(
+        watch: &mut File,
+        mut f: impl FnMut(u32, &[u8]) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match watch.read(&mut buf) {
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                // WouldBlock means every event has been read.
+                Err(_) => return ControlFlow::Continue(()),
+            };
+            parse_events(&buf[..n], &mut f)?;
+        }
+    }
+
+    /// Calls `f` with the mask and file name of each
+    /// `struct inotify_event` in `events`, until `f` breaks.
+    pub(super) fn parse_events(
+        mut events: &[u8],
+        mut f: impl FnMut(u32, &[u8]) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        while let Some((header, rest)) = events.split_first_chunk::<16>() {
+            let field = |i: usize| u32::from_ne_bytes(header[i..i + 4].try_into().unwrap());
+            let (mask, len) = (field(4), field(12) as usize);
+            let name = rest.get(..len).unwrap_or(rest);
+            events = rest.get(len..).unwrap_or_default();
+
+            // The name is padded with NULs to an alignment boundary.
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            f(mask, &name[..end])?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Lays out an event like the kernel, with its
+    /// name padded to 16 bytes, for tests.
+    #[cfg(test)]
+    pub fn test_event(mask: u32, name: &str) -> Vec<u8> {
+        let len = if name.is_empty() {
+            0
+        } else {
+            (name.len() / 16 + 1) * 16
+        };
+        let mut event = Vec::new();
+        event.extend_from_slice(&1i32.to_ne_bytes());
+        event.extend_from_slice(&mask.to_ne_bytes());
+        event.extend_from_slice(&0u32.to_ne_bytes());
+        event.extend_from_slice(&(len as u32).to_ne_bytes());
+        event.extend_from_slice(name.as_bytes());
+        event.resize(16 + len, 0);
+        event
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{test_event as event, *};
+
+        fn parse(events: &[u8]) -> Vec<(u32, String)> {
+            let mut parsed = Vec::new();
+            let _ = parse_events(events, |mask, name| {
+                parsed.push((mask, String::from_utf8(name.to_vec()).unwrap()));
+                ControlFlow::Continue(())
+            });
+            parsed
+        }
+
+        #[test]
+        fn events_are_split_and_names_unpadded() {
+            let events = [
+                event(libc::IN_CLOSE_WRITE, "port3"),
+                event(libc::IN_DELETE_SELF, ""),
+                event(libc::IN_CREATE, "a-name-longer-than-16"),
+            ]
+            .concat();
+            assert_eq!(
+                parse(&events),
+                [
+                    (libc::IN_CLOSE_WRITE, "port3".to_owned()),
+                    (libc::IN_DELETE_SELF, String::new()),
+                    (libc::IN_CREATE, "a-name-longer-than-16".to_owned()),
+                ]
+            );
+        }
+
+        #[test]
+        fn truncated_events_are_not_overread() {
+            let mut events = event(libc::IN_CREATE, "port1");
+            events.extend_from_slice(&event(libc::IN_CREATE, "port2")[..20]);
+            assert_eq!(
+                parse(&events),
+                [
+                    (libc::IN_CREATE, "port1".to_owned()),
+                    (libc::IN_CREATE, "port".to_owned()),
+                ]
+            );
+            assert!(parse(&events[..15]).is_empty());
+        }
+
+        #[test]
+        fn parsing_stops_when_told() {
+            let events = [
+                event(libc::IN_CREATE, "port1"),
+                event(libc::IN_CREATE, "port2"),
+            ]
+            .concat();
+            let mut seen = 0;
+            let flow = parse_events(&events, |_, _| {
+                seen += 1;
+                ControlFlow::Break(())
+            });
+            assert!(flow.is_break());
+            assert_eq!(seen, 1);
+        }
+    }
+}
+#[cfg(feature = "client")]
+mod proxy;
+#[cfg(feature = "client")]
 pub mod vhci2;
+#[cfg(feature = "client")]
+pub use net::{ShutdownHandle, SocketOptions, UsbipStream};
+#[cfg(feature = "client")]
+pub use proxy::{ParseProxyError, Proxy, ProxyError};
+#[cfg(feature = "server")]
 pub mod host {
-    use std::path::PathBuf;
+    use core::fmt;
+    use std::{ffi::OsString, path::PathBuf};
 
+    #[cfg(feature = "udev")]
     use crate::unix::udev_utils::UdevExt;
 
     mod sysfs {
@@ -115,7 +301,7 @@ pub mod host {
 
         use super::SYS_PATH;
 
-        use std::io::Write;
+        use std::{ffi::OsStr, io::Write};
 
         pub fn match_busid_add(bus_id: &str) -> std::io::Result<()> {
             let mut sys = SysAttr::open(SYS_PATH, "match_busid")?;
@@ -137,12 +323,14 @@ pub mod host {
             write!(sys, "{bus_id}")
         }
 
-        pub fn unbind_other(udev: &udev::Device, bus_id: &str) -> std::io::Result<()> {
-            if let Some(driver) = udev.driver() {
-                let driver = driver.to_str().expect("turning udev driver name into str");
+        pub fn unbind_other(driver: Option<&OsStr>, bus_id: &str) -> std::io::Result<()> {
+            if let Some(driver) = driver {
+                let driver = driver
+                    .to_str()
+                    .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
                 let syspath =
                     StackStr::<PATH_MAX>::try_from(format_args!("/sys/bus/usb/drivers/{driver}"))
-                        .unwrap();
+                        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
                 let mut sys = SysAttr::open(&*syspath, "unbind")?;
                 write!(sys, "{bus_id}")
             } else {
@@ -159,17 +347,56 @@ pub mod host {
     static DRIVER_NAME: &str = "usbip-host";
     static SYS_PATH: &str = "/sys/bus/usb/drivers/usbip-host";
 
+    #[derive(Debug)]
     pub enum Error {
         BusIdNotFound,
         BindLoop(PathBuf),
         AlreadyBound,
+        NotBound,
         UnbindFailed(Option<std::io::Error>),
         BindFailed(std::io::Error),
     }
 
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Error::BusIdNotFound => write!(f, "Device not found"),
+                Error::BindLoop(path) => {
+                    write!(f, "Device is under {DRIVER_NAME} at {}", path.display())
+                }
+                Error::AlreadyBound => write!(f, "Device is already bound to {DRIVER_NAME}"),
+                Error::NotBound => write!(f, "Device is not bound to {DRIVER_NAME}"),
+                Error::UnbindFailed(_) => write!(f, "Unbinding the device failed"),
+                Error::BindFailed(_) => write!(f, "Binding the device failed"),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Error::UnbindFailed(Some(err)) | Error::BindFailed(err) => Some(err),
+                _ => None,
+            }
+        }
+    }
+
     pub type Result<T> = std::result::Result<T, Error>;
 
+    /// What binding needs to know about a local device.
+    struct DeviceInfo {
+        /// The device's path under `/sys`.
+        devpath: PathBuf,
+        /// The driver the device is bound to, if any.
+        driver: Option<OsString>,
+        /// `bDeviceClass`, if it could be read.
+        class: Option<u16>,
+    }
+
+    /// The `usbip-host` driver, which exports
+    /// this machine's devices.
     pub struct Driver {
+        #[cfg(feature = "udev")]
         context: udev::Udev,
     }
 
@@ -177,97 +404,296 @@ pub mod host {
         #[inline]
         pub fn new() -> std::io::Result<Self> {
             Ok(Self {
+                #[cfg(feature = "udev")]
                 context: udev::Udev::new()?,
             })
         }
 
+        /// Unbinds the device at `bus_id` from its
+        /// driver and binds it to `usbip-host`.
+        ///
+        /// # Errors
+        /// This function will return an error if the device
+        /// doesn't exist, is a hub, or is already bound.
         pub fn bind(&self, bus_id: &str) -> Result<()> {
             // Do verification first
-            let dev = udev::Device::from_subsystem_sysname_with_context(
-                self.context.clone(),
-                "usb".to_owned(),
-                bus_id.to_owned(),
-            )
-            .map_err(|_| Error::BusIdNotFound)?;
+            let dev = self.device(bus_id)?;
 
-            if dev.devpath().to_str().unwrap().contains(DRIVER_NAME) {
-                return Err(Error::BindLoop(PathBuf::from(dev.devpath())));
+            if dev.devpath.to_string_lossy().contains(DRIVER_NAME) {
+                return Err(Error::BindLoop(dev.devpath));
             }
 
             self.unbind_other(bus_id)?;
 
             // Bind away!
             sysfs::match_busid_add(bus_id).map_err(Error::BindFailed)?;
-            sysfs::bind(bus_id).map_err(Error::BindFailed)?;
+            if let Err(err) = sysfs::bind(bus_id) {
+                let _ = sysfs::match_busid_del(bus_id);
+                return Err(Error::BindFailed(err));
+            }
+            Ok(())
+        }
 
-            todo!()
+        /// Unbinds the device at `bus_id` from `usbip-host`
+        /// and hands it back to its usual driver.
+        ///
+        /// # Errors
+        /// This function will return an error if the device
+        /// doesn't exist or isn't bound to `usbip-host`.
+        pub fn unbind(&self, bus_id: &str) -> Result<()> {
+            let dev = self.device(bus_id)?;
+            if dev.driver.as_deref().and_then(|driver| driver.to_str()) != Some(DRIVER_NAME) {
+                return Err(Error::NotBound);
+            }
+
+            sysfs::unbind(bus_id).map_err(|err| Error::UnbindFailed(Some(err)))?;
+            sysfs::match_busid_del(bus_id).map_err(|err| Error::UnbindFailed(Some(err)))?;
+            sysfs::rebind(bus_id).map_err(|err| Error::UnbindFailed(Some(err)))
         }
 
-        fn unbind_other(&self, bus_id: &str) -> Result<()> {
+        #[cfg(feature = "udev")]
+        fn device(&self, bus_id: &str) -> Result<DeviceInfo> {
             let dev = udev::Device::from_subsystem_sysname_with_context(
                 self.context.clone(),
                 "usb".to_owned(),
                 bus_id.to_owned(),
             )
             .map_err(|_| Error::BusIdNotFound)?;
+            Ok(DeviceInfo {
+                devpath: PathBuf::from(dev.devpath()),
+                driver: dev.driver().map(ToOwned::to_owned),
+                class: dev.sysattr_hex("bDeviceClass").ok(),
+            })
+        }
+
+        #[cfg(not(feature = "udev"))]
+        fn device(&self, bus_id: &str) -> Result<DeviceInfo> {
+            use std::{fs, path::Path};
+
+            if bus_id.is_empty() || bus_id.contains('/') || bus_id.starts_with('.') {
+                return Err(Error::BusIdNotFound);
+            }
+            let syspath = fs::canonicalize(Path::new("/sys/bus/usb/devices").join(bus_id))
+                .map_err(|_| Error::BusIdNotFound)?;
+            let driver = fs::read_link(syspath.join("driver"))
+                .ok()
+                .and_then(|link| link.file_name().map(ToOwned::to_owned));
+            let class = fs::read_to_string(syspath.join("bDeviceClass"))
+                .ok()
+                .and_then(|class| u16::from_str_radix(class.trim(), 16).ok());
+            // Like udev's, the devpath leaves out the `/sys`.
+            let devpath = match syspath.strip_prefix("/sys") {
+                Ok(devpath) => Path::new("/").join(devpath),
+                Err(_) => syspath,
+            };
+            Ok(DeviceInfo {
+                devpath,
+                driver,
+                class,
+            })
+        }
 
-            let b_dev_class: u32 = dev.sysattr("bDeviceClass").unwrap();
+        fn unbind_other(&self, bus_id: &str) -> Result<()> {
+            let dev = self.device(bus_id)?;
 
-            if b_dev_class == 9 {
+            // Hubs can't be exported.
+            let b_dev_class = dev.class.ok_or(Error::UnbindFailed(None))?;
+            if b_dev_class == 0x09 {
                 return Err(Error::UnbindFailed(None));
             }
 
-            if let Some(driver) = dev.driver() {
-                if driver.to_str().unwrap() == DRIVER_NAME {
+            if let Some(driver) = &dev.driver {
+                if driver.to_str() == Some(DRIVER_NAME) {
                     return Err(Error::AlreadyBound);
                 }
             }
 
-            sysfs::unbind_other(&dev, &bus_id).map_err(|err| Error::UnbindFailed(Some(err)))
+            sysfs::unbind_other(dev.driver.as_deref(), bus_id)
+                .map_err(|err| Error::UnbindFailed(Some(err)))
         }
     }
 }
+#[cfg(feature = "client")]
 mod net {
     use std::{
         ffi::c_int,
-        net::{SocketAddr, TcpStream},
-        os::fd::{AsFd, AsRawFd},
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream, ToSocketAddrs},
+        os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+        time::{Duration, Instant},
     };
 
     use libc::{c_void, socklen_t};
 
+    use super::Proxy;
     use crate::{
-        net::{bincode_config, Error, Recv},
+        net::{bincode_config, Error, IncrementalDecoder, Recv},
         util::__private::Sealed,
     };
 
-    pub struct UsbipStream(TcpStream);
+    /// A connection to a USB/IP host.
+    ///
+    /// Besides the blocking [`Send`] and [`Recv`], the stream
+    /// can be put in non-blocking mode and driven with
+    /// [`queue`], [`try_flush`] and [`try_recv`], which keep
+    /// partial PDUs around between calls.
+    ///
+    /// [`Send`]: crate::net::Send
+    /// [`queue`]: UsbipStream::queue
+    /// [`try_flush`]: UsbipStream::try_flush
+    /// [`try_recv`]: UsbipStream::try_recv
+    pub struct UsbipStream {
+        inner: TcpStream,
+        outgoing: Vec<u8>,
+        incoming: IncrementalDecoder,
+    }
 
     impl UsbipStream {
         #[inline(always)]
         const fn new(inner: TcpStream) -> Self {
-            Self(inner)
+            Self {
+                inner,
+                outgoing: Vec::new(),
+                incoming: IncrementalDecoder::new(),
+            }
         }
 
         #[inline(always)]
         const fn get(&self) -> &TcpStream {
-            &self.0
+            &self.inner
         }
 
         #[inline(always)]
         fn get_mut(&mut self) -> &mut TcpStream {
-            &mut self.0
+            &mut self.inner
         }
 
         pub fn connect(host: &SocketAddr) -> std::io::Result<Self> {
-            let socket = TcpStream::connect(host)?;
+            Self::connect_with(host, &SocketOptions::new())
+        }
+
+        /// Like [`connect`](Self::connect), but goes through the
+        /// proxy in `options`, if any, and tunes the connection
+        /// with them.
+        pub fn connect_with(host: &SocketAddr, options: &SocketOptions) -> std::io::Result<Self> {
+            let socket = options.connect_tcp(host)?;
             socket.set_nodelay(true)?;
             socket.set_keepalive(true)?;
+            options.apply(&socket)?;
             Ok(Self::new(socket))
         }
 
-        pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
-            self.get().peer_addr()
+        pub const fn as_tcp(&self) -> &TcpStream {
+            self.get()
+        }
+
+        /// Moves the stream in or out of non-blocking mode.
+        ///
+        /// In non-blocking mode, the blocking [`Send`] and [`Recv`]
+        /// fail with [`WouldBlock`] instead of waiting, and may
+        /// leave a PDU half sent or half read. Use the `try_`
+        /// methods instead.
+        ///
+        /// [`Send`]: crate::net::Send
+        /// [`WouldBlock`]: std::io::ErrorKind::WouldBlock
+        pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+            self.get().set_nonblocking(nonblocking)
+        }
+
+        /// Encodes `data` and adds it to the bytes waiting
+        /// to be written by [`try_flush`](Self::try_flush).
+        ///
+        /// # Errors
+        /// This function will return an error if `data` can't be encoded.
+        pub fn queue<T: bincode::Encode>(&mut self, data: &T) -> Result<(), Error> {
+            bincode::encode_into_std_write(data, &mut self.outgoing, bincode_config())
+                .map_err(Error::Enc)?;
+            crate::util::trace_event!(pdu = core::any::type_name::<T>(), "queued");
+            Ok(())
+        }
+
+        /// Writes as much of the queued data as the socket takes.
+        ///
+        /// Returns `true` once everything has been written, and
+        /// `false` if the socket would block first.
+        ///
+        /// # Errors
+        /// This function will return an error if writing failed.
+        pub fn try_flush(&mut self) -> Result<bool, Error> {
+            let write_err = |inner: std::io::Error| {
+                Error::Enc(bincode::error::EncodeError::Io { inner, index: 0 })
+            };
+            while !self.outgoing.is_empty() {
+                match self.inner.write(&self.outgoing) {
+                    Ok(0) => return Err(write_err(std::io::ErrorKind::WriteZero.into())),
+                    Ok(len) => {
+                        self.outgoing.drain(..len);
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(false),
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(write_err(err)),
+                }
+            }
+            Ok(true)
+        }
+
+        /// Reads what the socket has to offer, returning a `T`
+        /// once enough of it has arrived and `None` if the socket
+        /// would block first.
+        ///
+        /// Bytes read past the end of the `T` are kept for the
+        /// next call, or the next blocking [`Recv`].
+        ///
+        /// # Errors
+        /// This function will return an error if reading failed,
+        /// the host hung up, or the bytes can't be decoded.
+        pub fn try_recv<T: bincode::Decode>(&mut self) -> Result<Option<T>, Error> {
+            let data = self.fill()?;
+            if data.is_some() {
+                crate::util::trace_event!(pdu = core::any::type_name::<T>(), "received");
+            }
+            Ok(data)
+        }
+
+        /// Reads into the decoder until it has a `T`, a chunk at a
+        /// time, returning `None` if the socket would block first.
+        fn fill<T: bincode::Decode>(&mut self) -> Result<Option<T>, Error> {
+            let mut chunk = [0; 1024];
+            let mut read = 0;
+            loop {
+                if let Some(data) = self.incoming.feed(&chunk[..read])? {
+                    return Ok(Some(data));
+                }
+                read = match self.inner.read(&mut chunk) {
+                    Ok(0) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+                    Ok(read) => Ok(read),
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => Ok(0),
+                    Err(err) => Err(err),
+                }
+                .map_err(read_err)?;
+            }
+        }
+
+        /// Whether bytes have been read from the socket
+        /// that haven't been decoded yet.
+        pub(crate) fn has_unread(&self) -> bool {
+            !self.incoming.is_empty()
+        }
+
+        /// Returns a handle that can shut down this
+        /// stream from another thread, unblocking any
+        /// pending reads or writes.
+        pub fn shutdown_handle(&self) -> std::io::Result<ShutdownHandle> {
+            Ok(ShutdownHandle(self.get().try_clone()?))
+        }
+    }
+
+    pub struct ShutdownHandle(TcpStream);
+
+    impl ShutdownHandle {
+        pub fn shutdown(&self) {
+            let _ = self.0.shutdown(std::net::Shutdown::Both);
         }
     }
 
@@ -292,13 +718,311 @@ mod net {
 
     impl crate::net::Send for UsbipStream {
         fn send<T: bincode::Encode>(&mut self, data: &T) -> Result<usize, Error> {
-            bincode::encode_into_std_write(data, self, bincode_config()).map_err(Error::Enc)
+            // Encoding straight into the socket would write
+            // each field on its own, so stage it instead.
+            let bytes = bincode::encode_to_vec(data, bincode_config()).map_err(Error::Enc)?;
+            self.write_all(&bytes)
+                .map_err(|inner| Error::Enc(bincode::error::EncodeError::Io { inner, index: 0 }))?;
+            let len = bytes.len();
+            crate::util::trace_event!(pdu = core::any::type_name::<T>(), len, "sent");
+            Ok(len)
         }
     }
 
     impl Recv for UsbipStream {
         fn recv<T: bincode::Decode>(&mut self) -> Result<T, Error> {
-            bincode::decode_from_std_read(self, bincode_config()).map_err(Error::De)
+            // Decoding straight from the socket would read
+            // each field on its own, so buffer it instead.
+            let data = self
+                .fill()?
+                .ok_or_else(|| read_err(std::io::ErrorKind::WouldBlock.into()))?;
+            crate::util::trace_event!(pdu = core::any::type_name::<T>(), "received");
+            Ok(data)
+        }
+    }
+
+    fn read_err(inner: std::io::Error) -> Error {
+        Error::De(bincode::error::DecodeError::Io {
+            inner,
+            additional: 0,
+        })
+    }
+
+    /// Kernel socket settings for connections to hosts,
+    /// for links where the defaults hold back bulk transfers,
+    /// and the [`Proxy`] to reach them through.
+    ///
+    /// Unset options are left at the system's defaults.
+    ///
+    /// # Examples
+    /// ```
+    /// use usbip_core::unix::SocketOptions;
+    ///
+    /// let options = SocketOptions::new()
+    ///     .send_buffer(4 << 20)
+    ///     .recv_buffer(4 << 20)
+    ///     .quickack(true);
+    /// ```
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct SocketOptions {
+        send_buffer: Option<usize>,
+        recv_buffer: Option<usize>,
+        quickack: bool,
+        connect_timeout: Option<Duration>,
+        proxy: Option<Proxy>,
+    }
+
+    impl SocketOptions {
+        pub const fn new() -> Self {
+            Self {
+                send_buffer: None,
+                recv_buffer: None,
+                quickack: false,
+                connect_timeout: None,
+                proxy: None,
+            }
+        }
+
+        /// Sets `SO_SNDBUF`. The kernel doubles the
+        /// value and caps it at `net.core.wmem_max`.
+        pub const fn send_buffer(mut self, bytes: usize) -> Self {
+            self.send_buffer = Some(bytes);
+            self
+        }
+
+        /// Sets `SO_RCVBUF`. The kernel doubles the
+        /// value and caps it at `net.core.rmem_max`.
+        pub const fn recv_buffer(mut self, bytes: usize) -> Self {
+            self.recv_buffer = Some(bytes);
+            self
+        }
+
+        /// Sets `TCP_QUICKACK`, so that acknowledgements
+        /// aren't delayed. The kernel may go back to
+        /// delaying them later on its own.
+        pub const fn quickack(mut self, quickack: bool) -> Self {
+            self.quickack = quickack;
+            self
+        }
+
+        /// Gives up on connecting after `timeout`, instead of
+        /// waiting for the system's TCP timeout. With a proxy,
+        /// this bounds connecting to the proxy.
+        pub const fn connect_timeout(mut self, timeout: Duration) -> Self {
+            self.connect_timeout = Some(timeout);
+            self
+        }
+
+        /// Like [`connect_timeout`](Self::connect_timeout),
+        /// but `None` goes back to the system's TCP timeout.
+        #[cfg(feature = "config")]
+        pub(crate) fn set_connect_timeout(&mut self, timeout: Option<Duration>) {
+            self.connect_timeout = timeout;
+        }
+
+        /// Tunnels connections through `proxy`.
+        pub fn proxy(mut self, proxy: Proxy) -> Self {
+            self.proxy = Some(proxy);
+            self
+        }
+
+        /// Returns `true` if connecting takes more than a plain
+        /// `connect`, which has no async version here.
+        #[cfg(feature = "tokio")]
+        pub(crate) const fn connects_blocking(&self) -> bool {
+            self.proxy.is_some() || self.connect_timeout.is_some()
+        }
+
+        /// Connects to `host`, through the proxy if there is one,
+        /// with the buffer sizes set before connecting.
+        pub(crate) fn connect_tcp(&self, host: &SocketAddr) -> std::io::Result<TcpStream> {
+            match &self.proxy {
+                Some(proxy) => proxy.connect_within(host, self),
+                None => tcp_connect(host, self),
+            }
+        }
+
+        /// The buffer sizes, clamped to what `setsockopt` takes.
+        pub(crate) fn buffer_sizes(&self) -> (Option<c_int>, Option<c_int>) {
+            let clamp = |bytes: usize| c_int::try_from(bytes).unwrap_or(c_int::MAX);
+            (self.send_buffer.map(clamp), self.recv_buffer.map(clamp))
+        }
+
+        /// Sets the buffer sizes on a socket that isn't
+        /// connected yet. The TCP window scale is picked
+        /// during the handshake, so a receive buffer set
+        /// after it can't grow the window past 64 KiB.
+        fn apply_buffers(&self, socket: BorrowedFd) -> std::io::Result<()> {
+            let (send, recv) = self.buffer_sizes();
+            if let Some(bytes) = send {
+                setsockopt(socket, libc::SOL_SOCKET, libc::SO_SNDBUF, bytes)?;
+            }
+            if let Some(bytes) = recv {
+                setsockopt(socket, libc::SOL_SOCKET, libc::SO_RCVBUF, bytes)?;
+            }
+            Ok(())
+        }
+
+        /// Applies the options that are set
+        /// once the socket is connected.
+        pub(crate) fn apply(&self, socket: &impl AsFd) -> std::io::Result<()> {
+            if self.quickack {
+                setsockopt(socket.as_fd(), libc::IPPROTO_TCP, libc::TCP_QUICKACK, 1)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Connects to the first of `addr`'s addresses that accepts,
+    /// waiting up to `options`' connect timeout for each if there
+    /// is one, and sizing the buffers before connecting.
+    pub(crate) fn tcp_connect(
+        addr: impl ToSocketAddrs,
+        options: &SocketOptions,
+    ) -> std::io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match connect_one(&addr, options) {
+                Ok(socket) => return Ok(socket),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "could not resolve to any addresses",
+            )
+        }))
+    }
+
+    fn connect_one(addr: &SocketAddr, options: &SocketOptions) -> std::io::Result<TcpStream> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let flags = libc::SOCK_STREAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK;
+        // SAFETY: No pointers are involved.
+        let fd = unsafe { libc::socket(domain, flags, 0) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just opened, and nothing else owns it.
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        options.apply_buffers(socket.as_fd())?;
+
+        let (addr, len) = sockaddr(addr);
+        // SAFETY: `addr` holds a socket address `len` bytes long.
+        let rc = unsafe { libc::connect(fd, core::ptr::addr_of!(addr).cast(), len) };
+        if rc < 0 {
+            let err = std::io::Error::last_os_error();
+            if !matches!(err.raw_os_error(), Some(libc::EINPROGRESS | libc::EINTR)) {
+                return Err(err);
+            }
+            wait_connected(socket.as_fd(), options.connect_timeout)?;
+        }
+
+        let socket = TcpStream::from(socket);
+        socket.set_nonblocking(false)?;
+        Ok(socket)
+    }
+
+    /// Waits for a non-blocking `connect` on `socket` to finish.
+    fn wait_connected(socket: BorrowedFd, timeout: Option<Duration>) -> std::io::Result<()> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut pollfd = libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        loop {
+            let timeout_ms = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Err(std::io::ErrorKind::TimedOut.into());
+                    }
+                    // Round up, so that polling doesn't return early.
+                    c_int::try_from(left.as_nanos().div_ceil(1_000_000)).unwrap_or(c_int::MAX)
+                }
+                None => -1,
+            };
+            // SAFETY: `pollfd` is a single valid `pollfd`.
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                -1 => {
+                    let err = std::io::Error::last_os_error();
+                    if err.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                0 => {}
+                _ => break,
+            }
+        }
+
+        let mut err: c_int = 0;
+        let mut len = socklen_t::try_from(core::mem::size_of::<c_int>()).unwrap();
+        // SAFETY: `err` is a `c_int`, and `len` is its size.
+        let rc = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ERROR,
+                core::ptr::addr_of_mut!(err).cast::<c_void>(),
+                &mut len,
+            )
+        };
+        if rc < 0 {
+            Err(std::io::Error::last_os_error())
+        } else if err != 0 {
+            Err(std::io::Error::from_raw_os_error(err))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, socklen_t) {
+        // SAFETY: All zeroes is a valid `sockaddr_storage`.
+        let mut storage: libc::sockaddr_storage = unsafe { core::mem::zeroed() };
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: `sockaddr_storage` is big enough
+                //         and aligned for any socket address.
+                let sin =
+                    unsafe { &mut *core::ptr::addr_of_mut!(storage).cast::<libc::sockaddr_in>() };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                core::mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: Same as above.
+                let sin6 =
+                    unsafe { &mut *core::ptr::addr_of_mut!(storage).cast::<libc::sockaddr_in6>() };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+                core::mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, socklen_t::try_from(len).unwrap())
+    }
+
+    fn setsockopt(fd: BorrowedFd, level: c_int, name: c_int, val: c_int) -> std::io::Result<()> {
+        let rc = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                level,
+                name,
+                core::ptr::addr_of!(val).cast::<c_void>(),
+                socklen_t::try_from(core::mem::size_of::<c_int>()).unwrap(),
+            )
+        };
+        if rc < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
         }
     }
 
@@ -311,21 +1035,12 @@ mod net {
 
     impl TcpStreamExt for TcpStream {
         fn set_keepalive(&self, keepalive: bool) -> std::io::Result<()> {
-            let val = c_int::from(keepalive);
-            let rc = unsafe {
-                libc::setsockopt(
-                    self.as_raw_fd(),
-                    libc::SOL_SOCKET,
-                    libc::SO_KEEPALIVE,
-                    core::ptr::addr_of!(val).cast::<c_void>(),
-                    socklen_t::try_from(core::mem::size_of::<c_int>()).unwrap(),
-                )
-            };
-            if rc < 0 {
-                Err(std::io::Error::last_os_error())
-            } else {
-                Ok(())
-            }
+            setsockopt(
+                self.as_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_KEEPALIVE,
+                c_int::from(keepalive),
+            )
         }
     }
 
@@ -336,15 +1051,29 @@ mod net {
     }
 }
 
+#[cfg(feature = "udev")]
+use crate::{unix::udev_utils::UdevExt, UsbEndpoint};
 use crate::{
     containers::stacktools::{self, StackStr},
-    unix::udev_utils::UdevExt,
-    DeviceSpeed, BUS_ID_SIZE, DEV_PATH_MAX, SysPath, BusId,
+    DeviceSpeed, DEV_PATH_MAX, SysPath, BusId,
 };
-use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path, borrow::Cow};
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path, borrow::Cow, fmt};
 
 pub static USB_IDS: &str = "/usr/share/hwdata/usb.ids";
 
+/// Where distributions commonly install usb.ids,
+/// in the order [`Names::open_default`] tries them.
+///
+/// [`Names::open_default`]: crate::names::Names::open_default
+pub static USB_IDS_CANDIDATES: &[&str] = &[
+    USB_IDS,
+    "/usr/share/misc/usb.ids",
+    "/usr/share/usb.ids",
+    "/var/lib/usbutils/usb.ids",
+    "/usr/local/share/hwdata/usb.ids",
+    "/usr/local/share/usb.ids",
+];
+
 impl<const N: usize> TryFrom<&OsStr> for StackStr<N> {
     type Error = stacktools::TryFromStrErr;
 
@@ -363,17 +1092,20 @@ impl<const N: usize> TryFrom<&Path> for StackStr<N> {
     }
 }
 
+#[cfg(feature = "udev")]
 impl TryFrom<udev::Device> for crate::UsbDevice {
-    type Error = udev_utils::Error<Box<dyn std::error::Error>>;
+    type Error = udev_utils::Error<Box<dyn std::error::Error + Send + Sync>>;
 
     fn try_from(udev: udev::Device) -> Result<Self, Self::Error> {
         let path: StackStr<{ DEV_PATH_MAX - 1 }> = udev
             .syspath()
             .try_into()
             .map_err(|err| udev_utils::Error::CustomErr(err).into_dyn())?;
-        let busid: StackStr<{ BUS_ID_SIZE - 1 }> = udev
+        let busid: BusId<'static> = udev
             .sysname()
-            .try_into()
+            .to_str()
+            .ok_or(udev_utils::Error::NotUtf8)?
+            .parse()
             .map_err(|err| udev_utils::Error::CustomErr(err).into_dyn())?;
         let id_vendor: u16 = udev.sysattr("idVendor").map_err(|err| err.into_dyn())?;
         let id_product: u16 = udev.sysattr("idProduct").map_err(|err| err.into_dyn())?;
@@ -396,7 +1128,7 @@ impl TryFrom<udev::Device> for crate::UsbDevice {
 
         Ok(Self {
             path: SysPath::new(Cow::Owned(path)),
-            busid: BusId::new(Cow::Owned(busid)),
+            busid,
             id_vendor,
             id_product,
             busnum,
@@ -412,3 +1144,337 @@ impl TryFrom<udev::Device> for crate::UsbDevice {
         })
     }
 }
+
+#[derive(Debug)]
+pub enum FromSysPathError {
+    /// The device's path didn't fit in a [`SysPath`].
+    Path(stacktools::TryFromStrErr),
+    BusId(crate::ParseBusIdError),
+    Io {
+        attr: &'static str,
+        err: std::io::Error,
+    },
+    Parse {
+        attr: &'static str,
+        err: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+impl fmt::Display for FromSysPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromSysPathError::Path(_) => write!(f, "Invalid sys path"),
+            FromSysPathError::BusId(_) => write!(f, "Invalid bus id"),
+            FromSysPathError::Io { attr, .. } => write!(f, "Failed to read \"{attr}\""),
+            FromSysPathError::Parse { attr, .. } => write!(f, "Failed to parse \"{attr}\""),
+        }
+    }
+}
+
+impl std::error::Error for FromSysPathError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FromSysPathError::Path(p) => Some(p),
+            FromSysPathError::BusId(b) => Some(b),
+            FromSysPathError::Io { err, .. } => Some(err),
+            FromSysPathError::Parse { err, .. } => Some(&**err),
+        }
+    }
+}
+
+impl crate::UsbDevice {
+    /// Reads a device straight from its sysfs directory,
+    /// e.g. `/sys/bus/usb/devices/1-1`, without going
+    /// through udev.
+    ///
+    /// Useful in containers and chroots where `/sys` is
+    /// mounted but libudev can't enumerate devices.
+    ///
+    /// # Errors
+    /// This function will return an error if the path
+    /// doesn't name a USB device, or if one of its
+    /// attribute files is missing or malformed.
+    ///
+    /// # Platform-specific behavior
+    /// Only available on Linux, which is the only
+    /// platform with a sysfs.
+    pub fn from_syspath<P: AsRef<Path>>(path: P) -> Result<Self, FromSysPathError> {
+        let dir = std::fs::canonicalize(path.as_ref()).map_err(|err| FromSysPathError::Io {
+            attr: ".",
+            err,
+        })?;
+        let path: StackStr<{ DEV_PATH_MAX - 1 }> =
+            dir.as_path().try_into().map_err(FromSysPathError::Path)?;
+        let busid: BusId<'static> = dir
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .parse()
+            .map_err(FromSysPathError::BusId)?;
+
+        let read = |attr: &'static str| -> Result<String, FromSysPathError> {
+            std::fs::read_to_string(dir.join(attr))
+                .map(|value| value.trim().to_owned())
+                .map_err(|err| FromSysPathError::Io { attr, err })
+        };
+        let dec = |attr: &'static str| -> Result<u32, FromSysPathError> {
+            read(attr)?.parse().map_err(|err| FromSysPathError::Parse {
+                attr,
+                err: crate::util::into_dyn_err(err),
+            })
+        };
+        let hex = |attr: &'static str| -> Result<u16, FromSysPathError> {
+            u16::from_str_radix(&read(attr)?, 16).map_err(|err| FromSysPathError::Parse {
+                attr,
+                err: crate::util::into_dyn_err(err),
+            })
+        };
+        let hex_byte = |attr: &'static str| -> Result<u8, FromSysPathError> {
+            u8::try_from(hex(attr)?).map_err(|err| FromSysPathError::Parse {
+                attr,
+                err: crate::util::into_dyn_err(err),
+            })
+        };
+
+        let speed: DeviceSpeed = read("speed")?.parse().map_err(|err| FromSysPathError::Parse {
+            attr: "speed",
+            err: crate::util::into_dyn_err(err),
+        })?;
+
+        Ok(Self {
+            path: SysPath::new(Cow::Owned(path)),
+            busid,
+            busnum: dec("busnum")?,
+            devnum: dec("devnum")?,
+            speed,
+            id_vendor: hex("idVendor")?,
+            id_product: hex("idProduct")?,
+            bcd_device: hex("bcdDevice")?,
+            b_device_class: hex_byte("bDeviceClass")?,
+            b_device_subclass: hex_byte("bDeviceSubClass")?,
+            b_device_protocol: hex_byte("bDeviceProtocol")?,
+            // Like the udev path, treat these as optional, since
+            // unconfigured devices leave some of them empty.
+            b_configuration_value: dec("bConfigurationValue")
+                .ok()
+                .and_then(|value| u8::try_from(value).ok())
+                .unwrap_or_default(),
+            b_num_configurations: dec("bNumConfigurations")
+                .ok()
+                .and_then(|value| u8::try_from(value).ok())
+                .unwrap_or_default(),
+            b_num_interfaces: dec("bNumInterfaces")
+                .ok()
+                .and_then(|value| u8::try_from(value).ok())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Reads an endpoint from one of an interface's
+/// `ep_XX` sysfs directories.
+#[cfg(feature = "udev")]
+impl TryFrom<udev::Device> for UsbEndpoint {
+    type Error = udev_utils::Error<Box<dyn std::error::Error + Send + Sync>>;
+
+    fn try_from(udev: udev::Device) -> Result<Self, Self::Error> {
+        let byte = |attr| -> Result<u8, Self::Error> {
+            let value = udev.sysattr_hex(attr).map_err(|err| err.into_dyn())?;
+            u8::try_from(value)
+                .map_err(|err| udev_utils::Error::CustomErr(crate::util::into_dyn_err(err)))
+        };
+        let b_endpoint_address = byte("bEndpointAddress")?;
+        let bm_attributes = byte("bmAttributes")?;
+        let b_interval = byte("bInterval")?;
+        let w_max_packet_size = udev
+            .sysattr_hex("wMaxPacketSize")
+            .map_err(|err| err.into_dyn())?;
+
+        Ok(UsbEndpoint::new(
+            b_endpoint_address,
+            bm_attributes,
+            w_max_packet_size,
+            b_interval,
+        ))
+    }
+}
+
+/// Reads every endpoint of the interface at `interface`,
+/// e.g. `/sys/bus/usb/devices/1-1:1.0`, in the order
+/// sysfs lists them.
+///
+/// # Errors
+/// This function will return an error if the interface's
+/// directory can't be read or an endpoint is malformed.
+#[cfg(feature = "udev")]
+pub fn interface_endpoints(
+    interface: &udev::Device,
+) -> Result<Vec<UsbEndpoint>, udev_utils::Error<Box<dyn std::error::Error + Send + Sync>>> {
+    let io_err =
+        |err: std::io::Error| udev_utils::Error::CustomErr(crate::util::into_dyn_err(err));
+    let mut endpoints = Vec::new();
+    for entry in std::fs::read_dir(interface.syspath()).map_err(io_err)? {
+        let entry = entry.map_err(io_err)?;
+        if !entry.file_name().as_bytes().starts_with(b"ep_") {
+            continue;
+        }
+        let ep = udev::Device::from_syspath(&entry.path()).map_err(io_err)?;
+        endpoints.push(UsbEndpoint::try_from(ep)?);
+    }
+    Ok(endpoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usb_device_from_syspath_reads_attributes() {
+        let dir = std::env::temp_dir().join(format!("usbip-core-{}-sysfs", std::process::id()));
+        let dev = dir.join("1-1");
+        std::fs::create_dir_all(&dev).unwrap();
+        for (attr, value) in [
+            ("busnum", "1\n"),
+            ("devnum", "2\n"),
+            ("speed", "480\n"),
+            ("idVendor", "1d6b\n"),
+            ("idProduct", "0002\n"),
+            ("bcdDevice", "0610\n"),
+            ("bDeviceClass", "09\n"),
+            ("bDeviceSubClass", "00\n"),
+            ("bDeviceProtocol", "01\n"),
+            ("bConfigurationValue", "1\n"),
+            ("bNumConfigurations", "1\n"),
+            ("bNumInterfaces", " 1\n"),
+        ] {
+            std::fs::write(dev.join(attr), value).unwrap();
+        }
+
+        let device = crate::UsbDevice::from_syspath(&dev);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let device = device.unwrap();
+
+        assert_eq!(device.bus_id(), "1-1");
+        assert_eq!((device.id_vendor(), device.id_product()), (0x1d6b, 0x0002));
+        assert_eq!(device.bcd_device(), 0x0610);
+        assert_eq!(device.speed(), DeviceSpeed::High);
+        assert_eq!(device.num_interfaces(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "client")]
+    fn nonblocking_stream_waits_for_whole_pdus() {
+        use std::io::Write;
+        use std::os::fd::AsRawFd;
+
+        use crate::net::{bincode_config, OpCommon, Protocol, Status};
+
+        // Blocks until the host's bytes have reached the socket,
+        // instead of guessing how long that takes.
+        fn wait_readable(stream: &UsbipStream) {
+            let mut pollfd = libc::pollfd {
+                fd: stream.as_tcp().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `pollfd` is a single valid `pollfd`.
+            assert_eq!(unsafe { libc::poll(&mut pollfd, 1, 5000) }, 1);
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = UsbipStream::connect(&listener.local_addr().unwrap()).unwrap();
+        let (mut host, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+
+        stream
+            .queue(&OpCommon::request(Protocol::OP_REQ_DEVLIST))
+            .unwrap();
+        assert!(stream.try_flush().unwrap());
+        assert!(stream.try_recv::<OpCommon>().unwrap().is_none());
+
+        let rep = OpCommon::request(Protocol::OP_REP_DEVLIST).reply(Status::Success);
+        let bytes = bincode::encode_to_vec(rep, bincode_config()).unwrap();
+        host.write_all(&bytes[..5]).unwrap();
+        wait_readable(&stream);
+        assert!(stream.try_recv::<OpCommon>().unwrap().is_none());
+
+        host.write_all(&bytes[5..]).unwrap();
+        let rep = loop {
+            wait_readable(&stream);
+            if let Some(rep) = stream.try_recv::<OpCommon>().unwrap() {
+                break rep;
+            }
+        };
+        assert_eq!(
+            rep.validate(Protocol::OP_REP_DEVLIST).unwrap(),
+            Status::Success
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "client")]
+    fn socket_options_are_applied() {
+        use std::os::fd::AsRawFd;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = SocketOptions::new().recv_buffer(4096).quickack(true);
+        let stream = UsbipStream::connect_with(&listener.local_addr().unwrap(), &options).unwrap();
+
+        let mut val: libc::c_int = 0;
+        let mut len = core::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                stream.as_tcp().as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                core::ptr::addr_of_mut!(val).cast(),
+                &mut len,
+            )
+        };
+        assert_eq!(rc, 0);
+        // The kernel doubles it to make room for its own bookkeeping.
+        assert_eq!(val, 8192);
+    }
+
+    #[test]
+    #[cfg(feature = "client")]
+    fn connect_timeout_reports_refusals() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let options = SocketOptions::new()
+            .recv_buffer(4096)
+            .connect_timeout(std::time::Duration::from_secs(5));
+        let err = UsbipStream::connect_with(&addr, &options).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    #[cfg(feature = "client")]
+    fn blocking_recv_keeps_the_rest_of_a_read() {
+        use std::io::Write;
+
+        use crate::net::{bincode_config, OpCommon, OpDevlistReply, Protocol, Recv, Status};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = UsbipStream::connect(&listener.local_addr().unwrap()).unwrap();
+        let (mut host, _) = listener.accept().unwrap();
+
+        let rep = (
+            OpCommon::request(Protocol::OP_REP_DEVLIST).reply(Status::Success),
+            OpDevlistReply::new(3),
+        );
+        let bytes = bincode::encode_to_vec(rep, bincode_config()).unwrap();
+        host.write_all(&bytes).unwrap();
+
+        let rep: OpCommon = stream.recv().unwrap();
+        assert_eq!(
+            rep.validate(Protocol::OP_REP_DEVLIST).unwrap(),
+            Status::Success
+        );
+        let rep: OpDevlistReply = stream.recv().unwrap();
+        assert_eq!(rep.num_devices(), 3);
+        assert!(!stream.has_unread());
+    }
+}
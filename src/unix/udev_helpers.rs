@@ -16,13 +16,21 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::NoParent => write!(f, "No parent device"),
-            Error::Parse(p) => write!(f, "Attribute: {p}"),
-            Error::TryFromDev(d) => write!(f, "Device Read: {d}"),
+            Error::Parse(_) => write!(f, "Failed to parse attribute"),
+            Error::TryFromDev(_) => write!(f, "Failed to read device"),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::NoParent => None,
+            Error::Parse(p) => Some(p),
+            Error::TryFromDev(d) => Some(d),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum TryFromDeviceError {
@@ -33,13 +41,20 @@ pub enum TryFromDeviceError {
 impl std::fmt::Display for TryFromDeviceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            TryFromDeviceError::Io(i) => write!(f, "I/O: {i}"),
-            TryFromDeviceError::Parse(p) => write!(f, "Parse: {p}"),
+            TryFromDeviceError::Io(_) => write!(f, "I/O error"),
+            TryFromDeviceError::Parse(_) => write!(f, "Parse error"),
         }
     }
 }
 
-impl std::error::Error for TryFromDeviceError {}
+impl std::error::Error for TryFromDeviceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TryFromDeviceError::Io(i) => Some(i),
+            TryFromDeviceError::Parse(p) => Some(p),
+        }
+    }
+}
 
 impl From<std::io::Error> for TryFromDeviceError {
     fn from(value: std::io::Error) -> Self {
@@ -86,7 +101,7 @@ impl UdevHelper for udev::Device {}
 pub enum ParseAttributeError {
     NoAttribute(Cow<'static, str>),
     Int(ParseIntError),
-    Dyn(Box<dyn std::error::Error>),
+    Dyn(Box<dyn std::error::Error + Send + Sync>),
     NotUtf8,
     Buffer(stacktools::TryFromStrErr),
 }
@@ -95,15 +110,24 @@ impl std::fmt::Display for ParseAttributeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ParseAttributeError::NoAttribute(s) => write!(f, "No attribute found for \"{s}\""),
-            ParseAttributeError::Int(i) => write!(f, "Int: {i}"),
-            ParseAttributeError::Dyn(d) => write!(f, "Any: {d}"),
+            ParseAttributeError::Int(_) => write!(f, "Attribute value is not an integer"),
+            ParseAttributeError::Dyn(_) => write!(f, "Invalid attribute value"),
             ParseAttributeError::NotUtf8 => write!(f, "Attribute value was not in utf8"),
-            ParseAttributeError::Buffer(b) => write!(f, "Buffer Format: {b}"),
+            ParseAttributeError::Buffer(_) => write!(f, "Attribute value doesn't fit its buffer"),
         }
     }
 }
 
-impl std::error::Error for ParseAttributeError {}
+impl std::error::Error for ParseAttributeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseAttributeError::Int(i) => Some(i),
+            ParseAttributeError::Dyn(d) => Some(&**d),
+            ParseAttributeError::Buffer(b) => Some(b),
+            ParseAttributeError::NoAttribute(_) | ParseAttributeError::NotUtf8 => None,
+        }
+    }
+}
 
 impl From<ParseAttributeError> for Error {
     fn from(value: ParseAttributeError) -> Self {
@@ -111,8 +135,8 @@ impl From<ParseAttributeError> for Error {
     }
 }
 
-impl From<Box<dyn std::error::Error>> for ParseAttributeError {
-    fn from(value: Box<dyn std::error::Error>) -> Self {
+impl From<Box<dyn std::error::Error + Send + Sync>> for ParseAttributeError {
+    fn from(value: Box<dyn std::error::Error + Send + Sync>) -> Self {
         ParseAttributeError::Dyn(value)
     }
 }
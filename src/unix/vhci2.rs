@@ -9,6 +9,160 @@ mod tests {
         Driver::open().unwrap();
     }
 
+    #[test]
+    fn handshake_read_timeouts_are_timeouts() {
+        use std::{net::TcpListener, time::Duration};
+
+        use crate::vhci::{AttachStage, ErrorKind};
+
+        // A host that accepts, then never replies.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut socket = UsbipStream::connect(&listener.local_addr().unwrap()).unwrap();
+        let _host = listener.accept().unwrap();
+        socket
+            .as_tcp()
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        let started = Instant::now();
+        let err = Driver::import(&mut socket, &BusId::parse("1-1").unwrap())
+            .unwrap_err()
+            .at_stage_timed(AttachStage::Handshake, started);
+        assert!(matches!(
+            err,
+            Error::Timeout {
+                stage: AttachStage::Handshake,
+                ..
+            }
+        ));
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.is_transient());
+    }
+
+    #[test]
+    fn garbage_status_lines_are_errors() {
+        let lines = [
+            "",
+            "hs",
+            "hs 0004",
+            "xx 0004 004",
+            "hs -1 004",
+            "hs 0004 999",
+            "\u{0} \u{ffff}",
+        ];
+        for line in lines {
+            assert!(line.parse::<MaybeAvailableIdev>().is_err(), "{line:?}");
+            assert!(StatusLine::parse(line).is_err(), "{line:?}");
+        }
+
+        // Lines for ports in use only go wrong after the status.
+        let lines = [
+            "ss 0004 006",
+            "ss 0004 006 000 00000000 000000",
+            "hs 0004 006 000 zzzzzzzz 000000 1-1",
+            "hs 0004 006 000 00010002 000003 1-1.2.3.4.5.6.7.8.9.10.11.12.13.14.15.16.17.18",
+        ];
+        for line in lines {
+            assert!(StatusLine::parse(line).is_err(), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn parse_status_line() {
+        let line = "hs  0004 006 002 00010002 000003 1-1.2";
+        let line = StatusLine::parse(line).unwrap().unwrap();
+        assert_eq!(line.port, 4);
+        assert_eq!(line.status, DeviceStatus::PortInUse);
+        assert_eq!(line.busid, "1-1.2");
+
+        let line = "ss  0009 004 000 00000000 000000 0-0";
+        assert!(StatusLine::parse(line).unwrap().is_none());
+    }
+
+    #[test]
+    fn status_fixtures_parse() {
+        let status = include_str!("../../testdata/vhci/status.1");
+        let ports = status
+            .lines()
+            .skip(1)
+            .filter_map(|line| StatusLine::parse(line).unwrap())
+            .map(|line| line.port)
+            .collect::<Vec<_>>();
+        assert_eq!(ports, [16, 17, 18, 25]);
+
+        let line = StatusLine::parse("hs  0017 006 002 0001000a 000007 1-1.3.2")
+            .unwrap()
+            .unwrap();
+        assert_eq!(line.devid, DevId::new(1, 10));
+    }
+
+    #[cfg(feature = "udev")]
+    #[test]
+    fn device_cache_forgets_changed_devices() {
+        let cached = |bus_id: &'static str| {
+            let usb_dev = crate::test_device(bus_id).build().unwrap();
+            (BusId::parse(bus_id).unwrap(), DevId::new(1, 2), usb_dev)
+        };
+        let mut devices = vec![cached("1-1"), cached("1-10"), cached("2-1")];
+
+        DeviceCache::forget(&mut devices, "1-1:1.0");
+        DeviceCache::forget(&mut devices, "usb1");
+        let left: Vec<_> = devices.iter().map(|(busid, _, _)| busid.as_str()).collect();
+        assert_eq!(left, ["1-10", "2-1"]);
+
+        DeviceCache::forget(&mut devices, "2-1");
+        assert_eq!(devices.len(), 1);
+    }
+
+    #[test]
+    fn port_record_events_drop_stale_records() {
+        use super::inotify::{parse_events, test_event};
+
+        let record = || Err(Arc::new(PortRecordError::Invalid));
+        let mut records = PortRecords::new();
+        records.records = vec![(1, record()), (2, record()), (3, record())];
+
+        let events = [
+            test_event(libc::IN_CLOSE_WRITE, "port2"),
+            test_event(libc::IN_DELETE, "port9"),
+        ]
+        .concat();
+        let flow = parse_events(&events, |mask, name| records.on_event(mask, name));
+        assert!(flow.is_continue());
+        let ports: Vec<_> = records.records.iter().map(|(port, _)| *port).collect();
+        assert_eq!(ports, [1, 3]);
+
+        // Anything that isn't a port record could hide lost events.
+        let events = test_event(libc::IN_CREATE, "port3.tmp");
+        let _ = parse_events(&events, |mask, name| records.on_event(mask, name));
+        assert!(records.records.is_empty());
+
+        records.records = vec![(1, record())];
+        let events = [
+            test_event(libc::IN_DELETE_SELF, ""),
+            test_event(libc::IN_IGNORED, ""),
+            test_event(libc::IN_CREATE, "port1"),
+        ]
+        .concat();
+        let flow = parse_events(&events, |mask, name| records.on_event(mask, name));
+        assert!(flow.is_break());
+        assert!(records.records.is_empty());
+    }
+
+    #[test]
+    fn garbage_port_records_are_errors() {
+        let records = [
+            "",
+            "127.0.0.1",
+            "127.0.0.1 3240",
+            "host 3240 1-1",
+            "127.0.0.1 99999 1-1",
+        ];
+        for record in records {
+            assert!(record.parse::<PortRecord>().is_err(), "{record:?}");
+        }
+    }
+
     #[test]
     fn parse_record() {
         let record = str::parse::<PortRecord>("127.0.0.1 3240 1-1").unwrap();
@@ -19,70 +173,98 @@ mod tests {
         assert_eq!(record.bus_id(), "1-1");
     }
 }
+#[cfg(feature = "tokio")]
+pub(crate) mod asynchronous;
 mod sysfs {
-    use crate::{unix::sysfs::SysAttr, DeviceSpeed};
+    use crate::{unix::sysfs::SysAttr, DevId, DeviceSpeed};
 
     use std::{
         io::Write,
         os::fd::{AsRawFd, BorrowedFd},
     };
 
-    pub fn detach(udev: &udev::Device, port: u16) -> std::io::Result<()> {
-        let mut sys = SysAttr::open(udev.syspath().to_str().unwrap(), "detach")?;
-        write!(sys, "{port}")
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", err))]
+    pub fn detach(syspath: &str, port: u16) -> std::io::Result<()> {
+        let mut sys = SysAttr::open(syspath, "detach")?;
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let result = write!(sys, "{port}");
+        #[cfg(feature = "metrics")]
+        crate::stats::driver_call("detach", started);
+        result
     }
 
-    pub fn attach(udev: &udev::Device, new_connection: NewConnection) -> std::io::Result<()> {
-        let mut sys = SysAttr::open(udev.syspath().to_str().unwrap(), "attach")?;
+    pub fn attach(syspath: &str, new_connection: NewConnection) -> std::io::Result<()> {
+        let mut sys = SysAttr::open(syspath, "attach")?;
         let NewConnection {
             port,
             fd,
             dev_id,
             speed,
         } = new_connection;
+        crate::util::trace_event!(
+            port,
+            fd = fd.as_raw_fd(),
+            dev_id = dev_id.as_u32(),
+            speed = speed.to_kernel_value(),
+            "writing vhci attach"
+        );
 
-        write!(
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let result = write!(
             sys,
             "{} {} {} {}",
             port,
             fd.as_raw_fd(),
-            dev_id,
-            speed as u32
-        )
+            dev_id.as_u32(),
+            speed.to_kernel_value()
+        );
+        #[cfg(feature = "metrics")]
+        crate::stats::driver_call("attach", started);
+        result
     }
 
     pub struct NewConnection<'a> {
         pub port: u16,
         pub fd: BorrowedFd<'a>,
-        pub dev_id: u32,
+        pub dev_id: DevId,
         pub speed: DeviceSpeed,
     }
 }
 
-use core::fmt::{self, Write};
+use core::{cell::RefCell, fmt};
 use std::{
-    fs,
-    io::{self, Write as IoWrite},
-    net::{AddrParseError, IpAddr, SocketAddr},
+    fs::{self, File},
+    io::{self, Read, Write as IoWrite},
+    net::{AddrParseError, IpAddr, SocketAddr, TcpStream},
     num::{NonZeroUsize, ParseIntError},
-    ops::Deref,
+    ops::{ControlFlow, Deref},
     os::fd::AsFd,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Instant,
 };
 
 use crate::{
-    containers::{
-        beef::Beef,
-        stacktools::{self, StackStr},
+    cancel::CancellationToken,
+    containers::stacktools::StackStr,
+    unix::{
+        inotify,
+        net::{SocketOptions, UsbipStream},
+        vhci2::sysfs::NewConnection,
     },
-    net::{OpCommon, OpImportReply, OpImportRequest, Protocol, Recv, Send, Status},
-    unix::{net::UsbipStream, vhci2::sysfs::NewConnection},
-    util::{__private::Sealed, parse_token},
-    vhci::{base, error2::Error, AttachArgs, HubSpeed},
-    DeviceSpeed, DeviceStatus,
+    util::{__private::Sealed, parse_token, ParseTokenError},
+    vhci::{base, AttachArgs, AttachStage, Error, HubSpeed, PortCounts},
+    BusId, DevId, DeviceSpeed, DeviceStatus,
 };
 
+#[cfg(feature = "udev")]
 use super::udev_utils::UdevExt;
 
 pub static STATE_PATH: &str = "/var/run/vhci_hcd";
@@ -103,7 +285,7 @@ impl Deref for MaybeAvailableIdev {
 }
 
 impl FromStr for MaybeAvailableIdev {
-    type Err = Box<dyn std::error::Error>;
+    type Err = DataError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut tokens = s.split_whitespace();
@@ -122,53 +304,191 @@ impl FromStr for MaybeAvailableIdev {
     }
 }
 
-struct MaybeUnixImportedDevice(Option<UnixImportedDevice>);
+/// An error in the driver's data, see [`Error::InvalidDriverData`].
+type DataError = Box<dyn std::error::Error + core::marker::Send + Sync>;
 
-impl Deref for MaybeUnixImportedDevice {
-    type Target = Option<UnixImportedDevice>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
+/// A line of the vhci driver's status for a port
+/// that's in use, before its device is looked up.
+struct StatusLine<'a> {
+    hub: HubSpeed,
+    port: u16,
+    status: DeviceStatus,
+    devid: DevId,
+    busid: BusId<'a>,
 }
 
-impl FromStr for MaybeUnixImportedDevice {
-    type Err = Box<dyn std::error::Error>;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl<'a> StatusLine<'a> {
+    /// Parses a line of the status, returning
+    /// `None` if the port is available.
+    fn parse(s: &'a str) -> Result<Option<Self>, DataError> {
         let mut tokens = s.split_whitespace();
         let hub = parse_token::<HubSpeed>(&mut tokens)?;
         let port = parse_token::<u16>(&mut tokens)?;
         let status = parse_token::<DeviceStatus>(&mut tokens)?;
         if status == DeviceStatus::PortAvailable {
-            return Ok(MaybeUnixImportedDevice(None));
+            return Ok(None);
         }
 
         let _speed = parse_token::<u32>(&mut tokens)?;
-        let devid = parse_token::<u32>(&mut tokens)?;
+        // Unlike the other fields, the kernel prints devid in hex,
+        // which is what DevId's FromStr expects.
+        let devid = parse_token::<DevId>(&mut tokens)?;
         let _sockfd = parse_token::<u32>(&mut tokens)?;
-        let busid = tokens.next().unwrap().trim();
-        let sudev = udev::Device::from_subsystem_sysname("usb".to_owned(), busid.to_owned())?;
-        let usb_dev = crate::UsbDevice::try_from(sudev).map_err(|err| err.into_custom_err())?;
-        let idev = UnixImportedDevice {
+        let busid = tokens
+            .next()
+            .ok_or(ParseTokenError::<crate::ParseBusIdError>::Missing)?;
+        let busid = BusId::try_from(busid).map_err(ParseTokenError::Invalid)?;
+
+        Ok(Some(Self {
+            hub,
+            port,
+            status,
+            devid,
+            busid,
+        }))
+    }
+
+    fn into_imported(self, usb_dev: crate::UsbDevice, record: CachedRecord) -> UnixImportedDevice {
+        UnixImportedDevice {
             base: base::ImportedDevice {
-                vendor: usb_dev.id_vendor,
-                product: usb_dev.id_product,
-                devid,
+                vendor: usb_dev.id_vendor(),
+                product: usb_dev.id_product(),
+                devid: self.devid,
             },
-            port,
-            hub,
+            port: self.port,
+            hub: self.hub,
             usb_dev,
-            status,
+            status: self.status,
+            record,
+        }
+    }
+}
+
+/// Parses each port's line of a controller's `status`,
+/// returning how many ports are in use, for the benches.
+#[cfg(feature = "bench")]
+pub(crate) fn parse_status(status: &str) -> Result<usize, DataError> {
+    let mut in_use = 0;
+    for line in status.lines().skip(1) {
+        if StatusLine::parse(line)?.is_some() {
+            in_use += 1;
+        }
+    }
+    Ok(in_use)
+}
+
+/// Runs the status parsers on `line`, for the fuzz targets.
+#[cfg(fuzzing)]
+pub(crate) fn parse_status_line(line: &str) {
+    let _ = line.parse::<MaybeAvailableIdev>();
+    let _ = StatusLine::parse(line);
+}
+
+/// Reads the local USB device at `busid` from sysfs.
+#[cfg(feature = "udev")]
+fn read_usb_device(busid: &BusId) -> Result<crate::UsbDevice, DataError> {
+    let sudev = udev::Device::from_subsystem_sysname("usb".to_owned(), busid.as_str().to_owned())?;
+    Ok(crate::UsbDevice::try_from(sudev)?)
+}
+
+/// Reads the local USB device at `busid` from sysfs.
+#[cfg(not(feature = "udev"))]
+fn read_usb_device(busid: &BusId) -> Result<crate::UsbDevice, DataError> {
+    Ok(crate::UsbDevice::from_syspath(
+        Path::new("/sys/bus/usb/devices").join(busid.as_str()),
+    )?)
+}
+
+/// The [`UsbDevice`]s of imported devices, kept between calls
+/// to [`Driver::imported_devices`] so that polling doesn't
+/// re-read every device's attributes from sysfs.
+///
+/// A device is read again once udev reports a change to it,
+/// or if it's imported again from a different remote device.
+/// Without a udev monitor, like when the `udev` feature is
+/// disabled, nothing is cached.
+///
+/// [`UsbDevice`]: crate::UsbDevice
+struct DeviceCache {
+    #[cfg(feature = "udev")]
+    monitor: Option<udev::MonitorSocket>,
+    #[cfg(not(feature = "udev"))]
+    monitor: Option<core::convert::Infallible>,
+    devices: Vec<(BusId<'static>, DevId, crate::UsbDevice)>,
+}
+
+impl DeviceCache {
+    fn new() -> Self {
+        #[cfg(feature = "udev")]
+        let monitor = udev::MonitorBuilder::new()
+            .and_then(|builder| builder.match_subsystem("usb"))
+            .and_then(|builder| builder.listen())
+            .ok();
+        #[cfg(not(feature = "udev"))]
+        let monitor = None;
+        Self {
+            monitor,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Does nothing, since nothing is cached.
+    #[cfg(not(feature = "udev"))]
+    fn invalidate(&mut self) {}
+
+    /// Drops the devices udev has reported a
+    /// change to since the last call.
+    #[cfg(feature = "udev")]
+    fn invalidate(&mut self) {
+        let Some(monitor) = &self.monitor else {
+            return;
         };
+        for event in monitor.iter() {
+            let device = event.device();
+            Self::forget(&mut self.devices, &device.sysname().to_string_lossy());
+        }
+    }
 
-        Ok(MaybeUnixImportedDevice(Some(idev)))
+    /// Drops the device that `sysname`, the
+    /// name of a device or interface, belongs to.
+    #[cfg(feature = "udev")]
+    fn forget(devices: &mut Vec<(BusId<'static>, DevId, crate::UsbDevice)>, sysname: &str) {
+        // Interfaces are named after their device, like `1-1:1.0`.
+        let busid = sysname.split(':').next().unwrap_or_default();
+        devices.retain(|(cached, _, _)| cached.as_str() != busid);
+    }
+
+    fn get_or_read(&mut self, busid: &BusId, devid: DevId) -> Result<crate::UsbDevice, DataError> {
+        if self.monitor.is_none() {
+            return read_usb_device(busid);
+        }
+        let cached = self.devices.iter().find(|(cached, cached_devid, _)| {
+            cached.as_str() == busid.as_str() && *cached_devid == devid
+        });
+        if let Some((_, _, usb_dev)) = cached {
+            return Ok(usb_dev.clone());
+        }
+
+        let usb_dev = read_usb_device(busid)?;
+        self.devices
+            .push((busid.clone().into_owned(), devid, usb_dev.clone()));
+        Ok(usb_dev)
+    }
+
+    /// Drops the devices that aren't in `idevs` anymore.
+    fn retain(&mut self, idevs: &UnixImportedDevices) {
+        self.devices.retain(|(busid, devid, _)| {
+            idevs
+                .get()
+                .iter()
+                .any(|idev| idev.usb_dev.bus_id() == busid.as_str() && idev.base.devid == *devid)
+        });
     }
 }
 
 #[derive(Debug)]
 pub enum PortRecordError {
-    Buffer(stacktools::TryFromStrErr),
+    BusId(crate::ParseBusIdError),
     Io(io::Error),
     Addr(AddrParseError),
     Int(ParseIntError),
@@ -178,15 +498,27 @@ pub enum PortRecordError {
 impl fmt::Display for PortRecordError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            PortRecordError::Buffer(b) => write!(f, "{b}"),
-            PortRecordError::Io(i) => write!(f, "{i}"),
-            PortRecordError::Addr(a) => write!(f, "{a}"),
-            PortRecordError::Int(i) => write!(f, "{i}"),
+            PortRecordError::BusId(_) => write!(f, "Invalid bus id in port record"),
+            PortRecordError::Io(_) => write!(f, "Failed to read port record"),
+            PortRecordError::Addr(_) => write!(f, "Invalid host address in port record"),
+            PortRecordError::Int(_) => write!(f, "Invalid host port in port record"),
             PortRecordError::Invalid => write!(f, "Invalid port record"),
         }
     }
 }
 
+impl std::error::Error for PortRecordError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PortRecordError::BusId(b) => Some(b),
+            PortRecordError::Io(i) => Some(i),
+            PortRecordError::Addr(a) => Some(a),
+            PortRecordError::Int(i) => Some(i),
+            PortRecordError::Invalid => None,
+        }
+    }
+}
+
 impl From<io::Error> for PortRecordError {
     fn from(value: io::Error) -> Self {
         Self::Io(value)
@@ -205,20 +537,20 @@ impl From<ParseIntError> for PortRecordError {
     }
 }
 
-impl From<stacktools::TryFromStrErr> for PortRecordError {
-    fn from(value: stacktools::TryFromStrErr) -> Self {
-        Self::Buffer(value)
+impl From<crate::ParseBusIdError> for PortRecordError {
+    fn from(value: crate::ParseBusIdError) -> Self {
+        Self::BusId(value)
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct PortRecord {
     base: base::PortRecord,
 }
 
 impl PortRecord {
-    fn read(port: u16) -> Result<Self, PortRecordError> {
-        let path = PathBuf::from(format!("{}/port{}", STATE_PATH, port));
-        let s = fs::read_to_string(path)?;
+    fn read(state_path: &Path, port: u16) -> Result<Self, PortRecordError> {
+        let s = fs::read_to_string(state_path.join(format!("port{port}")))?;
         s.parse()
     }
 }
@@ -248,15 +580,125 @@ impl FromStr for PortRecord {
         Ok(Self {
             base: base::PortRecord {
                 host: SocketAddr::new(host, srv_port),
-                busid: busid.try_into()?,
+                busid: busid.parse()?,
             },
         })
     }
 }
 
+/// A port record as it was read, shared
+/// between the cache and imported devices.
+type CachedRecord = Result<PortRecord, Arc<PortRecordError>>;
+
+/// The port records in the state path, kept until inotify
+/// reports a change to their file or they're refreshed.
+///
+/// The state path may not exist until the first attach,
+/// so the watch is set up on first use. Until then,
+/// nothing is cached.
+struct PortRecords {
+    watch: Option<File>,
+    records: Vec<(u16, CachedRecord)>,
+}
+
+impl PortRecords {
+    const fn new() -> Self {
+        Self {
+            watch: None,
+            records: Vec::new(),
+        }
+    }
+
+    /// Drops the records whose files changed since the last call.
+    fn invalidate(&mut self, state_path: &Path) {
+        let Some(mut watch) = self.watch.take() else {
+            self.records.clear();
+            self.watch = watch_state_path(state_path).ok();
+            return;
+        };
+
+        let gone = inotify::read_events(&mut watch, |mask, name| self.on_event(mask, name));
+        if gone.is_continue() {
+            self.watch = Some(watch);
+        }
+    }
+
+    /// Drops the records an inotify event on the state path made
+    /// stale. Breaks once the state path is gone, in which case
+    /// it has to be watched again.
+    fn on_event(&mut self, mask: u32, name: &[u8]) -> ControlFlow<()> {
+        if mask & libc::IN_IGNORED != 0 {
+            self.records.clear();
+            return ControlFlow::Break(());
+        }
+
+        let port = core::str::from_utf8(name)
+            .ok()
+            .and_then(|name| name.strip_prefix("port"))
+            .and_then(|port| port.parse::<u16>().ok());
+        match port {
+            Some(port) => self.remove(port),
+            // Events were lost, or something else changed.
+            None => self.records.clear(),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn get(&mut self, state_path: &Path, port: u16) -> CachedRecord {
+        if self.watch.is_none() {
+            return PortRecord::read(state_path, port).map_err(Arc::new);
+        }
+        if let Some((_, record)) = self.records.iter().find(|(cached, _)| *cached == port) {
+            return record.clone();
+        }
+
+        let record = PortRecord::read(state_path, port).map_err(Arc::new);
+        self.records.push((port, record.clone()));
+        record
+    }
+
+    fn remove(&mut self, port: u16) {
+        self.records.retain(|(cached, _)| *cached != port);
+    }
+}
+
+/// Watches `state_path` for port records
+/// being written, replaced or removed.
+fn watch_state_path(state_path: &Path) -> io::Result<File> {
+    let mask = libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF;
+    inotify::watch(state_path, mask)
+}
+
 #[derive(Debug)]
 pub struct UnixImportedDevices(Box<[UnixImportedDevice]>);
 
+impl UnixImportedDevices {
+    pub fn get(&self) -> &[UnixImportedDevice] {
+        &self.0
+    }
+
+    #[cfg(feature = "mock-driver")]
+    pub(crate) fn from_mock(idevs: Vec<UnixImportedDevice>) -> Self {
+        Self(idevs.into_boxed_slice())
+    }
+
+    /// Formats every imported device in turn, the same
+    /// way [`UnixImportedDevice::display`] formats one,
+    /// without collecting them into a `String` first.
+    pub const fn display<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        names: &'b crate::names::Names,
+    ) -> impl fmt::Display + 'c {
+        UnixIdevsDisplay { idevs: self, names }
+    }
+}
+
 #[derive(Debug)]
 pub struct UnixImportedDevice {
     base: base::ImportedDevice,
@@ -264,6 +706,7 @@ pub struct UnixImportedDevice {
     hub: HubSpeed,
     status: crate::DeviceStatus,
     usb_dev: crate::UsbDevice,
+    record: CachedRecord,
 }
 
 impl UnixImportedDevice {
@@ -285,6 +728,52 @@ impl UnixImportedDevice {
     pub const fn port(&self) -> u16 {
         self.port
     }
+
+    pub const fn speed(&self) -> crate::DeviceSpeed {
+        self.usb_dev.speed()
+    }
+
+    /// The device as the kernel enumerated it,
+    /// with its local bus and device numbers.
+    #[cfg(feature = "verify")]
+    pub(crate) const fn local_device(&self) -> &crate::UsbDevice {
+        &self.usb_dev
+    }
+
+    /// The host and remote bus id the device was imported
+    /// from, as recorded when it was attached.
+    pub fn record(&self) -> Option<&PortRecord> {
+        self.record.as_ref().ok()
+    }
+}
+
+#[cfg(feature = "mock-driver")]
+impl UnixImportedDevice {
+    /// `usb_dev` as if it were imported from `host` and
+    /// attached to `port`, for [`crate::vhci::mock`].
+    pub(crate) fn from_mock(port: u16, host: SocketAddr, usb_dev: &crate::UsbDevice) -> Self {
+        let hub = match usb_dev.speed() {
+            DeviceSpeed::Super | DeviceSpeed::SuperPlus => HubSpeed::Super,
+            _ => HubSpeed::High,
+        };
+        Self {
+            base: base::ImportedDevice {
+                vendor: usb_dev.id_vendor(),
+                product: usb_dev.id_product(),
+                devid: usb_dev.dev_id(),
+            },
+            port,
+            hub,
+            status: DeviceStatus::PortInUse,
+            usb_dev: usb_dev.clone(),
+            record: Ok(PortRecord {
+                base: base::PortRecord {
+                    host,
+                    busid: usb_dev.busid.clone(),
+                },
+            }),
+        }
+    }
 }
 
 impl Deref for UnixImportedDevice {
@@ -310,8 +799,13 @@ impl fmt::Display for UnixIdevDisplay<'_, '_> {
             return write!(f, "");
         }
 
-        let record = PortRecord::read(idev.port()).inspect_err(|err| {
-            writeln!(f, "Error when reading port record: {err}").unwrap();
+        let record = idev.record.as_ref().inspect_err(|err| {
+            writeln!(
+                f,
+                "Error when reading port record: {}",
+                crate::util::ErrorChain(&**err)
+            )
+            .unwrap();
         });
 
         writeln!(
@@ -322,10 +816,7 @@ impl fmt::Display for UnixIdevDisplay<'_, '_> {
             usb_dev.speed()
         )?;
 
-        let product = self
-            .names
-            .product_display(idev.base.vendor(), idev.base.product());
-        writeln!(f, "       {product}")?;
+        writeln!(f, "       {}", usb_dev.describe(self.names).product())?;
 
         match record {
             Ok(record) => {
@@ -356,6 +847,20 @@ impl fmt::Display for UnixIdevDisplay<'_, '_> {
     }
 }
 
+struct UnixIdevsDisplay<'a, 'b> {
+    idevs: &'a UnixImportedDevices,
+    names: &'b crate::names::Names,
+}
+
+impl fmt::Display for UnixIdevsDisplay<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for idev in self.idevs.get() {
+            write!(f, "{}", idev.display(self.names))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct AvailableIdev {
     port: u16,
@@ -388,102 +893,138 @@ impl OpenPorts {
 }
 
 struct InitData<'a> {
-    hc_device: &'a udev::Device,
+    hc_path: &'a str,
+    status_buf: &'a mut String,
     num_controllers: NonZeroUsize,
     num_ports: NonZeroUsize,
 }
 
-impl From<InitData<'_>> for OpenPorts {
-    fn from(init: InitData<'_>) -> Self {
-        let mut attr = StackStr::<20>::try_from(format_args!("status")).unwrap();
-        let mut open_ports = Vec::<AvailableIdev>::with_capacity(init.num_ports.get());
-
-        for i in 0..init.num_controllers.get() {
-            if i > 0 {
-                attr.clear();
-                write!(attr, "status.{i}").unwrap();
+impl InitData<'_> {
+    /// Calls `f` with each port's line of every controller's
+    /// status, reading each status file once into the buffer.
+    fn for_each_status_line(
+        &mut self,
+        mut f: impl FnMut(&str) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let syspath = self.hc_path;
+
+        for i in 0..self.num_controllers.get() {
+            let path = if i == 0 {
+                StackStr::<256>::try_from(format_args!("{syspath}/status"))
+            } else {
+                StackStr::<256>::try_from(format_args!("{syspath}/status.{i}"))
             }
+            .map_err(|err| Error::InvalidDriverData(Box::new(err)))?;
+
+            self.status_buf.clear();
+            File::open(&*path)
+                .and_then(|mut file| file.read_to_string(self.status_buf))
+                .map_err(|err| Error::InvalidDriverData(Box::new(err)))?;
+            for line in self.status_buf.lines().skip(1) {
+                f(line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<InitData<'_>> for OpenPorts {
+    type Error = Error;
 
-            let status = init
-                .hc_device
-                .sysattr_str(&*attr)
-                .expect("vhci udev should have this controller");
-            for line in status.lines().skip(1) {
-                let open_port = if let MaybeAvailableIdev(Some(open_port)) = line.parse().unwrap() {
-                    open_port
-                } else {
-                    continue;
-                };
+    fn try_from(mut init: InitData<'_>) -> Result<Self, Self::Error> {
+        let mut open_ports = Vec::<AvailableIdev>::with_capacity(init.num_ports.get());
+
+        init.for_each_status_line(|line| {
+            if let MaybeAvailableIdev(Some(open_port)) =
+                line.parse().map_err(Error::InvalidDriverData)?
+            {
                 open_ports.push(open_port);
             }
-        }
+            Ok(())
+        })?;
 
-        OpenPorts(open_ports)
+        Ok(OpenPorts(open_ports))
     }
 }
 
-impl From<InitData<'_>> for UnixImportedDevices {
-    fn from(init: InitData) -> Self {
-        let mut attr = StackStr::<20>::new();
+impl UnixImportedDevices {
+    /// Reads the imported devices from the driver's status,
+    /// looking up each one's device with `lookup` and its
+    /// port record with `records`.
+    fn read(
+        mut init: InitData,
+        mut lookup: impl FnMut(&BusId, DevId) -> Result<crate::UsbDevice, DataError>,
+        mut records: impl FnMut(u16) -> CachedRecord,
+    ) -> Result<Self, Error> {
         let mut idevs = Vec::new();
 
-        write!(attr, "status").unwrap();
-
-        for i in 0..init.num_controllers.get() {
-            if i > 0 {
-                attr.clear();
-                write!(attr, "status.{i}").unwrap();
+        init.for_each_status_line(|line| {
+            if let Some(line) = StatusLine::parse(line).map_err(Error::InvalidDriverData)? {
+                let usb_dev = lookup(&line.busid, line.devid).map_err(Error::InvalidDriverData)?;
+                let record = records(line.port);
+                idevs.push(line.into_imported(usb_dev, record));
             }
+            Ok(())
+        })?;
 
-            let status = init.hc_device.sysattr_str(&*attr).unwrap();
-            for line in status.lines().skip(1) {
-                let idev = if let MaybeUnixImportedDevice(Some(idev)) = line
-                    .parse()
-                    .expect("data came from udev and should have been valid")
-                {
-                    idev
-                } else {
-                    continue;
-                };
-                idevs.push(idev);
-            }
-        }
-        UnixImportedDevices(idevs.into_boxed_slice())
+        Ok(UnixImportedDevices(idevs.into_boxed_slice()))
+    }
+}
+
+impl TryFrom<InitData<'_>> for UnixImportedDevices {
+    type Error = Error;
+
+    fn try_from(init: InitData) -> Result<Self, Self::Error> {
+        Self::read(
+            init,
+            |busid, _| read_usb_device(busid),
+            |port| PortRecord::read(Path::new(STATE_PATH), port).map_err(Arc::new),
+        )
     }
 }
 
 pub struct Driver {
-    hc_device: udev::Device,
+    hc_path: String,
     open_ports: OpenPorts,
+    device_cache: RefCell<DeviceCache>,
+    port_records: RefCell<PortRecords>,
+    status_buf: RefCell<String>,
+    socket_options: SocketOptions,
+    state_path: PathBuf,
     num_controllers: NonZeroUsize,
     num_ports: NonZeroUsize,
 }
 
 impl Driver {
     pub fn open() -> crate::vhci::Result<Self> {
-        let hc_device = udev::Device::from_subsystem_sysname(BUS_TYPE.into(), DEVICE_NAME.into())
-            .map_err(|_| Error::DriverNotFound)?;
-        let num_ports: NonZeroUsize = hc_device
-            .sysattr("nports")
-            .expect("udev should have this attribute");
-        let num_controllers = num_controllers(&hc_device)?;
+        let (hc_path, num_ports) = find_hc_device()?;
+        let num_controllers = num_controllers(Path::new(&hc_path))?;
+        let mut status_buf = String::new();
         let open_ports = InitData {
-            hc_device: &hc_device,
+            hc_path: &hc_path,
+            status_buf: &mut status_buf,
             num_controllers,
             num_ports,
-        }.into();
+        }
+        .try_into()?;
 
         Ok(Self {
-            hc_device,
+            hc_path,
             open_ports,
+            device_cache: RefCell::new(DeviceCache::new()),
+            port_records: RefCell::new(PortRecords::new()),
+            status_buf: RefCell::new(status_buf),
+            socket_options: SocketOptions::new(),
+            state_path: PathBuf::from(STATE_PATH),
             num_controllers,
             num_ports,
         })
     }
 
+    /// The first controller's directory in sysfs.
     #[inline(always)]
-    const fn udev(&self) -> &udev::Device {
-        &self.hc_device
+    fn hc_path(&self) -> &str {
+        &self.hc_path
     }
 
     #[inline(always)]
@@ -507,40 +1048,211 @@ impl Driver {
     }
 
     pub fn imported_devices(&self) -> crate::vhci::Result<UnixImportedDevices> {
-        Ok(UnixImportedDevices::try_from(InitData {
-            hc_device: self.udev(),
+        let mut cache = self.device_cache.borrow_mut();
+        let mut records = self.port_records.borrow_mut();
+        cache.invalidate();
+        records.invalidate(&self.state_path);
+        let init = InitData {
+            hc_path: self.hc_path(),
+            status_buf: &mut self.status_buf.borrow_mut(),
+            num_controllers: self.num_controllers(),
+            num_ports: self.num_ports(),
+        };
+        let idevs = UnixImportedDevices::read(
+            init,
+            |busid, devid| cache.get_or_read(busid, devid),
+            |port| records.get(&self.state_path, port),
+        )?;
+        cache.retain(&idevs);
+        Ok(idevs)
+    }
+
+    /// Forgets the port records read so far, so that the next
+    /// call to [`imported_devices`] reads them again.
+    ///
+    /// Records are refreshed on their own when their files
+    /// change, so this is only needed if inotify isn't
+    /// available or its events were missed.
+    ///
+    /// [`imported_devices`]: Driver::imported_devices
+    pub fn refresh_port_records(&self) {
+        self.port_records.borrow_mut().records.clear();
+    }
+
+    pub fn port_counts(&self) -> crate::vhci::Result<PortCounts> {
+        let open_ports = OpenPorts::try_from(InitData {
+            hc_path: self.hc_path(),
+            status_buf: &mut self.status_buf.borrow_mut(),
             num_controllers: self.num_controllers(),
             num_ports: self.num_ports(),
+        })?;
+
+        Ok(PortCounts {
+            num_controllers: self.num_controllers().get(),
+            num_ports: self.num_ports().get(),
+            free_ports: open_ports.get().len(),
         })
-        .expect(
-            "if vhci driver is open, then driver is loaded, and should have all the information",
-        ))
+    }
+
+    /// Sets the options for connections to hosts made by later attaches.
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    pub const fn socket_options(&self) -> &SocketOptions {
+        &self.socket_options
+    }
+
+    /// Keeps port records in `path` instead of [`STATE_PATH`].
+    ///
+    /// Other programs, like the usbip utility, only
+    /// look for the records in [`STATE_PATH`].
+    pub fn set_state_path(&mut self, path: impl Into<PathBuf>) {
+        self.state_path = path.into();
+        *self.port_records.borrow_mut() = PortRecords::new();
     }
 
     pub fn attach(&mut self, args: AttachArgs) -> crate::vhci::Result<u16> {
+        self.attach_with_cancel(args, &CancellationToken::new())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(host = %args.host, busid = %args.bus_id, port = tracing::field::Empty),
+            err
+        )
+    )]
+    pub fn attach_with_cancel(
+        &mut self,
+        args: AttachArgs,
+        token: &CancellationToken,
+    ) -> crate::vhci::Result<u16> {
+        self.attach_reporting(args, token).map(|(port, _)| port)
+    }
+
+    /// Like [`Driver::attach_with_cancel`], but also returns
+    /// the device the way the host described it.
+    pub(crate) fn attach_reporting(
+        &mut self,
+        args: AttachArgs,
+        token: &CancellationToken,
+    ) -> crate::vhci::Result<(u16, crate::UsbDevice)> {
         let AttachArgs { host, bus_id } = args;
 
-        let mut socket = UsbipStream::connect(&host)?;
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+
+        let (socket, usb_dev) = Self::handshake(host, &bus_id, &self.socket_options, token)?;
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
 
-        // Query host for USB info
-        let req = OpCommon::request(Protocol::OP_REQ_IMPORT);
-        socket.send(&req)?;
+        let port = self.submit(socket.as_tcp(), &usb_dev, &bus_id)?;
+        Ok((port, usb_dev))
+    }
+
+    /// Attaches each device in `args`, running up to
+    /// `max_parallel` handshakes with hosts at once.
+    ///
+    /// Ports are picked and handed to the kernel one device
+    /// at a time, as each handshake finishes. The results are
+    /// in the same order as `args`.
+    pub fn attach_many<'a>(
+        &mut self,
+        args: impl IntoIterator<Item = AttachArgs<'a>>,
+        max_parallel: NonZeroUsize,
+    ) -> Vec<crate::vhci::Result<u16>> {
+        self.attach_many_with_cancel(args, max_parallel, &CancellationToken::new())
+    }
 
-        let req = OpImportRequest::new(bus_id);
-        socket.send(&req)?;
+    /// Like [`Driver::attach_many`], but once `token` is cancelled,
+    /// shuts down the handshakes in progress and fails every
+    /// device that wasn't handed to the kernel yet with
+    /// [`Error::Cancelled`].
+    pub fn attach_many_with_cancel<'a>(
+        &mut self,
+        args: impl IntoIterator<Item = AttachArgs<'a>>,
+        max_parallel: NonZeroUsize,
+        token: &CancellationToken,
+    ) -> Vec<crate::vhci::Result<u16>> {
+        let args: Vec<_> = args.into_iter().collect();
+        let mut results: Vec<_> = args.iter().map(|_| None).collect();
+        let next = AtomicUsize::new(0);
+        let (done, handshakes) = mpsc::channel();
+        let options = self.socket_options.clone();
+
+        thread::scope(|scope| {
+            for _ in 0..max_parallel.get().min(args.len()) {
+                let (args, next, options, done) = (&args, &next, &options, done.clone());
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(args) = args.get(index) else {
+                        break;
+                    };
+                    let handshake = if token.is_cancelled() {
+                        Err(Error::Cancelled)
+                    } else {
+                        Self::handshake(args.host, &args.bus_id, options, token)
+                    };
+                    let _ = done.send((index, handshake));
+                });
+            }
+            drop(done);
+
+            for (index, handshake) in handshakes {
+                let result = handshake.and_then(|(socket, usb_dev)| {
+                    if token.is_cancelled() {
+                        return Err(Error::Cancelled);
+                    }
+                    self.submit(socket.as_tcp(), &usb_dev, &args[index].bus_id)
+                });
+                results[index] = Some(result);
+            }
+        });
 
-        let rep: OpCommon = socket.recv()?;
-        assert_ne!(rep.validate(Protocol::OP_REP_IMPORT)?, Status::Unexpected);
+        results.into_iter().map(Option::unwrap).collect()
+    }
 
-        let rep: OpImportReply = socket.recv()?;
-        let usb_dev = rep.into_inner();
+    /// Connects to `host` and imports the device at `bus_id`,
+    /// shutting down the connection if `token` is cancelled
+    /// while waiting on the host.
+    fn handshake(
+        host: SocketAddr,
+        bus_id: &BusId,
+        options: &SocketOptions,
+        token: &CancellationToken,
+    ) -> crate::vhci::Result<(UsbipStream, crate::UsbDevice)> {
+        let started = Instant::now();
+        let connect_err =
+            |err: std::io::Error| Error::from(err).at_stage_timed(AttachStage::Connect, started);
+        let mut socket = UsbipStream::connect_with(&host, options).map_err(connect_err)?;
+
+        let handle = socket.shutdown_handle().map_err(connect_err)?;
+        let guard = token.on_cancel(move || handle.shutdown());
+        let usb_dev = match Self::import(&mut socket, bus_id) {
+            Ok(usb_dev) => usb_dev,
+            Err(_) if token.is_cancelled() => return Err(Error::Cancelled),
+            Err(err) => return Err(err.at_stage_timed(AttachStage::Handshake, started)),
+        };
 
-        if usb_dev.bus_id() != bus_id {
-            return Err(
-                crate::net::Error::BusIdMismatch(Beef::Borrowed(usb_dev.bus_id()).into()).into(),
-            );
-        }
+        // The socket is about to be handed over to the
+        // kernel, so it mustn't be shut down anymore.
+        drop(guard);
+        Ok((socket, usb_dev))
+    }
 
+    /// Hands the connection of an imported device to the
+    /// kernel on a free port matching its speed, and
+    /// records the connection for [`PortRecord`].
+    fn submit(
+        &mut self,
+        socket: &TcpStream,
+        usb_dev: &crate::UsbDevice,
+        bus_id: &BusId,
+    ) -> crate::vhci::Result<u16> {
         // Find open port for attaching USB device
         let speed = usb_dev.speed();
         let dev_id = usb_dev.dev_id();
@@ -548,10 +1260,10 @@ impl Driver {
         let port = self
             .open_ports_mut()
             .get_next(speed)
-            .ok_or(Error::NoFreePorts)?;
+            .ok_or_else(|| Error::NoFreePorts.at_stage(AttachStage::PortSelect))?;
 
         sysfs::attach(
-            self.udev(),
+            self.hc_path(),
             NewConnection {
                 port: port.port,
                 fd: socket.as_fd(),
@@ -559,26 +1271,52 @@ impl Driver {
                 speed,
             },
         )
-        .inspect_err(|_| self.open_ports_mut().push(port))?;
+        .map_err(|err| {
+            self.open_ports_mut().push(port);
+            Error::from(err).at_stage(AttachStage::KernelSubmit)
+        })?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("port", port.port);
 
         // Record connection
-        if let Err(err) = self.record_connection(port.port, socket.peer_addr()?, bus_id) {
+        let peer = socket
+            .peer_addr()
+            .map_err(|err| Error::from(err).at_stage(AttachStage::Record))?;
+        if let Err(err) = self.record_connection(port.port, peer, bus_id) {
+            #[cfg(feature = "log")]
+            log::warn!(target: "usbip_core::vhci", "failed to record new connection: {err}");
+            #[cfg(not(feature = "log"))]
             eprintln!("Failed to record new connection: {err}");
         }
 
         Ok(port.port)
     }
 
-    fn record_connection(&self, port: u16, host: SocketAddr, bus_id: &str) -> std::io::Result<()> {
-        create_state_path()?;
+    fn import(socket: &mut UsbipStream, bus_id: &BusId) -> crate::vhci::Result<crate::UsbDevice> {
+        let usb_dev = crate::remote::import(socket, bus_id)?;
 
-        let path = StackStr::<256>::try_from(format_args!("{}/port{}", STATE_PATH, port)).unwrap();
-        let mut file = file_open(&*path)?;
+        // The host has nothing more to say until the kernel
+        // takes over, and the kernel can't see bytes we read.
+        if socket.has_unread() {
+            return Err(crate::net::Error::De(bincode::error::DecodeError::Other(
+                "host sent data past the import reply",
+            ))
+            .into());
+        }
+        Ok(usb_dev)
+    }
+
+    fn record_connection(&self, port: u16, host: SocketAddr, bus_id: &BusId) -> std::io::Result<()> {
+        create_state_path(&self.state_path)?;
+
+        let mut file = file_open(self.state_path.join(format!("port{port}")))?;
         writeln!(file, "{} {}", host, bus_id)?;
+        self.port_records.borrow_mut().remove(port);
 
         Ok(())
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), err))]
     pub fn detach(&mut self, port: u16) -> crate::vhci::Result<()> {
         if self
             .open_ports()
@@ -591,7 +1329,7 @@ impl Driver {
         }
 
         self.remove_connection(port);
-        sysfs::detach(self.udev(), port)?;
+        sysfs::detach(self.hc_path(), port)?;
 
         // TODO: Add some sort of way to add back the port
 
@@ -599,12 +1337,12 @@ impl Driver {
     }
 
     fn remove_connection(&self, port: u16) {
-        let path = StackStr::<200>::try_from(format_args!("{}/port{}", STATE_PATH, port)).unwrap();
-        let _ = std::fs::remove_file(&*path);
+        let _ = std::fs::remove_file(self.state_path.join(format!("port{port}")));
+        self.port_records.borrow_mut().remove(port);
     }
 }
 
-/// Creates the VHCI state path for persisting connection info,
+/// Creates `state_path` for persisting connection info,
 /// returning if the directory already exists.
 ///
 /// # Error
@@ -612,13 +1350,13 @@ impl Driver {
 /// This function will return an error if the path already
 /// exists and is NOT a directory, or if a general I/O error
 /// has occurred.
-fn create_state_path() -> std::io::Result<()> {
+fn create_state_path(state_path: &Path) -> std::io::Result<()> {
     use std::os::unix::fs::DirBuilderExt;
 
-    if let Err(err) = std::fs::DirBuilder::new().mode(0o700).create(STATE_PATH) {
+    if let Err(err) = std::fs::DirBuilder::new().mode(0o700).create(state_path) {
         match err.kind() {
             std::io::ErrorKind::AlreadyExists
-                if std::fs::metadata(STATE_PATH).is_ok_and(|s| s.is_dir()) => {}
+                if std::fs::metadata(state_path).is_ok_and(|s| s.is_dir()) => {}
             _ => Err(err)?,
         }
     }
@@ -639,10 +1377,42 @@ fn file_open<P: AsRef<Path>>(path: P) -> std::io::Result<std::fs::File> {
         .open(path)
 }
 
-fn num_controllers(hc_device: &udev::Device) -> crate::vhci::Result<NonZeroUsize> {
-    let platform = hc_device.parent().ok_or(Error::DriverNotFound)?;
-    let count: NonZeroUsize = platform
+/// Finds the first controller's directory in sysfs,
+/// and reads its number of ports.
+#[cfg(feature = "udev")]
+fn find_hc_device() -> crate::vhci::Result<(String, NonZeroUsize)> {
+    let hc_device = udev::Device::from_subsystem_sysname(BUS_TYPE.into(), DEVICE_NAME.into())
+        .map_err(|_| Error::DriverNotFound)?;
+    let num_ports: NonZeroUsize = hc_device
+        .sysattr("nports")
+        .map_err(|err| Error::InvalidDriverData(Box::new(err.into_dyn())))?;
+    let hc_path = hc_device
         .syspath()
+        .to_str()
+        .ok_or_else(|| Error::InvalidDriverData("vhci syspath is not UTF-8".into()))?;
+    Ok((hc_path.to_owned(), num_ports))
+}
+
+/// Finds the first controller's directory in sysfs,
+/// and reads its number of ports.
+#[cfg(not(feature = "udev"))]
+fn find_hc_device() -> crate::vhci::Result<(String, NonZeroUsize)> {
+    let hc_path = fs::canonicalize(format!("/sys/bus/{BUS_TYPE}/devices/{DEVICE_NAME}"))
+        .map_err(|_| Error::DriverNotFound)?
+        .into_os_string()
+        .into_string()
+        .map_err(|_| Error::InvalidDriverData("vhci syspath is not UTF-8".into()))?;
+    let num_ports: NonZeroUsize = fs::read_to_string(format!("{hc_path}/nports"))
+        .map_err(|err| Error::InvalidDriverData(Box::new(err)))?
+        .trim()
+        .parse()
+        .map_err(|err| Error::InvalidDriverData(Box::new(err)))?;
+    Ok((hc_path, num_ports))
+}
+
+fn num_controllers(hc_path: &Path) -> crate::vhci::Result<NonZeroUsize> {
+    let platform = hc_path.parent().ok_or(Error::DriverNotFound)?;
+    let count: NonZeroUsize = platform
         .read_dir()?
         .filter(|result| {
             result.as_ref().is_ok_and(|entry| {
@@ -660,20 +1430,25 @@ fn num_controllers(hc_device: &udev::Device) -> crate::vhci::Result<NonZeroUsize
 }
 
 pub trait UnixVhciExt: Sealed {
-    fn refresh_open_ports(&mut self);
+    /// Rereads the vhci driver's free ports.
+    ///
+    /// # Errors
+    /// This function will return an error if the
+    /// driver's port status couldn't be parsed.
+    fn refresh_open_ports(&mut self) -> crate::vhci::Result<()>;
 }
 
 impl Sealed for Driver {}
 impl UnixVhciExt for Driver {
-    fn refresh_open_ports(&mut self) {
-        let open_ports = InitData {
-            hc_device: self.udev(),
+    fn refresh_open_ports(&mut self) -> crate::vhci::Result<()> {
+        self.open_ports = InitData {
+            hc_path: self.hc_path(),
+            status_buf: &mut self.status_buf.borrow_mut(),
             num_controllers: self.num_controllers(),
             num_ports: self.num_ports(),
         }
-        .try_into()
-        .expect("parsing open port data from open udev context");
+        .try_into()?;
 
-        self.open_ports = open_ports;
+        Ok(())
     }
 }
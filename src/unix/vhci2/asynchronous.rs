@@ -0,0 +1,312 @@
+//! The vhci driver on tokio. The handshake with the host runs
+//! on a [`tokio::net::TcpStream`], and only the driver calls
+//! that hand the connection to the kernel block.
+
+use std::{
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+    time::Instant,
+};
+
+use tokio::{
+    net::{TcpSocket, TcpStream},
+    sync::oneshot,
+};
+
+use crate::{
+    containers::beef::Beef,
+    net::{IncrementalDecoder, OpCommon, OpImportReply, OpImportRequest, Protocol, Status},
+    unix::net::{SocketOptions, TcpStreamExt},
+    util::{recv_pdu, send_pdu},
+    vhci::{AttachArgs, AttachStage, Error, PortCounts},
+    BusId,
+};
+
+use super::{Driver, UnixImportedDevices};
+
+type Job = Box<dyn FnOnce(&mut Driver) + Send>;
+
+/// A handle to the vhci driver for use on a tokio runtime.
+///
+/// udev handles can't move between threads, so the driver
+/// lives on a thread of its own, which runs the blocking
+/// driver calls one at a time. Everything else, like the
+/// handshake with the host, runs on the caller's task, so
+/// any number of attaches can be in flight at once.
+///
+/// Handles are cheap to clone. The driver thread exits
+/// once every handle has been dropped.
+#[derive(Clone)]
+pub struct AsyncVhciDriver {
+    jobs: mpsc::Sender<Job>,
+    socket_options: SocketOptions,
+}
+
+impl AsyncVhciDriver {
+    /// Opens the vhci driver on a new thread.
+    ///
+    /// # Errors
+    /// This function will return an error if the underlying
+    /// kernel driver was not loaded, or if the thread
+    /// couldn't be spawned.
+    pub async fn open() -> crate::vhci::Result<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (opened, opening) = oneshot::channel();
+        std::thread::Builder::new()
+            .name("usbip-vhci".into())
+            .spawn(move || {
+                let mut driver = match Driver::open() {
+                    Ok(driver) => driver,
+                    Err(err) => {
+                        let _ = opened.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = opened.send(Ok(()));
+                for job in queue {
+                    job(&mut driver);
+                }
+            })?;
+
+        opening.await.unwrap_or(Err(Error::DriverNotFound))?;
+        Ok(Self {
+            jobs,
+            socket_options: SocketOptions::new(),
+        })
+    }
+
+    /// Sets the kernel socket options for the connections
+    /// to hosts made by later attaches through this handle.
+    pub fn set_socket_options(&mut self, options: SocketOptions) {
+        self.socket_options = options;
+    }
+
+    /// Like [`VhciDriver::attach`], but connects to and
+    /// imports the device from the host asynchronously.
+    ///
+    /// Dropping the future before the device is handed to the
+    /// kernel closes the connection. After that point, the
+    /// attach finishes on the driver's thread regardless.
+    ///
+    /// # Errors
+    /// Returned errors carry the host and bus id of the device,
+    /// just like [`VhciDriver::attach`].
+    ///
+    /// [`VhciDriver::attach`]: crate::vhci::VhciDriver::attach
+    pub async fn attach(&self, args: AttachArgs<'_>) -> crate::vhci::Result<u16> {
+        let AttachArgs { host, bus_id } = args;
+        let bus_id = bus_id.into_owned();
+        let result = self.attach_inner(host, bus_id.clone()).await;
+        #[cfg(feature = "metrics")]
+        crate::stats::attach(&result);
+        result.map_err(|err| err.for_device(Some(host), Some(bus_id), None))
+    }
+
+    async fn attach_inner(
+        &self,
+        host: SocketAddr,
+        bus_id: BusId<'static>,
+    ) -> crate::vhci::Result<u16> {
+        let started = Instant::now();
+        let connect_err =
+            |err: std::io::Error| Error::from(err).at_stage_timed(AttachStage::Connect, started);
+
+        let mut socket = connect(host, &self.socket_options)
+            .await
+            .map_err(connect_err)?;
+        socket.set_nodelay(true).map_err(connect_err)?;
+        self.socket_options.apply(&socket).map_err(connect_err)?;
+        let usb_dev = import(&mut socket, &bus_id)
+            .await
+            .map_err(|err| err.at_stage_timed(AttachStage::Handshake, started))?;
+
+        // The kernel reads from the socket itself,
+        // so it has to be back in blocking mode.
+        let socket = socket.into_std().map_err(connect_err)?;
+        socket.set_nonblocking(false).map_err(connect_err)?;
+        socket.set_keepalive(true).map_err(connect_err)?;
+
+        self.run(move |driver| driver.submit(&socket, &usb_dev, &bus_id))
+            .await
+    }
+
+    /// Like [`VhciDriver::detach`], run on the driver's thread.
+    ///
+    /// # Errors
+    /// Returned errors carry the port, see [`Error::port`].
+    ///
+    /// [`VhciDriver::detach`]: crate::vhci::VhciDriver::detach
+    pub async fn detach(&self, port: u16) -> crate::vhci::Result<()> {
+        let result = self.run(move |driver| driver.detach(port)).await;
+        #[cfg(feature = "metrics")]
+        crate::stats::detach(&result);
+        result.map_err(|err| err.for_device(None, None, Some(port)))
+    }
+
+    /// Like [`VhciDriver::imported_devices`],
+    /// run on the driver's thread.
+    ///
+    /// [`VhciDriver::imported_devices`]: crate::vhci::VhciDriver::imported_devices
+    pub async fn imported_devices(&self) -> crate::vhci::Result<UnixImportedDevices> {
+        self.run(|driver| driver.imported_devices()).await
+    }
+
+    /// Like [`VhciDriver::port_counts`],
+    /// run on the driver's thread.
+    ///
+    /// [`VhciDriver::port_counts`]: crate::vhci::VhciDriver::port_counts
+    pub async fn port_counts(&self) -> crate::vhci::Result<PortCounts> {
+        self.run(|driver| driver.port_counts()).await
+    }
+
+    /// Runs `f` on the driver's thread, resuming
+    /// its panic on this task if it panicked.
+    async fn run<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Driver) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move |driver| {
+                let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(|| f(driver))));
+            }))
+            .expect("the driver thread lives as long as its handles");
+        match rx.await.expect("the driver thread always replies") {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+/// Connects to `host`, through the proxy in `options` if there
+/// is one. The proxy's handshake is short, so it's done on
+/// a blocking thread rather than written twice.
+///
+/// The buffer sizes are set before connecting, like
+/// [`UsbipStream::connect_with`] does.
+///
+/// [`UsbipStream::connect_with`]: crate::unix::UsbipStream::connect_with
+async fn connect(host: SocketAddr, options: &SocketOptions) -> std::io::Result<TcpStream> {
+    if !options.connects_blocking() {
+        let socket = match host {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        let (send, recv) = options.buffer_sizes();
+        if let Some(bytes) = send {
+            socket.set_send_buffer_size(bytes.unsigned_abs())?;
+        }
+        if let Some(bytes) = recv {
+            socket.set_recv_buffer_size(bytes.unsigned_abs())?;
+        }
+        return socket.connect(host).await;
+    }
+    let options = options.clone();
+    let socket = tokio::task::spawn_blocking(move || options.connect_tcp(&host)).await??;
+    socket.set_nonblocking(true)?;
+    TcpStream::from_std(socket)
+}
+
+async fn import(
+    socket: &mut TcpStream,
+    bus_id: &BusId<'_>,
+) -> crate::vhci::Result<crate::UsbDevice> {
+    let mut decoder = IncrementalDecoder::new();
+    let req = (
+        OpCommon::request(Protocol::OP_REQ_IMPORT),
+        OpImportRequest::new(bus_id.clone()),
+    );
+    send_pdu::<_, _, Error>(socket, &req).await?;
+
+    let rep: OpCommon = recv_pdu::<_, _, Error>(socket, &mut decoder).await?;
+    let status = rep.validate(Protocol::OP_REP_IMPORT)?;
+    if status != Status::Success {
+        return Err(crate::net::Error::Rejected(status).into());
+    }
+
+    let rep: OpImportReply = recv_pdu::<_, _, Error>(socket, &mut decoder).await?;
+    let usb_dev = rep.into_inner();
+    if !decoder.is_empty() {
+        return Err(crate::net::Error::De(bincode::error::DecodeError::Other(
+            "host sent data past the import reply",
+        ))
+        .into());
+    }
+
+    if usb_dev.bus_id() != bus_id.as_str() {
+        return Err(
+            crate::net::Error::BusIdMismatch(Beef::Borrowed(usb_dev.bus_id()).into()).into(),
+        );
+    }
+    usb_dev.validate().map_err(crate::net::Error::from)?;
+
+    Ok(usb_dev)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::net::bincode_config;
+
+    #[tokio::test]
+    async fn import_reports_a_rejected_device() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let mut req = [0; 8 + 32];
+            client.read_exact(&mut req).await.unwrap();
+            let rep = OpCommon::request(Protocol::OP_REP_IMPORT).reply(Status::NoDev);
+            // Split the reply to make sure it's read incrementally.
+            let bytes = bincode::encode_to_vec(rep, bincode_config()).unwrap();
+            client.write_all(&bytes[..3]).await.unwrap();
+            client.write_all(&bytes[3..]).await.unwrap();
+        });
+
+        let mut socket = TcpStream::connect(host).await.unwrap();
+        let bus_id = "1-1".parse::<BusId>().unwrap();
+        let err = import(&mut socket, &bus_id).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Net(crate::net::Error::Rejected(Status::NoDev))
+        ));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connect_sizes_the_receive_buffer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = SocketOptions::new().recv_buffer(4096);
+        let socket = connect(listener.local_addr().unwrap(), &options)
+            .await
+            .unwrap();
+        let socket = socket.into_std().unwrap();
+        // The kernel doubles it to make room for its own bookkeeping.
+        assert_eq!(recv_buffer_size(&socket), 8192);
+    }
+
+    fn recv_buffer_size(socket: &std::net::TcpStream) -> libc::c_int {
+        use std::os::fd::AsRawFd;
+
+        let mut val: libc::c_int = 0;
+        let mut len = core::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                core::ptr::addr_of_mut!(val).cast(),
+                &mut len,
+            )
+        };
+        assert_eq!(rc, 0);
+        val
+    }
+}
@@ -1,8 +1,13 @@
+#[cfg(feature = "std")]
 pub mod __private {
     pub trait Sealed {}
 }
 
-use std::{ffi::c_char, str::FromStr};
+use core::{ffi::c_char, marker::PhantomData};
+
+use bincode::Decode;
+#[cfg(feature = "std")]
+use std::str::FromStr;
 
 /// Describes the encoded size of the object
 /// when written to/read from a [`bincode`] buffer.
@@ -13,15 +18,108 @@ use std::{ffi::c_char, str::FromStr};
 /// the size of the object when encoded into/decoded
 /// from [`bincode`]. Furthermore, the object's
 /// encoded size must be known at compile time.
+#[cfg_attr(not(any(windows, test)), allow(dead_code))]
 pub unsafe trait EncodedSize {
     const ENCODED_SIZE_OF: usize;
-    const IS_ZERO_SIZED: bool = <Self as EncodedSize>::ENCODED_SIZE_OF == 0;
 }
 
 
+/// Padding the size of `T`, like the bytes a C compiler adds
+/// to the end of a struct with an irregular size.
+///
+/// Encodes as `size_of::<T>()` zero bytes. Decoding skips the
+/// same number of bytes without checking them, since drivers
+/// don't always zero their padding.
+pub struct Padding<T>(PhantomData<T>);
+
+impl<T> Padding<T> {
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> bincode::Encode for Padding<T> {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        for _ in 0..core::mem::size_of::<T>() {
+            0u8.encode(encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> bincode::Decode for Padding<T> {
+    fn decode<D: bincode::de::Decoder>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        for _ in 0..core::mem::size_of::<T>() {
+            u8::decode(decoder)?;
+        }
+        Ok(Self::new())
+    }
+}
+
+impl<'de, T> bincode::BorrowDecode<'de> for Padding<T> {
+    fn borrow_decode<D: bincode::de::BorrowDecoder<'de>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        bincode::Decode::decode(decoder)
+    }
+}
+
+/// Emits a [`tracing`] debug event when the `tracing`
+/// feature is on, and expands to nothing otherwise.
+///
+/// [`tracing`]: https://docs.rs/tracing
+// Unused when neither side is built.
+#[cfg(feature = "std")]
+#[allow(unused_macros)]
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+#[cfg(feature = "std")]
+#[allow(unused_imports)]
+pub(crate) use trace_event;
+
+/// Displays an error followed by each of its
+/// [`source`]s, separated by `": "`.
+///
+/// [`source`]: core::error::Error::source
+#[cfg(any(
+    feature = "serde",
+    feature = "ffi",
+    feature = "dbus",
+    feature = "client",
+    all(feature = "log", feature = "server", feature = "tokio")
+))]
+pub struct ErrorChain<'a>(pub &'a (dyn core::error::Error + 'static));
+
+#[cfg(any(
+    feature = "serde",
+    feature = "ffi",
+    feature = "dbus",
+    feature = "client",
+    all(feature = "log", feature = "server", feature = "tokio")
+))]
+impl core::fmt::Display for ErrorChain<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(err) = source {
+            write!(f, ": {err}")?;
+            source = err.source();
+        }
+        Ok(())
+    }
+}
+
 pub fn decode_zero_byte<D: bincode::de::Decoder>(decoder: &mut D) -> Result<(), bincode::error::DecodeError> {
     // Gotta make sure it's a null byte!
-    use bincode::Decode;
     if u8::decode(decoder)? != 0u8 {
         Err(bincode::error::DecodeError::Other(
             "Nonzero value in the null-byte position",
@@ -31,32 +129,118 @@ pub fn decode_zero_byte<D: bincode::de::Decoder>(decoder: &mut D) -> Result<(),
     }
 }
 
+/// The error returned by [`parse_token`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum ParseTokenError<E> {
+    /// The stream ran out of tokens.
+    Missing,
+    Invalid(E),
+}
+
+#[cfg(feature = "std")]
+impl<E> core::fmt::Display for ParseTokenError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParseTokenError::Missing => write!(f, "Expected another token"),
+            ParseTokenError::Invalid(_) => write!(f, "Invalid token"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for ParseTokenError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseTokenError::Missing => None,
+            ParseTokenError::Invalid(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 #[allow(dead_code)]
 pub fn parse_token<'a, 'b: 'a, T>(
     tokens: &'a mut impl Iterator<Item = &'b str>,
-) -> Result<T, T::Err>
+) -> Result<T, ParseTokenError<T::Err>>
 where
     T: FromStr,
     T::Err: std::error::Error,
 {
     tokens
         .next()
-        .expect("There should be another item in the string stream")
+        .ok_or(ParseTokenError::Missing)?
         .trim()
         .parse()
+        .map_err(ParseTokenError::Invalid)
 }
 
-pub fn into_dyn_err<T: std::error::Error + 'static>(err: T) -> Box<dyn std::error::Error> {
+#[cfg(feature = "std")]
+pub fn into_dyn_err<T>(err: T) -> Box<dyn std::error::Error + Send + Sync>
+where
+    T: std::error::Error + Send + Sync + 'static,
+{
     Box::from(err)
 }
 
+/// Encodes `data` and writes it to `socket`.
+#[cfg(all(
+    feature = "tokio",
+    any(all(unix, feature = "client"), feature = "server")
+))]
+pub async fn send_pdu<S, T, E>(socket: &mut S, data: &T) -> Result<(), E>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+    T: bincode::Encode,
+    E: From<std::io::Error> + From<crate::net::Error>,
+{
+    use tokio::io::AsyncWriteExt;
+
+    let bytes = bincode::encode_to_vec(data, crate::net::bincode_config())
+        .map_err(crate::net::Error::Enc)?;
+    socket.write_all(&bytes).await?;
+    trace_event!(pdu = core::any::type_name::<T>(), len = bytes.len(), "sent");
+    Ok(())
+}
+
+/// Decodes a `T` with `decoder`, reading
+/// more from `socket` until there's enough.
+#[cfg(all(
+    feature = "tokio",
+    any(all(unix, feature = "client"), feature = "server")
+))]
+pub async fn recv_pdu<S, T, E>(
+    socket: &mut S,
+    decoder: &mut crate::net::IncrementalDecoder,
+) -> Result<T, E>
+where
+    S: tokio::io::AsyncRead + Unpin,
+    T: bincode::Decode,
+    E: From<std::io::Error> + From<crate::net::Error>,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut chunk = [0; 1024];
+    let mut read = 0;
+    loop {
+        if let Some(data) = decoder.feed(&chunk[..read])? {
+            trace_event!(pdu = core::any::type_name::<T>(), "received");
+            return Ok(data);
+        }
+        read = socket.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+}
+
 #[inline]
 pub const fn cast_cchar_to_u8(a: &[c_char]) -> &[u8] {
     // SAFETY: The slice is of type c_char, which can
     //         only be u8 (in which this cast does nothing)
     //         or i8. UTF-8 allows individual character bytes
     //         to be either a u8 or i8.
-    unsafe { std::slice::from_raw_parts(a.as_ptr().cast::<u8>(), a.len()) }
+    unsafe { core::slice::from_raw_parts(a.as_ptr().cast::<u8>(), a.len()) }
 }
 
 #[inline]
@@ -65,5 +249,5 @@ pub fn cast_cchar_to_u8_mut(a: &mut [c_char]) -> &mut [u8] {
     //         only be u8 (in which this cast does nothing)
     //         or i8. UTF-8 allows individual character bytes
     //         to be either a u8 or i8.
-    unsafe { std::slice::from_raw_parts_mut(a.as_mut_ptr().cast::<u8>(), a.len()) }
+    unsafe { core::slice::from_raw_parts_mut(a.as_mut_ptr().cast::<u8>(), a.len()) }
 }
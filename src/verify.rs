@@ -0,0 +1,292 @@
+//! Checking an attached device against what its host reported.
+//!
+//! A host describes a device in its import reply, but the kernel
+//! enumerates the device by asking it for its descriptors over
+//! the connection. A flaky host, or a man in the middle, can make
+//! the two disagree. [`VhciDriver::attach_verified`] waits for the
+//! device to show up locally and compares them through libusb.
+//!
+//! Serial numbers aren't part of the import reply, so they can't be
+//! compared here. The local one is kept in [`Report::serial`], to be
+//! checked against whatever the caller knows about the device.
+//!
+//! [`VhciDriver::attach_verified`]: crate::vhci::VhciDriver::attach_verified
+
+use core::fmt;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    vhci::{AttachStage, Driver, Error},
+    UsbDevice,
+};
+
+/// How often to look for the device while it's being enumerated.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A field of the device descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Field {
+    Vendor,
+    Product,
+    BcdDevice,
+    Class,
+    Subclass,
+    Protocol,
+    NumConfigurations,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Vendor => write!(f, "idVendor"),
+            Field::Product => write!(f, "idProduct"),
+            Field::BcdDevice => write!(f, "bcdDevice"),
+            Field::Class => write!(f, "bDeviceClass"),
+            Field::Subclass => write!(f, "bDeviceSubClass"),
+            Field::Protocol => write!(f, "bDeviceProtocol"),
+            Field::NumConfigurations => write!(f, "bNumConfigurations"),
+        }
+    }
+}
+
+/// A field the device and its host disagree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub field: Field,
+    /// What the host reported in its import reply.
+    pub remote: u16,
+    /// What the device's descriptor says.
+    pub local: u16,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is {:#06x}, but the host reported {:#06x}",
+            self.field, self.local, self.remote
+        )
+    }
+}
+
+/// The outcome of checking an attached device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    mismatches: Vec<Mismatch>,
+    serial: Option<String>,
+}
+
+impl Report {
+    /// A report with nothing to flag, for
+    /// devices that can't be read back.
+    #[cfg(feature = "mock-driver")]
+    pub(crate) const fn matching() -> Self {
+        Self {
+            mismatches: Vec::new(),
+            serial: None,
+        }
+    }
+
+    /// Returns `true` if the device is what the host said it is.
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    pub fn mismatches(&self) -> &[Mismatch] {
+        &self.mismatches
+    }
+
+    /// The device's serial number, if it has one
+    /// and the device could be opened to read it.
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+}
+
+/// The fields of a device descriptor that are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Descriptor {
+    vendor: u16,
+    product: u16,
+    bcd_device: u16,
+    class: u8,
+    subclass: u8,
+    protocol: u8,
+    num_configurations: u8,
+}
+
+impl From<&UsbDevice> for Descriptor {
+    fn from(dev: &UsbDevice) -> Self {
+        Self {
+            vendor: dev.id_vendor(),
+            product: dev.id_product(),
+            bcd_device: dev.bcd_device(),
+            class: dev.device_class(),
+            subclass: dev.device_subclass(),
+            protocol: dev.device_protocol(),
+            num_configurations: dev.num_configurations(),
+        }
+    }
+}
+
+impl From<&rusb::DeviceDescriptor> for Descriptor {
+    fn from(desc: &rusb::DeviceDescriptor) -> Self {
+        let version = desc.device_version();
+        let (major, minor, sub_minor) = (
+            u16::from(version.major()),
+            u16::from(version.minor()),
+            u16::from(version.sub_minor()),
+        );
+        Self {
+            vendor: desc.vendor_id(),
+            product: desc.product_id(),
+            bcd_device: (major / 10) << 12 | (major % 10) << 8 | minor << 4 | sub_minor,
+            class: desc.class_code(),
+            subclass: desc.sub_class_code(),
+            protocol: desc.protocol_code(),
+            num_configurations: desc.num_configurations(),
+        }
+    }
+}
+
+fn compare(remote: Descriptor, local: Descriptor) -> Vec<Mismatch> {
+    [
+        (Field::Vendor, remote.vendor, local.vendor),
+        (Field::Product, remote.product, local.product),
+        (Field::BcdDevice, remote.bcd_device, local.bcd_device),
+        (Field::Class, remote.class.into(), local.class.into()),
+        (
+            Field::Subclass,
+            remote.subclass.into(),
+            local.subclass.into(),
+        ),
+        (
+            Field::Protocol,
+            remote.protocol.into(),
+            local.protocol.into(),
+        ),
+        (
+            Field::NumConfigurations,
+            remote.num_configurations.into(),
+            local.num_configurations.into(),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, remote, local)| remote != local)
+    .map(|(field, remote, local)| Mismatch {
+        field,
+        remote,
+        local,
+    })
+    .collect()
+}
+
+/// Waits up to `timeout` for the device on `port` to be
+/// enumerated, then compares it with `remote`.
+pub(crate) fn check(
+    driver: &Driver,
+    port: u16,
+    remote: &UsbDevice,
+    timeout: Duration,
+) -> Result<Report, Error> {
+    let started = Instant::now();
+    let usb_err = |err: rusb::Error| {
+        let kind = match err {
+            rusb::Error::Access => std::io::ErrorKind::PermissionDenied,
+            rusb::Error::NoDevice | rusb::Error::NotFound => std::io::ErrorKind::NotFound,
+            rusb::Error::Timeout => std::io::ErrorKind::TimedOut,
+            _ => std::io::ErrorKind::Other,
+        };
+        Error::from(std::io::Error::new(kind, err)).at_stage(AttachStage::Verify)
+    };
+
+    let device = loop {
+        // Until the kernel is done enumerating, the port's
+        // local device may be missing or not readable yet.
+        let local = driver.imported_devices().ok().and_then(|idevs| {
+            let idev = idevs.get().iter().find(|idev| idev.port() == port)?;
+            let dev = idev.local_device();
+            Some((
+                u8::try_from(dev.bus_num()).ok()?,
+                u8::try_from(dev.dev_num()).ok()?,
+            ))
+        });
+        if let Some((bus, address)) = local {
+            let device = rusb::devices()
+                .map_err(usb_err)?
+                .iter()
+                .find(|dev| dev.bus_number() == bus && dev.address() == address);
+            if let Some(device) = device {
+                break device;
+            }
+        }
+        if started.elapsed() >= timeout {
+            return Err(Error::Timeout {
+                stage: AttachStage::Verify,
+                elapsed: started.elapsed(),
+            });
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let desc = device.device_descriptor().map_err(usb_err)?;
+    // Opening the device takes more privileges than reading
+    // its descriptor, so the serial number is best effort.
+    let serial = device
+        .open()
+        .and_then(|handle| handle.read_serial_number_string_ascii(&desc))
+        .ok();
+    Ok(Report {
+        mismatches: compare(remote.into(), (&desc).into()),
+        serial,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_differing_field() {
+        let remote = Descriptor {
+            vendor: 0x1d6b,
+            product: 0x0002,
+            bcd_device: 0x0515,
+            class: 0x09,
+            subclass: 0,
+            protocol: 1,
+            num_configurations: 1,
+        };
+        assert!(compare(remote, remote).is_empty());
+
+        let local = Descriptor {
+            product: 0x0003,
+            num_configurations: 2,
+            ..remote
+        };
+        let mismatches = compare(remote, local);
+        assert_eq!(
+            mismatches,
+            [
+                Mismatch {
+                    field: Field::Product,
+                    remote: 0x0002,
+                    local: 0x0003,
+                },
+                Mismatch {
+                    field: Field::NumConfigurations,
+                    remote: 1,
+                    local: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "idProduct is 0x0003, but the host reported 0x0002"
+        );
+    }
+}
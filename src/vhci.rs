@@ -1,17 +1,345 @@
 //! Ahh, the silly vhci module. This is where everything begins.
 
-pub mod error2 {
-    /// The error type for VHCI operations.
+mod error {
+    use std::{
+        net::SocketAddr,
+        time::{Duration, Instant},
+    };
+
+    use crate::{net::Status, BusId};
+
+    /// The error type for VHCI operations, shared
+    /// by every platform's driver.
+    ///
+    /// New variants may be added as the drivers
+    /// grow, so matches need a wildcard arm.
     #[derive(Debug)]
+    #[non_exhaustive]
     pub enum Error {
-        UserInput(Box<dyn std::error::Error>),
+        UserInput(Box<dyn std::error::Error + Send + Sync>),
         NoFreePorts,
         PortNotInUse,
+        Cancelled,
+        /// `stage` didn't finish in time, after
+        /// `elapsed` since the operation started.
+        Timeout {
+            stage: AttachStage,
+            elapsed: Duration,
+        },
         DriverNotFound,
         WriteSys(std::io::Error),
         Net(crate::net::Error),
+        /// The driver reported something that
+        /// couldn't be parsed.
+        InvalidDriverData(Box<dyn std::error::Error + Send + Sync>),
+        /// An attach failed during `stage`.
+        Attach {
+            stage: AttachStage,
+            source: Box<Error>,
+        },
+        /// An operation on a specific device failed.
+        Device {
+            host: Option<SocketAddr>,
+            bus_id: Option<BusId<'static>>,
+            port: Option<u16>,
+            source: Box<Error>,
+        },
+    }
+
+    /// The step of an attach that failed, telling apart
+    /// network, remote host and local driver failures.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(rename_all = "snake_case")
+    )]
+    #[non_exhaustive]
+    pub enum AttachStage {
+        /// Looking up the host's address.
+        Resolve,
+        /// Opening a connection to the host.
+        Connect,
+        /// Importing the device from the host.
+        Handshake,
+        /// Finding a free port on the vhci hub.
+        PortSelect,
+        /// Handing the connection to the vhci driver.
+        KernelSubmit,
+        /// Saving the connection's port record.
+        Record,
+        /// Checking the attached device against what the
+        /// host reported, see [`crate::verify`].
+        Verify,
+    }
+
+    impl core::fmt::Display for AttachStage {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                AttachStage::Resolve => write!(f, "resolving the host"),
+                AttachStage::Connect => write!(f, "connecting to the host"),
+                AttachStage::Handshake => write!(f, "importing the device"),
+                AttachStage::PortSelect => write!(f, "selecting a port"),
+                AttachStage::KernelSubmit => write!(f, "handing the device to the driver"),
+                AttachStage::Record => write!(f, "recording the connection"),
+                AttachStage::Verify => write!(f, "verifying the device"),
+            }
+        }
+    }
+
+    impl Error {
+        /// Wraps `self` as a failure during an attach's `stage`.
+        pub(crate) fn at_stage(self, stage: AttachStage) -> Self {
+            Error::Attach {
+                stage,
+                source: Box::new(self),
+            }
+        }
+
+        /// Like [`Error::at_stage`], but reports I/O
+        /// timeouts as [`Error::Timeout`], measured
+        /// from `started`.
+        pub(crate) fn at_stage_timed(self, stage: AttachStage, started: Instant) -> Self {
+            let timed_out = self.io_error().is_some_and(|io| {
+                matches!(
+                    io.kind(),
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                )
+            });
+            if timed_out {
+                Error::Timeout {
+                    stage,
+                    elapsed: started.elapsed(),
+                }
+            } else {
+                self.at_stage(stage)
+            }
+        }
+
+        /// The I/O error behind `self`, whether it came from the
+        /// driver or from reading or writing the host's socket.
+        fn io_error(&self) -> Option<&std::io::Error> {
+            use bincode::error::{DecodeError, EncodeError};
+
+            match self {
+                Error::WriteSys(io)
+                | Error::Net(crate::net::Error::De(DecodeError::Io { inner: io, .. }))
+                | Error::Net(crate::net::Error::Enc(EncodeError::Io { inner: io, .. })) => Some(io),
+                _ => None,
+            }
+        }
+
+        /// Wraps `self` with the device an operation was
+        /// working on when it failed.
+        ///
+        /// [`Error::Cancelled`], [`Error::NoFreePorts`] and
+        /// [`Error::PortNotInUse`] are returned as is, so
+        /// callers can keep matching on them directly.
+        pub(crate) fn for_device(
+            self,
+            host: Option<SocketAddr>,
+            bus_id: Option<BusId<'static>>,
+            port: Option<u16>,
+        ) -> Self {
+            if matches!(
+                self,
+                Error::Cancelled | Error::NoFreePorts | Error::PortNotInUse
+            ) {
+                return self;
+            }
+            Error::Device {
+                host,
+                bus_id,
+                port,
+                source: Box::new(self),
+            }
+        }
+
+        /// Returns the host the failed operation
+        /// was talking to, if known.
+        pub fn host(&self) -> Option<SocketAddr> {
+            match self {
+                Error::Device {
+                    host: Some(host), ..
+                } => Some(*host),
+                Error::Device { source, .. } | Error::Attach { source, .. } => source.host(),
+                _ => None,
+            }
+        }
+
+        /// Returns the bus id of the device the
+        /// failed operation was for, if known.
+        pub fn bus_id(&self) -> Option<&BusId<'static>> {
+            match self {
+                Error::Device {
+                    bus_id: Some(bus_id),
+                    ..
+                } => Some(bus_id),
+                Error::Device { source, .. } | Error::Attach { source, .. } => source.bus_id(),
+                _ => None,
+            }
+        }
+
+        /// Returns the vhci port the failed
+        /// operation was for, if known.
+        pub fn port(&self) -> Option<u16> {
+            match self {
+                Error::Device {
+                    port: Some(port), ..
+                } => Some(*port),
+                Error::Device { source, .. } | Error::Attach { source, .. } => source.port(),
+                _ => None,
+            }
+        }
+
+        /// Returns the OS error code behind this error,
+        /// if it came from a failed system call.
+        pub fn raw_os_error(&self) -> Option<i32> {
+            match self {
+                Error::WriteSys(io) => io.raw_os_error(),
+                Error::Attach { source, .. } | Error::Device { source, .. } => {
+                    source.raw_os_error()
+                }
+                _ => None,
+            }
+        }
+
+        /// Returns whether retrying the same request later
+        /// could succeed, e.g. when the device is busy or the
+        /// connection dropped, as opposed to failures that
+        /// won't change on their own, like a missing device
+        /// or a driver speaking a different ABI.
+        pub fn is_transient(&self) -> bool {
+            match self {
+                Error::NoFreePorts | Error::Timeout { .. } => true,
+                Error::Net(crate::net::Error::Rejected(Status::DevBusy)) => true,
+                Error::WriteSys(_) | Error::Net(_) => self.io_error().is_some_and(io_is_transient),
+                Error::Attach { source, .. } | Error::Device { source, .. } => {
+                    source.is_transient()
+                }
+                _ => false,
+            }
+        }
+
+        /// Returns the step an attach failed at, if
+        /// this error came from an attach.
+        pub fn attach_stage(&self) -> Option<AttachStage> {
+            match self {
+                Error::Attach { stage, .. } | Error::Timeout { stage, .. } => Some(*stage),
+                Error::Device { source, .. } => source.attach_stage(),
+                _ => None,
+            }
+        }
+
+        /// Sorts the error into a platform-independent
+        /// [`ErrorKind`], so callers can react to it
+        /// without matching each backend's variants.
+        pub fn kind(&self) -> ErrorKind {
+            match self {
+                Error::UserInput(_) => ErrorKind::InvalidInput,
+                Error::NoFreePorts => ErrorKind::Busy,
+                Error::PortNotInUse => ErrorKind::NotConnected,
+                Error::Cancelled => ErrorKind::Cancelled,
+                Error::Timeout { .. } => ErrorKind::TimedOut,
+                Error::DriverNotFound => ErrorKind::DriverMissing,
+                Error::InvalidDriverData(_) => ErrorKind::Protocol,
+                Error::WriteSys(io) => io_kind(io),
+                Error::Net(net) => match net {
+                    crate::net::Error::Rejected(Status::DevBusy) => ErrorKind::Busy,
+                    crate::net::Error::Rejected(Status::Unexpected) => ErrorKind::Protocol,
+                    crate::net::Error::Rejected(_) => ErrorKind::RemoteRejected,
+                    _ => ErrorKind::Protocol,
+                },
+                Error::Attach { source, .. } | Error::Device { source, .. } => source.kind(),
+            }
+        }
+    }
+
+    fn io_kind(io: &std::io::Error) -> ErrorKind {
+        use std::io::ErrorKind as Io;
+
+        match io.kind() {
+            Io::PermissionDenied => ErrorKind::PermissionDenied,
+            Io::NotConnected
+            | Io::ConnectionRefused
+            | Io::ConnectionReset
+            | Io::ConnectionAborted
+            | Io::HostUnreachable
+            | Io::NetworkUnreachable
+            | Io::BrokenPipe => ErrorKind::NotConnected,
+            Io::TimedOut => ErrorKind::TimedOut,
+            Io::InvalidData | Io::UnexpectedEof => ErrorKind::Protocol,
+            _ => ErrorKind::Io,
+        }
+    }
+
+    fn io_is_transient(io: &std::io::Error) -> bool {
+        use std::io::ErrorKind as Io;
+
+        // The driver asking for a bigger buffer after we already
+        // grew it means the port table changed under us.
         #[cfg(windows)]
-        MultipleDevInterfaces(usize),
+        if io.raw_os_error() == Some(::windows::Win32::Foundation::ERROR_MORE_DATA.0 as i32) {
+            return true;
+        }
+        matches!(
+            io.kind(),
+            Io::Interrupted
+                | Io::WouldBlock
+                | Io::TimedOut
+                | Io::NotConnected
+                | Io::ConnectionRefused
+                | Io::ConnectionReset
+                | Io::ConnectionAborted
+                | Io::HostUnreachable
+                | Io::NetworkUnreachable
+                | Io::BrokenPipe
+        )
+    }
+
+    /// A platform-independent category of [`Error`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(
+        feature = "serde",
+        derive(serde::Serialize, serde::Deserialize),
+        serde(rename_all = "snake_case")
+    )]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        InvalidInput,
+        /// The vhci driver isn't installed or loaded.
+        DriverMissing,
+        PermissionDenied,
+        /// The host refused to export the device.
+        RemoteRejected,
+        /// The device or the vhci hub has no room for the request.
+        Busy,
+        /// The host or the port isn't connected.
+        NotConnected,
+        /// The other side sent something unexpected.
+        Protocol,
+        Cancelled,
+        /// The host or the driver took too long to respond.
+        TimedOut,
+        /// Any other I/O error.
+        Io,
+    }
+
+    impl core::fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                ErrorKind::InvalidInput => write!(f, "invalid input"),
+                ErrorKind::DriverMissing => write!(f, "driver missing"),
+                ErrorKind::PermissionDenied => write!(f, "permission denied"),
+                ErrorKind::RemoteRejected => write!(f, "rejected by remote"),
+                ErrorKind::Busy => write!(f, "busy"),
+                ErrorKind::NotConnected => write!(f, "not connected"),
+                ErrorKind::Protocol => write!(f, "protocol error"),
+                ErrorKind::Cancelled => write!(f, "cancelled"),
+                ErrorKind::TimedOut => write!(f, "timed out"),
+                ErrorKind::Io => write!(f, "I/O error"),
+            }
+        }
     }
 
     impl From<std::io::Error> for Error {
@@ -20,25 +348,93 @@ pub mod error2 {
         }
     }
 
+    /// A machine-readable summary of an [`Error`], for
+    /// forwarding failures over RPC or JSON APIs.
+    ///
+    /// [`Error`] serializes as this report.
+    #[cfg(feature = "serde")]
+    #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+    pub struct ErrorReport {
+        pub kind: ErrorKind,
+        pub stage: Option<AttachStage>,
+        /// The error and its sources, separated by `": "`.
+        pub message: String,
+        pub os_code: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub host: Option<SocketAddr>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub bus_id: Option<BusId<'static>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub port: Option<u16>,
+    }
+
+    #[cfg(feature = "serde")]
+    impl From<&Error> for ErrorReport {
+        fn from(err: &Error) -> Self {
+            Self {
+                kind: err.kind(),
+                stage: err.attach_stage(),
+                message: crate::util::ErrorChain(err).to_string(),
+                os_code: err.raw_os_error(),
+                host: err.host(),
+                bus_id: err.bus_id().cloned(),
+                port: err.port(),
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl serde::Serialize for Error {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            ErrorReport::from(self).serialize(serializer)
+        }
+    }
+
     impl core::fmt::Display for Error {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             match self {
-                Error::UserInput(err) => write!(f, "Invalid user input: {err}"),
+                Error::UserInput(_) => write!(f, "Invalid user input"),
                 Error::NoFreePorts => write!(f, "No free port on USB/IP hub"),
                 Error::PortNotInUse => write!(f, "Port not in use"),
+                Error::Cancelled => write!(f, "Operation was cancelled"),
+                Error::Timeout { stage, elapsed } => {
+                    write!(f, "Timed out after {elapsed:?} while {stage}")
+                }
                 Error::DriverNotFound => write!(f, "VHCI device not found, is the driver loaded?"),
-                Error::WriteSys(io) => write!(f, "Driver I/O error: {io}"),
-                Error::Net(net) => write!(f, "Net error: {net}"),
-                #[cfg(windows)]
-                Error::MultipleDevInterfaces(num) => write!(
-                    f,
-                    "Multiple instances of VHCI device interface found ({num})"
-                ),
+                Error::WriteSys(_) => write!(f, "Driver I/O error"),
+                Error::Net(_) => write!(f, "Net error"),
+                Error::InvalidDriverData(_) => write!(f, "Driver reported malformed data"),
+                Error::Attach { stage, .. } => write!(f, "Attach failed while {stage}"),
+                Error::Device {
+                    host, bus_id, port, ..
+                } => {
+                    write!(f, "Operation failed")?;
+                    if let Some(bus_id) = bus_id {
+                        write!(f, " for {bus_id}")?;
+                    }
+                    if let Some(host) = host {
+                        write!(f, " on {host}")?;
+                    }
+                    if let Some(port) = port {
+                        write!(f, " at port {port}")?;
+                    }
+                    Ok(())
+                }
             }
         }
     }
 
-    impl std::error::Error for Error {}
+    impl std::error::Error for Error {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Error::UserInput(err) | Error::InvalidDriverData(err) => Some(&**err),
+                Error::WriteSys(io) => Some(io),
+                Error::Net(net) => Some(net),
+                Error::Attach { source, .. } | Error::Device { source, .. } => Some(&**source),
+                _ => None,
+            }
+        }
+    }
 }
 
 mod platform {
@@ -50,21 +446,29 @@ mod platform {
 
     #[cfg(windows)]
     pub use crate::windows::vhci::{
-        PortRecord, WindowsImportedDevice as ImportedDevice,
-        WindowsImportedDevices as ImportedDevices, WindowsVhciDriver as Driver, STATE_PATH,
+        cast_u8_to_u16_cow,
+        ioctl2::{ImportedDevice as ImportedDeviceRef, PortRecord as PortRecordRef},
+        read_persistent_registry, try_cast_u8_to_u16_slice, write_persistent_registry, CastError,
+        DeviceLocation, InterfaceSelector, ParseDeviceLocationError, PortRecord,
+        RawImportedDevices, WindowsImportedDevice as ImportedDevice,
+        WindowsImportedDevices as ImportedDevices, WindowsVhciDriver as Driver,
+        WindowsVhciDriverExt, PERSISTENT_REGISTRY_KEY, PERSISTENT_REGISTRY_VALUE, STATE_PATH,
     };
 }
 
+#[cfg(feature = "mock-driver")]
+pub mod mock;
+
 pub mod base {
     use std::net::SocketAddr;
 
-    use crate::{containers::stacktools::StackStr, BUS_ID_SIZE};
+    use crate::{BusId, DevId};
 
     #[derive(Debug)]
     pub struct ImportedDevice {
         pub(crate) vendor: u16,
         pub(crate) product: u16,
-        pub(crate) devid: u32,
+        pub(crate) devid: DevId,
     }
 
     impl ImportedDevice {
@@ -72,7 +476,7 @@ pub mod base {
             self.vendor
         }
 
-        pub const fn dev_id(&self) -> u32 {
+        pub const fn dev_id(&self) -> DevId {
             self.devid
         }
 
@@ -81,18 +485,18 @@ pub mod base {
         }
 
         pub const fn bus_num(&self) -> u32 {
-            self.dev_id() >> 16
+            self.dev_id().bus() as u32
         }
 
         pub const fn dev_num(&self) -> u32 {
-            self.dev_id() & 0x0000ffff
+            self.dev_id().dev() as u32
         }
     }
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct PortRecord {
         pub(crate) host: SocketAddr,
-        pub(crate) busid: StackStr<BUS_ID_SIZE>,
+        pub(crate) busid: BusId<'static>,
     }
 
     impl PortRecord {
@@ -101,22 +505,72 @@ pub mod base {
         }
 
         pub fn bus_id(&self) -> &str {
-            &*self.busid
+            self.busid.as_str()
         }
     }
 }
 
 use core::fmt;
-use std::{str::FromStr, net::SocketAddr};
+use std::{net::SocketAddr, num::NonZeroUsize, str::FromStr, sync::Arc};
+
+use crate::{
+    cancel::CancellationToken,
+    events::{Event, EventSink},
+};
+#[cfg(feature = "log")]
+use crate::util::ErrorChain;
 
 pub use platform::{Driver, ImportedDevice, ImportedDevices, PortRecord, STATE_PATH};
+#[cfg(all(unix, feature = "tokio"))]
+pub use crate::unix::vhci2::asynchronous::AsyncVhciDriver;
+#[cfg(windows)]
+pub use platform::{
+    cast_u8_to_u16_cow, read_persistent_registry, try_cast_u8_to_u16_slice,
+    write_persistent_registry, CastError, DeviceLocation, ImportedDeviceRef, InterfaceSelector,
+    ParseDeviceLocationError, PortRecordRef, RawImportedDevices, WindowsVhciDriverExt,
+    PERSISTENT_REGISTRY_KEY, PERSISTENT_REGISTRY_VALUE,
+};
+
+pub use error::{AttachStage, Error, ErrorKind};
+#[cfg(feature = "serde")]
+pub use error::ErrorReport;
+
+pub type Result<T> = std::result::Result<T, Error>;
 
-pub type Result<T> = std::result::Result<T, error2::Error>;
+/// A snapshot of the ports provided by the vhci driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortCounts {
+    pub(crate) num_controllers: usize,
+    pub(crate) num_ports: usize,
+    pub(crate) free_ports: usize,
+}
+
+impl PortCounts {
+    /// The number of virtual host controllers.
+    pub const fn num_controllers(&self) -> usize {
+        self.num_controllers
+    }
 
+    /// The total number of ports across all controllers.
+    pub const fn num_ports(&self) -> usize {
+        self.num_ports
+    }
+
+    /// The number of ports that can accept a new device.
+    pub const fn free_ports(&self) -> usize {
+        self.free_ports
+    }
 
+    /// The number of ports that currently have a device attached.
+    pub const fn used_ports(&self) -> usize {
+        self.num_ports - self.free_ports
+    }
+}
+
+/// The host and device to attach with [`VhciDriver::attach`].
 pub struct AttachArgs<'a> {
     pub host: SocketAddr,
-    pub bus_id: &'a str,
+    pub bus_id: crate::BusId<'a>,
 }
 
 /// The VHCI driver's supported USB device speeds.
@@ -177,45 +631,242 @@ impl fmt::Display for ParseHubSpeedError {
 
 impl std::error::Error for ParseHubSpeedError {}
 
+#[cfg(feature = "log")]
+const LOG_TARGET: &str = "usbip_core::vhci";
+
+#[cfg(feature = "log")]
+fn log_attach(result: &Result<u16>, host: SocketAddr, bus_id: &crate::BusId) {
+    match result {
+        Ok(port) => log::info!(target: LOG_TARGET, "attached {host}/{bus_id} to port {port}"),
+        Err(err) if err.kind() == ErrorKind::Protocol => log::error!(
+            target: "usbip_core::net",
+            "protocol error attaching {host}/{bus_id}: {}",
+            ErrorChain(err)
+        ),
+        Err(err) => log::warn!(
+            target: LOG_TARGET,
+            "failed to attach {host}/{bus_id}: {}",
+            ErrorChain(err)
+        ),
+    }
+}
+
 /// An object that provides an interface
 /// to the vhci driver.
 ///
 /// The platform's vhci driver needs to be installed
 /// and loaded for the driver operations to succeed.
 pub struct VhciDriver {
-    inner: Driver,
+    inner: Backend,
+    sink: Option<Arc<dyn EventSink>>,
+}
+
+/// The driver behind a [`VhciDriver`].
+///
+/// The platform driver is the one nearly every
+/// [`VhciDriver`] holds, so it isn't boxed.
+#[allow(clippy::large_enum_variant)]
+enum Backend {
+    Platform(Driver),
+    #[cfg(feature = "mock-driver")]
+    Mock(mock::MockDriver),
+}
+
+/// Calls `$call` on whichever driver `$backend` holds.
+macro_rules! dispatch {
+    ($backend:expr, $driver:ident => $call:expr) => {
+        match $backend {
+            Backend::Platform($driver) => $call,
+            #[cfg(feature = "mock-driver")]
+            Backend::Mock($driver) => $call,
+        }
+    };
 }
 
 impl VhciDriver {
-    /// Creates a new [`VhciDriver2`] from
+    /// Creates a new [`VhciDriver`] from
     /// a platform-specific driver implementation.
     #[inline(always)]
     const fn new(inner: Driver) -> Self {
-        Self { inner }
+        Self {
+            inner: Backend::Platform(inner),
+            sink: None,
+        }
     }
 
-    #[inline(always)]
-    const fn get(&self) -> &Driver {
-        &self.inner
+    /// Creates a [`VhciDriver`] backed by `mock` instead
+    /// of the platform's driver, see [`mock`].
+    #[cfg(feature = "mock-driver")]
+    pub const fn from_mock(mock: mock::MockDriver) -> Self {
+        Self {
+            inner: Backend::Mock(mock),
+            sink: None,
+        }
     }
 
+    /// Opens the vhci driver.
+    ///
+    /// # Errors
+    /// This function will return an error if
+    /// the underlying kernel driver was not loaded.
     #[inline(always)]
-    fn get_mut(&mut self) -> &mut Driver {
-        &mut self.inner
+    pub fn open() -> Result<Self> {
+        let driver = Driver::open();
+        #[cfg(feature = "log")]
+        match &driver {
+            Ok(_) => log::info!(target: LOG_TARGET, "opened vhci driver"),
+            Err(err) => log::error!(
+                target: LOG_TARGET,
+                "failed to open vhci driver: {}",
+                ErrorChain(err)
+            ),
+        }
+        Ok(Self::new(driver?))
     }
 
-    /// Opens the vhci driver.
+    /// Opens the vhci driver, and applies `config` to it
+    /// with [`VhciDriver::apply_config`].
     ///
     /// # Errors
     /// This function will return an error if
     /// the underlying kernel driver was not loaded.
+    #[cfg(feature = "config")]
+    pub fn open_with_config(config: &crate::config::Config) -> Result<Self> {
+        let mut driver = Self::open()?;
+        driver.apply_config(config);
+        Ok(driver)
+    }
+
+    /// Uses the state path and connect timeout in `config`
+    /// from now on, such as after it was reloaded.
+    ///
+    /// Socket options `config` has no setting for, like a
+    /// proxy, are kept. Without a state path in `config`,
+    /// port records go back to [`STATE_PATH`].
+    ///
+    /// # Platform-specific behavior
+    /// On windows, the driver keeps port records and connects
+    /// to hosts itself, so `config` is not used.
+    #[cfg(feature = "config")]
+    pub fn apply_config(&mut self, config: &crate::config::Config) {
+        #[cfg(unix)]
+        {
+            let state_path = config.state_path.as_deref();
+            self.set_state_path(state_path.unwrap_or(STATE_PATH.as_ref()));
+            let mut options = self.socket_options().clone();
+            config.update_socket_options(&mut options);
+            self.set_socket_options(options);
+        }
+        #[cfg(windows)]
+        let _ = config;
+    }
+
+    /// Sends [`Event`]s for this driver's attaches and detaches
+    /// to `sink`, replacing the sink that was set before.
+    pub fn set_event_sink(&mut self, sink: Arc<dyn EventSink>) {
+        self.sink = Some(sink);
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(sink) = &self.sink {
+            sink.event(&event);
+        }
+    }
+
+    /// Logs, counts and emits the result of an attach.
+    fn report_attach(
+        &self,
+        result: &Result<u16>,
+        host: SocketAddr,
+        bus_id: &crate::BusId<'static>,
+    ) {
+        #[cfg(feature = "log")]
+        log_attach(result, host, bus_id);
+        #[cfg(feature = "metrics")]
+        crate::stats::attach(result);
+        if self.sink.is_none() {
+            return;
+        }
+        let bus_id = bus_id.clone();
+        self.emit(match result {
+            Ok(port) => Event::Attached {
+                host,
+                bus_id,
+                port: *port,
+            },
+            Err(err) => Event::AttachFailed {
+                host,
+                bus_id,
+                kind: err.kind(),
+            },
+        });
+    }
+
+    /// Opens a specific vhci device interface,
+    /// for systems with more than one installed.
+    ///
+    /// # Errors
+    /// This function will return an error if no
+    /// interface matches `selector`.
+    #[cfg(windows)]
     #[inline(always)]
-    pub fn open() -> Result<Self> {
-        Ok(Self::new(Driver::open()?))
+    pub fn open_interface(selector: InterfaceSelector) -> Result<Self> {
+        Ok(Self::new(Driver::open_interface(selector)?))
+    }
+
+    /// Sets the kernel socket options and the proxy for
+    /// the connections to hosts made by later attaches.
+    ///
+    /// On windows, the driver connects to hosts itself,
+    /// so there's nothing to set.
+    #[cfg(unix)]
+    #[inline(always)]
+    pub fn set_socket_options(&mut self, options: crate::unix::SocketOptions) {
+        match &mut self.inner {
+            Backend::Platform(driver) => driver.set_socket_options(options),
+            #[cfg(feature = "mock-driver")]
+            Backend::Mock(_) => {}
+        }
+    }
+
+    /// The options set with [`set_socket_options`].
+    ///
+    /// [`set_socket_options`]: VhciDriver::set_socket_options
+    #[cfg(unix)]
+    #[inline(always)]
+    pub fn socket_options(&self) -> &crate::unix::SocketOptions {
+        match &self.inner {
+            Backend::Platform(driver) => driver.socket_options(),
+            #[cfg(feature = "mock-driver")]
+            Backend::Mock(_) => {
+                static NONE: crate::unix::SocketOptions = crate::unix::SocketOptions::new();
+                &NONE
+            }
+        }
+    }
+
+    /// Keeps port records in `path` instead of [`STATE_PATH`].
+    ///
+    /// Other programs, like the usbip utility, only
+    /// look for the records in [`STATE_PATH`].
+    #[cfg(unix)]
+    #[inline(always)]
+    pub fn set_state_path(&mut self, path: impl Into<std::path::PathBuf>) {
+        match &mut self.inner {
+            Backend::Platform(driver) => driver.set_state_path(path),
+            #[cfg(feature = "mock-driver")]
+            Backend::Mock(_) => {}
+        }
     }
 
     /// Attaches a host's USB device to this device.
     ///
+    /// # Errors
+    /// Returned errors carry the host and bus id
+    /// of the device, see [`Error::host`] and
+    /// [`Error::bus_id`]. [`Error::NoFreePorts`] and
+    /// [`Error::Cancelled`] are returned unwrapped.
+    ///
     /// # Platform-specific behavior
     /// On unix, this function assumes that a connection
     /// has already been established with the host system.
@@ -224,12 +875,178 @@ impl VhciDriver {
     /// a connection with the host.
     #[inline(always)]
     pub fn attach(&mut self, args: AttachArgs) -> Result<u16> {
-        self.get_mut().attach(args)
+        let (host, bus_id) = (args.host, args.bus_id.clone().into_owned());
+        let result = dispatch!(&mut self.inner, driver => driver.attach(args));
+        self.report_attach(&result, host, &bus_id);
+        result.map_err(|err| err.for_device(Some(host), Some(bus_id), None))
+    }
+
+    /// Like [`VhciDriver::attach`], but then waits up to `timeout`
+    /// for the device to be enumerated, and compares its descriptor
+    /// with what the host reported. See [`crate::verify`].
+    ///
+    /// # Errors
+    /// Besides the errors of [`VhciDriver::attach`], this function
+    /// fails at [`AttachStage::Verify`] if the device didn't show up
+    /// in time or couldn't be read. The device stays attached then,
+    /// and the error carries its port.
+    #[cfg(all(unix, feature = "verify"))]
+    pub fn attach_verified(
+        &mut self,
+        args: AttachArgs,
+        timeout: std::time::Duration,
+    ) -> Result<(u16, crate::verify::Report)> {
+        let (host, bus_id) = (args.host, args.bus_id.clone().into_owned());
+        let attached = match &mut self.inner {
+            Backend::Platform(driver) => driver.attach_reporting(args, &CancellationToken::new()),
+            #[cfg(feature = "mock-driver")]
+            Backend::Mock(mock) => mock.attach_reporting(args),
+        };
+        let (result, remote) = match attached {
+            Ok((port, remote)) => (Ok(port), Some(remote)),
+            Err(err) => (Err(err), None),
+        };
+        self.report_attach(&result, host, &bus_id);
+        let port = result.map_err(|err| err.for_device(Some(host), Some(bus_id.clone()), None))?;
+
+        let report = match &self.inner {
+            Backend::Platform(driver) => {
+                crate::verify::check(driver, port, &remote.unwrap(), timeout)
+                    .map_err(|err| err.for_device(Some(host), Some(bus_id.clone()), Some(port)))?
+            }
+            // The mock attaches exactly what the host reported.
+            #[cfg(feature = "mock-driver")]
+            Backend::Mock(_) => crate::verify::Report::matching(),
+        };
+        #[cfg(feature = "log")]
+        for mismatch in report.mismatches() {
+            log::warn!(target: LOG_TARGET, "{host}/{bus_id} on port {port}: {mismatch}");
+        }
+        Ok((port, report))
+    }
+
+    /// Like [`VhciDriver::attach_verified`], but waits
+    /// for as long as [`Timeouts::verify`] in `config`.
+    ///
+    /// [`Timeouts::verify`]: crate::config::Timeouts::verify
+    ///
+    /// # Errors
+    /// See [`VhciDriver::attach_verified`].
+    #[cfg(all(unix, feature = "verify", feature = "config"))]
+    pub fn attach_verified_with_config(
+        &mut self,
+        args: AttachArgs,
+        config: &crate::config::Config,
+    ) -> Result<(u16, crate::verify::Report)> {
+        self.attach_verified(args, config.timeouts.verify)
+    }
+
+    /// Like [`VhciDriver::attach`], but aborts the attach
+    /// with [`Error::Cancelled`] once `token` is cancelled.
+    ///
+    /// Cancelling after the device was handed over to
+    /// the driver has no effect.
+    ///
+    /// # Platform-specific behavior
+    /// On unix, cancelling shuts down the connection
+    /// to the host if the handshake is still in progress.
+    ///
+    /// On windows, cancelling calls `CancelIoEx` on the
+    /// in-flight ioctl, which would otherwise block until
+    /// the driver's TCP timeout if the host is unreachable.
+    #[inline(always)]
+    pub fn attach_with_cancel(
+        &mut self,
+        args: AttachArgs,
+        token: &CancellationToken,
+    ) -> Result<u16> {
+        let (host, bus_id) = (args.host, args.bus_id.clone().into_owned());
+        let result = dispatch!(&mut self.inner, driver => driver.attach_with_cancel(args, token));
+        self.report_attach(&result, host, &bus_id);
+        result.map_err(|err| err.for_device(Some(host), Some(bus_id), None))
+    }
+
+    /// Attaches every device in `args`, returning
+    /// their results in the same order.
+    ///
+    /// # Errors
+    /// Each device's error carries its host and bus id,
+    /// just like [`VhciDriver::attach`].
+    ///
+    /// # Platform-specific behavior
+    /// On unix, up to `max_parallel` handshakes with hosts
+    /// run at once, while ports are handed to the kernel one
+    /// device at a time.
+    ///
+    /// On windows, the driver does the handshake within the
+    /// attach ioctl, so up to `max_parallel` ioctls run at
+    /// once, each on its own handle to the interface.
+    pub fn attach_many<'a>(
+        &mut self,
+        args: impl IntoIterator<Item = AttachArgs<'a>>,
+        max_parallel: NonZeroUsize,
+    ) -> Vec<Result<u16>> {
+        self.attach_many_with_cancel(args, max_parallel, &CancellationToken::new())
+    }
+
+    /// Like [`VhciDriver::attach_many`], but once `token` is
+    /// cancelled, every device that wasn't handed over to the
+    /// driver yet fails with [`Error::Cancelled`].
+    ///
+    /// # Platform-specific behavior
+    /// On unix, cancelling also shuts down the connections
+    /// of the handshakes in progress.
+    ///
+    /// On windows, cancelling calls `CancelIoEx` on the
+    /// in-flight attach ioctl, like [`VhciDriver::attach_with_cancel`].
+    pub fn attach_many_with_cancel<'a>(
+        &mut self,
+        args: impl IntoIterator<Item = AttachArgs<'a>>,
+        max_parallel: NonZeroUsize,
+        token: &CancellationToken,
+    ) -> Vec<Result<u16>> {
+        let args: Vec<_> = args.into_iter().collect();
+        let devices: Vec<_> = args
+            .iter()
+            .map(|args| (args.host, args.bus_id.clone().into_owned()))
+            .collect();
+        let results = dispatch!(
+            &mut self.inner,
+            driver => driver.attach_many_with_cancel(args, max_parallel, token)
+        );
+        results
+            .into_iter()
+            .zip(devices)
+            .map(|(result, (host, bus_id))| {
+                self.report_attach(&result, host, &bus_id);
+                result.map_err(|err| err.for_device(Some(host), Some(bus_id), None))
+            })
+            .collect()
     }
 
+    /// Detaches the device on `port`.
+    ///
+    /// # Errors
+    /// Returned errors carry the port, see [`Error::port`],
+    /// except [`Error::PortNotInUse`], which is returned unwrapped.
     #[inline(always)]
     pub fn detach(&mut self, port: u16) -> Result<()> {
-        self.get_mut().detach(port)
+        let result = dispatch!(&mut self.inner, driver => driver.detach(port));
+        #[cfg(feature = "log")]
+        match &result {
+            Ok(()) => log::info!(target: LOG_TARGET, "detached port {port}"),
+            Err(err) => log::warn!(
+                target: LOG_TARGET,
+                "failed to detach port {port}: {}",
+                ErrorChain(err)
+            ),
+        }
+        #[cfg(feature = "metrics")]
+        crate::stats::detach(&result);
+        if result.is_ok() {
+            self.emit(Event::Detached { port });
+        }
+        result.map_err(|err| err.for_device(None, None, Some(port)))
     }
 
     /// Returns a list of usb devices that are
@@ -255,6 +1072,223 @@ impl VhciDriver {
     /// usb devices.
     #[inline(always)]
     pub fn imported_devices(&self) -> Result<ImportedDevices> {
-        self.get().imported_devices()
+        dispatch!(&self.inner, driver => driver.imported_devices())
+    }
+
+    /// Returns the total and free port counts
+    /// of the vhci driver.
+    ///
+    /// Like [`VhciDriver::imported_devices`], the
+    /// returned counts are only a snapshot.
+    ///
+    /// # Platform-specific behavior
+    /// On windows, the driver does not report its
+    /// port count, so the total is the fixed number
+    /// of ports usbip-win2 creates, and the free
+    /// count is derived from the imported devices.
+    #[inline(always)]
+    pub fn port_counts(&self) -> Result<PortCounts> {
+        dispatch!(&self.inner, driver => driver.port_counts())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::Status;
+
+    #[test]
+    fn error_kind_sorts_platform_errors() {
+        let refused = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        assert_eq!(refused.kind(), ErrorKind::NotConnected);
+        assert_eq!(
+            Error::Net(crate::net::Error::Rejected(Status::NoDev)).kind(),
+            ErrorKind::RemoteRejected
+        );
+        assert_eq!(
+            Error::Net(crate::net::Error::Rejected(Status::DevBusy)).kind(),
+            ErrorKind::Busy
+        );
+        assert_eq!(Error::DriverNotFound.kind(), ErrorKind::DriverMissing);
+    }
+
+    #[test]
+    fn is_transient_separates_retryable_errors() {
+        let busy = Error::Net(crate::net::Error::Rejected(Status::DevBusy));
+        assert!(busy.at_stage(AttachStage::Handshake).is_transient());
+        assert!(Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut)).is_transient());
+        assert!(!Error::Net(crate::net::Error::Rejected(Status::NoDev)).is_transient());
+        assert!(!Error::from(std::io::Error::from(std::io::ErrorKind::InvalidData)).is_transient());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn error_serializes_as_report() {
+        let err = Error::Net(crate::net::Error::Rejected(Status::NoDev))
+            .at_stage(AttachStage::Handshake);
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "remote_rejected",
+                "stage": "handshake",
+                "message": "Attach failed while importing the device: Net error: \
+                            Host rejected the request: Device not found",
+                "os_code": null,
+            })
+        );
+    }
+
+    #[test]
+    fn device_context_is_visible_through_wrappers() {
+        let host: SocketAddr = "10.0.0.1:3240".parse().unwrap();
+        let bus_id: crate::BusId = "1-1".parse().unwrap();
+        let err = Error::NoFreePorts
+            .at_stage(AttachStage::PortSelect)
+            .for_device(Some(host), Some(bus_id.clone()), None);
+        assert_eq!(err.host(), Some(host));
+        assert_eq!(err.bus_id(), Some(&bus_id));
+        assert_eq!(err.port(), None);
+        assert_eq!(err.attach_stage(), Some(AttachStage::PortSelect));
+        assert_eq!(err.kind(), ErrorKind::Busy);
+        assert_eq!(err.to_string(), "Operation failed for 1-1 on 10.0.0.1:3240");
+    }
+
+    #[test]
+    fn unit_errors_are_not_wrapped_with_device() {
+        let host: SocketAddr = "10.0.0.1:3240".parse().unwrap();
+        let bus_id: crate::BusId = "1-1".parse().unwrap();
+        let cancelled = Error::Cancelled.for_device(Some(host), Some(bus_id), None);
+        assert!(matches!(cancelled, Error::Cancelled));
+        let unused = Error::PortNotInUse.for_device(None, None, Some(3));
+        assert!(matches!(unused, Error::PortNotInUse));
+    }
+
+    #[test]
+    fn refused_connections_are_not_timeouts() {
+        let started = std::time::Instant::now();
+        let refused = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .at_stage_timed(AttachStage::Connect, started);
+        assert_eq!(refused.kind(), ErrorKind::NotConnected);
+    }
+
+    #[cfg(all(unix, feature = "mock-driver", feature = "verify", feature = "config"))]
+    #[test]
+    fn attach_verified_with_config_attaches() {
+        let host = SocketAddr::from(([10, 0, 0, 2], 3240));
+        let mock = mock::MockDriver::new(1);
+        mock.export(host, crate::test_device("1-1").build().unwrap());
+
+        let mut driver = VhciDriver::from_mock(mock);
+        let args = AttachArgs {
+            host,
+            bus_id: crate::BusId::parse("1-1").unwrap(),
+        };
+        let config = crate::config::Config::default();
+        let (_, report) = driver.attach_verified_with_config(args, &config).unwrap();
+        assert!(report.mismatches().is_empty());
+    }
+
+    #[cfg(feature = "mock-driver")]
+    #[test]
+    fn mock_driver_reports_events() {
+        use std::sync::Mutex;
+
+        let host = SocketAddr::from(([10, 0, 0, 2], 3240));
+        let mock = mock::MockDriver::new(1);
+        mock.export(host, crate::test_device("1-1").build().unwrap());
+        mock.fail_next(mock::Operation::Attach, Error::NoFreePorts);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut driver = VhciDriver::from_mock(mock);
+        let sink = Arc::clone(&events);
+        driver.set_event_sink(Arc::new(move |event: &Event| {
+            sink.lock().unwrap().push(event.clone())
+        }));
+
+        let args = || AttachArgs {
+            host,
+            bus_id: crate::BusId::parse("1-1").unwrap(),
+        };
+        let err = driver.attach(args()).unwrap_err();
+        assert!(matches!(err, Error::NoFreePorts));
+        let port = driver.attach(args()).unwrap();
+        assert_eq!(driver.imported_devices().unwrap().get().len(), 1);
+        driver.detach(port).unwrap();
+        assert!(matches!(driver.detach(port), Err(Error::PortNotInUse)));
+
+        let bus_id = crate::BusId::parse("1-1").unwrap().into_owned();
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::AttachFailed {
+                    host,
+                    bus_id: bus_id.clone(),
+                    kind: ErrorKind::Busy,
+                },
+                Event::Attached { host, bus_id, port },
+                Event::Detached { port },
+            ]
+        );
+    }
+
+    #[cfg(feature = "mock-driver")]
+    #[test]
+    fn attach_many_keeps_order_through_failures() {
+        let host = SocketAddr::from(([10, 0, 0, 2], 3240));
+        let mock = mock::MockDriver::new(2);
+        for bus_id in ["1-1", "1-3", "1-4"] {
+            mock.export(host, crate::test_device(bus_id).build().unwrap());
+        }
+        let mut driver = VhciDriver::from_mock(mock.clone());
+
+        let args = ["1-1", "1-2", "1-3", "1-4"].map(|bus_id| AttachArgs {
+            host,
+            bus_id: crate::BusId::parse(bus_id).unwrap(),
+        });
+        let results = driver.attach_many(args, NonZeroUsize::new(2).unwrap());
+
+        let ports = mock.attached_ports();
+        assert_eq!(ports.len(), 2);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0].as_ref().ok(), Some(&ports[0]));
+        let not_exported = results[1].as_ref().unwrap_err();
+        assert_eq!(not_exported.kind(), ErrorKind::RemoteRejected);
+        assert_eq!(not_exported.bus_id().map(|id| id.as_str()), Some("1-2"));
+        assert_eq!(results[2].as_ref().ok(), Some(&ports[1]));
+        assert_eq!(results[3].as_ref().unwrap_err().kind(), ErrorKind::Busy);
+    }
+
+    #[cfg(feature = "mock-driver")]
+    #[test]
+    fn attach_many_stops_once_cancelled() {
+        let host = SocketAddr::from(([10, 0, 0, 2], 3240));
+        let mock = mock::MockDriver::new(2);
+        let mut driver = VhciDriver::from_mock(mock.clone());
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let args = ["1-1", "1-2"].map(|bus_id| AttachArgs {
+            host,
+            bus_id: crate::BusId::parse(bus_id).unwrap(),
+        });
+        let results = driver.attach_many_with_cancel(args, NonZeroUsize::MIN, &token);
+
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Err(Error::Cancelled))));
+        assert!(mock.attached_ports().is_empty());
+    }
+
+    #[test]
+    fn attach_stage_wraps_the_cause() {
+        use std::error::Error as _;
+
+        let err = Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .at_stage(AttachStage::Connect);
+        assert_eq!(err.attach_stage(), Some(AttachStage::Connect));
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert_eq!(err.to_string(), "Attach failed while connecting to the host");
+        assert!(matches!(err.source(), Some(source) if source.is::<Error>()));
     }
 }
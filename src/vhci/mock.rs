@@ -0,0 +1,319 @@
+//! An in-memory vhci driver, for testing code built on
+//! [`VhciDriver`](super::VhciDriver) without vhci_hcd or
+//! usbip-win2 installed.
+//!
+//! A [`MockDriver`] has a fixed number of ports and a list of
+//! devices that hosts "export". Attaching one of those devices
+//! takes the lowest free port, and anything else fails the way
+//! a real host or driver would. Failures can also be scripted
+//! with [`MockDriver::fail_next`].
+//!
+//! # Examples
+//! ```
+//! use usbip_core::{
+//!     vhci::{mock::MockDriver, AttachArgs, VhciDriver},
+//!     BusId, UsbDeviceBuilder,
+//! };
+//!
+//! let host = "10.0.0.2:3240".parse().unwrap();
+//! let mock = MockDriver::new(2);
+//! mock.export(
+//!     host,
+//!     UsbDeviceBuilder::new()
+//!         .path("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1")
+//!         .bus_id("1-1")
+//!         .bus_num(1)
+//!         .dev_num(2)
+//!         .build()
+//!         .unwrap(),
+//! );
+//!
+//! let mut driver = VhciDriver::from_mock(mock.clone());
+//! let bus_id = BusId::parse("1-1").unwrap();
+//! let port = driver.attach(AttachArgs { host, bus_id }).unwrap();
+//! assert_eq!(mock.attached_ports(), [port]);
+//! assert_eq!(driver.port_counts().unwrap().used_ports(), 1);
+//! ```
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    cancel::CancellationToken,
+    net::Status,
+    vhci::{AttachArgs, AttachStage, Error, ImportedDevice, ImportedDevices, PortCounts, Result},
+    UsbDevice,
+};
+
+/// The number of the first port, which matches
+/// the platform's driver.
+#[cfg(unix)]
+const FIRST_PORT: u16 = 0;
+#[cfg(windows)]
+const FIRST_PORT: u16 = 1;
+
+/// A driver operation that a scripted failure applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Operation {
+    Attach,
+    Detach,
+    ImportedDevices,
+}
+
+/// An in-memory stand-in for the platform's vhci driver.
+///
+/// Clones share the same ports, so a test can keep one to
+/// inspect and script the driver while a
+/// [`VhciDriver`](super::VhciDriver) owns another.
+#[derive(Debug, Clone)]
+pub struct MockDriver {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    num_ports: u16,
+    exported: Vec<(SocketAddr, UsbDevice)>,
+    attached: Vec<Attached>,
+    failures: VecDeque<(Operation, Error)>,
+}
+
+#[derive(Debug)]
+struct Attached {
+    port: u16,
+    host: SocketAddr,
+    device: UsbDevice,
+}
+
+impl State {
+    fn take_failure(&mut self, operation: Operation) -> Option<Error> {
+        let index = self.failures.iter().position(|(op, _)| *op == operation)?;
+        self.failures.remove(index).map(|(_, err)| err)
+    }
+
+    fn is_attached(&self, host: SocketAddr, bus_id: &str) -> bool {
+        self.attached
+            .iter()
+            .any(|attached| attached.host == host && attached.device.bus_id() == bus_id)
+    }
+}
+
+impl MockDriver {
+    /// Creates a driver with `num_ports` free ports
+    /// and no exported devices.
+    pub fn new(num_ports: u16) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                num_ports,
+                exported: Vec::new(),
+                attached: Vec::new(),
+                failures: VecDeque::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Makes `device` attachable from `host`, by the
+    /// bus id it was built with.
+    pub fn export(&self, host: SocketAddr, device: UsbDevice) {
+        self.lock().exported.push((host, device));
+    }
+
+    /// Fails the next call of `operation` with `err`, instead
+    /// of carrying it out. Failures for the same operation
+    /// are returned in the order they were scripted.
+    pub fn fail_next(&self, operation: Operation, err: Error) {
+        self.lock().failures.push_back((operation, err));
+    }
+
+    /// The ports that have a device attached, lowest first.
+    pub fn attached_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<_> = self.lock().attached.iter().map(|a| a.port).collect();
+        ports.sort_unstable();
+        ports
+    }
+
+    /// Attaches the device, returning its port and the
+    /// device as the host reported it.
+    pub(crate) fn attach_reporting(&self, args: AttachArgs) -> Result<(u16, UsbDevice)> {
+        let mut state = self.lock();
+        if let Some(err) = state.take_failure(Operation::Attach) {
+            return Err(err);
+        }
+
+        let device = state
+            .exported
+            .iter()
+            .find(|(host, device)| *host == args.host && device.bus_id() == args.bus_id.as_str())
+            .map(|(_, device)| device.clone())
+            .ok_or_else(|| {
+                Error::Net(crate::net::Error::Rejected(Status::NoDev))
+                    .at_stage(AttachStage::Handshake)
+            })?;
+        if state.is_attached(args.host, device.bus_id()) {
+            return Err(Error::Net(crate::net::Error::Rejected(Status::DevBusy))
+                .at_stage(AttachStage::Handshake));
+        }
+
+        let port = (FIRST_PORT..FIRST_PORT + state.num_ports)
+            .find(|port| !state.attached.iter().any(|a| a.port == *port))
+            .ok_or_else(|| Error::NoFreePorts.at_stage(AttachStage::PortSelect))?;
+        state.attached.push(Attached {
+            port,
+            host: args.host,
+            device: device.clone(),
+        });
+        Ok((port, device))
+    }
+
+    pub(crate) fn attach(&self, args: AttachArgs) -> Result<u16> {
+        self.attach_reporting(args).map(|(port, _)| port)
+    }
+
+    pub(crate) fn attach_with_cancel(
+        &self,
+        args: AttachArgs,
+        token: &CancellationToken,
+    ) -> Result<u16> {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        self.attach(args)
+    }
+
+    pub(crate) fn attach_many_with_cancel<'a>(
+        &self,
+        args: impl IntoIterator<Item = AttachArgs<'a>>,
+        _max_parallel: NonZeroUsize,
+        token: &CancellationToken,
+    ) -> Vec<Result<u16>> {
+        args.into_iter()
+            .map(|args| self.attach_with_cancel(args, token))
+            .collect()
+    }
+
+    pub(crate) fn detach(&self, port: u16) -> Result<()> {
+        let mut state = self.lock();
+        if let Some(err) = state.take_failure(Operation::Detach) {
+            return Err(err);
+        }
+        let index = state
+            .attached
+            .iter()
+            .position(|a| a.port == port)
+            .ok_or(Error::PortNotInUse)?;
+        state.attached.remove(index);
+        Ok(())
+    }
+
+    pub(crate) fn imported_devices(&self) -> Result<ImportedDevices> {
+        let mut state = self.lock();
+        if let Some(err) = state.take_failure(Operation::ImportedDevices) {
+            return Err(err);
+        }
+        let mut idevs: Vec<_> = state
+            .attached
+            .iter()
+            .map(|a| ImportedDevice::from_mock(a.port, a.host, &a.device))
+            .collect();
+        idevs.sort_unstable_by_key(|idev| idev.port());
+        Ok(ImportedDevices::from_mock(idevs))
+    }
+
+    pub(crate) fn port_counts(&self) -> Result<PortCounts> {
+        let state = self.lock();
+        Ok(PortCounts {
+            num_controllers: 1,
+            num_ports: state.num_ports.into(),
+            free_ports: (state.num_ports as usize).saturating_sub(state.attached.len()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{vhci::ErrorKind, BusId};
+
+    fn host() -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 2], 3240))
+    }
+
+    fn device(bus_id: &str) -> UsbDevice {
+        crate::test_device(bus_id).build().unwrap()
+    }
+
+    fn args(bus_id: &str) -> AttachArgs<'_> {
+        AttachArgs {
+            host: host(),
+            bus_id: BusId::parse(bus_id).unwrap(),
+        }
+    }
+
+    #[test]
+    fn attach_takes_the_lowest_free_port() {
+        let mock = MockDriver::new(2);
+        mock.export(host(), device("1-1"));
+        mock.export(host(), device("1-2"));
+
+        assert_eq!(mock.attach(args("1-1")).unwrap(), FIRST_PORT);
+        assert_eq!(mock.attach(args("1-2")).unwrap(), FIRST_PORT + 1);
+        mock.detach(FIRST_PORT).unwrap();
+        assert_eq!(mock.attached_ports(), [FIRST_PORT + 1]);
+
+        let idevs = mock.imported_devices().unwrap();
+        let record = idevs.get()[0].record().unwrap();
+        assert_eq!((*record.host(), record.bus_id()), (host(), "1-2"));
+        assert_eq!(
+            mock.detach(FIRST_PORT).unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+    }
+
+    #[test]
+    fn attach_fails_like_a_host() {
+        let mock = MockDriver::new(1);
+        mock.export(host(), device("1-1"));
+        mock.export(host(), device("1-2"));
+
+        let err = mock.attach(args("2-1")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::RemoteRejected);
+        assert_eq!(err.attach_stage(), Some(AttachStage::Handshake));
+
+        mock.attach(args("1-1")).unwrap();
+        assert_eq!(
+            mock.attach(args("1-1")).unwrap_err().kind(),
+            ErrorKind::Busy
+        );
+        let err = mock.attach(args("1-2")).unwrap_err();
+        assert_eq!(err.attach_stage(), Some(AttachStage::PortSelect));
+        assert_eq!(mock.port_counts().unwrap().free_ports(), 0);
+    }
+
+    #[test]
+    fn scripted_failures_are_taken_in_order() {
+        let mock = MockDriver::new(1);
+        mock.export(host(), device("1-1"));
+        mock.fail_next(Operation::Attach, Error::DriverNotFound);
+        mock.fail_next(Operation::Attach, Error::Cancelled);
+
+        assert_eq!(
+            mock.attach(args("1-1")).unwrap_err().kind(),
+            ErrorKind::DriverMissing
+        );
+        assert_eq!(
+            mock.attach(args("1-1")).unwrap_err().kind(),
+            ErrorKind::Cancelled
+        );
+        assert_eq!(mock.attach(args("1-1")).unwrap(), FIRST_PORT);
+        assert!(mock.attached_ports().contains(&FIRST_PORT));
+    }
+}
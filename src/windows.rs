@@ -1,22 +1,32 @@
+#[cfg(feature = "client")]
 use windows::Win32::{
     Devices::DeviceAndDriverInstallation::{CM_MapCrToWin32Err, CONFIGRET},
     Foundation::WIN32_ERROR,
 };
 
+#[cfg(feature = "client")]
 mod util;
+#[cfg(feature = "client")]
 pub mod vhci {
-    mod ioctl;
     pub mod ioctl2;
+    mod persistent;
     use std::{
         ffi::OsString,
         fs::File,
         net::{SocketAddr, ToSocketAddrs},
+        num::NonZeroUsize,
         os::windows::{
             ffi::OsStringExt,
             fs::OpenOptionsExt,
-            io::{AsHandle, BorrowedHandle},
+            io::{AsHandle, AsRawHandle, FromRawHandle, OwnedHandle, RawHandle},
         },
-        path::PathBuf,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::Duration,
     };
 
     use ioctl2::DriverError;
@@ -24,17 +34,30 @@ pub mod vhci {
         core::{GUID, PCWSTR},
         Win32::{
             Devices::DeviceAndDriverInstallation::CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            Foundation::{ERROR_OPERATION_ABORTED, HANDLE},
             Storage::FileSystem::{FILE_SHARE_READ, FILE_SHARE_WRITE},
+            System::{
+                Threading::{GetCurrentThreadId, OpenThread, THREAD_TERMINATE},
+                IO::CancelSynchronousIo,
+            },
         },
     };
 
     use crate::{
-        vhci::{base, error2::Error, AttachArgs},
-        BusId, BUS_ID_SIZE,
+        cancel::CancellationToken,
+        util::EncodedSize,
+        vhci::{base, AttachArgs, AttachStage, Error, PortCounts},
+        BusId,
     };
 
     use super::util;
 
+    pub use persistent::{
+        read_persistent_registry, write_persistent_registry, PERSISTENT_REGISTRY_KEY,
+        PERSISTENT_REGISTRY_VALUE,
+    };
+    pub use util::{cast_u8_to_u16_cow, try_cast_u8_to_u16_slice, CastError};
+
     pub static STATE_PATH: &str = "";
     const GUID_DEVINTERFACE_USB_HOST_CONTROLLER: GUID = GUID::from_values(
         0xB4030C06,
@@ -43,34 +66,149 @@ pub mod vhci {
         [0x87, 0xEB, 0xE5, 0x51, 0x5A, 0x09, 0x35, 0xC0],
     );
 
+    /// The number of ports on the usbip-win2 USB2 hub.
+    pub const USB2_PORTS: usize = 30;
+    /// The number of ports on the usbip-win2 USB3 hub.
+    pub const USB3_PORTS: usize = USB2_PORTS;
+    /// The total number of ports created by the usbip-win2 driver.
+    pub const TOTAL_PORTS: usize = USB2_PORTS + USB3_PORTS;
+
+    /// A device for the driver to attach whenever it starts.
+    ///
+    /// The host is kept as it was given, which may be a
+    /// name that the driver resolves each time it starts.
+    #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct DeviceLocation {
-        host: SocketAddr,
+        host: String,
+        port: u16,
         busid: BusId<'static>,
     }
 
+    impl DeviceLocation {
+        pub fn new(host: SocketAddr, busid: BusId<'static>) -> Self {
+            Self::with_hostname(host.ip().to_string(), host.port(), busid)
+        }
+
+        /// A location whose host is `hostname`,
+        /// such as a DNS name, on `port`.
+        pub fn with_hostname(
+            hostname: impl Into<String>,
+            port: u16,
+            busid: BusId<'static>,
+        ) -> Self {
+            Self {
+                host: hostname.into(),
+                port,
+                busid,
+            }
+        }
+
+        /// The host's name or ip address.
+        pub fn host(&self) -> &str {
+            &self.host
+        }
+
+        pub const fn port(&self) -> u16 {
+            self.port
+        }
+
+        pub fn bus_id(&self) -> &str {
+            self.busid.as_str()
+        }
+    }
+
+    /// Formats the location the way usbip-win2 stores
+    /// it, as `host,service,busid`.
+    impl core::fmt::Display for DeviceLocation {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "{},{},{}", self.host, self.port, self.busid)
+        }
+    }
+
+    /// Parses the `host,service,busid` format usbip-win2
+    /// stores locations in, without resolving the host.
+    impl core::str::FromStr for DeviceLocation {
+        type Err = ParseDeviceLocationError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut fields = s.splitn(3, ',');
+            let (Some(host), Some(service), Some(busid)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(ParseDeviceLocationError::MissingField);
+            };
+            if host.is_empty() {
+                return Err(ParseDeviceLocationError::MissingField);
+            }
+            let port = service
+                .parse()
+                .map_err(|_| ParseDeviceLocationError::BadPort)?;
+            let busid = BusId::parse(busid)
+                .map_err(|_| ParseDeviceLocationError::BadBusId)?
+                .into_owned();
+            Ok(Self::with_hostname(host, port, busid))
+        }
+    }
+
     impl From<ioctl2::DeviceLocation<'static>> for DeviceLocation {
         fn from(value: ioctl2::DeviceLocation<'static>) -> Self {
             let ioctl2::DeviceLocation { host, busid } = value;
-            Self { host, busid }
+            Self::new(host, busid)
+        }
+    }
+
+    /// A [`DeviceLocation`] couldn't be parsed.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ParseDeviceLocationError {
+        /// The host, service or bus id is missing.
+        MissingField,
+        BadPort,
+        BadBusId,
+    }
+
+    impl core::fmt::Display for ParseDeviceLocationError {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                ParseDeviceLocationError::MissingField => {
+                    write!(f, "Device location is not host,service,busid")
+                }
+                ParseDeviceLocationError::BadPort => write!(f, "Device location has a bad port"),
+                ParseDeviceLocationError::BadBusId => {
+                    write!(f, "Device location has a bad bus id")
+                }
+            }
         }
     }
 
+    impl std::error::Error for ParseDeviceLocationError {}
+
     #[derive(Debug)]
     pub struct PortRecord {
         base: base::PortRecord,
         port: u16,
     }
 
-    impl From<ioctl2::PortRecord<'_>> for PortRecord {
-        fn from(value: ioctl2::PortRecord) -> Self {
-            let host = (value.host.as_str(), value.service.as_str().parse().unwrap());
-            Self {
-                base: base::PortRecord {
-                    host: host.to_socket_addrs().unwrap().next().unwrap(),
-                    busid: value.busid.to_owned(),
-                },
-                port: value.port as u16,
+    impl TryFrom<ioctl2::PortRecord<'_>> for PortRecord {
+        type Error = Error;
+
+        fn try_from(value: ioctl2::PortRecord) -> Result<Self, Self::Error> {
+            fn invalid(err: impl std::error::Error + Send + Sync + 'static) -> Error {
+                Error::InvalidDriverData(Box::new(err))
             }
+
+            let service = value.service.as_str().parse::<u16>().map_err(invalid)?;
+            let host = (value.host.as_str(), service)
+                .to_socket_addrs()
+                .map_err(invalid)?
+                .next()
+                .ok_or_else(|| invalid(std::io::Error::from(std::io::ErrorKind::NotFound)))?;
+            let busid = BusId::parse(value.busid.trim_padding().as_str())
+                .map_err(invalid)?
+                .into_owned();
+            Ok(Self {
+                base: base::PortRecord { host, busid },
+                port: value.port as u16,
+            })
         }
     }
 
@@ -81,20 +219,75 @@ pub mod vhci {
         speed: crate::DeviceSpeed,
     }
 
-    impl From<ioctl2::ImportedDevice<'_>> for WindowsImportedDevice {
-        fn from(value: ioctl2::ImportedDevice) -> Self {
-            Self {
+    impl TryFrom<ioctl2::ImportedDevice<'_>> for WindowsImportedDevice {
+        type Error = Error;
+
+        fn try_from(value: ioctl2::ImportedDevice) -> Result<Self, Self::Error> {
+            Ok(Self {
                 base: base::ImportedDevice {
                     vendor: value.vendor,
                     product: value.product,
                     devid: value.devid,
                 },
-                record: PortRecord::from(value.record),
+                record: PortRecord::try_from(value.record)?,
                 speed: value.speed,
+            })
+        }
+    }
+
+    impl core::ops::Deref for PortRecord {
+        type Target = base::PortRecord;
+
+        fn deref(&self) -> &Self::Target {
+            &self.base
+        }
+    }
+
+    impl WindowsImportedDevice {
+        pub const fn port(&self) -> u16 {
+            self.record.port
+        }
+
+        pub const fn speed(&self) -> crate::DeviceSpeed {
+            self.speed
+        }
+
+        /// The host and remote bus id the device was imported
+        /// from. The driver always reports it on windows.
+        pub const fn record(&self) -> Option<&PortRecord> {
+            Some(&self.record)
+        }
+
+        /// `usb_dev` as if it were imported from `host` and
+        /// attached to `port`, for [`crate::vhci::mock`].
+        #[cfg(feature = "mock-driver")]
+        pub(crate) fn from_mock(port: u16, host: SocketAddr, usb_dev: &crate::UsbDevice) -> Self {
+            Self {
+                base: base::ImportedDevice {
+                    vendor: usb_dev.id_vendor(),
+                    product: usb_dev.id_product(),
+                    devid: usb_dev.dev_id(),
+                },
+                record: PortRecord {
+                    base: base::PortRecord {
+                        host,
+                        busid: usb_dev.busid.clone(),
+                    },
+                    port,
+                },
+                speed: usb_dev.speed(),
             }
         }
     }
 
+    impl core::ops::Deref for WindowsImportedDevice {
+        type Target = base::ImportedDevice;
+
+        fn deref(&self) -> &Self::Target {
+            &self.base
+        }
+    }
+
     #[derive(Debug)]
     pub struct WindowsImportedDevices(Box<[WindowsImportedDevice]>);
 
@@ -102,138 +295,513 @@ pub mod vhci {
         pub fn get(&self) -> &[WindowsImportedDevice] {
             &self.0
         }
+
+        #[cfg(feature = "mock-driver")]
+        pub(crate) fn from_mock(idevs: Vec<WindowsImportedDevice>) -> Self {
+            Self(idevs.into_boxed_slice())
+        }
     }
 
-    #[derive(Debug, Clone, Copy)]
-    pub struct TryFromAttachArgsErr;
+    /// The imported devices exactly as the driver reported
+    /// them, for callers that only format or filter them.
+    ///
+    /// Entries are decoded as they're iterated, and borrow
+    /// their strings from the driver's reply instead of
+    /// copying them out like [`WindowsImportedDevice`] does.
+    #[derive(Debug)]
+    pub struct RawImportedDevices(Box<[u8]>);
 
-    impl std::fmt::Display for TryFromAttachArgsErr {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(
-                f,
-                "the given busid was greater than BUS_ID_SIZE ({})",
-                BUS_ID_SIZE - 1
-            )
+    impl RawImportedDevices {
+        pub fn len(&self) -> usize {
+            self.0.len() / ioctl2::ImportedDevice::ENCODED_SIZE_OF
         }
-    }
 
-    impl std::error::Error for TryFromAttachArgsErr {}
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
 
-    impl<'a> TryFrom<AttachArgs<'a>> for ioctl2::DeviceLocation<'a> {
-        type Error = TryFromAttachArgsErr;
+        /// Decodes each entry in turn.
+        ///
+        /// # Errors
+        /// Yields [`Error::Net`] for an entry that
+        /// the driver didn't encode properly.
+        pub fn iter(
+            &self,
+        ) -> impl Iterator<Item = crate::vhci::Result<ioctl2::ImportedDevice<'_>>> {
+            self.0
+                .chunks_exact(ioctl2::ImportedDevice::ENCODED_SIZE_OF)
+                .map(|entry| {
+                    bincode::borrow_decode_from_slice(entry, win_deviceioctl::bincode_config())
+                        .map(|(idev, _)| idev)
+                        .map_err(|err| Error::Net(crate::net::Error::De(err)))
+                })
+        }
+    }
 
-        fn try_from(value: AttachArgs<'a>) -> Result<Self, Self::Error> {
-            Self::new(value.host, value.bus_id).ok_or(TryFromAttachArgsErr)
+    impl<'a> From<AttachArgs<'a>> for ioctl2::DeviceLocation<'a> {
+        fn from(value: AttachArgs<'a>) -> Self {
+            Self::new(value.host, value.bus_id)
         }
     }
 
     impl From<win_deviceioctl::Error<DriverError>> for Error {
         fn from(err: win_deviceioctl::Error<DriverError>) -> Self {
+            use std::io::ErrorKind as Io;
+
             match err {
-                win_deviceioctl::Error::Driver(DriverError::DevNotConnected) => {
-                    Error::WriteSys(std::io::ErrorKind::NotConnected.into())
-                }
-                win_deviceioctl::Error::Driver(DriverError::IncompatibleProtocolVersion)
-                | win_deviceioctl::Error::Driver(DriverError::InvalidAbi) => {
-                    Error::WriteSys(std::io::ErrorKind::InvalidData.into())
+                win_deviceioctl::Error::Driver(DriverError::PortFull) => Error::NoFreePorts,
+                win_deviceioctl::Error::Driver(DriverError::Driver) => Error::DriverNotFound,
+                win_deviceioctl::Error::Driver(driver) => {
+                    let kind = match driver {
+                        DriverError::AddrInfo
+                        | DriverError::Connect
+                        | DriverError::Network
+                        | DriverError::DevNotConnected => Io::NotConnected,
+                        DriverError::IncompatibleProtocolVersion
+                        | DriverError::Protocol
+                        | DriverError::InvalidAbi => Io::InvalidData,
+                        DriverError::FileNotFound => Io::NotFound,
+                        _ => Io::Other,
+                    };
+                    Error::WriteSys(std::io::Error::new(kind, driver))
                 }
                 win_deviceioctl::Error::Io(io) => Error::WriteSys(io),
-                win_deviceioctl::Error::Driver(DriverError::FileNotFound) => {
-                    Error::WriteSys(std::io::ErrorKind::NotFound.into())
-                }
-                _ => unreachable!("Dev error in parsing data"),
+                // The request didn't fit its layout, or the driver's
+                // reply was short or malformed. Both are protocol
+                // failures, the same as on a unix socket.
+                win_deviceioctl::Error::Send(err) => Error::Net(crate::net::Error::Enc(err)),
+                win_deviceioctl::Error::Recv(err) => Error::Net(crate::net::Error::De(err)),
             }
         }
     }
 
-    struct InnerDriver {
-        handle: File,
+    /// Guesses which step of the attach failed, since
+    /// the driver does the whole attach in one ioctl.
+    fn attach_stage(err: &win_deviceioctl::Error<DriverError>) -> AttachStage {
+        match err {
+            win_deviceioctl::Error::Driver(DriverError::AddrInfo) => AttachStage::Resolve,
+            win_deviceioctl::Error::Driver(
+                DriverError::Connect | DriverError::Network | DriverError::DevNotConnected,
+            ) => AttachStage::Connect,
+            win_deviceioctl::Error::Driver(
+                DriverError::IncompatibleProtocolVersion
+                | DriverError::Protocol
+                | DriverError::InvalidAbi,
+            ) => AttachStage::Handshake,
+            win_deviceioctl::Error::Driver(DriverError::PortFull) => AttachStage::PortSelect,
+            _ => AttachStage::KernelSubmit,
+        }
+    }
+
+    /// Performs `call`, the ioctl named `op`, recording
+    /// how long it took when metrics are enabled.
+    #[inline(always)]
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn driver_call<T>(op: &'static str, call: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "metrics")]
+        let started = std::time::Instant::now();
+        let result = call();
+        #[cfg(feature = "metrics")]
+        crate::stats::driver_call(op, started);
+        result
     }
 
-    impl InnerDriver {
-        fn as_handle(&self) -> BorrowedHandle {
-            self.handle.as_handle()
+    type IoctlResult<T> = Result<T, win_deviceioctl::Error<DriverError>>;
+
+    /// Sends ioctls to the vhci driver.
+    ///
+    /// [`InnerDriver`] goes through this trait instead of calling
+    /// `win_deviceioctl` directly, so that its encoding, decoding
+    /// and error mapping can be tested with canned replies from
+    /// a mock driver.
+    trait DeviceIoctl {
+        fn send<I>(&self, ioctl: I) -> IoctlResult<()>
+        where
+            I: win_deviceioctl::Send + win_deviceioctl::CtrlCode;
+
+        fn send_recv<I>(&self, ioctl: I) -> IoctlResult<I::Output>
+        where
+            I: win_deviceioctl::Send + win_deviceioctl::Recv + win_deviceioctl::CtrlCode;
+
+        fn recv<I>(&self, ioctl: I) -> IoctlResult<I::Output>
+        where
+            I: win_deviceioctl::Recv + win_deviceioctl::CtrlCode;
+    }
+
+    impl DeviceIoctl for File {
+        fn send<I>(&self, ioctl: I) -> IoctlResult<()>
+        where
+            I: win_deviceioctl::Send + win_deviceioctl::CtrlCode,
+        {
+            win_deviceioctl::send(self.as_handle(), ioctl)
         }
 
-        fn try_open() -> crate::vhci::Result<Self> {
+        fn send_recv<I>(&self, ioctl: I) -> IoctlResult<I::Output>
+        where
+            I: win_deviceioctl::Send + win_deviceioctl::Recv + win_deviceioctl::CtrlCode,
+        {
+            win_deviceioctl::send_recv(self.as_handle(), ioctl)
+        }
+
+        fn recv<I>(&self, ioctl: I) -> IoctlResult<I::Output>
+        where
+            I: win_deviceioctl::Recv + win_deviceioctl::CtrlCode,
+        {
+            win_deviceioctl::recv(self.as_handle(), ioctl)
+        }
+    }
+
+    struct InnerDriver<D = File> {
+        handle: D,
+        /// How many devices the last call to `imported_devices`
+        /// saw, to size the next call's buffer with.
+        last_imported: AtomicUsize,
+        /// Same as `last_imported`, for `persistent_devices`.
+        last_persistent: AtomicUsize,
+    }
+
+    impl InnerDriver {
+        fn open(path: &Path) -> crate::vhci::Result<Self> {
             let file = File::options()
                 .create(true)
                 .read(true)
                 .write(true)
                 .attributes((FILE_SHARE_READ | FILE_SHARE_WRITE).0)
-                .open(Self::path()?)?;
+                .open(path)?;
+
+            Ok(Self::new(file))
+        }
+
+        /// Returns the paths of every present
+        /// vhci device interface.
+        fn paths() -> crate::vhci::Result<Vec<PathBuf>> {
+            let v = util::get_device_interface_list(
+                GUID_DEVINTERFACE_USB_HOST_CONTROLLER,
+                PCWSTR::null(),
+                CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+            )
+            .map_err(|err| std::io::Error::from_raw_os_error(err.get().to_hresult().0))?;
+            Ok(v.split(|&elm| elm == 0)
+                .filter(|slice| !slice.is_empty())
+                .map(|path| PathBuf::from(OsString::from_wide(path)))
+                .collect())
+        }
+
+        fn path(selector: InterfaceSelector) -> crate::vhci::Result<PathBuf> {
+            let mut paths = Self::paths()?;
+            let index = match selector {
+                InterfaceSelector::First => Some(0),
+                InterfaceSelector::Index(index) => Some(index),
+                InterfaceSelector::Path(path) => paths.iter().position(|p| p == path),
+            };
+            index
+                .filter(|&index| index < paths.len())
+                .map(|index| paths.swap_remove(index))
+                .ok_or(Error::DriverNotFound)
+        }
+    }
+
+    impl<D: DeviceIoctl> InnerDriver<D> {
+        const fn new(handle: D) -> Self {
+            Self {
+                handle,
+                last_imported: AtomicUsize::new(0),
+                last_persistent: AtomicUsize::new(0),
+            }
+        }
+
+        /// Like [`InnerDriver::attach`], but gives up
+        /// once `token` is cancelled.
+        ///
+        /// The handle isn't opened for overlapped I/O, and
+        /// `CancelIoEx` doesn't reliably interrupt a synchronous
+        /// `DeviceIoControl`. So cancelling instead runs
+        /// `CancelSynchronousIo` on the thread blocked in the
+        /// attach ioctl, which is where the driver connects to
+        /// the host.
+        fn attach_with_cancel(
+            &mut self,
+            args: AttachArgs,
+            token: &CancellationToken,
+        ) -> crate::vhci::Result<u16> {
+            if token.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+
+            let thread_id = unsafe { GetCurrentThreadId() };
+            // SAFETY: OpenThread returns a new handle that
+            //         nothing else owns, so it's ours to close.
+            let thread = unsafe {
+                let handle =
+                    OpenThread(THREAD_TERMINATE, false, thread_id).map_err(std::io::Error::from)?;
+                OwnedHandle::from_raw_handle(handle.0 as RawHandle)
+            };
 
-            Ok(Self { handle: file })
+            // HANDLEs aren't Send, but the raw value is just an
+            // address, and the guard below is dropped before
+            // the thread handle is closed.
+            let raw_thread = thread.as_raw_handle() as isize;
+            let done = Arc::new(AtomicBool::new(false));
+            let guard = token.on_cancel({
+                let done = Arc::clone(&done);
+                move || {
+                    // Cancelled between the check above and registering,
+                    // so this is running on the attaching thread, before
+                    // any I/O. The check below catches that.
+                    if unsafe { GetCurrentThreadId() } == thread_id {
+                        return;
+                    }
+                    // The ioctl may not have been issued yet, leaving
+                    // nothing to cancel, so retry until it's cancelled
+                    // or the attach finishes on its own.
+                    // SAFETY: The thread handle is valid for as long
+                    //         as the guard is alive.
+                    while !done.load(Ordering::Acquire)
+                        && unsafe { CancelSynchronousIo(HANDLE(raw_thread)) }.is_err()
+                    {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            });
+            if token.is_cancelled() {
+                return Err(Error::Cancelled);
+            }
+
+            let result = self.attach(args);
+            done.store(true, Ordering::Release);
+            drop(guard);
+            drop(thread);
+
+            match result {
+                Err(Error::Attach { source, .. })
+                    if matches!(
+                        &*source,
+                        Error::WriteSys(io)
+                            if io.raw_os_error() == Some(ERROR_OPERATION_ABORTED.0 as i32)
+                    ) =>
+                {
+                    Err(Error::Cancelled)
+                }
+                Err(_) if token.is_cancelled() => Err(Error::Cancelled),
+                result => result,
+            }
         }
 
+        #[cfg_attr(
+            feature = "tracing",
+            tracing::instrument(
+                skip_all,
+                fields(host = %args.host, busid = %args.bus_id, port = tracing::field::Empty),
+                err
+            )
+        )]
         fn attach(&mut self, args: AttachArgs) -> crate::vhci::Result<u16> {
-            let device_location = ioctl2::DeviceLocation::try_from(args)
-                .map_err(|err| Error::UserInput(Box::from(err)))?;
-            let port =
-                win_deviceioctl::send_recv(self.as_handle(), ioctl2::Attach::new(device_location))
-                    .map_err(Error::from)?;
+            let device_location = ioctl2::DeviceLocation::from(args);
+            let started = std::time::Instant::now();
+            let port = driver_call("attach", || {
+                self.handle.send_recv(ioctl2::Attach::new(device_location))
+            })
+            .map_err(|err| {
+                let stage = attach_stage(&err);
+                Error::from(err).at_stage_timed(stage, started)
+            })?;
+            #[cfg(feature = "tracing")]
+            tracing::Span::current().record("port", port);
 
             Ok(port)
         }
 
+        #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), err))]
         fn detach(&mut self, port: u16) -> crate::vhci::Result<()> {
-            win_deviceioctl::send(self.as_handle(), ioctl2::Detach::new(port)).map_err(Error::from)
+            driver_call("detach", || self.handle.send(ioctl2::Detach::new(port)))
+                .map_err(Error::from)
         }
 
         fn imported_devices(&self) -> crate::vhci::Result<WindowsImportedDevices> {
-            win_deviceioctl::send_recv(self.as_handle(), ioctl2::GetImportedDevices)
-                .map_err(Error::from)
-                .map(|vec| WindowsImportedDevices(vec.into_boxed_slice()))
+            let idevs = self
+                .imported_devices_raw()?
+                .iter()
+                .map(|idev| WindowsImportedDevice::try_from(idev?))
+                .collect::<crate::vhci::Result<Box<[_]>>>()?;
+            Ok(WindowsImportedDevices(idevs))
+        }
+
+        fn port_counts(&self) -> crate::vhci::Result<PortCounts> {
+            // The driver has no ioctl for its port layout, so
+            // everything that isn't imported must be free.
+            let used = self.imported_devices()?.get().len();
+            Ok(PortCounts {
+                num_controllers: 1,
+                num_ports: TOTAL_PORTS,
+                free_ports: TOTAL_PORTS.saturating_sub(used),
+            })
+        }
+
+        fn imported_devices_raw(&self) -> crate::vhci::Result<RawImportedDevices> {
+            let expected = self.last_imported.load(Ordering::Relaxed);
+            let raw = driver_call("get_imported_devices", || {
+                self.handle
+                    .send_recv(ioctl2::GetImportedDevices::expecting(expected))
+            })
+            .map_err(Error::from)
+            .map(RawImportedDevices)?;
+            self.last_imported.store(raw.len(), Ordering::Relaxed);
+            Ok(raw)
         }
 
         fn persistent_devices(&self) -> crate::vhci::Result<Box<[DeviceLocation]>> {
-            let devs = match win_deviceioctl::recv(self.as_handle(), ioctl2::GetPersistentDevices) {
+            let expected = self.last_persistent.load(Ordering::Relaxed);
+            let devs = match driver_call("get_persistent_devices", || {
+                self.handle
+                    .recv(ioctl2::GetPersistentDevices::expecting(expected))
+            }) {
                 Ok(devs) => devs,
                 Err(win_deviceioctl::Error::Driver(DriverError::FileNotFound)) => Vec::new(),
                 Err(err) => Err(Error::from(err))?,
             };
-            Ok(devs.into_iter().map(DeviceLocation::from).collect())
+            self.last_persistent.store(devs.len(), Ordering::Relaxed);
+            Ok(devs.into_boxed_slice())
         }
 
-        fn path() -> crate::vhci::Result<PathBuf> {
-            let v = util::get_device_interface_list(
-                GUID_DEVINTERFACE_USB_HOST_CONTROLLER,
-                PCWSTR::null(),
-                CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
-            )
-            .map_err(|err| std::io::Error::from_raw_os_error(err.get().to_hresult().0))?;
-            let mut p = v.split(|&elm| elm == 0).filter(|slice| !slice.is_empty());
-            if let Some(path) = p.next() {
-                if p.next().is_some() {
-                    // We add 2 because of the first slice and
-                    // this second slice we just found.
-                    Err(Error::MultipleDevInterfaces(2 + p.count()))
-                } else {
-                    Ok(PathBuf::from(OsString::from_wide(path)))
-                }
-            } else {
-                Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
-            }
+        fn set_persistent_devices(&self, devices: &[DeviceLocation]) -> crate::vhci::Result<()> {
+            let multi_sz = persistent::to_multi_sz(devices);
+            driver_call("set_persistent_devices", || {
+                self.handle.send(ioctl2::SetPersistentDevices(&multi_sz))
+            })
+            .map_err(Error::from)?;
+            persistent::write_multi_sz(&multi_sz)?;
+            Ok(())
         }
     }
 
+    /// Chooses which vhci device interface to open
+    /// when more than one is present.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub enum InterfaceSelector<'a> {
+        /// The first interface reported by the system.
+        #[default]
+        First,
+        /// The interface at this index in
+        /// [`WindowsVhciDriver::device_interfaces`].
+        Index(usize),
+        /// The interface with this exact path.
+        Path(&'a Path),
+    }
+
     pub struct WindowsVhciDriver {
         inner: InnerDriver,
+        /// The interface `inner` was opened from, which
+        /// [`WindowsVhciDriver::attach_many`] reopens.
+        path: PathBuf,
     }
 
     impl WindowsVhciDriver {
+        /// Opens the first vhci device interface.
         #[inline(always)]
         pub fn open() -> crate::vhci::Result<Self> {
+            Self::open_interface(InterfaceSelector::First)
+        }
+
+        /// Opens the vhci device interface chosen by `selector`.
+        ///
+        /// # Errors
+        /// This function will return [`Error::DriverNotFound`]
+        /// if no interface matches `selector`.
+        #[inline(always)]
+        pub fn open_interface(selector: InterfaceSelector) -> crate::vhci::Result<Self> {
+            let path = InnerDriver::path(selector)?;
             Ok(Self {
-                inner: InnerDriver::try_open()?,
+                inner: InnerDriver::open(&path)?,
+                path,
             })
         }
 
+        /// Returns the paths of every vhci device
+        /// interface present on the system.
+        #[inline(always)]
+        pub fn device_interfaces() -> crate::vhci::Result<Box<[PathBuf]>> {
+            InnerDriver::paths().map(Vec::into_boxed_slice)
+        }
+
         #[inline(always)]
         pub fn attach(&mut self, args: AttachArgs) -> crate::vhci::Result<u16> {
             self.inner.attach(args)
         }
 
+        /// Like [`WindowsVhciDriver::attach`], but returns
+        /// [`Error::Cancelled`] once `token` is cancelled,
+        /// even while the driver is still connecting to
+        /// a host that doesn't answer.
+        #[inline(always)]
+        pub fn attach_with_cancel(
+            &mut self,
+            args: AttachArgs,
+            token: &CancellationToken,
+        ) -> crate::vhci::Result<u16> {
+            self.inner.attach_with_cancel(args, token)
+        }
+
+        /// Attaches every device in `args`, up to `max_parallel`
+        /// at a time, returning the results in the same order.
+        ///
+        /// The driver connects to the host within the attach
+        /// ioctl, and a handle runs one ioctl at a time, so
+        /// each extra worker opens its own handle to the same
+        /// interface. If that fails, the remaining workers
+        /// share the devices instead.
+        pub fn attach_many<'a>(
+            &mut self,
+            args: impl IntoIterator<Item = AttachArgs<'a>>,
+            max_parallel: NonZeroUsize,
+        ) -> Vec<crate::vhci::Result<u16>> {
+            self.attach_many_with_cancel(args, max_parallel, &CancellationToken::new())
+        }
+
+        /// Like [`WindowsVhciDriver::attach_many`], but each
+        /// attach in progress, and every one not started
+        /// yet, fails with [`Error::Cancelled`] once `token`
+        /// is cancelled.
+        pub fn attach_many_with_cancel<'a>(
+            &mut self,
+            args: impl IntoIterator<Item = AttachArgs<'a>>,
+            max_parallel: NonZeroUsize,
+            token: &CancellationToken,
+        ) -> Vec<crate::vhci::Result<u16>> {
+            let args: Vec<_> = args.into_iter().collect();
+            let mut results: Vec<_> = args.iter().map(|_| None).collect();
+            let next = AtomicUsize::new(0);
+            let (done, attached) = mpsc::channel();
+            let work = |inner: &mut InnerDriver, done: mpsc::Sender<_>| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(args) = args.get(index) else {
+                    break;
+                };
+                let args = AttachArgs {
+                    host: args.host,
+                    bus_id: args.bus_id.clone(),
+                };
+                let _ = done.send((index, inner.attach_with_cancel(args, token)));
+            };
+
+            thread::scope(|scope| {
+                // This driver's own handle is the first worker.
+                for _ in 1..max_parallel.get().min(args.len()) {
+                    let Ok(mut inner) = InnerDriver::open(&self.path) else {
+                        break;
+                    };
+                    let (work, done) = (&work, done.clone());
+                    scope.spawn(move || work(&mut inner, done));
+                }
+                work(&mut self.inner, done);
+
+                for (index, result) in attached {
+                    results[index] = Some(result);
+                }
+            });
+
+            results.into_iter().map(Option::unwrap).collect()
+        }
+
         #[inline(always)]
         pub fn detach(&mut self, port: u16) -> crate::vhci::Result<()> {
             self.inner.detach(port)
@@ -243,33 +811,299 @@ pub mod vhci {
         pub fn imported_devices(&self) -> crate::vhci::Result<WindowsImportedDevices> {
             self.inner.imported_devices()
         }
+
+        #[inline(always)]
+        pub fn port_counts(&self) -> crate::vhci::Result<PortCounts> {
+            self.inner.port_counts()
+        }
     }
 
     pub trait WindowsVhciDriverExt {
         fn persistent_devices(&self) -> crate::vhci::Result<Box<[DeviceLocation]>>;
+
+        /// Replaces the devices the driver attaches whenever it
+        /// starts with `devices`, and mirrors them into
+        /// [`PERSISTENT_REGISTRY_KEY`] for other tools to see.
+        ///
+        /// # Errors
+        /// This function will return an error if the driver
+        /// refused the list, or the registry couldn't be written.
+        fn set_persistent_devices(&self, devices: &[DeviceLocation]) -> crate::vhci::Result<()>;
+
+        /// Like [`WindowsVhciDriver::imported_devices`], but
+        /// leaves the entries undecoded until they're iterated.
+        fn imported_devices_raw(&self) -> crate::vhci::Result<RawImportedDevices>;
     }
 
     impl WindowsVhciDriverExt for WindowsVhciDriver {
         fn persistent_devices(&self) -> crate::vhci::Result<Box<[DeviceLocation]>> {
             self.inner.persistent_devices()
         }
+
+        fn set_persistent_devices(&self, devices: &[DeviceLocation]) -> crate::vhci::Result<()> {
+            self.inner.set_persistent_devices(devices)
+        }
+
+        fn imported_devices_raw(&self) -> crate::vhci::Result<RawImportedDevices> {
+            self.inner.imported_devices_raw()
+        }
     }
 
     #[cfg(test)]
     mod tests {
+        use std::{cell::RefCell, collections::VecDeque};
+
         use super::*;
 
+        /// Answers [`InnerDriver`]'s ioctls with canned replies,
+        /// and keeps the encoded input of each one it was sent.
+        #[derive(Default)]
+        struct MockDriver {
+            inputs: RefCell<Vec<Vec<u8>>>,
+            replies: RefCell<VecDeque<IoctlResult<Vec<u8>>>>,
+        }
+
+        impl MockDriver {
+            fn reply(self, reply: IoctlResult<Vec<u8>>) -> Self {
+                self.replies.borrow_mut().push_back(reply);
+                self
+            }
+
+            fn next_reply(&self, input: Vec<u8>) -> IoctlResult<Vec<u8>> {
+                self.inputs.borrow_mut().push(input);
+                self.replies
+                    .borrow_mut()
+                    .pop_front()
+                    .expect("mock driver ran out of replies")
+            }
+
+            fn encode<I: win_deviceioctl::Send>(ioctl: &I) -> Vec<u8> {
+                struct Bytes(Vec<u8>);
+
+                impl bincode::enc::write::Writer for Bytes {
+                    fn write(&mut self, bytes: &[u8]) -> Result<(), bincode::error::EncodeError> {
+                        self.0.extend_from_slice(bytes);
+                        Ok(())
+                    }
+                }
+
+                let mut encoder = bincode::enc::EncoderImpl::new(
+                    Bytes(Vec::new()),
+                    win_deviceioctl::bincode_config(),
+                );
+                win_deviceioctl::Send::send(ioctl, &mut encoder).unwrap();
+                encoder.into_writer().0
+            }
+
+            fn decode<I: win_deviceioctl::Recv>(reply: &[u8]) -> IoctlResult<I::Output> {
+                <I as win_deviceioctl::Recv>::recv(reply).map_err(win_deviceioctl::Error::Recv)
+            }
+        }
+
+        impl DeviceIoctl for MockDriver {
+            fn send<I>(&self, ioctl: I) -> IoctlResult<()>
+            where
+                I: win_deviceioctl::Send + win_deviceioctl::CtrlCode,
+            {
+                self.next_reply(Self::encode(&ioctl)).map(drop)
+            }
+
+            fn send_recv<I>(&self, ioctl: I) -> IoctlResult<I::Output>
+            where
+                I: win_deviceioctl::Send + win_deviceioctl::Recv + win_deviceioctl::CtrlCode,
+            {
+                Self::decode::<I>(&self.next_reply(Self::encode(&ioctl))?)
+            }
+
+            fn recv<I>(&self, _ioctl: I) -> IoctlResult<I::Output>
+            where
+                I: win_deviceioctl::Recv + win_deviceioctl::CtrlCode,
+            {
+                Self::decode::<I>(&self.next_reply(Vec::new())?)
+            }
+        }
+
+        fn mock_args() -> AttachArgs<'static> {
+            AttachArgs {
+                host: ([10, 0, 0, 2], 3240).into(),
+                bus_id: BusId::parse("1-1").unwrap(),
+            }
+        }
+
+        #[test]
+        fn attach_sends_location_and_decodes_port() {
+            let mut driver =
+                InnerDriver::new(MockDriver::default().reply(Ok(vec![8, 0, 0, 0, 5, 0, 0, 0])));
+            assert_eq!(driver.attach(mock_args()).unwrap(), 5);
+
+            let inputs = driver.handle.inputs.borrow();
+            let input = &inputs[0];
+            assert_eq!(input.len(), 4 + ioctl2::DeviceLocation::ENCODED_SIZE_OF);
+            assert_eq!(input[..4], (input.len() as u32).to_le_bytes());
+        }
+
+        #[test]
+        fn attach_maps_driver_errors() {
+            let mut driver = InnerDriver::new(
+                MockDriver::default()
+                    .reply(Err(win_deviceioctl::Error::Driver(DriverError::PortFull))),
+            );
+            let err = driver.attach(mock_args()).unwrap_err();
+            assert_eq!(err.kind(), crate::vhci::ErrorKind::Busy);
+        }
+
+        #[test]
+        fn short_attach_reply_is_a_protocol_error() {
+            let mut driver = InnerDriver::new(MockDriver::default().reply(Ok(vec![8, 0])));
+            let err = driver.attach(mock_args()).unwrap_err();
+            assert_eq!(err.kind(), crate::vhci::ErrorKind::Protocol);
+        }
+
+        #[test]
+        fn detach_encodes_port() {
+            let mut driver = InnerDriver::new(MockDriver::default().reply(Ok(Vec::new())));
+            driver.detach(3).unwrap();
+            assert_eq!(driver.handle.inputs.borrow()[0], [8, 0, 0, 0, 3, 0, 0, 0]);
+        }
+
+        #[test]
+        fn imported_devices_decode_driver_entries() {
+            let location = ioctl2::DeviceLocation::new(mock_args().host, mock_args().bus_id);
+            let entry = (
+                location,
+                0x0001_0002u32,
+                crate::DeviceSpeed::High,
+                0x1d6bu16,
+                0x0002u16,
+            );
+            let mut reply = vec![0; 4];
+            reply.extend(bincode::encode_to_vec(entry, win_deviceioctl::bincode_config()).unwrap());
+
+            let driver = InnerDriver::new(MockDriver::default().reply(Ok(reply)));
+            let idevs = driver.imported_devices().unwrap();
+            let [idev] = idevs.get() else {
+                panic!("expected one device, got {idevs:?}");
+            };
+            assert_eq!(idev.record().unwrap().host, mock_args().host);
+            assert_eq!(idev.speed(), crate::DeviceSpeed::High);
+            assert_eq!((idev.vendor, idev.product), (0x1d6b, 0x0002));
+            // The next call's buffer is sized for one device.
+            assert_eq!(driver.last_imported.load(Ordering::Relaxed), 1);
+        }
+
+        #[test]
+        fn missing_persistent_devices_are_empty() {
+            let driver = InnerDriver::new(MockDriver::default().reply(Err(
+                win_deviceioctl::Error::Driver(DriverError::FileNotFound),
+            )));
+            assert!(driver.persistent_devices().unwrap().is_empty());
+        }
+
         #[test]
         fn driver_can_open() {
             WindowsVhciDriver::open().unwrap();
         }
 
+        #[test]
+        fn cancel_unblocks_a_pending_attach() {
+            let mut driver = WindowsVhciDriver::open().unwrap();
+            let token = CancellationToken::new();
+            let canceller = {
+                let token = token.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(500));
+                    token.cancel();
+                })
+            };
+
+            // TEST-NET-1 is never routed, so without cancelling, the
+            // driver would wait out its whole connect timeout.
+            let started = std::time::Instant::now();
+            let result = driver.attach_with_cancel(
+                AttachArgs {
+                    host: ([192, 0, 2, 1], 3240).into(),
+                    bus_id: BusId::parse("1-1").unwrap(),
+                },
+                &token,
+            );
+            canceller.join().unwrap();
+
+            assert!(matches!(result, Err(Error::Cancelled)), "{result:?}");
+            assert!(started.elapsed() < Duration::from_secs(5));
+        }
+
+        #[test]
+        fn attach_many_cancels_every_worker() {
+            let mut driver = WindowsVhciDriver::open().unwrap();
+            let token = CancellationToken::new();
+            let canceller = {
+                let token = token.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(500));
+                    token.cancel();
+                })
+            };
+
+            // Both attaches hang on unrouted hosts, so this only
+            // returns once each worker's pending ioctl is cancelled.
+            let args = (1..=2).map(|host| AttachArgs {
+                host: ([192, 0, 2, host], 3240).into(),
+                bus_id: BusId::parse("1-1").unwrap(),
+            });
+            let results =
+                driver.attach_many_with_cancel(args, NonZeroUsize::new(2).unwrap(), &token);
+            canceller.join().unwrap();
+
+            assert_eq!(results.len(), 2);
+            assert!(results
+                .iter()
+                .all(|result| matches!(result, Err(Error::Cancelled))));
+        }
+
+        #[test]
+        fn driver_error_codes_round_trip() {
+            for code in [0xE1000005u32, 0xE1000007, 0xE10000FF, 0x8007048F] {
+                let err = DriverError::try_from(code as i32).unwrap();
+                assert_eq!(err.code(), code);
+            }
+            assert_eq!(
+                DriverError::try_from(0xE10000FFu32 as i32),
+                Ok(DriverError::Unknown(0xE10000FF))
+            );
+            // Plain Win32 errors stay OS errors.
+            assert!(DriverError::try_from(ERROR_OPERATION_ABORTED.to_hresult().0).is_err());
+        }
+
+        #[test]
+        fn malformed_replies_are_protocol_errors() {
+            let err = Error::from(win_deviceioctl::Error::<DriverError>::Recv(
+                bincode::error::DecodeError::UnexpectedEnd { additional: 4 },
+            ));
+            assert!(matches!(err, Error::Net(crate::net::Error::De(_))));
+            assert_eq!(err.kind(), crate::vhci::ErrorKind::Protocol);
+        }
+
+        #[test]
+        fn first_interface_is_enumerated() {
+            let interfaces = WindowsVhciDriver::device_interfaces().unwrap();
+            let first = interfaces.first().unwrap();
+            WindowsVhciDriver::open_interface(InterfaceSelector::Path(first)).unwrap();
+        }
+
         #[test]
         fn imported_devices_doesnt_die() {
             let driver = WindowsVhciDriver::open().unwrap();
             driver.imported_devices().unwrap();
         }
 
+        #[test]
+        fn port_counts_within_total() {
+            let driver = WindowsVhciDriver::open().unwrap();
+            let counts = driver.port_counts().unwrap();
+            assert_eq!(counts.num_ports(), TOTAL_PORTS);
+            assert!(counts.free_ports() <= TOTAL_PORTS);
+        }
+
         #[test]
         fn get_persistent_doesnt_die() {
             let driver = WindowsVhciDriver::open().unwrap();
@@ -291,8 +1125,87 @@ pub mod vhci {
 
 pub static USB_IDS: &str = "";
 
+/// Windows doesn't ship usb.ids, so the candidates
+/// are found at runtime by [`Names::open_default`].
+///
+/// [`Names::open_default`]: crate::names::Names::open_default
+pub static USB_IDS_CANDIDATES: &[&str] = &[];
+
+/// The registry key, under both `HKEY_CURRENT_USER` and
+/// `HKEY_LOCAL_MACHINE`, that may name a usb.ids file.
+pub static USB_IDS_REGISTRY_KEY: &str = r"SOFTWARE\usbip-core";
+
+/// The string value of [`USB_IDS_REGISTRY_KEY`]
+/// that holds the path to usb.ids.
+pub static USB_IDS_REGISTRY_VALUE: &str = "UsbIdsPath";
+
+/// Finds the places usb.ids may have been installed,
+/// from most to least preferred.
+pub(crate) fn usb_ids_paths() -> Vec<std::path::PathBuf> {
+    use std::path::{Path, PathBuf};
+    use windows::Win32::System::Registry::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+    let mut paths = Vec::new();
+    paths.extend(registry_path(HKEY_CURRENT_USER));
+    paths.extend(registry_path(HKEY_LOCAL_MACHINE));
+
+    let exe_dir = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    paths.extend(exe_dir.map(|dir| dir.join("usb.ids")));
+
+    // usbip-win2 and Wireshark both install a copy.
+    for program_files in ["ProgramFiles", "ProgramFiles(x86)"] {
+        if let Some(dir) = std::env::var_os(program_files).map(PathBuf::from) {
+            paths.push(dir.join("USBip").join("usb.ids"));
+            paths.push(dir.join("Wireshark").join("usb.ids"));
+        }
+    }
+
+    paths
+}
+
+/// Reads the usb.ids path configured under `root`, if any.
+fn registry_path(root: windows::Win32::System::Registry::HKEY) -> Option<std::path::PathBuf> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+    use windows::{
+        core::HSTRING,
+        Win32::{
+            Foundation::ERROR_SUCCESS,
+            System::Registry::{RegGetValueW, RRF_RT_REG_SZ},
+        },
+    };
+
+    let key = HSTRING::from(USB_IDS_REGISTRY_KEY);
+    let value = HSTRING::from(USB_IDS_REGISTRY_VALUE);
+    let mut buf = [0u16; 1024];
+    let mut size = core::mem::size_of_val(&buf) as u32;
+
+    // SAFETY: `buf` is `size` bytes long, and RegGetValueW
+    //         always NUL-terminates string values.
+    let result = unsafe {
+        RegGetValueW(
+            root,
+            &key,
+            &value,
+            RRF_RT_REG_SZ,
+            None,
+            Some(buf.as_mut_ptr().cast()),
+            Some(&mut size),
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return None;
+    }
+
+    let len = (size as usize / 2).saturating_sub(1);
+    Some(OsString::from_wide(&buf[..len]).into())
+}
+
+#[cfg(feature = "client")]
 struct Win32Error(WIN32_ERROR);
 
+#[cfg(feature = "client")]
 impl Win32Error {
     pub fn get(self) -> WIN32_ERROR {
         self.0
@@ -304,6 +1217,7 @@ impl Win32Error {
     }
 }
 
+#[cfg(feature = "client")]
 impl From<Win32Error> for ::windows::core::Error {
     fn from(value: Win32Error) -> Self {
         ::windows::core::Error::from(value.get())
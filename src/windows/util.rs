@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use windows::{
     core::{GUID, PCWSTR},
     Win32::{
@@ -11,11 +13,6 @@ use windows::{
 
 use crate::windows::Win32Error;
 
-pub mod consts {
-    pub const NI_MAXSERV: usize = 32;
-    pub const NI_MAXHOST: usize = 1025;
-}
-
 pub fn get_device_interface_list<P>(
     guid: GUID,
     pdeviceid: P,
@@ -52,21 +49,96 @@ where
     }
 }
 
+/// The error returned when a byte slice can't
+/// be viewed as a slice of `u16`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastError {
+    /// The slice doesn't start on a `u16` boundary.
+    Misaligned,
+    /// The slice's length isn't a multiple of two.
+    OddLength,
+}
+
+impl std::fmt::Display for CastError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CastError::Misaligned => write!(f, "Byte slice is not aligned to u16"),
+            CastError::OddLength => write!(f, "Byte slice has an odd length"),
+        }
+    }
+}
+
+impl std::error::Error for CastError {}
+
+/// Reinterprets a byte slice as native-endian `u16`s,
+/// without copying.
+///
 /// Modified slightly from the `bytemuck` crate.
+///
+/// # Errors
+/// This function will return an error if `a` isn't
+/// aligned to `u16`, or has an odd length.
+///
+/// # Examples
+/// ```
+/// use usbip_core::vhci::{try_cast_u8_to_u16_slice, CastError};
+///
+/// let units = [0x0031u16, 0x002d, 0x0031];
+/// let bytes: Vec<u8> = units.iter().flat_map(|unit| unit.to_ne_bytes()).collect();
+/// assert_eq!(try_cast_u8_to_u16_slice(&bytes[..4]), Ok(&units[..2]));
+/// assert_eq!(try_cast_u8_to_u16_slice(&bytes[..3]), Err(CastError::OddLength));
+/// ```
 #[inline]
-pub fn cast_u8_to_u16_slice(a: &[u8]) -> &[u16] {
+pub fn try_cast_u8_to_u16_slice(a: &[u8]) -> Result<&[u16], CastError> {
     use core::mem::{align_of, size_of};
     // Note(Lokathor): everything with `align_of` and `size_of` will optimize away
     // after monomorphization.
     if align_of::<u16>() > align_of::<u8>()
         && !is_aligned_to(a.as_ptr() as *const (), align_of::<u16>())
     {
-        panic!("Target alignment greater and input not aligned")
+        Err(CastError::Misaligned)
     } else if core::mem::size_of_val(a) % size_of::<u16>() == 0 {
         let new_len = core::mem::size_of_val(a) / size_of::<u16>();
-        unsafe { core::slice::from_raw_parts(a.as_ptr() as *const u16, new_len) }
+        // SAFETY: The pointer is aligned for u16, and the new
+        //         length covers exactly the bytes of `a`.
+        Ok(unsafe { core::slice::from_raw_parts(a.as_ptr() as *const u16, new_len) })
     } else {
-        panic!("Output slice would have slop")
+        Err(CastError::OddLength)
+    }
+}
+
+/// Like [`try_cast_u8_to_u16_slice`], but copies
+/// the bytes out when `a` isn't aligned.
+///
+/// Use this for UTF-16 payloads returned by the
+/// driver, where the buffer's alignment is up to
+/// whoever allocated it.
+///
+/// # Errors
+/// This function will return an error if
+/// `a` has an odd length.
+///
+/// # Examples
+/// ```
+/// use usbip_core::vhci::cast_u8_to_u16_cow;
+///
+/// let utf16: Vec<u16> = "1-1".encode_utf16().collect();
+/// let mut bytes = vec![0u8];
+/// bytes.extend(utf16.iter().flat_map(|unit| unit.to_ne_bytes()));
+///
+/// // Skipping the first byte misaligns the slice, so it gets copied.
+/// let units = cast_u8_to_u16_cow(&bytes[1..]).unwrap();
+/// assert_eq!(String::from_utf16(&units).unwrap(), "1-1");
+/// ```
+pub fn cast_u8_to_u16_cow(a: &[u8]) -> Result<Cow<'_, [u16]>, CastError> {
+    match try_cast_u8_to_u16_slice(a) {
+        Ok(slice) => Ok(Cow::Borrowed(slice)),
+        Err(CastError::Misaligned) if a.len() % 2 == 0 => Ok(Cow::Owned(
+            a.chunks_exact(2)
+                .map(|pair| u16::from_ne_bytes([pair[0], pair[1]]))
+                .collect(),
+        )),
+        Err(_) => Err(CastError::OddLength),
     }
 }
 
@@ -84,3 +156,19 @@ fn is_aligned_to(ptr: *const (), align: usize) -> bool {
     // some compiler optimizations.
     ptr.align_offset(align) == 0
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cast_falls_back_to_copying_when_misaligned() {
+        let units = [0x0031u16, 0x002d, 0x0031];
+        let mut bytes = vec![0u8];
+        bytes.extend(units.iter().flat_map(|unit| unit.to_ne_bytes()));
+
+        // One of these two is misaligned, but both read the same.
+        assert_eq!(&*cast_u8_to_u16_cow(&bytes[1..]).unwrap(), &units);
+        assert_eq!(cast_u8_to_u16_cow(&bytes[1..6]), Err(CastError::OddLength));
+    }
+}
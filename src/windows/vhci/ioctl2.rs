@@ -1,41 +1,144 @@
-use std::{borrow::Cow, net::SocketAddr, str::FromStr};
+use std::{net::SocketAddr, str::FromStr};
 
 use bincode::{
-    de::{read::Reader, Decoder},
+    de::Decoder,
     impl_borrow_decode, BorrowDecode, Encode,
 };
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
 use win_deviceioctl::{ControlCode, DeviceType, EncResult, RequiredAccess, TransferMethod};
 
 use crate::{
     containers::stacktools::{StackStr, Str},
-    util::EncodedSize,
-    BusId, DeviceSpeed, BUS_ID_SIZE,
+    util::{EncodedSize, Padding},
+    BusId, DevId, DeviceSpeed, BUS_ID_SIZE,
 };
 
-/// A non-exhaustive list of the error codes
-/// that can be returned by the vhci driver.
+/// The base of usbip-win2's custom error codes. Its
+/// own errors count up from here in the order of
+/// its `err_t` enum.
+const USBIP_ERROR_BASE: u32 = 0xE1000000;
+
+/// The error codes that can be returned by the vhci driver.
+///
+/// Codes in usbip-win2's custom range that this crate
+/// doesn't know yet are kept as [`DriverError::Unknown`].
 #[non_exhaustive]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DriverError {
-    InvalidAbi = 0xE1000008,
-    IncompatibleProtocolVersion = 0xE1000005,
-    //DevNotConnected = 0x8007048F,
-    DevNotConnected = -2147023729,
-    FileNotFound = -2147024894,
+    General,
+    /// The host's address couldn't be resolved.
+    AddrInfo,
+    Connect,
+    Network,
+    IncompatibleProtocolVersion,
+    Protocol,
+    /// Every port of the vhci hub is in use.
+    PortFull,
+    InvalidAbi,
+    /// The driver is missing or didn't respond.
+    Driver,
+    /// `HRESULT_FROM_WIN32(ERROR_DEVICE_NOT_CONNECTED)`
+    DevNotConnected,
+    /// `HRESULT_FROM_WIN32(ERROR_FILE_NOT_FOUND)`
+    FileNotFound,
+    Unknown(u32),
+}
+
+impl DriverError {
+    /// Returns the raw code the driver reported.
+    pub const fn code(&self) -> u32 {
+        match self {
+            DriverError::General => USBIP_ERROR_BASE + 1,
+            DriverError::AddrInfo => USBIP_ERROR_BASE + 2,
+            DriverError::Connect => USBIP_ERROR_BASE + 3,
+            DriverError::Network => USBIP_ERROR_BASE + 4,
+            DriverError::IncompatibleProtocolVersion => USBIP_ERROR_BASE + 5,
+            DriverError::Protocol => USBIP_ERROR_BASE + 6,
+            DriverError::PortFull => USBIP_ERROR_BASE + 7,
+            DriverError::InvalidAbi => USBIP_ERROR_BASE + 8,
+            DriverError::Driver => USBIP_ERROR_BASE + 9,
+            DriverError::DevNotConnected => 0x8007048F,
+            DriverError::FileNotFound => 0x80070002,
+            DriverError::Unknown(code) => *code,
+        }
+    }
+
+    /// Returns a human-readable description
+    /// of the error, like `strerror`.
+    pub const fn message(&self) -> &'static str {
+        match self {
+            DriverError::General => "General driver error",
+            DriverError::AddrInfo => "Failed to resolve the host's address",
+            DriverError::Connect => "Failed to connect to the host",
+            DriverError::Network => "Network error while talking to the host",
+            DriverError::IncompatibleProtocolVersion => {
+                "Host speaks an incompatible USB/IP version"
+            }
+            DriverError::Protocol => "Host violated the USB/IP protocol",
+            DriverError::PortFull => "No free port on the vhci hub",
+            DriverError::InvalidAbi => "Driver rejected the request's layout",
+            DriverError::Driver => "Driver is not loaded or not responding",
+            DriverError::DevNotConnected => "Device is not connected",
+            DriverError::FileNotFound => "Driver file not found",
+            DriverError::Unknown(_) => "Unknown usbip-win2 error",
+        }
+    }
+
+    /// Sorts the error into a platform-independent
+    /// [`crate::vhci::ErrorKind`].
+    pub const fn kind(&self) -> crate::vhci::ErrorKind {
+        use crate::vhci::ErrorKind;
+
+        match self {
+            DriverError::AddrInfo
+            | DriverError::Connect
+            | DriverError::Network
+            | DriverError::DevNotConnected => ErrorKind::NotConnected,
+            DriverError::IncompatibleProtocolVersion
+            | DriverError::Protocol
+            | DriverError::InvalidAbi => ErrorKind::Protocol,
+            DriverError::PortFull => ErrorKind::Busy,
+            DriverError::Driver => ErrorKind::DriverMissing,
+            DriverError::General | DriverError::FileNotFound | DriverError::Unknown(_) => {
+                ErrorKind::Io
+            }
+        }
+    }
 }
 
 impl TryFrom<i32> for DriverError {
     type Error = ();
+
+    /// Fails for codes outside of usbip-win2's range, which
+    /// are left to be reported as plain OS errors.
     fn try_from(value: i32) -> Result<Self, Self::Error> {
-        Self::from_i32(value).ok_or(())
+        let code = value as u32;
+        let err = match code {
+            0x8007048F => DriverError::DevNotConnected,
+            0x80070002 => DriverError::FileNotFound,
+            _ if code & 0xFFFF0000 != USBIP_ERROR_BASE => return Err(()),
+            _ => match code - USBIP_ERROR_BASE {
+                1 => DriverError::General,
+                2 => DriverError::AddrInfo,
+                3 => DriverError::Connect,
+                4 => DriverError::Network,
+                5 => DriverError::IncompatibleProtocolVersion,
+                6 => DriverError::Protocol,
+                7 => DriverError::PortFull,
+                8 => DriverError::InvalidAbi,
+                9 => DriverError::Driver,
+                _ => DriverError::Unknown(code),
+            },
+        };
+        Ok(err)
     }
 }
 
 impl std::fmt::Display for DriverError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+        match self {
+            DriverError::Unknown(code) => write!(f, "{} ({code:#010x})", self.message()),
+            _ => write!(f, "{}", self.message()),
+        }
     }
 }
 
@@ -67,14 +170,8 @@ pub struct DeviceLocation<'a> {
 }
 
 impl<'a> DeviceLocation<'a> {
-    pub const fn new(host: SocketAddr, busid: &'a str) -> Option<Self> {
-        match Str::new(busid) {
-            Some(busid) => Some(Self {
-                host,
-                busid: BusId::new(Cow::Borrowed(busid)),
-            }),
-            None => None,
-        }
+    pub const fn new(host: SocketAddr, busid: BusId<'a>) -> Self {
+        Self { host, busid }
     }
 }
 
@@ -83,7 +180,6 @@ impl bincode::Encode for DeviceLocation<'_> {
         &self,
         encoder: &mut E,
     ) -> Result<(), bincode::error::EncodeError> {
-        use bincode::enc::write::Writer;
         0i32.encode(encoder)?;
         self.busid.encode(encoder)?;
         StackStr::<32>::try_from(format_args!("{}", self.host.port()))
@@ -92,9 +188,7 @@ impl bincode::Encode for DeviceLocation<'_> {
         StackStr::<1025>::try_from(format_args!("{}", self.host.ip()))
             .unwrap()
             .encode(encoder)?;
-        encoder.writer().write(&[0, 0, 0])?;
-
-        Ok(())
+        Padding::<[u8; 3]>::new().encode(encoder)
     }
 }
 
@@ -112,11 +206,11 @@ impl FromStr for DeviceLocation<'static> {
             .to_socket_addrs()
             .map_err(|_| ())?
             .next()
-            .unwrap();
+            .ok_or(())?;
 
         Ok(Self {
             host,
-            busid: BusId::new(Cow::Owned(StackStr::try_from(busid).unwrap())),
+            busid: busid.parse().map_err(|_| ())?,
         })
     }
 }
@@ -232,8 +326,7 @@ impl<'de> bincode::BorrowDecode<'de> for PortRecord<'de> {
         let service: &Str<32> = bincode::BorrowDecode::borrow_decode(decoder)?;
         let host: &Str<1025> = bincode::BorrowDecode::borrow_decode(decoder)?;
         // Account for padding from irregular array size
-        decoder.claim_bytes_read(3)?;
-        decoder.reader().consume(3);
+        Padding::<[u8; 3]>::borrow_decode(decoder)?;
 
         Ok(Self {
             port,
@@ -246,7 +339,7 @@ impl<'de> bincode::BorrowDecode<'de> for PortRecord<'de> {
 
 pub struct ImportedDevice<'a> {
     pub record: PortRecord<'a>,
-    pub devid: u32,
+    pub devid: DevId,
     pub speed: DeviceSpeed,
     pub vendor: u16,
     pub product: u16,
@@ -257,7 +350,7 @@ impl<'de> bincode::BorrowDecode<'de> for ImportedDevice<'de> {
         decoder: &mut D,
     ) -> Result<Self, bincode::error::DecodeError> {
         let record = PortRecord::borrow_decode(decoder)?;
-        let devid = u32::borrow_decode(decoder)?;
+        let devid = DevId::borrow_decode(decoder)?;
         let speed = DeviceSpeed::borrow_decode(decoder)?;
         let vendor = u16::borrow_decode(decoder)?;
         let product = u16::borrow_decode(decoder)?;
@@ -276,7 +369,20 @@ unsafe impl EncodedSize for ImportedDevice<'_> {
     const ENCODED_SIZE_OF: usize = 1108;
 }
 
-pub struct GetImportedDevices;
+/// Lists the imported devices.
+///
+/// The driver's reply holds every device at once, so the
+/// output buffer is sized for the number of devices the
+/// caller expects, which saves a regrow when it's right.
+pub struct GetImportedDevices {
+    expected: usize,
+}
+
+impl GetImportedDevices {
+    pub const fn expecting(expected: usize) -> Self {
+        Self { expected }
+    }
+}
 
 impl win_deviceioctl::Send for GetImportedDevices {
     fn send<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> EncResult {
@@ -286,30 +392,22 @@ impl win_deviceioctl::Send for GetImportedDevices {
 }
 
 impl win_deviceioctl::Recv for GetImportedDevices {
-    type Output = Vec<super::WindowsImportedDevice>;
+    type Output = Box<[u8]>;
 
     fn buf_starting_capacity(&self) -> Option<usize> {
-        Some(ImportedDevice::ENCODED_SIZE_OF + core::mem::size_of::<u32>())
+        let expected = self.expected.clamp(1, super::TOTAL_PORTS);
+        Some(core::mem::size_of::<u32>() + expected * ImportedDevice::ENCODED_SIZE_OF)
     }
 
+    /// Keeps the driver's entries as is, to be decoded
+    /// with [`ImportedDevice::borrow_decode`].
     fn recv(bytes: &[u8]) -> win_deviceioctl::DecResult<Self::Output> {
-        let buf_len = bytes.len();
-        let num_items = (buf_len - core::mem::size_of::<u32>()) / ImportedDevice::ENCODED_SIZE_OF;
-        let mut buf = Vec::with_capacity(num_items);
-
-        let reader = bincode::de::read::SliceReader::new(&bytes[core::mem::size_of::<u32>()..]);
-        let mut decoder = bincode::de::DecoderImpl::new(reader, win_deviceioctl::bincode_config());
-
-        decoder.claim_container_read::<[u8; ImportedDevice::ENCODED_SIZE_OF]>(num_items)?;
-
-        for _ in 0..num_items {
-            decoder.unclaim_bytes_read(ImportedDevice::ENCODED_SIZE_OF);
-
-            let idev = ImportedDevice::borrow_decode(&mut decoder)?;
-            buf.push(idev);
-        }
-
-        Ok(buf.into_iter().map(|idev| idev.into()).collect::<Vec<_>>())
+        let entries = bytes.get(core::mem::size_of::<u32>()..).ok_or(
+            bincode::error::DecodeError::UnexpectedEnd {
+                additional: core::mem::size_of::<u32>() - bytes.len(),
+            },
+        )?;
+        Ok(entries.into())
     }
 }
 
@@ -317,23 +415,42 @@ impl win_deviceioctl::CtrlCode for GetImportedDevices {
     const CODE: ControlCode = Function::GetImportedDevices.make_ctrl_code();
 }
 
-pub struct GetPersistentDevices;
+/// Lists the persistent devices.
+///
+/// Like [`GetImportedDevices`], the output buffer is sized
+/// for the number of devices the caller expects.
+pub struct GetPersistentDevices {
+    expected: usize,
+}
+
+impl GetPersistentDevices {
+    /// The longest `host,service,busid` entry, in UTF-16
+    /// units with its nul, when the host is an ip address.
+    const MAX_ENTRY_LEN: usize =
+        "ffff:ffff:ffff:ffff:ffff:ffff:255.255.255.255".len() + ",65535,".len() + BUS_ID_SIZE;
+
+    pub const fn expecting(expected: usize) -> Self {
+        Self { expected }
+    }
+}
 
 impl win_deviceioctl::Recv for GetPersistentDevices {
-    type Output = Vec<DeviceLocation<'static>>;
+    type Output = Vec<super::DeviceLocation>;
 
     fn buf_starting_capacity(&self) -> Option<usize> {
-        None
+        // Room for every entry, plus the
+        // list's terminating nul.
+        let units = self.expected.max(1) * Self::MAX_ENTRY_LEN + 1;
+        Some(units * core::mem::size_of::<u16>())
     }
 
     fn recv(bytes: &[u8]) -> win_deviceioctl::DecResult<Self::Output> {
-        // Now we're going to be silly.
-        // This will panic if not properly aligned, which will
-        // definitely mean that I did something wrong.
-        let phat_buf = crate::windows::util::cast_u8_to_u16_slice(bytes);
+        let phat_buf = crate::windows::util::cast_u8_to_u16_cow(bytes).map_err(|_| {
+            bincode::error::DecodeError::Other("Persistent device list has an odd length")
+        })?;
 
         // If this fails this might also be my fault
-        let entries = String::from_utf16(phat_buf).map_err(|_| {
+        let entries = String::from_utf16(&phat_buf).map_err(|_| {
             bincode::error::DecodeError::Other("Failed to decode UTF-16 slice as a String")
         })?;
 
@@ -347,3 +464,66 @@ impl win_deviceioctl::Recv for GetPersistentDevices {
 impl win_deviceioctl::CtrlCode for GetPersistentDevices {
     const CODE: ControlCode = Function::GetPersistent.make_ctrl_code();
 }
+
+/// Replaces the driver's persistent devices with a
+/// `REG_MULTI_SZ` list of `host,service,busid` entries.
+pub struct SetPersistentDevices<'a>(pub &'a [u16]);
+
+impl win_deviceioctl::Send for SetPersistentDevices<'_> {
+    fn send<E: bincode::enc::Encoder>(&self, encoder: &mut E) -> EncResult {
+        use bincode::enc::write::Writer;
+
+        for unit in self.0 {
+            encoder.writer().write(&unit.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl win_deviceioctl::CtrlCode for SetPersistentDevices<'_> {
+    const CODE: ControlCode = Function::SetPersistent.make_ctrl_code();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use proptest::prelude::any;
+
+    use super::*;
+
+    proptest::proptest! {
+        #[test]
+        fn imported_device_decodes_at_its_encoded_size(
+            ip in any::<IpAddr>(),
+            port in any::<u16>(),
+            busid in "[0-9.-]{1,31}",
+            ids in any::<(u32, u16, u16)>(),
+        ) {
+            let config = win_deviceioctl::bincode_config();
+            let location = DeviceLocation::new(
+                SocketAddr::new(ip, port),
+                BusId::parse(&busid).unwrap(),
+            );
+            let location_bytes = bincode::encode_to_vec(&location, config).unwrap();
+            assert_eq!(location_bytes.len(), DeviceLocation::ENCODED_SIZE_OF);
+
+            // The driver lays out an imported device
+            // as its location followed by the ids.
+            let (devid, vendor, product) = ids;
+            let bytes =
+                bincode::encode_to_vec((location, devid, DeviceSpeed::High, vendor, product), config)
+                    .unwrap();
+            assert_eq!(bytes.len(), ImportedDevice::ENCODED_SIZE_OF);
+
+            let (idev, read): (ImportedDevice, _) =
+                bincode::borrow_decode_from_slice(&bytes, config).unwrap();
+            assert_eq!(read, bytes.len());
+            assert_eq!(idev.record.busid.trim_padding().as_str(), busid);
+            assert_eq!(idev.record.service.trim_padding().as_str(), port.to_string());
+            assert_eq!(idev.record.host.trim_padding().as_str(), ip.to_string());
+            assert_eq!(idev.devid, DevId::from(devid));
+            assert_eq!((idev.vendor, idev.product), (vendor, product));
+        }
+    }
+}
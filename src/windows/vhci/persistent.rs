@@ -0,0 +1,171 @@
+//! The persistent devices, mirrored into the registry where
+//! usbip-win2's driver and tools keep them, so that scripts
+//! managing that key see what this crate sets.
+
+use std::{ffi::OsString, io, os::windows::ffi::OsStringExt};
+
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Foundation::{ERROR_FILE_NOT_FOUND, ERROR_MORE_DATA, ERROR_SUCCESS},
+        System::Registry::{
+            RegGetValueW, RegSetKeyValueW, HKEY_LOCAL_MACHINE, REG_MULTI_SZ, RRF_RT_REG_MULTI_SZ,
+        },
+    },
+};
+
+use super::DeviceLocation;
+
+/// The registry key, under `HKEY_LOCAL_MACHINE`, that holds
+/// usbip-win2's persistent devices.
+pub static PERSISTENT_REGISTRY_KEY: &str =
+    r"SYSTEM\CurrentControlSet\Services\usbip2_ude\Parameters";
+
+/// The `REG_MULTI_SZ` value of [`PERSISTENT_REGISTRY_KEY`]
+/// that lists the devices, one `host,service,busid` each.
+pub static PERSISTENT_REGISTRY_VALUE: &str = "devices";
+
+/// Lays out `devices` as a `REG_MULTI_SZ`.
+pub(super) fn to_multi_sz(devices: &[DeviceLocation]) -> Vec<u16> {
+    let mut multi_sz = Vec::new();
+    for device in devices {
+        multi_sz.extend(device.to_string().encode_utf16());
+        multi_sz.push(0);
+    }
+    multi_sz.push(0);
+    multi_sz
+}
+
+/// Reads the entries of a `REG_MULTI_SZ`, skipping
+/// those that aren't valid device locations.
+pub(super) fn from_multi_sz(multi_sz: &[u16]) -> Vec<DeviceLocation> {
+    multi_sz
+        .split(|&unit| unit == 0)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let entry = OsString::from_wide(entry);
+            entry.to_str()?.parse().ok()
+        })
+        .collect()
+}
+
+/// Reads the persistent devices from [`PERSISTENT_REGISTRY_KEY`].
+///
+/// Hosts are kept as written, without resolving them.
+/// Entries that aren't valid locations are skipped.
+///
+/// # Errors
+/// This function will return an error if the value
+/// exists but couldn't be read.
+pub fn read_persistent_registry() -> io::Result<Vec<DeviceLocation>> {
+    let key = HSTRING::from(PERSISTENT_REGISTRY_KEY);
+    let value = HSTRING::from(PERSISTENT_REGISTRY_VALUE);
+    let mut buf = vec![0u16; 1024];
+    loop {
+        let mut size = u32::try_from(buf.len() * 2).unwrap_or(u32::MAX);
+        // SAFETY: `buf` is `size` bytes long.
+        let result = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                &key,
+                &value,
+                RRF_RT_REG_MULTI_SZ,
+                None,
+                Some(buf.as_mut_ptr().cast()),
+                Some(&mut size),
+            )
+        };
+        match result {
+            ERROR_SUCCESS => {
+                buf.truncate(size as usize / 2);
+                return Ok(from_multi_sz(&buf));
+            }
+            ERROR_FILE_NOT_FOUND => return Ok(Vec::new()),
+            // The value may grow between calls, so
+            // leave room for a little more.
+            ERROR_MORE_DATA => buf.resize(size as usize / 2 + 256, 0),
+            err => return Err(io::Error::from_raw_os_error(err.0 as i32)),
+        }
+    }
+}
+
+/// Replaces the persistent devices in [`PERSISTENT_REGISTRY_KEY`]
+/// with `devices`, creating the key if needed.
+///
+/// # Errors
+/// This function will return an error if the value couldn't
+/// be written, like when the caller isn't an administrator.
+pub fn write_persistent_registry(devices: &[DeviceLocation]) -> io::Result<()> {
+    write_multi_sz(&to_multi_sz(devices))
+}
+
+pub(super) fn write_multi_sz(multi_sz: &[u16]) -> io::Result<()> {
+    let key = HSTRING::from(PERSISTENT_REGISTRY_KEY);
+    let value = HSTRING::from(PERSISTENT_REGISTRY_VALUE);
+    let size = u32::try_from(core::mem::size_of_val(multi_sz))
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    // SAFETY: `multi_sz` is `size` bytes long.
+    let result = unsafe {
+        RegSetKeyValueW(
+            HKEY_LOCAL_MACHINE,
+            &key,
+            &value,
+            REG_MULTI_SZ.0,
+            Some(multi_sz.as_ptr().cast()),
+            size,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(result.0 as i32));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::BusId;
+
+    #[test]
+    fn multi_sz_round_trips() {
+        let devices = [
+            DeviceLocation::new(
+                SocketAddr::from(([10, 0, 0, 2], 3240)),
+                BusId::parse("1-1").unwrap().into_owned(),
+            ),
+            DeviceLocation::new(
+                SocketAddr::from(([10, 0, 0, 3], 3241)),
+                BusId::parse("2-1.4").unwrap().into_owned(),
+            ),
+        ];
+        let multi_sz = to_multi_sz(&devices);
+        assert_eq!(
+            String::from_utf16(&multi_sz).unwrap(),
+            "10.0.0.2,3240,1-1\010.0.0.3,3241,2-1.4\0\0"
+        );
+        assert_eq!(from_multi_sz(&multi_sz), devices);
+        assert!(from_multi_sz(&to_multi_sz(&[])).is_empty());
+    }
+
+    #[test]
+    fn hostnames_are_kept_unresolved() {
+        let multi_sz: Vec<u16> = "usbip.invalid,3240,1-1\0,3240,1-2\0host,port,1-3\0\0"
+            .encode_utf16()
+            .collect();
+        let devices = from_multi_sz(&multi_sz);
+        assert_eq!(
+            devices,
+            [DeviceLocation::with_hostname(
+                "usbip.invalid",
+                3240,
+                BusId::parse("1-1").unwrap().into_owned()
+            )]
+        );
+        assert_eq!(
+            String::from_utf16(&to_multi_sz(&devices)).unwrap(),
+            "usbip.invalid,3240,1-1\0\0"
+        );
+    }
+}